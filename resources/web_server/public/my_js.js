@@ -91,6 +91,27 @@ function error_to_graphic(cell) {
     $(cell).html(symbol);
 }
 
+function timestamp_to_human(cell) {
+    var millis = parseInt(cell.innerHTML);
+    if (millis == 0) {
+        $(cell).html("Never");
+    } else {
+        $(cell).html(new Date(millis).toLocaleString());
+    }
+}
+
+function alert_status_to_graphic(cell) {
+    var status = cell.innerHTML;
+    if (status == "Firing") {
+        var symbol = $('<span>', { class: 'label label-danger', text: status });
+    } else if (status == "Ok") {
+        var symbol = $('<span>', { class: 'label label-success', text: status });
+    } else {
+        var symbol = $('<span>', { class: 'label label-default', text: status });
+    }
+    $(cell).html(symbol);
+}
+
 $(document).ready(function() {
     $('#datatable-brokers-ajax').each(function(index) {
         $(this).DataTable({
@@ -253,6 +274,147 @@ $(document).ready(function() {
             }
         });
     });
+    $('#datatable-topic-config-ajax').each(function(index) {
+        $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, -1], [10, 50, "All"] ],
+            "language": { "search": "Regex search:", "emptyTable": "No configuration overrides" },
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true
+        });
+    });
+    $('#datatable-health-brokers-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 2, 3, 4 ] }
+            ],
+            "processing": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                broker_to_url(cluster_id, $(row).children()[0]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
+    $('#datatable-health-partitions-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "language": { "search": "Regex search:", "emptyTable": "All partitions are healthy" },
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                topic_to_url(cluster_id, $(row).children()[0]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
+    $('#datatable-broker-partitions-ajax').each(function(index) {
+        $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "language": { "search": "Regex search:", "emptyTable": "No partition hosted" },
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 1, 4 ] }
+            ],
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                topic_to_url(cluster_id, $(row).children()[0]);
+                bytes_to_human($(row).children()[4], "");
+            }
+        });
+    });
+    $('#datatable-leadership-ajax').each(function(index) {
+        $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "columnDefs": [
+                { "className": "dt-body-right", "targets": [ 2, 3, 4, 5 ] }
+            ],
+            "processing": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                broker_to_url(cluster_id, $(row).children()[0]);
+                $($(row).children()[5]).append("%");
+            }
+        });
+    });
+    $('#datatable-non-preferred-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "language": { "search": "Regex search:", "emptyTable": "All partitions are led by their preferred replica" },
+            "processing": true,
+            "deferRender": true,
+            "createdRow": function(row, data, index) {
+                var cluster_id = $(this).attr("data-param");
+                var row = $(row).children();
+                topic_to_url(cluster_id, row[0]);
+                broker_to_url(cluster_id, row[2]);
+                broker_to_url(cluster_id, row[3]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
+    $('#datatable-events-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "language": { "search": "Regex search:", "emptyTable": "No events since the last restart" },
+            "processing": true,
+            "deferRender": true,
+            "order": [[0, "desc"]],
+            "createdRow": function(row, data, index) {
+                var row = $(row).children();
+                timestamp_to_human(row[0]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
+    $('#datatable-alerts-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "search": { "regex": true},
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, 200, -1], [10, 50, 200, "All"] ],
+            "pageLength": 50,
+            "language": { "search": "Regex search:" },
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                var row = $(row).children();
+                cluster_to_url(row[1]);
+                alert_status_to_graphic(row[2]);
+                timestamp_to_human(row[3]);
+                timestamp_to_human(row[4]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
     $('#datatable-internals-cache-brokers-ajax').each(function(index) {
         var table = $(this).DataTable({
             "ajax": $(this).attr("data-url"),
@@ -292,6 +454,24 @@ $(document).ready(function() {
             table.ajax.reload();
         }, 20000 );
     });
+    $('#datatable-internals-ownership-ajax').each(function(index) {
+        var table = $(this).DataTable({
+            "ajax": $(this).attr("data-url"),
+            "lengthMenu": [ [10, 50, -1], [10, 50, "All"] ],
+            "pageLength": 50,
+            "processing": true,
+            "deferRender": true,
+            "stateSave": true,
+            "createdRow": function(row, data, index) {
+                var row = $(row).children();
+                timestamp_to_human(row[2]);
+                timestamp_to_human(row[3]);
+            }
+        });
+        setInterval( function () {
+            table.ajax.reload();
+        }, 20000 );
+    });
     $('#datatable-internals-live-consumers-ajax').each(function(index) {
         var table = $(this).DataTable({
             "ajax": $(this).attr("data-url"),
@@ -329,6 +509,32 @@ var max_msg_length = 1024;
 var poll_interval = 1000;
 
 var tailer_active = true;
+var tailer_filter = {};
+
+// Returns false if the response contains an error.
+function append_tailer_messages(response) {
+  var div_tailer = $('div.topic_tailer');
+  var bottom = isScrolledToBottom(div_tailer);
+  if (response.error) {
+    $('span.tailer_counters').text(response.error);
+    return false;
+  }
+  var messages = response.messages;
+  for (var i = 0; i < messages.length; i++) {
+    var message = messages[i];
+    var p = $("<p>", {class: "message"});
+    p.text(truncate(message[2], max_msg_length));
+    div_tailer.append(p);
+  }
+  if (bottom)
+      scroll_to_bottom(div_tailer);
+  var message_count = div_tailer.children().length;
+  if (message_count > max_msg_count)
+      div_tailer.children().slice(0, message_count - max_msg_count).remove();
+  if (!$.isEmptyObject(tailer_filter))
+      $('span.tailer_counters').text(response.matched + " matching messages out of " + response.scanned + " scanned");
+  return true;
+}
 
 function background_tailer(cluster_id, topic_name, tailer_id) {
   if (!tailer_active) {
@@ -336,23 +542,12 @@ function background_tailer(cluster_id, topic_name, tailer_id) {
     return
   }
   var url = '/api/tailer/' + cluster_id + '/' + topic_name + '/' + tailer_id;
+  if (!$.isEmptyObject(tailer_filter))
+    url += '?' + $.param(tailer_filter);
   $.ajax({
     url: url,
     success: function(data) {
-      var div_tailer = $('div.topic_tailer');
-      var bottom = isScrolledToBottom(div_tailer);
-      messages = JSON.parse(data);
-      for (var i = 0; i < messages.length; i++) {
-        var message = messages[i];
-        var p = $("<p>", {class: "message"});
-        p.append(truncate(message[2], max_msg_length));
-        div_tailer.append(p);
-      }
-      if (bottom)
-          scroll_to_bottom(div_tailer);
-      var message_count = div_tailer.children().length;
-      if (message_count > max_msg_count)
-          div_tailer.children().slice(0, message_count - max_msg_count).remove();
+      append_tailer_messages(JSON.parse(data));
     },
     error: function(data) {
       console.log("error");
@@ -364,13 +559,61 @@ function background_tailer(cluster_id, topic_name, tailer_id) {
   });
 }
 
+var tailer_event_source = null;
+
+// Streams the messages using Server-Sent Events, falling back to polling if the stream
+// can't be opened.
+function streaming_tailer(cluster_id, topic_name, tailer_id) {
+  if (tailer_event_source != null)
+    tailer_event_source.close();
+  var url = '/api/tailer_stream/' + cluster_id + '/' + topic_name;
+  if (!$.isEmptyObject(tailer_filter))
+    url += '?' + $.param(tailer_filter);
+  var source = new EventSource(url);
+  var opened = false;
+  source.onopen = function() { opened = true; };
+  source.addEventListener('messages', function(event) {
+    if (tailer_active)
+      append_tailer_messages(JSON.parse(event.data));
+  });
+  source.onerror = function() {
+    if (!opened) {
+      source.close();
+      tailer_event_source = null;
+      background_tailer(cluster_id, topic_name, tailer_id);
+    }
+  };
+  tailer_event_source = source;
+}
+
 // Load topic tailers
 $(document).ready(function() {
     $('.topic_tailer').each(function(index) {
         var cluster_id = $(this).attr("data-cluster");
         var topic_name = $(this).attr("data-topic");
         var tailer_id = $(this).attr("data-tailer");
-        background_tailer(cluster_id, topic_name, tailer_id);
+        if (window.EventSource)
+            streaming_tailer(cluster_id, topic_name, tailer_id);
+        else
+            background_tailer(cluster_id, topic_name, tailer_id);
+    });
+    $('form.tailer_filter_form').submit(function(event) {
+        event.preventDefault();
+        tailer_filter = {};
+        var form = $(this);
+        var filter = form.find('input[name="filter"]').val();
+        if (filter != "") {
+            tailer_filter.filter_type = form.find('select[name="filter_type"]').val();
+            tailer_filter.filter = filter;
+            tailer_filter.value = form.find('input[name="value"]').val();
+            tailer_filter.key_only = form.find('input[name="key_only"]').is(':checked');
+        }
+        $('div.topic_tailer').empty();
+        $('span.tailer_counters').text("");
+        if (tailer_event_source != null) {
+            var div_tailer = $('div.topic_tailer');
+            streaming_tailer(div_tailer.attr("data-cluster"), div_tailer.attr("data-topic"), div_tailer.attr("data-tailer"));
+        }
     });
     $('#start_tailer_button').click(function(event) {
         event.preventDefault();
@@ -384,6 +627,397 @@ $(document).ready(function() {
     })
 });
 
+var chart_colors = ['#337ab7', '#d9534f', '#5cb85c', '#f0ad4e', '#5bc0de', '#9b59b6', '#34495e', '#e67e22'];
+
+// Draws one line per series on the canvas. Each series is {label: string, points: [[x, y], ...]},
+// where x is a timestamp in milliseconds.
+function draw_line_chart(canvas, series) {
+    var ctx = canvas.getContext('2d');
+    var width = canvas.width, height = canvas.height;
+    var margin = {left: 70, right: 10, top: 10, bottom: 40};
+    ctx.clearRect(0, 0, width, height);
+
+    var min_x = Infinity, max_x = -Infinity, max_y = 0;
+    series.forEach(function(s) {
+        s.points.forEach(function(p) {
+            min_x = Math.min(min_x, p[0]);
+            max_x = Math.max(max_x, p[0]);
+            max_y = Math.max(max_y, p[1]);
+        });
+    });
+    if (min_x === Infinity) {
+        ctx.fillText("No lag samples collected yet.", margin.left, height / 2);
+        return;
+    }
+    if (max_x == min_x) max_x = min_x + 1;
+    if (max_y == 0) max_y = 1;
+
+    var to_x = function(x) { return margin.left + (x - min_x) / (max_x - min_x) * (width - margin.left - margin.right); };
+    var to_y = function(y) { return height - margin.bottom - y / max_y * (height - margin.top - margin.bottom); };
+
+    // Axes and labels
+    ctx.strokeStyle = '#ccc';
+    ctx.fillStyle = '#333';
+    ctx.beginPath();
+    ctx.moveTo(margin.left, margin.top);
+    ctx.lineTo(margin.left, height - margin.bottom);
+    ctx.lineTo(width - margin.right, height - margin.bottom);
+    ctx.stroke();
+    ctx.textAlign = 'right';
+    for (var i = 0; i <= 4; i++) {
+        var y = max_y * i / 4;
+        ctx.fillText(Math.round(y), margin.left - 5, to_y(y) + 3);
+    }
+    ctx.textAlign = 'center';
+    for (var i = 0; i <= 4; i++) {
+        var x = min_x + (max_x - min_x) * i / 4;
+        ctx.fillText(new Date(x).toLocaleTimeString(), to_x(x), height - margin.bottom + 15);
+    }
+
+    // Series
+    series.forEach(function(s, index) {
+        var color = chart_colors[index % chart_colors.length];
+        ctx.strokeStyle = color;
+        ctx.beginPath();
+        s.points.forEach(function(p, i) {
+            if (i == 0) ctx.moveTo(to_x(p[0]), to_y(p[1]));
+            else ctx.lineTo(to_x(p[0]), to_y(p[1]));
+        });
+        ctx.stroke();
+        ctx.fillStyle = color;
+        ctx.textAlign = 'left';
+        ctx.fillText(s.label, margin.left + 10 + index * 150, height - 5);
+    });
+}
+
+function load_lag_history_chart(div) {
+    $.ajax({
+        url: div.attr("data-url"),
+        success: function(data) {
+            var series = JSON.parse(data).data.map(function(topic_history) {
+                return {
+                    label: topic_history.topic,
+                    points: topic_history.samples.map(function(sample) { return [sample[0], sample[1]]; })
+                };
+            });
+            draw_line_chart(div.children('canvas')[0], series);
+        }
+    });
+}
+
+// Load lag history charts
+$(document).ready(function() {
+    $('.lag_history_chart').each(function(index) {
+        var div = $(this);
+        load_lag_history_chart(div);
+        setInterval(function() { load_lag_history_chart(div); }, 60000);
+    });
+});
+
+// Message browser: every request returns a page of messages, and the offsets needed to load
+// the previous and next pages.
+var browser_page = null;
+
+function browser_time_to_millis(value) {
+    if (/^\d+$/.test(value))
+        return parseInt(value);
+    return new Date(value.replace(' ', 'T')).getTime();
+}
+
+function load_browser_page(form, params) {
+    var url = form.attr("data-url") + '/' + $('#browser_partition').val();
+    params.count = $('#browser_count').val();
+    var status = $('div.message_browser_status');
+    status.html("Loading...");
+    $.ajax({
+        url: url + '?' + $.param(params),
+        success: function(data) {
+            browser_page = JSON.parse(data);
+            var tbody = $('#message_browser_table tbody');
+            tbody.empty();
+            browser_page.messages.forEach(function(message) {
+                var row = $('<tr>');
+                row.append($('<td>', {text: message.offset}));
+                row.append($('<td>', {text: message.timestamp == null ? "" : new Date(message.timestamp).toLocaleString()}));
+                row.append($('<td>', {text: message.key == null ? "" : message.key}));
+                row.append($('<td>', {text: message.payload == null ? "" : message.payload}));
+                tbody.append(row);
+            });
+            status.html("Partition " + browser_page.partition + ", offsets " + browser_page.low + " to " +
+                        browser_page.high + ": " + browser_page.messages.length + " messages loaded.");
+            $('#browser_previous').prop('disabled', browser_page.previous_offset <= browser_page.low);
+            $('#browser_next').prop('disabled', browser_page.next_offset >= browser_page.high);
+        },
+        error: function(data) {
+            status.html("Failed to load messages.");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.message_browser_form').each(function(index) {
+        var form = $(this);
+        form.submit(function(event) {
+            event.preventDefault();
+            var mode = $('#browser_mode').val();
+            var position = $('#browser_position').val().trim();
+            var params = {};
+            if (mode == "time")
+                params.time = browser_time_to_millis(position);
+            else if (position != "")
+                params[mode] = parseInt(position);
+            load_browser_page(form, params);
+        });
+        $('#browser_previous').click(function() {
+            if (browser_page != null)
+                load_browser_page(form, {before: browser_page.previous_offset});
+        });
+        $('#browser_next').click(function() {
+            if (browser_page != null)
+                load_browser_page(form, {offset: browser_page.next_offset});
+        });
+        form.submit();
+    });
+});
+
+function reset_offsets_request(form, dry_run) {
+    var strategy = {type: $('#reset_strategy').val()};
+    var value = $('#reset_value').val().trim();
+    if (strategy.type == "offset")
+        strategy.offset = parseInt(value);
+    else if (strategy.type == "timestamp")
+        strategy.timestamp = browser_time_to_millis(value);
+    else if (strategy.type == "shift")
+        strategy.by = parseInt(value);
+    var request = {topic: $('#reset_topic').val(), strategy: strategy, dry_run: dry_run};
+    var partitions = $('#reset_partitions').val().trim();
+    if (partitions != "")
+        request.partitions = partitions.split(",").map(function(p) { return parseInt(p); });
+
+    var status = $('div.reset_offsets_status');
+    status.text(dry_run ? "Computing new offsets..." : "Resetting offsets...");
+    $.ajax({
+        url: form.attr("data-url"),
+        method: "POST",
+        contentType: "application/json",
+        data: JSON.stringify(request),
+        success: function(data) {
+            var response = JSON.parse(data);
+            var tbody = $('#reset_offsets_table tbody');
+            tbody.empty();
+            if (response.error) {
+                status.text(response.error);
+                return;
+            }
+            response.data.forEach(function(reset) {
+                var row = $('<tr>');
+                [reset.partition, reset.low, reset.high, reset.current, reset.target].forEach(function(v) {
+                    row.append($('<td>', {text: v}));
+                });
+                tbody.append(row);
+            });
+            status.text(response.dry_run ? "Preview of the new offsets, nothing has been changed." : "Offsets reset.");
+        },
+        error: function(data) {
+            status.text("Request failed.");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.reset_offsets_form').each(function(index) {
+        var form = $(this);
+        $('#reset_preview').click(function() { reset_offsets_request(form, true); });
+        $('#reset_apply').click(function() {
+            if (confirm("Reset the offsets of the group on " + $('#reset_topic').val() + "?"))
+                reset_offsets_request(form, false);
+        });
+    });
+});
+
+// Parses "key=value" lines in an object.
+function parse_key_values(text) {
+    var result = {};
+    text.split("\n").forEach(function(line) {
+        var index = line.indexOf("=");
+        if (index > 0)
+            result[line.substring(0, index).trim()] = line.substring(index + 1).trim();
+    });
+    return result;
+}
+
+function reassignment_request(form) {
+    var brokers = $('#reassignment_brokers').val().split(",")
+        .map(function(b) { return b.trim(); })
+        .filter(function(b) { return b != ""; })
+        .map(function(b) { return parseInt(b); });
+    var request = {brokers: brokers, topics: $('#reassignment_topics').val().trim()};
+    var status = $('div.reassignment_status');
+    var tbody = $('#reassignment_table tbody');
+    status.text("Generating plan...");
+    tbody.empty();
+    $('#reassignment_json').val("");
+    $.ajax({
+        url: form.attr("data-url"),
+        method: "POST",
+        contentType: "application/json",
+        data: JSON.stringify(request),
+        success: function(data) {
+            var response = JSON.parse(data);
+            if (response.error) {
+                status.text(response.error);
+                return;
+            }
+            response.brokers.forEach(function(broker) {
+                var row = $('<tr>');
+                broker.forEach(function(v) { row.append($('<td>', {text: v})); });
+                bytes_to_human(row.children()[2], "");
+                bytes_to_human(row.children()[4], "");
+                tbody.append(row);
+            });
+            $('#reassignment_json').val(JSON.stringify(response.plan, null, 2));
+            status.text(response.plan.partitions.length + " partitions to reassign, " + response.moved_replicas +
+                        " replicas to move (" + formatToHuman(response.moved_bytes, 1, "", 1024,
+                        [' B', ' KiB', ' MiB', ' GiB', ' TiB', ' PiB']).text() + ").");
+        },
+        error: function(data) {
+            status.text("Request failed.");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.reassignment_form').each(function(index) {
+        var form = $(this);
+        form.submit(function(event) {
+            event.preventDefault();
+            reassignment_request(form);
+        });
+    });
+});
+
+function leader_election_request(form) {
+    var request = {topics: [], partitions: []};
+    form.find('[name="partitions"]').val().split("\n").forEach(function(line) {
+        line = line.trim();
+        var index = line.lastIndexOf("/");
+        if (line == "")
+            return;
+        else if (index > 0)
+            request.partitions.push({topic: line.substring(0, index), partition: parseInt(line.substring(index + 1))});
+        else
+            request.topics.push(line);
+    });
+    var status = form.find('.leader_election_status');
+    status.text("Running...");
+    $.ajax({
+        url: form.attr("data-url"),
+        method: "POST",
+        contentType: "application/json",
+        data: JSON.stringify(request),
+        success: function(data) {
+            var response = JSON.parse(data);
+            if (response.error)
+                status.text(response.error);
+            else
+                status.text("Election started for " + response.partitions + " partitions.");
+        },
+        error: function(data) {
+            status.text("Request failed.");
+        }
+    });
+}
+
+$(document).ready(function() {
+    $('form.leader_election_form').each(function(index) {
+        var form = $(this);
+        form.submit(function(event) {
+            event.preventDefault();
+            if (confirm("Move the leadership of the selected partitions to their preferred replica?"))
+                leader_election_request(form);
+        });
+    });
+});
+
+function admin_form_request(form) {
+    var field = function(name) { return form.find('[name="' + name + '"]').val(); };
+    var action = form.attr("data-action");
+    var method = "POST";
+    var body = {};
+    if (action == "create_topic") {
+        body = {topic: field("topic"), partitions: parseInt(field("partitions")),
+                replication_factor: parseInt(field("replication_factor")), configs: parse_key_values(field("configs"))};
+    } else if (action == "add_partitions") {
+        body = {partitions: parseInt(field("partitions"))};
+    } else if (action == "alter_topic_config") {
+        var deleted = field("delete").split(",").map(function(k) { return k.trim(); }).filter(function(k) { return k != ""; });
+        body = {set: parse_key_values(field("set")), delete: deleted};
+    } else if (action == "delete_topic") {
+        var topic = form.attr("data-topic");
+        if (prompt("Type the name of the topic to confirm the deletion") != topic)
+            return;
+        method = "DELETE";
+    }
+    var status = form.find('.admin_status');
+    status.text("Running...");
+    $.ajax({
+        url: form.attr("data-url"),
+        method: method,
+        contentType: "application/json",
+        data: method == "DELETE" ? undefined : JSON.stringify(body),
+        success: function(data) {
+            var response = JSON.parse(data);
+            status.text(response.error ? response.error : "Done. The change will be visible after the next metadata refresh.");
+        },
+        error: function(data) {
+            status.text("Request failed.");
+        }
+    });
+}
+
+function import_snapshot_request(form) {
+    var status = form.find('.admin_status');
+    var file = form.find('[name="snapshot"]')[0].files[0];
+    if (!file) {
+        status.text("Select a snapshot file.");
+        return;
+    }
+    var reader = new FileReader();
+    reader.onload = function() {
+        status.text("Importing...");
+        $.ajax({
+            url: form.attr("data-url"),
+            method: "POST",
+            contentType: "application/json",
+            data: reader.result,
+            success: function(data) {
+                var response = JSON.parse(data);
+                if (response.error) {
+                    status.text(response.error);
+                } else {
+                    var counts = $.map(response.imported, function(count, name) { return name + ": " + count; });
+                    status.text("Imported " + counts.join(", "));
+                }
+            },
+            error: function(data) {
+                status.text("Request failed.");
+            }
+        });
+    };
+    reader.readAsText(file);
+}
+
+$(document).ready(function() {
+    $('form.admin_form').submit(function(event) {
+        event.preventDefault();
+        admin_form_request($(this));
+    });
+    $('form.snapshot_import_form').submit(function(event) {
+        event.preventDefault();
+        import_snapshot_request($(this));
+    });
+});
+
 $(document).ready(function(){
     $('[data-toggle="tooltip"]').tooltip();
     $(window).resize();
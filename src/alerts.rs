@@ -0,0 +1,512 @@
+use curl::easy::{Easy, List};
+use rdkafka::util::millis_to_epoch;
+use regex::Regex;
+use scheduled_executor::TaskGroup;
+use serde_json;
+
+use cache::{AlertCache, Cache};
+use config::{AlertCondition, AlertRuleConfig, SharedConfig};
+use error::*;
+use lag_history::LagSample;
+use metadata::{ClusterId, Partition, TopicName};
+use ownership::Ownership;
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub enum AlertStatus {
+    Pending,  // never evaluated
+    Ok,
+    Firing,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct AlertState {
+    pub rule: String,
+    pub cluster_id: ClusterId,
+    pub status: AlertStatus,
+    pub since: u64,            // millis since epoch of the last status change
+    pub last_evaluation: u64,  // millis since epoch
+    pub details: String,
+}
+
+impl AlertState {
+    fn new(rule: &AlertRuleConfig) -> AlertState {
+        AlertState {
+            rule: rule.name.clone(),
+            cluster_id: rule.cluster.clone(),
+            status: AlertStatus::Pending,
+            since: millis_to_epoch(SystemTime::now()) as u64,
+            last_evaluation: 0,
+            details: "Not evaluated yet".to_owned(),
+        }
+    }
+}
+
+//
+// ********** EVALUATION **********
+//
+
+/// Returns true if the total lag has been above the threshold for the whole window. The
+/// history must contain at least one sample taken at or before the start of the window,
+/// otherwise there is not enough information and the condition is not met.
+fn lag_above_threshold(samples: &[LagSample], threshold: i64, duration_ms: u64, now: u64) -> bool {
+    let window_start = now.saturating_sub(duration_ms);
+    match samples.iter().rposition(|sample| sample.timestamp <= window_start) {
+        Some(start) => samples[start..].iter().all(|sample| sample.total_lag() > threshold),
+        None => false,
+    }
+}
+
+fn topic_filter(topic_regex: &Option<String>) -> Result<Option<Regex>> {
+    match *topic_regex {
+        Some(ref regex) => Regex::new(regex)
+            .map(Some)
+            .chain_err(|| format!("Invalid topic regex: {}", regex)),
+        None => Ok(None),
+    }
+}
+
+/// Returns all the partitions of the topics in the cluster matching the filter and the
+/// predicate.
+fn matching_partitions<F>(cache: &Cache, cluster_id: &ClusterId, filter: &Option<Regex>, predicate: F)
+        -> Vec<(TopicName, Partition)>
+        where F: Fn(&Partition) -> bool {
+    let topics = cache.topics.filter_clone(|&(ref c, ref topic)| {
+        c == cluster_id && filter.as_ref().map(|r| r.is_match(topic)).unwrap_or(true)
+    });
+    let mut result = Vec::new();
+    for ((_, topic), partitions) in topics {
+        for partition in partitions {
+            if predicate(&partition) {
+                result.push((topic.clone(), partition));
+            }
+        }
+    }
+    result.sort_by(|a, b| (&a.0, a.1.id).cmp(&(&b.0, b.1.id)));
+    result
+}
+
+fn partitions_summary(partitions: &[(TopicName, Partition)]) -> String {
+    let mut summary = partitions.iter()
+        .take(5)
+        .map(|&(ref topic, ref p)| format!("{}/{}", topic, p.id))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if partitions.len() > 5 {
+        summary.push_str(&format!(" and {} more", partitions.len() - 5));
+    }
+    summary
+}
+
+/// Evaluates the rule against the content of the cache. Returns whether the rule is firing
+/// and a human readable description of the current condition.
+pub fn evaluate_rule(rule: &AlertRuleConfig, cache: &Cache, now: u64) -> Result<(bool, String)> {
+    match rule.condition {
+        AlertCondition::GroupLag { ref group, ref topic, threshold, duration } => {
+            let samples = cache.lag_history.get(&(rule.cluster.clone(), group.clone(), topic.clone()))
+                .unwrap_or_else(Vec::new);
+            let firing = lag_above_threshold(&samples, threshold, duration * 1000, now);
+            let details = match samples.last() {
+                Some(sample) => format!("Current lag of {} on {}: {} (threshold: {} for {}s)",
+                                        group, topic, sample.total_lag(), threshold, duration),
+                None => format!("No lag information for {} on {}", group, topic),
+            };
+            Ok((firing, details))
+        },
+        AlertCondition::PartitionError { ref topic } => {
+            let filter = topic_filter(topic)?;
+            let partitions = matching_partitions(cache, &rule.cluster, &filter, |p| p.error.is_some());
+            let details = if partitions.is_empty() {
+                "No partition in error".to_owned()
+            } else {
+                format!("{} partitions in error: {}", partitions.len(), partitions_summary(&partitions))
+            };
+            Ok((!partitions.is_empty(), details))
+        },
+        AlertCondition::UnderReplicated { ref topic } => {
+            let filter = topic_filter(topic)?;
+            let partitions = matching_partitions(cache, &rule.cluster, &filter, |p| p.isr.len() < p.replicas.len());
+            let details = if partitions.is_empty() {
+                "No under-replicated partition".to_owned()
+            } else {
+                format!("{} under-replicated partitions: {}", partitions.len(), partitions_summary(&partitions))
+            };
+            Ok((!partitions.is_empty(), details))
+        },
+    }
+}
+
+//
+// ********** NOTIFICATIONS **********
+//
+
+/// Receives the new state of an alert every time it changes status.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, state: &AlertState) -> Result<()>;
+}
+
+const WEBHOOK_CONNECT_TIMEOUT_SECS: u64 = 5;
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Sends each alert state change as a JSON POST request to the configured URL.
+pub struct WebhookNotifier {
+    url: String,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> WebhookNotifier {
+        WebhookNotifier { url: url.to_owned() }
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(&self, state: &AlertState) -> Result<()> {
+        let payload = serde_json::to_vec(state)
+            .chain_err(|| "Failed to serialize alert state")?;
+
+        let mut headers = List::new();
+        headers.append("Content-Type: application/json").chain_err(|| "Failed to set headers")?;
+
+        let mut req = Easy::new();
+        req.url(&self.url).chain_err(|| format!("Unable to parse url: '{}'", self.url))?;
+        req.http_headers(headers).chain_err(|| "Failed to set headers")?;
+        req.post(true).chain_err(|| "Failed to set request method")?;
+        req.post_fields_copy(&payload).chain_err(|| "Failed to set request body")?;
+        req.connect_timeout(Duration::from_secs(WEBHOOK_CONNECT_TIMEOUT_SECS)).chain_err(|| "Failed to set connect timeout")?;
+        req.timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS)).chain_err(|| "Failed to set timeout")?;
+        {
+            let mut transfer = req.transfer();
+            transfer.write_function(|data| Ok(data.len()))  // response body is ignored
+                .chain_err(|| "Data transfer failure")?;
+            transfer.perform().chain_err(|| "Connection failure")?;
+        }
+        let code = req.response_code().chain_err(|| "Missing response code")?;
+        if code < 200 || code >= 300 {
+            bail!("Webhook {} returned status code {}", self.url, code);
+        }
+        Ok(())
+    }
+}
+
+//
+// ********** ALERT STORE **********
+//
+
+/// Current state of all the alerts, shared between the evaluation task and the web server.
+/// States are stored in the replicated cache, so that every instance shows the state computed
+/// by the owner of the cluster, and a new owner doesn't notify again the changes that were
+/// already notified.
+pub struct AlertStore {
+    alerts: AlertCache,
+}
+
+impl AlertStore {
+    pub fn new(cache: &Cache) -> AlertStore {
+        AlertStore { alerts: cache.alerts.alias() }
+    }
+
+    pub fn alias(&self) -> AlertStore {
+        AlertStore { alerts: self.alerts.alias() }
+    }
+
+    /// Returns the stored state of the rule, unless the rule moved to another cluster.
+    fn state(&self, rule: &AlertRuleConfig) -> AlertState {
+        self.alerts.get(&rule.name)
+            .and_then(|state| if state.cluster_id == rule.cluster { Some(state) } else { None })
+            .unwrap_or_else(|| AlertState::new(rule))
+    }
+
+    /// Returns the state of each rule, sorted by rule name. Rules that haven't been evaluated
+    /// yet are pending.
+    pub fn states(&self, rules: &[AlertRuleConfig]) -> Vec<AlertState> {
+        let mut states = rules.iter()
+            .map(|rule| self.state(rule))
+            .collect::<Vec<_>>();
+        states.sort_by(|a, b| a.rule.cmp(&b.rule));
+        states
+    }
+
+    /// Records the result of an evaluation. If the status changed and the change should be
+    /// notified, returns the new state without applying it: the status is only changed by
+    /// `commit`, once the notification has been sent, so that failed notifications are
+    /// retried at the next evaluation.
+    fn update(&self, rule: &AlertRuleConfig, firing: bool, details: String, now: u64) -> Result<Option<AlertState>> {
+        let mut state = self.state(rule);
+        let new_status = if firing { AlertStatus::Firing } else { AlertStatus::Ok };
+        let previous_status = state.status;
+        state.last_evaluation = now;
+        state.details = details;
+        let mut new_state = state.clone();
+        new_state.status = new_status;
+        new_state.since = now;
+        // The first evaluation is only notified if the alert is already firing
+        let (stored, notified) = if previous_status == new_status {
+            (state, None)
+        } else if previous_status == AlertStatus::Pending && new_status == AlertStatus::Ok {
+            (new_state, None)
+        } else {
+            (state, Some(new_state))
+        };
+        self.alerts.insert(rule.name.clone(), stored)
+            .chain_err(|| format!("Failed to store the state of alert {}", rule.name))?;
+        Ok(notified)
+    }
+
+    /// Applies a state returned by `update`.
+    fn commit(&self, state: AlertState) -> Result<()> {
+        let rule = state.rule.clone();
+        self.alerts.insert(rule.clone(), state)
+            .chain_err(|| format!("Failed to store the state of alert {}", rule))
+    }
+
+    /// Removes the state of the rules not in the list, after a configuration reload. Only the
+    /// states of the clusters accepted by `owned` are removed.
+    fn retain_rules<F>(&self, rules: &HashSet<String>, owned: F) -> Result<()>
+            where F: Fn(&ClusterId) -> bool {
+        let deleted = self.alerts.filter_clone(|rule| !rules.contains(rule));
+        for (rule, state) in deleted {
+            if owned(&state.cluster_id) {
+                self.alerts.remove(&rule)
+                    .chain_err(|| format!("Failed to remove the state of alert {}", rule))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Evaluates the alert rules of the clusters owned by this instance, so that each change is
+/// only notified once among the instances sharing the cache.
+pub struct AlertEvaluationTaskGroup {
+    cache: Cache,
+    config: SharedConfig,
+    ownership: Ownership,
+    store: AlertStore,
+}
+
+impl AlertEvaluationTaskGroup {
+    pub fn new(cache: &Cache, config: &SharedConfig, ownership: &Ownership, store: &AlertStore)
+            -> AlertEvaluationTaskGroup {
+        AlertEvaluationTaskGroup {
+            cache: cache.alias(),
+            config: config.alias(),
+            ownership: ownership.alias(),
+            store: store.alias(),
+        }
+    }
+
+    fn evaluate(&self, rule: &AlertRuleConfig, notifier: Option<&Notifier>) -> Result<()> {
+        let now = millis_to_epoch(SystemTime::now()) as u64;
+        let (firing, details) = evaluate_rule(rule, &self.cache, now)
+            .chain_err(|| format!("Failed to evaluate alert rule {}", rule.name))?;
+        if let Some(state) = self.store.update(rule, firing, details, now)? {
+            if let Some(notifier) = notifier {
+                notifier.notify(&state)
+                    .chain_err(|| format!("Failed to send notification for alert {}", rule.name))?;
+            }
+            info!("Alert {} is now {:?}: {}", state.rule, state.status, state.details);
+            self.store.commit(state)?;
+        }
+        Ok(())
+    }
+}
+
+impl TaskGroup for AlertEvaluationTaskGroup {
+    type TaskId = String;
+
+    fn get_tasks(&self) -> Vec<String> {
+        let rules = self.config.get().alerts.rules.iter()
+            .map(|rule| rule.name.clone())
+            .collect::<HashSet<_>>();
+        if let Err(e) = self.store.retain_rules(&rules, |cluster_id| self.ownership.is_owner(cluster_id)) {
+            format_error_chain!(e);
+        }
+        rules.into_iter().collect::<Vec<_>>()
+    }
+
+    fn execute(&self, rule_name: String) {
+        let config = self.config.get();
+        let rule = match config.alerts.rules.iter().find(|rule| rule.name == rule_name) {
+            Some(rule) => rule,
+            None => return,
+        };
+        if !self.ownership.is_owner(&rule.cluster) {
+            return;
+        }
+        let notifier = config.alerts.webhook_url.as_ref().map(|url| WebhookNotifier::new(url));
+        if let Err(e) = self.evaluate(rule, notifier.as_ref().map(|n| n as &Notifier)) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::{ReplicaReader, ReplicaWriter};
+    use config::CacheEncoding;
+    use storage::{CacheStorage, MemoryStorage};
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    fn rule() -> AlertRuleConfig {
+        AlertRuleConfig {
+            name: "partition_errors".to_owned(),
+            cluster: ClusterId::from("local"),
+            condition: AlertCondition::PartitionError { topic: None },
+        }
+    }
+
+    fn firing_state() -> AlertState {
+        let mut state = AlertState::new(&rule());
+        state.status = AlertStatus::Firing;
+        state.details = "1 partitions in error: topic/0".to_owned();
+        state
+    }
+
+    /// Accepts a single request, answers with the status line and returns the request body.
+    fn webhook_stand_in(status_line: &'static str) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).into_owned();
+                if let Some(headers_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..headers_end].lines()
+                        .filter_map(|line| {
+                            let mut parts = line.splitn(2, ':');
+                            match (parts.next(), parts.next()) {
+                                (Some(name), Some(value)) if name.eq_ignore_ascii_case("content-length") =>
+                                    value.trim().parse::<usize>().ok(),
+                                _ => None,
+                            }
+                        })
+                        .next()
+                        .unwrap_or(0);
+                    if request.len() >= headers_end + 4 + content_length || read == 0 {
+                        let response = format!("{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status_line);
+                        stream.write_all(response.as_bytes()).unwrap();
+                        sender.send(text[headers_end + 4..].to_owned()).unwrap();
+                        return;
+                    }
+                }
+            }
+        });
+        (url, receiver)
+    }
+
+    #[test]
+    fn webhook_posts_state() {
+        let (url, receiver) = webhook_stand_in("HTTP/1.1 200 OK");
+        WebhookNotifier::new(&url).notify(&firing_state()).unwrap();
+
+        let body = serde_json::from_str::<serde_json::Value>(&receiver.recv().unwrap()).unwrap();
+        assert_eq!(body["rule"], json!("partition_errors"));
+        assert_eq!(body["status"], json!("Firing"));
+    }
+
+    #[test]
+    fn webhook_reports_error_status() {
+        let (url, _receiver) = webhook_stand_in("HTTP/1.1 500 Internal Server Error");
+        assert!(WebhookNotifier::new(&url).notify(&firing_state()).is_err());
+    }
+
+    #[test]
+    fn webhook_reports_connection_failure() {
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/hook", listener.local_addr().unwrap())
+        };
+        assert!(WebhookNotifier::new(&url).notify(&firing_state()).is_err());
+    }
+
+    /// A store on a cache loaded from the shared storage, as after the start of an instance.
+    fn store(storage: &Arc<CacheStorage>, instance_id: &str) -> AlertStore {
+        let cache = Cache::new(ReplicaWriter::new(storage.clone(), CacheEncoding::Json, instance_id));
+        ReplicaReader::new(storage.clone(), instance_id).load_state(cache.alias()).unwrap();
+        AlertStore::new(&cache)
+    }
+
+    #[test]
+    fn status_changes_until_committed() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let store = store(&storage, "owner");
+        let rule = rule();
+        assert_eq!(store.states(&[rule.clone()])[0].status, AlertStatus::Pending);
+
+        // Pending to Ok is applied without notification
+        assert!(store.update(&rule, false, "ok".to_owned(), 1).unwrap().is_none());
+        assert_eq!(store.states(&[rule.clone()])[0].status, AlertStatus::Ok);
+
+        // A change that wasn't committed, e.g. because the notification failed, is returned again
+        let state = store.update(&rule, true, "firing".to_owned(), 2).unwrap().unwrap();
+        assert_eq!((state.status, state.since), (AlertStatus::Firing, 2));
+        assert_eq!(store.states(&[rule.clone()])[0].status, AlertStatus::Ok);
+        assert_eq!(store.states(&[rule.clone()])[0].details, "firing");
+        let state = store.update(&rule, true, "firing".to_owned(), 3).unwrap().unwrap();
+        assert_eq!((state.status, state.since), (AlertStatus::Firing, 3));
+
+        store.commit(state).unwrap();
+        assert!(store.update(&rule, true, "firing".to_owned(), 4).unwrap().is_none());
+        assert_eq!(store.states(&[rule.clone()])[0].status, AlertStatus::Firing);
+        assert_eq!(store.states(&[rule.clone()])[0].since, 3);
+
+        // A rule moved to another cluster starts over
+        let mut moved = rule.clone();
+        moved.cluster = ClusterId::from("other");
+        assert_eq!(store.states(&[moved])[0].status, AlertStatus::Pending);
+    }
+
+    #[test]
+    fn shares_states_between_instances() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let owner = store(&storage, "owner");
+        let rule = rule();
+        let state = owner.update(&rule, true, "firing".to_owned(), 1).unwrap().unwrap();
+        owner.commit(state).unwrap();
+
+        // The other instances show the state of the owner
+        let replica = store(&storage, "replica");
+        assert_eq!(replica.states(&[rule.clone()]), owner.states(&[rule.clone()]));
+        assert_eq!(replica.states(&[rule.clone()])[0].status, AlertStatus::Firing);
+
+        // and don't notify the change again once they take over the cluster
+        assert!(replica.update(&rule, true, "still firing".to_owned(), 2).unwrap().is_none());
+        let state = replica.update(&rule, false, "resolved".to_owned(), 3).unwrap().unwrap();
+        assert_eq!(state.status, AlertStatus::Ok);
+    }
+
+    #[test]
+    fn removes_states_of_deleted_rules() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let store = store(&storage, "owner");
+        store.update(&rule(), false, "ok".to_owned(), 1).unwrap();
+
+        // States of clusters owned by other instances are left to their owner
+        store.retain_rules(&HashSet::new(), |_| false).unwrap();
+        assert_eq!(store.alerts.keys().len(), 1);
+        store.retain_rules(&HashSet::new(), |_| true).unwrap();
+        assert!(store.alerts.keys().is_empty());
+        assert!(self::store(&storage, "replica").alerts.keys().is_empty());
+    }
+
+    #[test]
+    fn lag_above_threshold_needs_whole_window() {
+        let sample = |timestamp, lag| LagSample { timestamp, lag: vec![lag] };
+        let samples = vec![sample(1000, 5), sample(2000, 50), sample(3000, 60)];
+        assert!(lag_above_threshold(&samples, 10, 1000, 3000));
+        assert!(!lag_above_threshold(&samples, 10, 2000, 3000));
+        assert!(!lag_above_threshold(&samples, 10, 5000, 3000));
+    }
+}
@@ -0,0 +1,69 @@
+use chrono::Utc;
+use serde_json::{self, Value};
+
+use error::*;
+use metadata::ClusterId;
+use utils::error_chain_message;
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+
+
+#[derive(Serialize, Debug)]
+pub struct AuditEntry<'a> {
+    pub timestamp: String,
+    pub user: &'a str,
+    pub cluster_id: &'a ClusterId,
+    pub action: &'a str,
+    pub target: &'a str,
+    pub details: Value,
+    pub error: Option<String>,  // missing if the action succeeded
+}
+
+/// Records every change applied to the clusters. Entries are logged and, if configured,
+/// appended to a file as JSON lines.
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn new(path: &Option<String>) -> Result<AuditLog> {
+        let file = match *path {
+            Some(ref path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)
+                    .chain_err(|| format!("Unable to open audit log {}", path))?;
+                Some(Mutex::new(file))
+            },
+            None => None,
+        };
+        Ok(AuditLog { file })
+    }
+
+    pub fn record<T>(&self, user: &str, cluster_id: &ClusterId, action: &str, target: &str, details: Value,
+                     result: &Result<T>) {
+        let entry = AuditEntry {
+            timestamp: Utc::now().to_rfc3339(),
+            user,
+            cluster_id,
+            action,
+            target,
+            details,
+            error: result.as_ref().err().map(error_chain_message),
+        };
+        let line = match serde_json::to_string(&entry) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to serialize audit entry: {}", e);
+                return;
+            },
+        };
+        info!("Audit: {}", line);
+        if let Some(ref file) = self.file {
+            let mut file = file.lock().expect("Poison error");
+            if let Err(e) = writeln!(file, "{}", line) {
+                error!("Failed to write audit entry: {}", e);
+            }
+        }
+    }
+}
@@ -1,36 +1,37 @@
-use futures::stream::Stream;
-use rdkafka::client::EmptyContext;
-use rdkafka::config::{ClientConfig, TopicConfig};
-use rdkafka::consumer::stream_consumer::StreamConsumer;
-use rdkafka::consumer::{Consumer, EmptyConsumerContext};
-use rdkafka::producer::FutureProducer;
-use rdkafka::error::KafkaError;
-use rdkafka::message::{Message, BorrowedMessage, OwnedMessage};
 use rdkafka::util::{millis_to_epoch, duration_to_millis};
+use rmp_serde;
 use serde::de::{Deserialize, DeserializeOwned};
 use serde::ser::Serialize;
 use serde_json;
-use rand::random;
 
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet};
+use std::cmp;
+use std::collections::HashMap;
 use std::collections::hash_map;
 use std::hash::Hash;
+use std::str;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime};
 
+use alerts::AlertState;
+use config::CacheEncoding;
 use error::*;
+use events::ClusterEvent;
+use lag_history::LagSample;
 use metadata::{Broker, ClusterId, Group, Partition, TopicName};
 use metrics::TopicMetrics;
+use ownership::Lease;
+use storage::{CacheStorage, StoredEntry};
 
 
 #[derive(Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
 struct WrappedKey(String, String);
 
 impl WrappedKey {
-    fn new<'de, K>(cache_name: String, key: &'de K) -> WrappedKey
-            where K: Serialize + Deserialize<'de> {
-        WrappedKey(cache_name, serde_json::to_string(key).unwrap())  //TODO: error handling
+    fn new<K: Serialize>(cache_name: String, key: &K) -> Result<WrappedKey> {
+        let serialized_key = serde_json::to_string(key)
+            .chain_err(|| format!("Failed to serialize key for {}", cache_name))?;
+        Ok(WrappedKey(cache_name, serialized_key))
     }
 
     pub fn cache_name(&self) -> &str {
@@ -43,78 +44,106 @@ impl WrappedKey {
 }
 
 
+//
+// ********* VALUE ENCODING **********
+//
+
+// Encoded values start with a header byte identifying the format. Values written before the
+// header was introduced are plain JSON, which never starts with one of these bytes.
+const JSON_HEADER: u8 = 1;
+const MSGPACK_HEADER: u8 = 2;
+// The value is prefixed by the id of the instance that wrote it: length (u8), id, encoded value.
+const ORIGIN_HEADER: u8 = 3;
+
+fn add_origin(instance_id: &str, encoded: Vec<u8>) -> Vec<u8> {
+    let id = &instance_id.as_bytes()[..cmp::min(instance_id.len(), 255)];
+    let mut payload = Vec::with_capacity(2 + id.len() + encoded.len());
+    payload.push(ORIGIN_HEADER);
+    payload.push(id.len() as u8);
+    payload.extend_from_slice(id);
+    payload.extend_from_slice(&encoded);
+    payload
+}
+
+/// Returns the id of the instance that wrote the value, if known, and the encoded value.
+fn split_origin(payload: &[u8]) -> (Option<&str>, &[u8]) {
+    if payload.len() < 2 || payload[0] != ORIGIN_HEADER {
+        return (None, payload);
+    }
+    let id_end = 2 + payload[1] as usize;
+    if payload.len() < id_end {
+        return (None, payload);
+    }
+    (str::from_utf8(&payload[2..id_end]).ok(), &payload[id_end..])
+}
+
+fn encode_value<V: Serialize>(value: &V, encoding: CacheEncoding) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    match encoding {
+        CacheEncoding::Json => {
+            encoded.push(JSON_HEADER);
+            serde_json::to_writer(&mut encoded, value)
+                .chain_err(|| "Failed to serialize value as JSON")?;
+        },
+        CacheEncoding::MessagePack => {
+            encoded.push(MSGPACK_HEADER);
+            let serialized = rmp_serde::to_vec_named(value)
+                .chain_err(|| "Failed to serialize value as MessagePack")?;
+            encoded.extend_from_slice(&serialized);
+        },
+    }
+    Ok(encoded)
+}
+
+fn decode_value<V: DeserializeOwned>(encoded: &[u8]) -> Result<V> {
+    match encoded.first() {
+        Some(&JSON_HEADER) => serde_json::from_slice(&encoded[1..])
+            .chain_err(|| "Failed to parse JSON value"),
+        Some(&MSGPACK_HEADER) => rmp_serde::from_slice(&encoded[1..])
+            .chain_err(|| "Failed to parse MessagePack value"),
+        _ => serde_json::from_slice(encoded)
+            .chain_err(|| "Failed to parse legacy JSON value"),
+    }
+}
+
 //
 // ********* REPLICA WRITER **********
 //
 
 pub struct ReplicaWriter {
-    topic_name: String,
-    producer: FutureProducer<EmptyContext>,
+    storage: Arc<CacheStorage>,
+    encoding: CacheEncoding,
+    instance_id: String,
 }
 
 impl ReplicaWriter {
-    pub fn new(brokers: &str, topic_name: &str) -> Result<ReplicaWriter> {
-        let producer = ClientConfig::new()
-            .set("bootstrap.servers", brokers)
-            .set("compression.codec", "gzip")
-            .set("message.max.bytes", "10000000")
-            .set("api.version.request", "true")
-            .create::<FutureProducer<_>>()
-            .expect("Producer creation error");
-
-        let writer = ReplicaWriter {
-            topic_name: topic_name.to_owned(),
-            producer: producer,
-        };
-
-        Ok(writer)
+    pub fn new(storage: Arc<CacheStorage>, encoding: CacheEncoding, instance_id: &str) -> ReplicaWriter {
+        ReplicaWriter { storage, encoding, instance_id: instance_id.to_owned() }
     }
 
     // TODO: use structure for value
-    /// Writes a new update into the topic. The name of the replicated map and the key will be
-    /// serialized together as key of the message, and the value will be serialized in the payload.
+    /// Writes a new update into the storage. The name of the replicated map and the key will be
+    /// serialized together as JSON key of the entry, and the value will be encoded in the payload.
+    /// Keys are always JSON, so that the storage sees the same key regardless of the encoding.
     pub fn update<'de, K, V>(&self, name: &str, key: &'de K, value: &'de V) -> Result<()>
             where K: Serialize + Deserialize<'de> + Clone,
                   V: Serialize + Deserialize<'de> {
-        let serialized_key = serde_json::to_vec(&WrappedKey::new(name.to_owned(), key))
+        let serialized_key = serde_json::to_vec(&WrappedKey::new(name.to_owned(), key)?)
             .chain_err(|| "Failed to serialize key")?;
-        let serialized_value = serde_json::to_vec(&value)
-            .chain_err(|| "Failed to serialize value")?;
+        let serialized_value = add_origin(&self.instance_id, encode_value(value, self.encoding)?);
         trace!("Serialized update size: key={:.3}KB value={:.3}KB",
             (serialized_key.len() as f64 / 1000f64), (serialized_value.len() as f64 / 1000f64));
-        let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy(
-            self.topic_name.as_str(),
-            None,
-            Some(&serialized_value),
-            Some(&serialized_key),
-            Some(ts),
-            1000,
-        );
-        // _f.wait();  // Uncomment to make production synchronous
-        Ok(())
+        let ts = millis_to_epoch(SystemTime::now()) as u64;
+        self.storage.write(&serialized_key, Some(&serialized_value), ts)
     }
 
     /// Deletes an element from the specified cache
     pub fn delete<'de, K>(&self, name: &str, key: &'de K) -> Result<()>
             where K: Serialize + Deserialize<'de> + Clone {
-        let serialized_key = serde_json::to_vec(&WrappedKey::new(name.to_owned(), key))
+        let serialized_key = serde_json::to_vec(&WrappedKey::new(name.to_owned(), key)?)
             .chain_err(|| "Failed to serialize key")?;
-        self.write_tombstone(&serialized_key)
-    }
-
-    /// Writes a tombstone for the specified message key.
-    fn write_tombstone(&self, message_key: &[u8]) -> Result<()> {
-        let ts = millis_to_epoch(SystemTime::now());
-        let _f = self.producer.send_copy::<[u8], [u8]>(
-            self.topic_name.as_str(),
-            None,
-            None,
-            Some(&message_key),
-            Some(ts),
-            1000
-        );
-        Ok(())
+        let ts = millis_to_epoch(SystemTime::now()) as u64;
+        self.storage.write(&serialized_key, None, ts)
     }
 }
 
@@ -125,50 +154,57 @@ impl ReplicaWriter {
 #[derive(Debug)]
 pub enum ReplicaCacheUpdate<'a> {
     Set { key: &'a str, payload: &'a[u8], timestamp: u64 },
-    Delete { key: &'a str }
+    Delete { key: &'a str, timestamp: u64 }
 }
 
 pub trait UpdateReceiver: Send + 'static {
     fn receive_update(&self, name: &str, update: ReplicaCacheUpdate) -> Result<()>;
 }
 
-type ReplicaConsumer = StreamConsumer<EmptyConsumerContext>;
+/// Passes the entry to the receiver. Entries written by `skip_origin` are ignored.
+fn apply_entry<R: UpdateReceiver>(entry: &StoredEntry, receiver: &R, skip_origin: Option<&str>) {
+    let w_key = match serde_json::from_slice::<WrappedKey>(&entry.key) {
+        Ok(w_key) => w_key,
+        Err(e) => {
+            error!("Failed to decode wrapped key: {}", e);
+            return;
+        }
+    };
+    let update = match entry.payload {
+        Some(ref payload) => {
+            let (origin, encoded_value) = split_origin(payload);
+            if origin.is_some() && origin == skip_origin {
+                return;
+            }
+            ReplicaCacheUpdate::Set {
+                key: w_key.serialized_key(),
+                payload: encoded_value,
+                timestamp: entry.timestamp,
+            }
+        },
+        None => ReplicaCacheUpdate::Delete {
+            key: w_key.serialized_key(),
+            timestamp: entry.timestamp,
+        },
+    };
+    if let Err(e) = receiver.receive_update(w_key.cache_name(), update) {
+        format_error_chain!(e);
+    }
+}
 
 pub struct ReplicaReader {
-    consumer: ReplicaConsumer,
-    brokers: String,
-    topic_name: String,
+    storage: Arc<CacheStorage>,
+    instance_id: String,
     processed_messages: i64,
 }
 
 impl ReplicaReader {
-    pub fn new(brokers: &str, topic_name: &str) -> Result<ReplicaReader> {
-        let consumer: ReplicaConsumer = ClientConfig::new()
-            .set("group.id", &format!("kafka_web_cache_reader_{}", random::<i64>()))
-            .set("bootstrap.servers", brokers)
-            .set("session.timeout.ms", "6000")
-            .set("enable.auto.commit", "false")
-            .set("queued.min.messages", "10000") // Reduce memory usage
-            //.set("fetch.message.max.bytes", "102400")
-            .set("api.version.request", "true")
-            .set_default_topic_config(
-                TopicConfig::new()
-                .set("auto.offset.reset", "smallest")
-                .finalize())
-            .create()
-            .chain_err(|| "Consumer creation failed")?;
-
-        //let topic_partition = TopicPartitionList::with_topics(&vec![topic_name]);
-        // consumer.assign(&topic_partition)
-        consumer.subscribe(&[topic_name])
-            .chain_err(|| "Can't subscribe to specified topics")?;
-
-        Ok(ReplicaReader {
-            consumer: consumer,
-            brokers: brokers.to_owned(),
-            topic_name: topic_name.to_owned(),
+    pub fn new(storage: Arc<CacheStorage>, instance_id: &str) -> ReplicaReader {
+        ReplicaReader {
+            storage,
+            instance_id: instance_id.to_owned(),
             processed_messages: 0,
-        })
+        }
     }
 
     pub fn processed_messages(&self) -> i64 {
@@ -176,25 +212,15 @@ impl ReplicaReader {
     }
 
     pub fn load_state<R: UpdateReceiver>(&mut self, receiver: R) -> Result<()> {
-        info!("Started creating state");
-        match self.last_message_per_key() {
+        info!("Started creating state from {}", self.storage.description());
+        match self.storage.load() {
             Err(e) => format_error_chain!(e),
             Ok(state) => {
-                for (w_key, message) in state {
-                    let update = match message.payload() {
-                        Some(payload) => ReplicaCacheUpdate::Set {
-                            key: w_key.serialized_key(),
-                            payload: payload,
-                            timestamp: message.timestamp().to_millis()
-                                .unwrap_or_else(|| millis_to_epoch(SystemTime::now())) as u64,
-                        },
-                        None => ReplicaCacheUpdate::Delete {
-                            key: w_key.serialized_key()
-                        },
-                    };
-                    if let Err(e) = receiver.receive_update(w_key.cache_name(), update) {
-                        format_error_chain!(e);
-                    }
+                self.processed_messages = state.processed_records;
+                info!("Total unique items in caches: {}", state.entries.len());
+                // The writes of previous runs of this instance are part of the state as well
+                for entry in state.entries.values() {
+                    apply_entry(entry, &receiver, None);
                 }
             }
         }
@@ -202,69 +228,22 @@ impl ReplicaReader {
         Ok(())
     }
 
-    fn last_message_per_key(&mut self) -> Result<HashMap<WrappedKey, OwnedMessage>> {
-        let mut eof_set = HashSet::new();
-        let mut borrowed_state = HashMap::new();
-        let mut state = HashMap::new();
-
-        let topic_name = &self.topic_name;
-        let metadata = self.consumer.fetch_metadata(Some(topic_name), 30000)
-            .chain_err(|| "Failed to fetch metadata")?;
-
-        if metadata.topics().is_empty() {
-            warn!("No replicator topic found ({} {})", self.brokers, self.topic_name);
-            return Ok(HashMap::new());
-        }
-        let topic_metadata = &metadata.topics()[0];
-        if topic_metadata.partitions().is_empty() {
-            return Ok(state);  // Topic is empty and auto created
-        }
-
-        let message_stream = self.consumer.start();
-
-        for message in message_stream.wait() {
-            match message {
-                Ok(Ok(m)) => {
-                    self.processed_messages += 1;
-                    match parse_message_key(&m).chain_err(|| "Failed to parse message key") {
-                        Ok(wrapped_key) => { borrowed_state.insert(wrapped_key, m); () },
-                        Err(e) => format_error_chain!(e),
-                    };
-                },
-                Ok(Err(KafkaError::PartitionEOF(p))) => { eof_set.insert(p); () },
-                Ok(Err(e)) => error!("Error while reading from Kafka: {}", e),
-                Err(_) => error!("Stream receive error"),
-            };
-            if borrowed_state.len() >= 10000 {
-                for (key, message) in borrowed_state {
-                    state.insert(key, message.detach());
-                }
-                borrowed_state = HashMap::new();
-            }
-            if eof_set.len() == topic_metadata.partitions().len() {
-                break;
-            }
-        }
-        for (key, message) in borrowed_state {
-            state.insert(key, message.detach());
+    /// Keeps applying the updates written to the storage by other instances after the initial
+    /// load, so that all the instances sharing the storage serve the same state.
+    pub fn follow<R: UpdateReceiver>(&self, receiver: R) -> Result<()> {
+        let instance_id = self.instance_id.clone();
+        let following = self.storage.follow(Box::new(move |entry| {
+            apply_entry(&entry, &receiver, Some(&instance_id));
+        }))?;
+        if following {
+            info!("Following updates from {} (instance id: {})", self.storage.description(), self.instance_id);
+        } else {
+            info!("Updates from other instances are not supported by {}", self.storage.description());
         }
-        self.consumer.stop();
-        info!("Total unique items in caches: {}", state.len());
-        Ok(state)
+        Ok(())
     }
 }
 
-fn parse_message_key(message: &BorrowedMessage) -> Result<WrappedKey> {
-    let key_bytes = match message.key() {
-        Some(k) => k,
-        None => bail!("Empty key found"),
-    };
-
-    let wrapped_key = serde_json::from_slice::<WrappedKey>(key_bytes)
-        .chain_err(|| "Failed to decode wrapped key")?;
-    Ok(wrapped_key)
-}
-
 
 //
 // ********** REPLICATED MAP **********
@@ -319,6 +298,20 @@ impl<K, V> ReplicatedMap<K, V>
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns a copy of all the entries, with the time of their last update.
+    pub fn dump(&self) -> Vec<(K, V, u64)> {
+        match self.map.read() {
+            Ok(ref cache) => (*cache).iter()
+                .map(|(k, v)| (k.clone(), v.value.clone(), v.updated))
+                .collect::<Vec<_>>(),
+            Err(_) => panic!("Poison error"),
+        }
+    }
+
     pub fn keys(&self) -> Vec<K> {
         match self.map.read() {
             Ok(ref cache) => (*cache).keys().cloned().collect::<Vec<_>>(),
@@ -331,19 +324,32 @@ impl<K, V> ReplicatedMap<K, V>
             ReplicaCacheUpdate::Set { key, payload, timestamp } => {
                 let key = serde_json::from_str::<K>(key)
                     .chain_err(|| "Failed to parse key")?;
-                let value = serde_json::from_slice::<V>(payload)
+                let value = decode_value::<V>(payload)
                     .chain_err(|| "Failed to parse payload")?;
-                self.local_update(key, value, Some(timestamp));
+                if !self.is_newer_locally(&key, timestamp) {
+                    self.local_update(key, value, Some(timestamp));
+                }
             },
-            ReplicaCacheUpdate::Delete { key } => {
+            ReplicaCacheUpdate::Delete { key, timestamp } => {
                 let key = serde_json::from_str::<K>(key)
                     .chain_err(|| "Failed to parse key")?;
-                self.local_remove(&key);
+                if !self.is_newer_locally(&key, timestamp) {
+                    self.local_remove(&key);
+                }
             }
         }
         Ok(())
     }
 
+    /// Returns true if the local value has been updated after the timestamp, in which case
+    /// updates received from other instances with that timestamp are outdated.
+    fn is_newer_locally(&self, key: &K, timestamp: u64) -> bool {
+        match self.map.read() {
+            Ok(cache) => (*cache).get(key).map(|v| v.updated > timestamp).unwrap_or(false),
+            Err(_) => panic!("Poison error"),
+        }
+    }
+
     fn local_update(&self, key: K, value: V, timestamp: Option<u64>) {
         let value = if let Some(ts) = timestamp {
             ValueContainer::new_with_timestamp(value, ts)
@@ -373,6 +379,12 @@ impl<K, V> ReplicatedMap<K, V>
         Ok(())
     }
 
+    /// Updates the value in memory only: the other instances, and this one after a restart,
+    /// will keep seeing the previous value until the next `insert`.
+    pub fn insert_local(&self, key: K, new_value: V) {
+        self.local_update(key, new_value, None);
+    }
+
     pub fn remove(&self, key: &K) -> Result<()> {
         self.replica_writer.delete(&self.name, key)
             .chain_err(|| "Failed to write cache delete")?;
@@ -380,13 +392,15 @@ impl<K, V> ReplicatedMap<K, V>
         Ok(())
     }
 
-    pub fn remove_expired(&self, max_age: Duration) -> Vec<K> {
+    /// Removes the entries not updated in the last `max_age` whose key matches the filter.
+    pub fn remove_expired<F>(&self, max_age: Duration, filter: F) -> Vec<K>
+            where F: Fn(&K) -> bool {
         let to_remove = {
             let cache = self.map.read().unwrap();
             let max_ms = duration_to_millis(max_age) as i64;
             let current_ms = millis_to_epoch(SystemTime::now());
             cache.iter()
-                .filter(|&(_, v)| (current_ms as i64) - (v.updated as i64) > max_ms)
+                .filter(|&(k, v)| (current_ms as i64) - (v.updated as i64) > max_ms && filter(k))
                 .map(|(k, _)| k.clone())
                 .collect::<Vec<_>>()
         };
@@ -498,6 +512,18 @@ pub type OffsetsCache = ReplicatedMap<(ClusterId, String, TopicName), Vec<i64>>;
 /// Offsets for the internal consumers of the __consumer_offsets topic
 pub type InternalConsumerOffsetCache = ReplicatedMap<ClusterId, Vec<i64>>;
 
+/// Recent lag samples of a consumer group per topic
+pub type LagHistoryCache = ReplicatedMap<(ClusterId, String, TopicName), Vec<LagSample>>;
+
+/// Instance currently fetching the data of each cluster
+pub type LeaseCache = ReplicatedMap<ClusterId, Lease>;
+
+/// Recent metadata change events of each cluster
+pub type EventCache = ReplicatedMap<ClusterId, Vec<ClusterEvent>>;
+
+/// State of each alert rule
+pub type AlertCache = ReplicatedMap<String, AlertState>;
+
 
 pub struct Cache {
     pub metrics: MetricsCache,
@@ -506,6 +532,10 @@ pub struct Cache {
     pub topics: TopicCache,
     pub groups: GroupCache,
     pub internal_offsets: InternalConsumerOffsetCache,
+    pub lag_history: LagHistoryCache,
+    pub leases: LeaseCache,
+    pub events: EventCache,
+    pub alerts: AlertCache,
 }
 
 impl Cache {
@@ -517,7 +547,11 @@ impl Cache {
             brokers: ReplicatedMap::new("brokers", replica_writer_arc.clone()),
             topics: ReplicatedMap::new("topics", replica_writer_arc.clone()),
             groups: ReplicatedMap::new("groups", replica_writer_arc.clone()),
-            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc)
+            internal_offsets: ReplicatedMap::new("internal_offsets", replica_writer_arc.clone()),
+            lag_history: ReplicatedMap::new("lag_history", replica_writer_arc.clone()),
+            leases: ReplicatedMap::new("leases", replica_writer_arc.clone()),
+            events: ReplicatedMap::new("events", replica_writer_arc.clone()),
+            alerts: ReplicatedMap::new("alerts", replica_writer_arc),
         }
     }
 
@@ -529,6 +563,10 @@ impl Cache {
             topics: self.topics.alias(),
             groups: self.groups.alias(),
             internal_offsets: self.internal_offsets.alias(),
+            lag_history: self.lag_history.alias(),
+            leases: self.leases.alias(),
+            events: self.events.alias(),
+            alerts: self.alerts.alias(),
         }
     }
 }
@@ -542,7 +580,142 @@ impl UpdateReceiver for Cache {
             "topics" => self.topics.receive_update(update),
             "groups" => self.groups.receive_update(update),
             "internal_offsets" => self.internal_offsets.receive_update(update),
+            "lag_history" => self.lag_history.receive_update(update),
+            "leases" => self.leases.receive_update(update),
+            "events" => self.events.receive_update(update),
+            "alerts" => self.alerts.receive_update(update),
             _ => bail!("Unknown cache name: {}", cache_name),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alerts::AlertStatus;
+    use events::ChangeEvent;
+    use metadata::{GroupMember, MemberAssignment};
+    use metrics::{PartitionMetrics, TopicBrokerMetrics};
+
+    use std::fmt::Debug;
+
+    fn assert_round_trip<V: Serialize + DeserializeOwned + PartialEq + Debug>(value: V) {
+        for &encoding in &[CacheEncoding::Json, CacheEncoding::MessagePack] {
+            let encoded = encode_value(&value, encoding).unwrap();
+            assert_eq!(decode_value::<V>(&encoded).unwrap(), value, "{:?}", encoding);
+
+            let payload = add_origin("instance-1", encoded.clone());
+            let (origin, split) = split_origin(&payload);
+            assert_eq!(origin, Some("instance-1"));
+            assert_eq!(split, &encoded[..]);
+            assert_eq!(decode_value::<V>(split).unwrap(), value, "{:?} with origin", encoding);
+        }
+
+        let legacy = serde_json::to_vec(&value).unwrap();
+        assert_eq!(split_origin(&legacy), (None, &legacy[..]));
+        assert_eq!(decode_value::<V>(&legacy).unwrap(), value, "legacy JSON");
+    }
+
+    #[test]
+    fn round_trips_topic_metrics() {
+        let mut metrics = TopicMetrics::new();
+        metrics.brokers.insert(1, TopicBrokerMetrics {
+            m_rate_15: 12.5,
+            b_rate_15: 1024.25,
+            partitions: vec![PartitionMetrics { size_bytes: 4096f64 }, PartitionMetrics::default()],
+        });
+        assert_round_trip(metrics);
+    }
+
+    #[test]
+    fn round_trips_partitions() {
+        assert_round_trip(vec![
+            Partition { id: 0, leader: 1, replicas: vec![1, 2], isr: vec![1, 2], error: None },
+            Partition { id: 1, leader: -1, replicas: vec![2, 1], isr: vec![], error: Some("LeaderNotAvailable".to_owned()) },
+        ]);
+    }
+
+    #[test]
+    fn round_trips_group() {
+        assert_round_trip(Group {
+            name: "group".to_owned(),
+            state: "Stable".to_owned(),
+            members: vec![GroupMember {
+                id: "consumer-1-abc".to_owned(),
+                client_id: "consumer-1".to_owned(),
+                client_host: "/10.0.0.1".to_owned(),
+                assignments: vec![MemberAssignment { topic: "topic".to_owned(), partitions: vec![0, 2] }],
+            }],
+        });
+    }
+
+    #[test]
+    fn round_trips_brokers() {
+        assert_round_trip(vec![
+            Broker { id: 1, hostname: "kafka1".to_owned(), port: 9092 },
+            Broker { id: 2, hostname: "kafka2".to_owned(), port: 9093 },
+        ]);
+    }
+
+    #[test]
+    fn round_trips_offsets() {
+        assert_round_trip(vec![0i64, -1, 1 << 40]);
+        assert_round_trip(Vec::<i64>::new());
+    }
+
+    #[test]
+    fn round_trips_lease() {
+        assert_round_trip(Lease { owner: "instance-1".to_owned(), acquired: 1_500_000_000_000, expires: 1_500_000_030_000 });
+    }
+
+    #[test]
+    fn round_trips_lag_samples() {
+        assert_round_trip(vec![
+            LagSample { timestamp: 1_500_000_000_000, lag: vec![10, -1, 0] },
+            LagSample { timestamp: 1_500_000_060_000, lag: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn round_trips_events() {
+        assert_round_trip(vec![
+            ClusterEvent {
+                timestamp: 1_500_000_000_000,
+                event: ChangeEvent::IsrShrank { topic: "orders".to_owned(), partition: 2, removed: vec![3] },
+            },
+            ClusterEvent {
+                timestamp: 1_500_000_060_000,
+                event: ChangeEvent::BrokerJoined { broker: 3, hostname: "kafka3".to_owned() },
+            },
+        ]);
+    }
+
+    #[test]
+    fn round_trips_alert_state() {
+        assert_round_trip(AlertState {
+            rule: "orders_lag".to_owned(),
+            cluster_id: ClusterId::from("local"),
+            status: AlertStatus::Firing,
+            since: 1_500_000_000_000,
+            last_evaluation: 1_500_000_060_000,
+            details: "Current lag of billing on orders: 1200 (threshold: 1000 for 300s)".to_owned(),
+        });
+    }
+
+    #[test]
+    fn split_origin_ignores_malformed_header() {
+        let truncated = [ORIGIN_HEADER, 10, b'a'];
+        assert_eq!(split_origin(&truncated), (None, &truncated[..]));
+
+        let long_id = "x".repeat(300);
+        let payload = add_origin(&long_id, vec![JSON_HEADER]);
+        assert_eq!(split_origin(&payload), (Some(&long_id[..255]), &[JSON_HEADER][..]));
+    }
+
+    #[test]
+    fn decode_value_rejects_invalid_payload() {
+        assert!(decode_value::<Lease>(&[MSGPACK_HEADER, 0xc1]).is_err());
+        assert!(decode_value::<Lease>(b"{\"owner\": 1}").is_err());
+        assert!(decode_value::<Lease>(&[]).is_err());
+    }
+}
@@ -1,48 +1,427 @@
-use serde_yaml;
+use rdkafka::config::ClientConfig;
+use rand::random;
+use serde_yaml::{self, Mapping, Value};
 
 use metadata::ClusterId;
 use error::*;
 
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
 use std::io::prelude::*;
-use std::fs::File;
+use std::fs::{self, File};
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 
 
 fn default_true() -> bool { true }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+fn default_lag_history_interval() -> u64 { 60 }
+
+fn default_lag_history_size() -> usize { 1440 }
+
+fn default_lag_history_persist_interval() -> u64 { 600 }
+
+fn default_events_history_size() -> usize { 1000 }
+
+fn default_alert_evaluation_interval() -> u64 { 60 }
+
+fn default_compaction_interval() -> u64 { 3600 }
+
+fn default_lease_duration() -> u64 { 30 }
+
+fn default_prometheus_path() -> String { "/metrics".to_owned() }
+
+fn default_instance_id() -> String { format!("kafka-view-{:08x}", random::<u32>()) }
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MetricsSourceConfig {
+    /// Jolokia JVM agent running on each broker.
+    Jolokia { port: i32 },
+    /// Prometheus text endpoint running on each broker, such as the Prometheus JMX exporter.
+    Prometheus {
+        port: i32,
+        #[serde(default = "default_prometheus_path")]
+        path: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SecurityProtocol {
+    #[serde(rename = "plaintext")]
+    Plaintext,
+    #[serde(rename = "ssl")]
+    Ssl,
+    #[serde(rename = "sasl_plaintext")]
+    SaslPlaintext,
+    #[serde(rename = "sasl_ssl")]
+    SaslSsl,
+}
+
+impl SecurityProtocol {
+    fn as_rdkafka_str(&self) -> &'static str {
+        match *self {
+            SecurityProtocol::Plaintext => "plaintext",
+            SecurityProtocol::Ssl => "ssl",
+            SecurityProtocol::SaslPlaintext => "sasl_plaintext",
+            SecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+    #[serde(rename = "GSSAPI")]
+    Gssapi,
+}
+
+impl SaslMechanism {
+    fn as_rdkafka_str(&self) -> &'static str {
+        match *self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+            SaslMechanism::Gssapi => "GSSAPI",
+        }
+    }
+}
+
+/// Passwords are never serialized nor included in the debug output.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct SecurityConfig {
+    pub protocol: SecurityProtocol,
+    pub sasl_mechanism: Option<SaslMechanism>,
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub password_file: Option<String>,  // the first line of the file is used as password
+    pub password_env: Option<String>,   // name of the environment variable containing the password
+    pub ca_location: Option<String>,
+    pub certificate_location: Option<String>,
+    pub key_location: Option<String>,
+    #[serde(skip_serializing)]
+    pub key_password: Option<String>,
+}
+
+fn redacted(secret: &Option<String>) -> Option<&'static str> {
+    secret.as_ref().map(|_| "<redacted>")
+}
+
+impl fmt::Debug for SecurityConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SecurityConfig")
+            .field("protocol", &self.protocol)
+            .field("sasl_mechanism", &self.sasl_mechanism)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("password_file", &self.password_file)
+            .field("password_env", &self.password_env)
+            .field("ca_location", &self.ca_location)
+            .field("certificate_location", &self.certificate_location)
+            .field("key_location", &self.key_location)
+            .field("key_password", &redacted(&self.key_password))
+            .finish()
+    }
+}
+
+impl SecurityConfig {
+    /// Returns the SASL password, reading it from the file or the environment if needed.
+    pub fn password(&self) -> Result<Option<String>> {
+        if let Some(ref password) = self.password {
+            return Ok(Some(password.to_owned()));
+        }
+        if let Some(ref path) = self.password_file {
+            let mut content = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut content))
+                .chain_err(|| format!("Unable to read password file {}", path))?;
+            return Ok(Some(content.lines().next().unwrap_or("").to_owned()));
+        }
+        if let Some(ref variable) = self.password_env {
+            let password = env::var(variable)
+                .chain_err(|| format!("Unable to read password from environment variable {}", variable))?;
+            return Ok(Some(password));
+        }
+        Ok(None)
+    }
+
+    fn apply(&self, client_config: &mut ClientConfig) -> Result<()> {
+        client_config.set("security.protocol", self.protocol.as_rdkafka_str());
+        if let Some(mechanism) = self.sasl_mechanism {
+            client_config.set("sasl.mechanisms", mechanism.as_rdkafka_str());
+        }
+        if let Some(ref username) = self.username {
+            client_config.set("sasl.username", username);
+        }
+        if let Some(password) = self.password()? {
+            client_config.set("sasl.password", &password);
+        }
+        if let Some(ref ca_location) = self.ca_location {
+            client_config.set("ssl.ca.location", ca_location);
+        }
+        if let Some(ref certificate_location) = self.certificate_location {
+            client_config.set("ssl.certificate.location", certificate_location);
+        }
+        if let Some(ref key_location) = self.key_location {
+            client_config.set("ssl.key.location", key_location);
+        }
+        if let Some(ref key_password) = self.key_password {
+            client_config.set("ssl.key.password", key_password);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ClusterConfig {
     pub cluster_id: Option<ClusterId>, // This will always be available after load
     pub broker_list: Vec<String>,
     pub zookeeper: String,
     pub jolokia_port: Option<i32>,
+    pub metrics_source: Option<MetricsSourceConfig>,
     pub graph_url: Option<String>,
     #[serde(default = "default_true")]
     pub enable_tailing: bool,
+    #[serde(default)]
+    pub enable_offset_reset: bool,
+    #[serde(default)]
+    pub enable_topic_create: bool,
+    #[serde(default)]
+    pub enable_topic_delete: bool,
+    #[serde(default)]
+    pub enable_topic_alter: bool,  // add partitions and change configuration
+    #[serde(default)]
+    pub enable_leader_election: bool,  // preferred replica leader election
+    pub security: Option<SecurityConfig>,
+    #[serde(default)]
+    pub client_properties: HashMap<String, String>,  // additional librdkafka properties
 }
 
 impl ClusterConfig {
     pub fn bootstrap_servers(&self) -> String {
         self.broker_list.join(",")
     }
+
+    /// Returns the base configuration for every Kafka client connecting to the cluster,
+    /// including the security settings and the additional properties.
+    pub fn client_config(&self) -> Result<ClientConfig> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &self.bootstrap_servers())
+            .set("api.version.request", "true");
+        if let Some(ref security) = self.security {
+            security.apply(&mut client_config)
+                .chain_err(|| "Invalid security configuration")?;
+        }
+        for (key, value) in &self.client_properties {
+            client_config.set(key, value);
+        }
+        Ok(client_config)
+    }
+
+    /// Returns the configured metrics source, falling back to Jolokia if only `jolokia_port`
+    /// is specified.
+    pub fn metrics_source(&self) -> Option<MetricsSourceConfig> {
+        match self.metrics_source {
+            Some(ref source) => Some(source.clone()),
+            None => self.jolokia_port.map(|port| MetricsSourceConfig::Jolokia { port }),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CachingBackend {
+    Kafka,   // compacted topic, shared by all the instances using it
+    File,    // local append-only file
+    Memory,  // no persistence, the cache is rebuilt at every restart
+}
+
+impl Default for CachingBackend {
+    fn default() -> CachingBackend {
+        CachingBackend::Kafka
+    }
+}
+
+/// Encoding of the cache values written to the storage. Values in any format can be read.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CacheEncoding {
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Default for CacheEncoding {
+    fn default() -> CacheEncoding {
+        CacheEncoding::MessagePack
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct CachingConfig {
+    #[serde(default)]
+    pub backend: CachingBackend,
+    #[serde(default)]
+    pub encoding: CacheEncoding,
+    pub cluster: Option<ClusterId>,  // kafka backend
+    pub topic: Option<String>,       // kafka backend
+    pub path: Option<String>,        // file backend
+    #[serde(default = "default_compaction_interval")]
+    pub compaction_interval: u64,    // file backend, in seconds
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertCondition {
+    /// Total lag of the group on the topic above `threshold` for `duration` seconds.
+    GroupLag {
+        group: String,
+        topic: String,
+        threshold: i64,
+        #[serde(default)]
+        duration: u64,
+    },
+    /// At least one partition of the topics matching the optional regex has an error.
+    PartitionError {
+        #[serde(default)]
+        topic: Option<String>,
+    },
+    /// At least one partition of the topics matching the optional regex has fewer in-sync
+    /// replicas than replicas.
+    UnderReplicated {
+        #[serde(default)]
+        topic: Option<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertRuleConfig {
+    pub name: String,
     pub cluster: ClusterId,
-    pub topic: String,
+    pub condition: AlertCondition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlertsConfig {
+    pub webhook_url: Option<String>,
+    #[serde(default = "default_alert_evaluation_interval")]
+    pub evaluation_interval: u64,
+    #[serde(default)]
+    pub rules: Vec<AlertRuleConfig>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> AlertsConfig {
+        AlertsConfig {
+            webhook_url: None,
+            evaluation_interval: default_alert_evaluation_interval(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DecoderConfig {
+    Utf8,
+    Json,
+    Hex,
+    Base64,
+    /// Avro binary encoding. The schema is either read from a local file, or fetched from a
+    /// schema registry using the id contained in each message.
+    Avro {
+        schema_file: Option<String>,
+        registry_url: Option<String>,
+    },
+    /// Protobuf message of the given type, described by a descriptor set file generated with
+    /// `protoc --include_imports --descriptor_set_out`.
+    Protobuf {
+        descriptor_set: String,
+        message_type: String,
+    },
+}
+
+impl Default for DecoderConfig {
+    fn default() -> DecoderConfig {
+        DecoderConfig::Utf8
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DecoderRuleConfig {
+    pub cluster: Option<ClusterId>,  // all clusters if missing
+    pub topic: String,               // regex on the topic name
+    #[serde(default)]
+    pub key: DecoderConfig,
+    #[serde(default)]
+    pub payload: DecoderConfig,
+}
+
+/// Roles are ordered: each role can access everything the previous ones can.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Viewer,    // cluster metadata, metrics and consumer groups
+    Operator,  // topic content and offset reset
+    Admin,     // topic administration and internals
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UserConfig {
+    pub name: String,
+    pub password_hash: Option<String>,  // bcrypt hash, for HTTP basic authentication
+    pub role: Role,
+    pub clusters: Option<Vec<ClusterId>>,  // all clusters if missing
+    pub topics: Option<String>,            // regex, all topics if missing
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    pub proxy_header: Option<String>,  // header containing the user name set by a trusted proxy
+    pub proxy_role: Option<Role>,      // role of the proxy users missing from the user list
+    pub anonymous_role: Option<Role>,  // role of unauthenticated users, if allowed
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
+    #[serde(default = "default_instance_id")]
+    pub instance_id: String,  // identifies the instance among the ones sharing the cache storage
+    #[serde(default = "default_lease_duration")]
+    pub lease_duration: u64,  // seconds an instance keeps owning a cluster without renewing the lease
     pub listen_port: u16,
     pub listen_host: String,
     pub metadata_refresh: u64,
     pub metrics_refresh: u64,
     pub offsets_store_duration: u64,
     pub consumer_offsets_group_id: String,
+    #[serde(default = "default_lag_history_interval")]
+    pub lag_history_interval: u64,
+    #[serde(default = "default_lag_history_size")]
+    pub lag_history_size: usize,
+    #[serde(default = "default_lag_history_persist_interval")]
+    pub lag_history_persist_interval: u64,  // seconds between two writes of the history to the storage
+    #[serde(default = "default_events_history_size")]
+    pub events_history_size: usize,
     pub clusters: HashMap<ClusterId, ClusterConfig>,
     pub caching: CachingConfig,
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+    #[serde(default)]
+    pub decoders: Vec<DecoderRuleConfig>,
+    pub kafka_tools_path: Option<String>,  // directory containing kafka-topics.sh, default: PATH
+    pub audit_log: Option<String>,
+    pub auth: Option<AuthConfig>,  // authentication is disabled if missing
 }
 
 impl Config {
@@ -51,15 +430,236 @@ impl Config {
     }
 }
 
-pub fn read_config(path: &str) -> Result<Config> {
-    let mut f = File::open(path)
-        .chain_err(|| "Unable to open configuration file")?;;
-    let mut s = String::new();
-    f.read_to_string(&mut s)
-        .chain_err(|| "Unable to read configuration file")?;
+/// Maximum nesting of included files, which also stops circular includes.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Replaces `${NAME}` and `${NAME:-default}` with the value of the environment variable,
+/// or with the default if the variable is not defined. `$${` is replaced with `${`.
+fn interpolate(text: &str) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => bail!("Unterminated variable reference"),
+        };
+        let expression = &rest[start + 2..end];
+        let (name, default) = match expression.find(":-") {
+            Some(position) => (&expression[..position], Some(&expression[position + 2..])),
+            None => (expression, None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), _) => result.push_str(&value),
+            (Err(_), Some(default)) => result.push_str(default),
+            (Err(_), None) => bail!("Environment variable {} is not defined", name),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
 
-    let mut config: Config = serde_yaml::from_str(&s)
-        .chain_err(|| "Unable to parse configuration file")?;
+/// Interpolates the environment variables in all the string values, after parsing: the
+/// result is always a string, and can't change the structure of the document.
+/// Interpolates a string value. If the value is a single reference, a number or boolean
+/// result is parsed as such, so that it can be used for the numeric and boolean settings.
+/// Anything else, including results that wouldn't be written the same way once parsed
+/// (e.g. `0123`), stays a string.
+fn interpolated_scalar(text: &str) -> Result<Value> {
+    let result = interpolate(text)?;
+    let single_reference = text.starts_with("${") && text.find('}') == Some(text.len() - 1);
+    if single_reference {
+        if let Ok(parsed) = serde_yaml::from_str::<Value>(&result) {
+            let is_scalar = match parsed {
+                Value::Bool(_) | Value::Number(_) => true,
+                _ => false,
+            };
+            let same_text = serde_yaml::to_string(&parsed)
+                .map(|s| s.trim_left_matches("---").trim() == result)
+                .unwrap_or(false);
+            if is_scalar && same_text {
+                return Ok(parsed);
+            }
+        }
+    }
+    Ok(Value::String(result))
+}
+
+fn interpolate_value(value: &mut Value) -> Result<()> {
+    let interpolated = match *value {
+        Value::String(ref text) if text.contains("${") => Some(interpolated_scalar(text)?),
+        _ => None,
+    };
+    if let Some(interpolated) = interpolated {
+        *value = interpolated;
+        return Ok(());
+    }
+    match *value {
+        Value::Sequence(ref mut items) => {
+            for item in items {
+                interpolate_value(item)?;
+            }
+        },
+        Value::Mapping(ref mut mapping) => {
+            for (key, item) in mapping.iter_mut() {
+                interpolate_value(item)
+                    .chain_err(|| format!("Invalid value for {}", key.as_str().unwrap_or("key")))?;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Merges `overlay` into `base`. Mappings are merged recursively, any other value is replaced.
+fn merge(base: &mut Value, overlay: Value) {
+    let overlay_mapping = match overlay {
+        Value::Mapping(mapping) => mapping,
+        other => {
+            *base = other;
+            return;
+        },
+    };
+    if let Value::Mapping(ref mut base_mapping) = *base {
+        for (key, value) in overlay_mapping {
+            let merged = match base_mapping.remove(&key) {
+                Some(mut existing) => {
+                    merge(&mut existing, value);
+                    existing
+                },
+                None => value,
+            };
+            base_mapping.insert(key, merged);
+        }
+        return;
+    }
+    *base = Value::Mapping(overlay_mapping);
+}
+
+fn included_paths(include: Value) -> Result<Vec<String>> {
+    match include {
+        Value::String(path) => Ok(vec![path]),
+        Value::Sequence(paths) => paths.into_iter()
+            .map(|path| match path {
+                Value::String(path) => Ok(path),
+                _ => bail!("Include paths must be strings"),
+            })
+            .collect(),
+        _ => bail!("Include must be a path or a list of paths"),
+    }
+}
+
+/// Loads a configuration file, or all the `.yaml` and `.yml` files of a directory in lexical
+/// order, merging the files they include. Every file and directory read is added to `files`.
+fn load_yaml(path: &Path, depth: usize, files: &mut Vec<PathBuf>) -> Result<Value> {
+    if depth > MAX_INCLUDE_DEPTH {
+        bail!("Too many nested includes in {}, maybe a circular include", path.display());
+    }
+    files.push(path.to_owned());
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .chain_err(|| format!("Unable to read configuration directory {}", path.display()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|entry| match entry.extension().and_then(|e| e.to_str()) {
+                Some("yaml") | Some("yml") => entry.is_file(),
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+        let mut merged = Value::Mapping(Mapping::new());
+        for entry in entries {
+            merge(&mut merged, load_yaml(&entry, depth + 1, files)?);
+        }
+        return Ok(merged);
+    }
+
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .chain_err(|| format!("Unable to read configuration file {}", path.display()))?;
+    let mut value: Value = serde_yaml::from_str(&text)
+        .chain_err(|| format!("Unable to parse configuration file {}", path.display()))?;
+    interpolate_value(&mut value)
+        .chain_err(|| format!("Unable to interpolate configuration file {}", path.display()))?;
+
+    // Included files are merged first, so that the including file can override them
+    let include = match value {
+        Value::Mapping(ref mut mapping) => mapping.remove(&Value::String("include".to_owned())),
+        _ => None,
+    };
+    let include = match include {
+        Some(include) => included_paths(include)
+            .chain_err(|| format!("Invalid include in {}", path.display()))?,
+        None => return Ok(value),
+    };
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut merged = Value::Mapping(Mapping::new());
+    for included in include {
+        merge(&mut merged, load_yaml(&base_dir.join(included), depth + 1, files)?);
+    }
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+/// Sets the value at a dotted path, such as `clusters.local.zookeeper=zk:2181`. The value is
+/// parsed as YAML, so lists can be specified with the `[a, b]` syntax.
+fn apply_override(root: &mut Value, setting: &str) -> Result<()> {
+    let position = match setting.find('=') {
+        Some(position) => position,
+        None => bail!("Invalid override '{}', expected key=value", setting),
+    };
+    let keys = setting[..position].split('.').collect::<Vec<_>>();
+    if keys.iter().any(|key| key.is_empty()) {
+        bail!("Invalid key in override '{}'", setting);
+    }
+    let raw_value = &setting[position + 1..];
+    let value = serde_yaml::from_str::<Value>(raw_value)
+        .unwrap_or_else(|_| Value::String(raw_value.to_owned()));
+    set_path(root, &keys, value)
+        .chain_err(|| format!("Unable to apply override '{}'", setting))
+}
+
+fn set_path(root: &mut Value, keys: &[&str], value: Value) -> Result<()> {
+    let mapping = match *root {
+        Value::Mapping(ref mut mapping) => mapping,
+        _ => bail!("Can't set {} in a value that is not a mapping", keys[0]),
+    };
+    let key = Value::String(keys[0].to_owned());
+    if keys.len() == 1 {
+        mapping.insert(key, value);
+        return Ok(());
+    }
+    let mut child = mapping.remove(&key).unwrap_or_else(|| Value::Mapping(Mapping::new()));
+    set_path(&mut child, &keys[1..], value)?;
+    mapping.insert(key, child);
+    Ok(())
+}
+
+/// Loads the configuration as a YAML value, interpolating the environment variables, merging
+/// the included files and applying the overrides (`key.subkey=value`). Returns the value and
+/// all the files and directories it was read from.
+pub fn load_config_value(path: &str, overrides: &[String]) -> Result<(Value, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut value = load_yaml(Path::new(path), 0, &mut files)?;
+    for setting in overrides {
+        apply_override(&mut value, setting)?;
+    }
+    Ok((value, files))
+}
+
+/// Deserializes a configuration loaded with `load_config_value`.
+pub fn parse_config(value: &Value) -> Result<Config> {
+    let text = serde_yaml::to_string(value)
+        .chain_err(|| "Unable to serialize configuration")?;
+    let mut config: Config = serde_yaml::from_str(&text)
+        .chain_err(|| "Unable to parse configuration")?;
 
     for (cluster_id, cluster) in &mut config.clusters {
         cluster.cluster_id = Some(cluster_id.clone());
@@ -67,3 +667,264 @@ pub fn read_config(path: &str) -> Result<Config> {
 
     Ok(config)
 }
+
+/// Reads the configuration, returning it with the files and directories it was read from.
+pub fn read_config_files(path: &str, overrides: &[String]) -> Result<(Config, Vec<PathBuf>)> {
+    let (value, files) = load_config_value(path, overrides)?;
+    Ok((parse_config(&value)?, files))
+}
+
+pub fn read_config(path: &str, overrides: &[String]) -> Result<Config> {
+    read_config_files(path, overrides).map(|(config, _)| config)
+}
+
+/// The configuration in use, shared between the background tasks and the web server. It's
+/// replaced as a whole when the configuration file is reloaded.
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    pub fn alias(&self) -> SharedConfig {
+        SharedConfig {
+            current: self.current.clone(),
+        }
+    }
+
+    /// Returns the current configuration, which is not affected by later reloads.
+    pub fn get(&self) -> CurrentConfig {
+        match self.current.read() {
+            Ok(current) => CurrentConfig(current.clone()),
+            Err(_) => panic!("Poison error"),
+        }
+    }
+
+    pub fn replace(&self, config: Config) {
+        match self.current.write() {
+            Ok(mut current) => *current = Arc::new(config),
+            Err(_) => panic!("Poison error"),
+        }
+    }
+}
+
+/// A snapshot of the shared configuration.
+#[derive(Clone)]
+pub struct CurrentConfig(Arc<Config>);
+
+impl Deref for CurrentConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let path = env::temp_dir().join(format!("kafka-view-config-{}", random::<u64>()));
+            fs::create_dir(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn write(&self, name: &str, content: &str) -> PathBuf {
+            let path = self.0.join(name);
+            File::create(&path).and_then(|mut f| f.write_all(content.as_bytes())).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn load(path: &Path) -> Result<Value> {
+        load_yaml(path, 0, &mut Vec::new())
+    }
+
+    fn get<'a>(value: &'a Value, path: &str) -> &'a Value {
+        path.split('.').fold(value, |value, key| &value[key])
+    }
+
+    #[test]
+    fn interpolates_variables() {
+        env::set_var("KAFKA_VIEW_TEST_HOST", "kafka1");
+        env::remove_var("KAFKA_VIEW_TEST_MISSING");
+        assert_eq!(interpolate("${KAFKA_VIEW_TEST_HOST}:9092").unwrap(), "kafka1:9092");
+        assert_eq!(interpolate("${KAFKA_VIEW_TEST_MISSING:-localhost}").unwrap(), "localhost");
+        assert_eq!(interpolate("${KAFKA_VIEW_TEST_MISSING:-}").unwrap(), "");
+        assert_eq!(interpolate("$${KAFKA_VIEW_TEST_HOST}").unwrap(), "${KAFKA_VIEW_TEST_HOST}");
+        assert_eq!(interpolate("no references").unwrap(), "no references");
+        assert!(interpolate("${KAFKA_VIEW_TEST_MISSING}").is_err());
+        assert!(interpolate("${KAFKA_VIEW_TEST_HOST").is_err());
+    }
+
+    #[test]
+    fn interpolates_string_values_only() {
+        env::set_var("KAFKA_VIEW_TEST_YES", "yes");
+        env::set_var("KAFKA_VIEW_TEST_OCTAL", "0123");
+        env::set_var("KAFKA_VIEW_TEST_YAML", "value\nother: injected");
+        env::remove_var("KAFKA_VIEW_TEST_UNDEFINED");
+        let dir = TempDir::new();
+        let path = dir.write("config.yaml", "\
+# Comments can mention ${KAFKA_VIEW_TEST_UNDEFINED}
+flag: ${KAFKA_VIEW_TEST_YES}
+number: ${KAFKA_VIEW_TEST_OCTAL}
+secret: ${KAFKA_VIEW_TEST_YAML}
+list: [a, '${KAFKA_VIEW_TEST_YES}']
+port: 9092
+");
+        let value = load(&path).unwrap();
+        assert_eq!(value["flag"], Value::String("yes".to_owned()));
+        assert_eq!(value["number"], Value::String("0123".to_owned()));
+        assert_eq!(value["secret"], Value::String("value\nother: injected".to_owned()));
+        assert!(value.get("other").is_none());
+        assert_eq!(value["list"][1], Value::String("yes".to_owned()));
+        assert_eq!(value["port"], Value::Number(9092.into()));
+    }
+
+    #[test]
+    fn parses_interpolated_numbers_and_booleans() {
+        env::set_var("KAFKA_VIEW_TEST_PORT", "9000");
+        env::set_var("KAFKA_VIEW_TEST_FALSE", "false");
+        env::set_var("KAFKA_VIEW_TEST_DIGITS", "0123");
+        env::set_var("KAFKA_VIEW_TEST_NUMERIC", "12345");
+        env::set_var("KAFKA_VIEW_TEST_MAPPING", "a: b");
+        env::remove_var("KAFKA_VIEW_TEST_UNDEFINED");
+        let dir = TempDir::new();
+        let path = dir.write("config.yaml", "\
+listen_port: ${KAFKA_VIEW_TEST_PORT}
+listen_host: host-${KAFKA_VIEW_TEST_PORT}
+lease_duration: ${KAFKA_VIEW_TEST_UNDEFINED:-45}
+metadata_refresh: 60
+metrics_refresh: 60
+offsets_store_duration: 3600
+consumer_offsets_group_id: ${KAFKA_VIEW_TEST_MAPPING}
+clusters:
+  local:
+    broker_list: ['localhost:9092']
+    zookeeper: localhost:2181
+    enable_tailing: ${KAFKA_VIEW_TEST_FALSE}
+    security:
+      protocol: sasl_plaintext
+      username: ${KAFKA_VIEW_TEST_DIGITS}
+      password: ${KAFKA_VIEW_TEST_NUMERIC}
+caching:
+  backend: memory
+");
+        let config = parse_config(&load(&path).unwrap()).unwrap();
+        assert_eq!(config.listen_port, 9000);
+        assert_eq!(config.listen_host, "host-9000");
+        assert_eq!(config.lease_duration, 45);
+        assert_eq!(config.consumer_offsets_group_id, "a: b");
+        let cluster = &config.clusters[&ClusterId::from("local")];
+        assert!(!cluster.enable_tailing);
+        let security = cluster.security.as_ref().unwrap();
+        assert_eq!(security.username, Some("0123".to_owned()));
+        assert_eq!(security.password, Some("12345".to_owned()));
+    }
+
+    #[test]
+    fn reports_undefined_variables() {
+        env::remove_var("KAFKA_VIEW_TEST_UNDEFINED");
+        let dir = TempDir::new();
+        let path = dir.write("config.yaml", "host: ${KAFKA_VIEW_TEST_UNDEFINED}\n");
+        assert!(load(&path).is_err());
+    }
+
+    #[test]
+    fn merges_includes_in_order() {
+        let dir = TempDir::new();
+        dir.write("base.yaml", "listen_port: 8080\nclusters:\n  local:\n    broker_list: ['a:9092']\n    zookeeper: zk\n");
+        dir.write("extra.yaml", "listen_port: 8081\nclusters:\n  local:\n    zookeeper: zk2\n");
+        let path = dir.write("config.yaml", "include: [base.yaml, extra.yaml]\nlisten_host: 0.0.0.0\n");
+
+        let mut files = Vec::new();
+        let value = load_yaml(&path, 0, &mut files).unwrap();
+        assert_eq!(value["listen_port"], Value::Number(8081.into()));
+        assert_eq!(get(&value, "clusters.local.zookeeper"), &Value::String("zk2".to_owned()));
+        assert_eq!(get(&value, "clusters.local.broker_list")[0], Value::String("a:9092".to_owned()));
+        assert_eq!(value["listen_host"], Value::String("0.0.0.0".to_owned()));
+        assert!(value.get("include").is_none());
+        assert_eq!(files.len(), 3);
+
+        // The including file overrides the included ones
+        let path = dir.write("override.yaml", "include: base.yaml\nlisten_port: 9000\n");
+        assert_eq!(load(&path).unwrap()["listen_port"], Value::Number(9000.into()));
+    }
+
+    #[test]
+    fn merges_directory_in_lexical_order() {
+        let dir = TempDir::new();
+        dir.write("10-base.yaml", "listen_port: 8080\nlisten_host: localhost\n");
+        dir.write("20-override.yml", "listen_port: 8081\n");
+        dir.write("ignored.txt", "listen_port: 1\n");
+        let value = load(&dir.0).unwrap();
+        assert_eq!(value["listen_port"], Value::Number(8081.into()));
+        assert_eq!(value["listen_host"], Value::String("localhost".to_owned()));
+    }
+
+    #[test]
+    fn stops_circular_includes() {
+        let dir = TempDir::new();
+        dir.write("a.yaml", "include: b.yaml\n");
+        let path = dir.write("b.yaml", "include: a.yaml\n");
+        let error = load(&path).unwrap_err();
+        assert!(error.iter().any(|e| e.to_string().contains("circular include")));
+    }
+
+    #[test]
+    fn applies_overrides() {
+        let mut value = serde_yaml::from_str::<Value>("listen_port: 8080\nclusters:\n  local:\n    zookeeper: zk\n").unwrap();
+        apply_override(&mut value, "listen_port=9000").unwrap();
+        apply_override(&mut value, "clusters.local.broker_list=['a:9092', 'b:9092']").unwrap();
+        apply_override(&mut value, "clusters.other.zookeeper=zk2").unwrap();
+        apply_override(&mut value, "listen_host=a: b: c").unwrap();
+
+        assert_eq!(value["listen_port"], Value::Number(9000.into()));
+        assert_eq!(get(&value, "clusters.local.zookeeper"), &Value::String("zk".to_owned()));
+        assert_eq!(get(&value, "clusters.local.broker_list")[1], Value::String("b:9092".to_owned()));
+        assert_eq!(get(&value, "clusters.other.zookeeper"), &Value::String("zk2".to_owned()));
+        assert_eq!(value["listen_host"], Value::String("a: b: c".to_owned()));
+
+        assert!(apply_override(&mut value, "listen_port").is_err());
+        assert!(apply_override(&mut value, "clusters..zookeeper=zk").is_err());
+        assert!(apply_override(&mut value, "listen_port.value=1").is_err());
+    }
+
+    #[test]
+    fn hides_security_secrets() {
+        let security: SecurityConfig = serde_yaml::from_str("\
+protocol: sasl_ssl
+sasl_mechanism: PLAIN
+username: kafka-view
+password: sasl-secret
+key_location: /etc/kafka-view/client.key
+key_password: key-secret
+").unwrap();
+        assert_eq!(security.password, Some("sasl-secret".to_owned()));
+        assert_eq!(security.key_password, Some("key-secret".to_owned()));
+
+        let debug = format!("{:?}", security);
+        assert!(debug.contains("kafka-view") && debug.contains("<redacted>"));
+        let serialized = serde_yaml::to_string(&security).unwrap();
+        assert!(serialized.contains("kafka-view") && serialized.contains("client.key"));
+        for output in &[debug, serialized] {
+            assert!(!output.contains("sasl-secret") && !output.contains("key-secret"));
+        }
+    }
+}
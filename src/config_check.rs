@@ -0,0 +1,421 @@
+use regex::Regex;
+use serde_yaml::{self, Value};
+
+use config::{load_config_value, parse_config, AlertCondition, CachingBackend, ClusterConfig, Config,
+             DecoderConfig, MetricsSourceConfig, SecurityConfig};
+use error::*;
+use metadata::ClusterId;
+
+use std::collections::HashSet;
+use std::fmt;
+
+
+const CONFIG_KEYS: &[&str] = &[
+    "instance_id", "lease_duration", "listen_port", "listen_host", "metadata_refresh", "metrics_refresh",
+    "offsets_store_duration", "consumer_offsets_group_id", "lag_history_interval", "lag_history_size",
+    "lag_history_persist_interval", "events_history_size", "clusters", "caching", "alerts", "decoders", "kafka_tools_path", "audit_log", "auth",
+];
+const CLUSTER_KEYS: &[&str] = &[
+    "cluster_id", "broker_list", "zookeeper", "jolokia_port", "metrics_source", "graph_url", "enable_tailing",
+    "enable_offset_reset", "enable_topic_create", "enable_topic_delete", "enable_topic_alter",
+    "enable_leader_election", "security", "client_properties",
+];
+const SECURITY_KEYS: &[&str] = &[
+    "protocol", "sasl_mechanism", "username", "password", "password_file", "password_env", "ca_location",
+    "certificate_location", "key_location", "key_password",
+];
+const CACHING_KEYS: &[&str] = &["backend", "encoding", "cluster", "topic", "path", "compaction_interval"];
+const ALERTS_KEYS: &[&str] = &["webhook_url", "evaluation_interval", "rules"];
+const ALERT_RULE_KEYS: &[&str] = &["name", "cluster", "condition"];
+const DECODER_RULE_KEYS: &[&str] = &["cluster", "topic", "key", "payload"];
+const AUTH_KEYS: &[&str] = &["users", "proxy_header", "proxy_role", "anonymous_role"];
+const USER_KEYS: &[&str] = &["name", "password_hash", "role", "clusters", "topics"];
+
+/// The placeholder replaced with the topic name in `graph_url`.
+const GRAPH_URL_PLACEHOLDER: &str = "{%s}";
+
+/// A problem found in the configuration file, with the YAML path of the offending value.
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub path: String,  // such as "clusters.local.broker_list[1]", empty for the whole document
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+#[derive(Default)]
+struct Problems(Vec<ConfigProblem>);
+
+impl Problems {
+    fn add<P: Into<String>, M: Into<String>>(&mut self, path: P, message: M) {
+        self.0.push(ConfigProblem { path: path.into(), message: message.into() });
+    }
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_owned()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn index(path: &str, index: usize) -> String {
+    format!("{}[{}]", path, index)
+}
+
+//
+// ********** UNKNOWN KEYS **********
+//
+
+fn get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match *value {
+        Value::Mapping(ref mapping) => mapping.get(&Value::String(key.to_owned())),
+        _ => None,
+    }
+}
+
+fn key_name(key: &Value) -> String {
+    match *key {
+        Value::String(ref key) => key.clone(),
+        ref other => serde_yaml::to_string(other).map(|s| s.trim_left_matches("---").trim().to_owned())
+            .unwrap_or_else(|_| "?".to_owned()),
+    }
+}
+
+/// Reports the keys of the mapping not contained in `allowed`. Values that are not
+/// mappings are left to the deserialization.
+fn check_keys(value: &Value, path: &str, allowed: &[&str], problems: &mut Problems) {
+    if let Value::Mapping(ref mapping) = *value {
+        for (key, _) in mapping.iter() {
+            let name = key_name(key);
+            if !allowed.contains(&name.as_str()) {
+                problems.add(join(path, &name), "unknown key");
+            }
+        }
+    }
+}
+
+fn check_child_keys(value: &Value, path: &str, key: &str, allowed: &[&str], problems: &mut Problems) {
+    if let Some(child) = get(value, key) {
+        check_keys(child, &join(path, key), allowed, problems);
+    }
+}
+
+fn sequence(value: Option<&Value>) -> &[Value] {
+    match value {
+        Some(&Value::Sequence(ref items)) => items,
+        _ => &[],
+    }
+}
+
+fn tagged_keys(value: &Value, keys_for_type: fn(&str) -> Option<&'static [&'static str]>) -> &'static [&'static str] {
+    match get(value, "type") {
+        Some(&Value::String(ref type_name)) => keys_for_type(type_name).unwrap_or(&["type"][..]),
+        _ => &["type"],  // missing or invalid types are reported by the deserialization
+    }
+}
+
+fn metrics_source_keys(type_name: &str) -> Option<&'static [&'static str]> {
+    match type_name {
+        "jolokia" => Some(&["type", "port"][..]),
+        "prometheus" => Some(&["type", "port", "path"][..]),
+        _ => None,
+    }
+}
+
+fn alert_condition_keys(type_name: &str) -> Option<&'static [&'static str]> {
+    match type_name {
+        "group_lag" => Some(&["type", "group", "topic", "threshold", "duration"][..]),
+        "partition_error" | "under_replicated" => Some(&["type", "topic"][..]),
+        _ => None,
+    }
+}
+
+fn decoder_keys(type_name: &str) -> Option<&'static [&'static str]> {
+    match type_name {
+        "utf8" | "json" | "hex" | "base64" => Some(&["type"][..]),
+        "avro" => Some(&["type", "schema_file", "registry_url"][..]),
+        "protobuf" => Some(&["type", "descriptor_set", "message_type"][..]),
+        _ => None,
+    }
+}
+
+fn check_unknown_keys(root: &Value, problems: &mut Problems) {
+    check_keys(root, "", CONFIG_KEYS, problems);
+
+    if let Some(&Value::Mapping(ref clusters)) = get(root, "clusters") {
+        for (cluster_id, cluster) in clusters.iter() {
+            let path = join("clusters", &key_name(cluster_id));
+            check_keys(cluster, &path, CLUSTER_KEYS, problems);
+            check_child_keys(cluster, &path, "security", SECURITY_KEYS, problems);
+            if let Some(source) = get(cluster, "metrics_source") {
+                check_keys(source, &join(&path, "metrics_source"), tagged_keys(source, metrics_source_keys), problems);
+            }
+        }
+    }
+
+    check_child_keys(root, "", "caching", CACHING_KEYS, problems);
+
+    if let Some(alerts) = get(root, "alerts") {
+        check_keys(alerts, "alerts", ALERTS_KEYS, problems);
+        for (i, rule) in sequence(get(alerts, "rules")).iter().enumerate() {
+            let path = index("alerts.rules", i);
+            check_keys(rule, &path, ALERT_RULE_KEYS, problems);
+            if let Some(condition) = get(rule, "condition") {
+                check_keys(condition, &join(&path, "condition"), tagged_keys(condition, alert_condition_keys), problems);
+            }
+        }
+    }
+
+    for (i, rule) in sequence(get(root, "decoders")).iter().enumerate() {
+        let path = index("decoders", i);
+        check_keys(rule, &path, DECODER_RULE_KEYS, problems);
+        for field in &["key", "payload"] {
+            if let Some(decoder) = get(rule, field) {
+                check_keys(decoder, &join(&path, field), tagged_keys(decoder, decoder_keys), problems);
+            }
+        }
+    }
+
+    if let Some(auth) = get(root, "auth") {
+        check_keys(auth, "auth", AUTH_KEYS, problems);
+        for (i, user) in sequence(get(auth, "users")).iter().enumerate() {
+            check_keys(user, &index("auth.users", i), USER_KEYS, problems);
+        }
+    }
+}
+
+//
+// ********** VALUES **********
+//
+
+fn check_positive(path: &str, value: u64, problems: &mut Problems) {
+    if value == 0 {
+        problems.add(path, "must be greater than zero");
+    }
+}
+
+fn check_port(path: &str, port: i64, problems: &mut Problems) {
+    if port < 1 || port > 65535 {
+        problems.add(path, format!("port {} is out of range (1-65535)", port));
+    }
+}
+
+fn check_regex(path: &str, regex: &str, problems: &mut Problems) {
+    if let Err(e) = Regex::new(regex) {
+        problems.add(path, format!("invalid regex: {}", e));
+    }
+}
+
+fn check_cluster_reference(path: &str, cluster_id: &ClusterId, config: &Config, problems: &mut Problems) {
+    if config.cluster(cluster_id).is_none() {
+        problems.add(path, format!("unknown cluster '{}'", cluster_id));
+    }
+}
+
+fn check_broker_list(path: &str, broker_list: &[String], problems: &mut Problems) {
+    if broker_list.is_empty() {
+        problems.add(path, "at least one broker is required");
+    }
+    let mut seen = HashSet::new();
+    for (i, broker) in broker_list.iter().enumerate() {
+        let broker_path = index(path, i);
+        match broker.rfind(':') {
+            Some(position) if position > 0 => {
+                match broker[position + 1..].parse::<i64>() {
+                    Ok(port) => check_port(&broker_path, port, problems),
+                    Err(_) => problems.add(broker_path.as_str(), format!("invalid port in '{}'", broker)),
+                }
+            },
+            _ => problems.add(broker_path.as_str(), format!("'{}' is not in the host:port format", broker)),
+        }
+        if !seen.insert(broker.to_lowercase()) {
+            problems.add(broker_path, format!("duplicate broker '{}'", broker));
+        }
+    }
+}
+
+fn check_graph_url(path: &str, graph_url: &str, problems: &mut Problems) {
+    let mut rest = graph_url;
+    let mut found = false;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end + 1,
+            None => break,
+        };
+        let placeholder = &rest[start..end];
+        if placeholder == GRAPH_URL_PLACEHOLDER {
+            found = true;
+        } else {
+            problems.add(path, format!("unknown placeholder '{}', only {} (the topic name) is supported",
+                                       placeholder, GRAPH_URL_PLACEHOLDER));
+        }
+        rest = &rest[end..];
+    }
+    if !found {
+        problems.add(path, format!("missing the {} topic placeholder", GRAPH_URL_PLACEHOLDER));
+    }
+}
+
+fn check_security(path: &str, security: &SecurityConfig, problems: &mut Problems) {
+    let password_sources = [&security.password, &security.password_file, &security.password_env].iter()
+        .filter(|source| source.is_some())
+        .count();
+    if password_sources > 1 {
+        problems.add(path, "only one of password, password_file and password_env can be specified");
+    }
+}
+
+fn check_cluster(path: &str, cluster_config: &ClusterConfig, problems: &mut Problems) {
+    check_broker_list(&join(path, "broker_list"), &cluster_config.broker_list, problems);
+    if cluster_config.zookeeper.trim().is_empty() {
+        problems.add(join(path, "zookeeper"), "can't be empty");
+    }
+    if let Some(port) = cluster_config.jolokia_port {
+        check_port(&join(path, "jolokia_port"), port as i64, problems);
+    }
+    match cluster_config.metrics_source {
+        Some(MetricsSourceConfig::Jolokia { port }) | Some(MetricsSourceConfig::Prometheus { port, .. }) => {
+            check_port(&join(path, "metrics_source.port"), port as i64, problems);
+        },
+        None => {},
+    }
+    if let Some(ref graph_url) = cluster_config.graph_url {
+        check_graph_url(&join(path, "graph_url"), graph_url, problems);
+    }
+    if let Some(ref security) = cluster_config.security {
+        check_security(&join(path, "security"), security, problems);
+    }
+}
+
+fn check_decoder(path: &str, decoder: &DecoderConfig, problems: &mut Problems) {
+    if let DecoderConfig::Avro { ref schema_file, ref registry_url } = *decoder {
+        if schema_file.is_some() == registry_url.is_some() {
+            problems.add(path, "exactly one of schema_file and registry_url is required");
+        }
+    }
+}
+
+fn check_values(config: &Config, problems: &mut Problems) {
+    if config.listen_port == 0 {
+        problems.add("listen_port", "port 0 is out of range (1-65535)");
+    }
+    check_positive("metadata_refresh", config.metadata_refresh, problems);
+    check_positive("metrics_refresh", config.metrics_refresh, problems);
+    check_positive("lag_history_interval", config.lag_history_interval, problems);
+    check_positive("lease_duration", config.lease_duration, problems);
+    check_positive("alerts.evaluation_interval", config.alerts.evaluation_interval, problems);
+
+    let mut cluster_ids = config.clusters.keys().collect::<Vec<_>>();
+    cluster_ids.sort();
+    for cluster_id in cluster_ids {
+        check_cluster(&join("clusters", cluster_id.name()), &config.clusters[cluster_id], problems);
+    }
+
+    let caching = &config.caching;
+    match caching.backend {
+        CachingBackend::Kafka => {
+            match caching.cluster {
+                Some(ref cluster_id) => check_cluster_reference("caching.cluster", cluster_id, config, problems),
+                None => problems.add("caching.cluster", "required by the kafka backend"),
+            }
+            if caching.topic.is_none() {
+                problems.add("caching.topic", "required by the kafka backend");
+            }
+        },
+        CachingBackend::File => {
+            if caching.path.is_none() {
+                problems.add("caching.path", "required by the file backend");
+            }
+            check_positive("caching.compaction_interval", caching.compaction_interval, problems);
+        },
+        CachingBackend::Memory => {},
+    }
+
+    let mut rule_names = HashSet::new();
+    for (i, rule) in config.alerts.rules.iter().enumerate() {
+        let path = index("alerts.rules", i);
+        if !rule_names.insert(&rule.name) {
+            problems.add(join(&path, "name"), format!("duplicate rule name '{}'", rule.name));
+        }
+        check_cluster_reference(&join(&path, "cluster"), &rule.cluster, config, problems);
+        match rule.condition {
+            AlertCondition::PartitionError { topic: Some(ref topic) } |
+            AlertCondition::UnderReplicated { topic: Some(ref topic) } => {
+                check_regex(&join(&path, "condition.topic"), topic, problems);
+            },
+            _ => {},
+        }
+    }
+
+    for (i, rule) in config.decoders.iter().enumerate() {
+        let path = index("decoders", i);
+        if let Some(ref cluster_id) = rule.cluster {
+            check_cluster_reference(&join(&path, "cluster"), cluster_id, config, problems);
+        }
+        check_regex(&join(&path, "topic"), &rule.topic, problems);
+        check_decoder(&join(&path, "key"), &rule.key, problems);
+        check_decoder(&join(&path, "payload"), &rule.payload, problems);
+    }
+
+    if let Some(ref auth) = config.auth {
+        if auth.proxy_role.is_some() && auth.proxy_header.is_none() {
+            problems.add("auth.proxy_role", "requires proxy_header");
+        }
+        let mut user_names = HashSet::new();
+        for (i, user) in auth.users.iter().enumerate() {
+            let path = index("auth.users", i);
+            if !user_names.insert(&user.name) {
+                problems.add(join(&path, "name"), format!("duplicate user name '{}'", user.name));
+            }
+            for (j, cluster_id) in user.clusters.iter().flat_map(|clusters| clusters.iter()).enumerate() {
+                check_cluster_reference(&index(&join(&path, "clusters"), j), cluster_id, config, problems);
+            }
+            if let Some(ref topics) = user.topics {
+                check_regex(&join(&path, "topics"), topics, problems);
+            }
+        }
+    }
+}
+
+/// Validates a configuration already loaded, returning all the problems found.
+pub fn validate(config: &Config) -> Vec<ConfigProblem> {
+    let mut problems = Problems::default();
+    check_values(config, &mut problems);
+    problems.0
+}
+
+fn error_description(error: &Error) -> String {
+    error.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")
+}
+
+/// Validates the configuration, composed from its files and the overrides as in
+/// `read_config`, returning all the problems found. Unknown keys, which are ignored when the
+/// configuration is loaded, are reported as well. Values are only checked if the
+/// configuration can be deserialized.
+pub fn check_config(path: &str, overrides: &[String]) -> Vec<ConfigProblem> {
+    let mut problems = Problems::default();
+    let root = match load_config_value(path, overrides) {
+        Ok((root, _)) => root,
+        Err(e) => {
+            problems.add("", error_description(&e));
+            return problems.0;
+        },
+    };
+    check_unknown_keys(&root, &mut problems);
+
+    match parse_config(&root) {
+        Ok(config) => check_values(&config, &mut problems),
+        Err(e) => problems.add("", error_description(&e)),
+    }
+    problems.0
+}
@@ -0,0 +1,572 @@
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use serde_json::{self, Map, Number, Value};
+
+use decoders::Decoder;
+use error::*;
+use metrics::http_get;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+
+/// Maximum nesting of the decoded values, as for Protobuf messages. Unions and references
+/// count as a level, since each of them is a recursive call as well.
+const MAX_DEPTH: usize = 100;
+
+/// Maximum number of items in an array whose items might be encoded with no bytes at all,
+/// such as nulls: unlike the other items, their number isn't bounded by the message size.
+const MAX_EMPTY_ITEMS: usize = 10000;
+
+#[derive(Debug, Clone)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    Reference(String),  // named type, possibly defined later or recursive
+}
+
+/// A parsed Avro schema, with all the named types it defines.
+#[derive(Debug)]
+pub struct AvroSchema {
+    root: Schema,
+    named: HashMap<String, Schema>,
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => format!("{}.{}", namespace, name),
+        _ => name.to_owned(),
+    }
+}
+
+fn parse_primitive(name: &str) -> Option<Schema> {
+    let schema = match name {
+        "null" => Schema::Null,
+        "boolean" => Schema::Boolean,
+        "int" => Schema::Int,
+        "long" => Schema::Long,
+        "float" => Schema::Float,
+        "double" => Schema::Double,
+        "bytes" => Schema::Bytes,
+        "string" => Schema::String,
+        _ => return None,
+    };
+    Some(schema)
+}
+
+fn parse_schema(value: &Value, namespace: Option<&str>, named: &mut HashMap<String, Schema>) -> Result<Schema> {
+    match *value {
+        Value::String(ref name) => Ok(parse_primitive(name)
+            .unwrap_or_else(|| Schema::Reference(full_name(name, namespace)))),
+        Value::Array(ref variants) => {
+            let variants = variants.iter()
+                .map(|variant| parse_schema(variant, namespace, named))
+                .collect::<Result<Vec<_>>>()?;
+            Ok(Schema::Union(variants))
+        },
+        Value::Object(ref object) => parse_complex_schema(object, namespace, named),
+        _ => bail!("Invalid schema: {}", value),
+    }
+}
+
+fn parse_complex_schema(object: &Map<String, Value>, namespace: Option<&str>, named: &mut HashMap<String, Schema>)
+        -> Result<Schema> {
+    let schema_type = match object.get("type") {
+        Some(&Value::String(ref schema_type)) => schema_type.as_str(),
+        Some(nested) => return parse_schema(nested, namespace, named),
+        None => bail!("Missing type in schema"),
+    };
+    if let Some(primitive) = parse_primitive(schema_type) {
+        return Ok(primitive);  // logical types are shown as their underlying type
+    }
+
+    let name = object.get("name").and_then(Value::as_str)
+        .map(|name| full_name(name, object.get("namespace").and_then(Value::as_str).or(namespace)));
+    // Types nested in a named type inherit its namespace
+    let inner_namespace = name.as_ref()
+        .and_then(|name| name.rfind('.').map(|index| name[..index].to_owned()))
+        .or_else(|| namespace.map(|n| n.to_owned()));
+
+    let schema = match schema_type {
+        "record" | "error" => {
+            let fields = match object.get("fields") {
+                Some(&Value::Array(ref fields)) => fields,
+                _ => bail!("Missing fields in record schema"),
+            };
+            let mut parsed_fields = Vec::with_capacity(fields.len());
+            for field in fields {
+                let field_name = field.get("name").and_then(Value::as_str)
+                    .ok_or_else(|| Error::from("Missing field name in record schema"))?;
+                let field_type = field.get("type")
+                    .ok_or_else(|| Error::from(format!("Missing type for field {}", field_name)))?;
+                let field_schema = parse_schema(field_type, inner_namespace.as_ref().map(|n| n.as_str()), named)?;
+                parsed_fields.push((field_name.to_owned(), field_schema));
+            }
+            Schema::Record(parsed_fields)
+        },
+        "enum" => {
+            let symbols = match object.get("symbols") {
+                Some(&Value::Array(ref symbols)) => symbols.iter()
+                    .map(|symbol| symbol.as_str().unwrap_or("").to_owned())
+                    .collect::<Vec<_>>(),
+                _ => bail!("Missing symbols in enum schema"),
+            };
+            Schema::Enum(symbols)
+        },
+        "array" => {
+            let items = object.get("items").ok_or_else(|| Error::from("Missing items in array schema"))?;
+            Schema::Array(Box::new(parse_schema(items, inner_namespace.as_ref().map(|n| n.as_str()), named)?))
+        },
+        "map" => {
+            let values = object.get("values").ok_or_else(|| Error::from("Missing values in map schema"))?;
+            Schema::Map(Box::new(parse_schema(values, inner_namespace.as_ref().map(|n| n.as_str()), named)?))
+        },
+        "fixed" => {
+            let size = object.get("size").and_then(Value::as_u64)
+                .ok_or_else(|| Error::from("Missing size in fixed schema"))?;
+            Schema::Fixed(size as usize)
+        },
+        _ => bail!("Unknown schema type: {}", schema_type),
+    };
+
+    if let Some(name) = name {
+        named.insert(name, schema.clone());
+    }
+    Ok(schema)
+}
+
+impl AvroSchema {
+    pub fn parse(schema: &str) -> Result<AvroSchema> {
+        let value = serde_json::from_str::<Value>(schema)
+            .chain_err(|| "Invalid JSON in Avro schema")?;
+        let mut named = HashMap::new();
+        let root = parse_schema(&value, None, &mut named)?;
+        Ok(AvroSchema { root, named })
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> Result<Value> {
+        let mut reader = AvroReader { bytes, position: 0 };
+        let value = self.decode_value(&self.root, &mut reader, 0)?;
+        if reader.position != bytes.len() {
+            bail!("{} unexpected bytes after the end of the message", bytes.len() - reader.position);
+        }
+        Ok(value)
+    }
+
+    fn lookup(&self, name: &str) -> Result<&Schema> {
+        // References without namespace might refer to a type defined in any namespace
+        self.named.get(name)
+            .or_else(|| {
+                let short_name = name.rsplit('.').next().unwrap_or(name);
+                self.named.iter()
+                    .find(|&(full_name, _)| full_name.rsplit('.').next() == Some(short_name))
+                    .map(|(_, schema)| schema)
+            })
+            .ok_or_else(|| Error::from(format!("Unknown type {}", name)))
+    }
+
+    /// Returns a lower bound of the number of bytes used to encode a value of the schema.
+    /// References are only followed from the top level schema, so that recursive types
+    /// terminate.
+    fn min_size(&self, schema: &Schema, follow_references: bool) -> usize {
+        match *schema {
+            Schema::Null => 0,
+            Schema::Float => 4,
+            Schema::Double => 8,
+            Schema::Fixed(size) => size,
+            Schema::Record(ref fields) => fields.iter()
+                .fold(0, |size, &(_, ref field)| size.saturating_add(self.min_size(field, follow_references))),
+            Schema::Reference(ref name) if follow_references => self.lookup(name)
+                .map(|referenced| self.min_size(referenced, false))
+                .unwrap_or(0),
+            Schema::Reference(_) => 0,
+            _ => 1,  // booleans, variable length integers, lengths, enum and union indexes
+        }
+    }
+
+    fn decode_value(&self, schema: &Schema, reader: &mut AvroReader, depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("Values nested more than {} levels deep", MAX_DEPTH);
+        }
+        let value = match *schema {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(reader.read_bytes(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::Number(Number::from(reader.read_long()?)),
+            Schema::Float => {
+                let float = LittleEndian::read_f32(reader.read_bytes(4)?);
+                Number::from_f64(f64::from(float)).map(Value::Number).unwrap_or(Value::Null)
+            },
+            Schema::Double => {
+                let double = LittleEndian::read_f64(reader.read_bytes(8)?);
+                Number::from_f64(double).map(Value::Number).unwrap_or(Value::Null)
+            },
+            Schema::Bytes => {
+                let length = reader.read_length()?;
+                Value::String(bytes_to_string(reader.read_bytes(length)?))
+            },
+            Schema::String => {
+                let length = reader.read_length()?;
+                Value::String(String::from_utf8_lossy(reader.read_bytes(length)?).into_owned())
+            },
+            Schema::Record(ref fields) => {
+                let mut record = Map::new();
+                for &(ref name, ref field_schema) in fields {
+                    let field_value = self.decode_value(field_schema, reader, depth + 1)
+                        .chain_err(|| format!("Failed to decode field {}", name))?;
+                    record.insert(name.clone(), field_value);
+                }
+                Value::Object(record)
+            },
+            Schema::Enum(ref symbols) => {
+                let index = reader.read_long()?;
+                match symbols.get(index as usize) {
+                    Some(symbol) => Value::String(symbol.clone()),
+                    None => bail!("Invalid enum index {}", index),
+                }
+            },
+            Schema::Array(ref items) => {
+                let mut array = Vec::new();
+                let item_size = self.min_size(items, true);
+                while let Some(count) = reader.read_block_count(item_size, array.len())? {
+                    for _ in 0..count {
+                        array.push(self.decode_value(items, reader, depth + 1)?);
+                    }
+                }
+                Value::Array(array)
+            },
+            Schema::Map(ref values) => {
+                let mut map = Map::new();
+                let entry_size = self.min_size(values, true).saturating_add(1);  // key length
+                let mut entries = 0;
+                while let Some(count) = reader.read_block_count(entry_size, entries)? {
+                    entries += count;
+                    for _ in 0..count {
+                        let length = reader.read_length()?;
+                        let key = String::from_utf8_lossy(reader.read_bytes(length)?).into_owned();
+                        map.insert(key, self.decode_value(values, reader, depth + 1)?);
+                    }
+                }
+                Value::Object(map)
+            },
+            Schema::Union(ref variants) => {
+                let index = reader.read_long()?;
+                match variants.get(index as usize) {
+                    Some(variant) => self.decode_value(variant, reader, depth + 1)?,
+                    None => bail!("Invalid union index {}", index),
+                }
+            },
+            Schema::Fixed(size) => Value::String(bytes_to_string(reader.read_bytes(size)?)),
+            Schema::Reference(ref name) => {
+                let referenced = self.lookup(name)?;
+                self.decode_value(referenced, reader, depth + 1)?
+            },
+        };
+        Ok(value)
+    }
+}
+
+/// Bytes are shown using the Avro JSON encoding, where each byte is a code point.
+fn bytes_to_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect::<String>()
+}
+
+struct AvroReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> AvroReader<'a> {
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = match self.position.checked_add(length) {
+            Some(end) if end <= self.bytes.len() => end,
+            _ => bail!("Unexpected end of message at byte {}", self.position),
+        };
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    /// Reads a zig-zag encoded variable length integer.
+    fn read_long(&mut self) -> Result<i64> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            if shift >= 64 {
+                bail!("Invalid variable length integer at byte {}", self.position);
+            }
+            let byte = self.read_bytes(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn read_length(&mut self) -> Result<usize> {
+        let length = self.read_long()?;
+        if length < 0 {
+            bail!("Invalid negative length {}", length);
+        }
+        Ok(length as usize)
+    }
+
+    /// Returns the number of items in the next block of an array or map, or `None` at the end.
+    /// The items must fit in the rest of the message, given the minimum size of each item, and
+    /// `read` is the number of items already read from the previous blocks.
+    fn read_block_count(&mut self, item_size: usize, read: usize) -> Result<Option<usize>> {
+        let count = self.read_long()?;
+        let count = if count == 0 {
+            return Ok(None);
+        } else if count < 0 {
+            let _block_size = self.read_long()?;
+            match count.checked_neg() {
+                Some(count) => count as u64,
+                None => bail!("Invalid block count {}", count),
+            }
+        } else {
+            count as u64
+        };
+        let max_count = if item_size == 0 {
+            MAX_EMPTY_ITEMS.saturating_sub(read)
+        } else {
+            (self.bytes.len() - self.position) / item_size
+        };
+        if count > max_count as u64 {
+            bail!("Block of {} items at byte {} exceeds the size of the message", count, self.position);
+        }
+        Ok(Some(count as usize))
+    }
+}
+
+//
+// ********** DECODER **********
+//
+
+enum SchemaSource {
+    File(AvroSchema),
+    Registry {
+        url: String,
+        schemas: Mutex<HashMap<u32, Arc<AvroSchema>>>,
+    },
+}
+
+/// Decodes Avro messages to pretty printed JSON. If a schema registry is configured, the
+/// messages are expected in the registry wire format: a zero byte, the id of the schema as a
+/// four bytes big endian integer, then the Avro binary encoded data.
+pub struct AvroDecoder {
+    source: SchemaSource,
+}
+
+fn fetch_registry_schema(url: &str, id: u32) -> Result<AvroSchema> {
+    let schema_url = format!("{}/schemas/ids/{}", url.trim_right_matches('/'), id);
+    let response = http_get(&schema_url)
+        .chain_err(|| format!("Failed to fetch schema {} from {}", id, url))?;
+    let value = serde_json::from_str::<Value>(&response)
+        .chain_err(|| "Invalid schema registry response")?;
+    match value.get("schema").and_then(Value::as_str) {
+        Some(schema) => AvroSchema::parse(schema),
+        None => bail!("Schema {} not found in registry: {}", id, response),
+    }
+}
+
+impl AvroDecoder {
+    pub fn new(schema_file: &Option<String>, registry_url: &Option<String>) -> Result<AvroDecoder> {
+        let source = match (schema_file, registry_url) {
+            (_, &Some(ref url)) => SchemaSource::Registry { url: url.clone(), schemas: Mutex::new(HashMap::new()) },
+            (&Some(ref path), &None) => {
+                let mut schema = String::new();
+                File::open(path)
+                    .and_then(|mut f| f.read_to_string(&mut schema))
+                    .chain_err(|| format!("Unable to read Avro schema file {}", path))?;
+                let schema = AvroSchema::parse(&schema)
+                    .chain_err(|| format!("Invalid Avro schema in {}", path))?;
+                SchemaSource::File(schema)
+            },
+            (&None, &None) => bail!("Either schema_file or registry_url should be specified for Avro decoding"),
+        };
+        Ok(AvroDecoder { source })
+    }
+
+    fn registry_schema(&self, url: &str, schemas: &Mutex<HashMap<u32, Arc<AvroSchema>>>, id: u32) -> Result<Arc<AvroSchema>> {
+        if let Some(schema) = schemas.lock().expect("Poison error").get(&id) {
+            return Ok(schema.clone());
+        }
+        // The lock is not held during the request; concurrent fetches of the same id are harmless.
+        let schema = Arc::new(fetch_registry_schema(url, id)?);
+        schemas.lock().expect("Poison error").insert(id, schema.clone());
+        Ok(schema)
+    }
+}
+
+impl Decoder for AvroDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        let value = match self.source {
+            SchemaSource::File(ref schema) => schema.decode(bytes)?,
+            SchemaSource::Registry { ref url, ref schemas } => {
+                if bytes.len() < 5 || bytes[0] != 0 {
+                    bail!("Message is not in the schema registry format");
+                }
+                let schema = self.registry_schema(url, schemas, BigEndian::read_u32(&bytes[1..5]))?;
+                schema.decode(&bytes[5..])?
+            },
+        };
+        serde_json::to_string_pretty(&value).chain_err(|| "Failed to format JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_SCHEMA: &str = r#"{
+        "type": "record", "name": "User", "namespace": "test",
+        "fields": [
+            {"name": "name", "type": "string"},
+            {"name": "age", "type": "int"},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "score", "type": ["null", "double"]},
+            {"name": "color", "type": {"type": "enum", "name": "Color", "symbols": ["RED", "GREEN"]}}
+        ]
+    }"#;
+
+    fn user_bytes() -> Vec<u8> {
+        let mut bytes = vec![
+            0x04, b'a', b'b',  // name
+            0x2a,  // age
+            0x02, 0x02, b'x', 0x00,  // tags
+            0x02,  // score, second branch of the union
+        ];
+        let mut score = [0u8; 8];
+        LittleEndian::write_f64(&mut score, 1.5);
+        bytes.extend_from_slice(&score);
+        bytes.push(0x02);  // color
+        bytes
+    }
+
+    #[test]
+    fn decodes_record() {
+        let schema = AvroSchema::parse(USER_SCHEMA).unwrap();
+        let expected = json!({"name": "ab", "age": 21, "tags": ["x"], "score": 1.5, "color": "GREEN"});
+        assert_eq!(schema.decode(&user_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_input() {
+        let schema = AvroSchema::parse(USER_SCHEMA).unwrap();
+        let bytes = user_bytes();
+        assert!(schema.decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(schema.decode(&bytes[..3]).is_err());
+        let mut trailing = bytes.clone();
+        trailing.push(0x00);
+        assert!(schema.decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn decodes_blocks_with_size() {
+        let schema = AvroSchema::parse(r#"{"type": "map", "values": "long"}"#).unwrap();
+        let bytes = [0x01, 0x08, 0x02, b'k', 0x06, 0x00];  // one item block of 4 bytes, then the end
+        assert_eq!(schema.decode(&bytes).unwrap(), json!({"k": 3}));
+    }
+
+    #[test]
+    fn decodes_recursive_type() {
+        let schema = AvroSchema::parse(NODE_SCHEMA).unwrap();
+        let bytes = [0x02, 0x02, 0x04, 0x00];
+        assert_eq!(schema.decode(&bytes).unwrap(), json!({"value": 1, "next": {"value": 2, "next": null}}));
+    }
+
+    const NODE_SCHEMA: &str = r#"{
+        "type": "record", "name": "Node",
+        "fields": [{"name": "value", "type": "int"}, {"name": "next", "type": ["null", "Node"]}]
+    }"#;
+
+    /// A linked list of `length` nodes.
+    fn nested(length: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for _ in 1..length {
+            bytes.extend_from_slice(&[0x02, 0x02]);  // value, then the Node branch of the union
+        }
+        bytes.extend_from_slice(&[0x02, 0x00]);
+        bytes
+    }
+
+    /// Zig-zag encodes a variable length integer.
+    fn long(value: i64) -> Vec<u8> {
+        let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+        let mut bytes = Vec::new();
+        while zigzag >= 0x80 {
+            bytes.push((zigzag & 0x7f) as u8 | 0x80);
+            zigzag >>= 7;
+        }
+        bytes.push(zigzag as u8);
+        bytes
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let schema = AvroSchema::parse(NODE_SCHEMA).unwrap();
+        assert!(schema.decode(&nested(10)).is_ok());
+        assert!(schema.decode(&nested(MAX_DEPTH / 3 + 10)).is_err());  // record, union and reference
+        assert!(schema.decode(&nested(100_000)).is_err());
+    }
+
+    #[test]
+    fn rejects_block_counts_exceeding_the_message() {
+        let longs = AvroSchema::parse(r#"{"type": "array", "items": "long"}"#).unwrap();
+        assert_eq!(longs.decode(&[0x04, 0x02, 0x04, 0x00]).unwrap(), json!([1, 2]));
+        assert!(longs.decode(&[0x06, 0x02, 0x04, 0x00]).is_err());
+        let mut huge = long(1 << 40);
+        huge.push(0x00);
+        assert!(longs.decode(&huge).is_err());
+        let mut min = long(i64::min_value());
+        min.extend_from_slice(&[0x00, 0x00]);
+        assert!(longs.decode(&min).is_err());
+
+        let nodes = AvroSchema::parse(&format!(r#"{{"type": "map", "values": {}}}"#, NODE_SCHEMA)).unwrap();
+        let mut huge = long(1 << 40);
+        huge.extend_from_slice(&[0x02, b'k', 0x02, 0x00, 0x00]);
+        assert!(nodes.decode(&huge).is_err());
+    }
+
+    #[test]
+    fn caps_empty_items() {
+        let nulls = AvroSchema::parse(r#"{"type": "array", "items": "null"}"#).unwrap();
+        let mut bytes = long(3);
+        bytes.push(0x00);
+        assert_eq!(nulls.decode(&bytes).unwrap(), json!([null, null, null]));
+        let mut bytes = long(1 << 40);
+        bytes.push(0x00);
+        assert!(nulls.decode(&bytes).is_err());
+
+        // Many blocks, each of them within the limit
+        let empty = AvroSchema::parse(r#"{"type": "array", "items": {"type": "record", "name": "E", "fields": []}}"#).unwrap();
+        let mut bytes = Vec::new();
+        for _ in 0..100 {
+            bytes.extend(long(MAX_EMPTY_ITEMS as i64 / 10));
+        }
+        bytes.push(0x00);
+        assert!(empty.decode(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_schema() {
+        assert!(AvroSchema::parse(r#"{"type": "record", "name": "Empty"}"#).is_err());
+        assert!(AvroSchema::parse(r#"{"type": "unknown"}"#).is_err());
+        assert!(AvroSchema::parse("not json").is_err());
+    }
+}
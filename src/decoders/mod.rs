@@ -0,0 +1,103 @@
+mod avro;
+mod proto;
+mod text;
+
+use regex::Regex;
+
+use config::{Config, DecoderConfig};
+use error::*;
+use metadata::ClusterId;
+use utils::error_chain_message;
+
+use std::sync::Arc;
+
+
+/// Converts the raw bytes of a message key or payload to a human readable string.
+pub trait Decoder: Send + Sync {
+    fn decode(&self, bytes: &[u8]) -> Result<String>;
+}
+
+pub fn new_decoder(config: &DecoderConfig) -> Result<Box<Decoder>> {
+    let decoder: Box<Decoder> = match *config {
+        DecoderConfig::Utf8 => Box::new(text::Utf8Decoder),
+        DecoderConfig::Json => Box::new(text::JsonDecoder),
+        DecoderConfig::Hex => Box::new(text::HexDecoder),
+        DecoderConfig::Base64 => Box::new(text::Base64Decoder),
+        DecoderConfig::Avro { ref schema_file, ref registry_url } =>
+            Box::new(avro::AvroDecoder::new(schema_file, registry_url)?),
+        DecoderConfig::Protobuf { ref descriptor_set, ref message_type } =>
+            Box::new(proto::ProtobufDecoder::new(descriptor_set, message_type)?),
+    };
+    Ok(decoder)
+}
+
+/// Decodes the bytes, reporting the error in the output if decoding fails.
+fn decode_or_report(decoder: &Decoder, bytes: &[u8]) -> String {
+    match decoder.decode(bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => format!("[decode error: {}] {}", error_chain_message(&e), text::hex_preview(bytes, 64)),
+    }
+}
+
+/// The decoders to be used for the keys and payloads of a topic.
+#[derive(Clone)]
+pub struct TopicDecoders {
+    key: Arc<Decoder>,
+    payload: Arc<Decoder>,
+}
+
+impl TopicDecoders {
+    pub fn decode_key(&self, key: Option<&[u8]>) -> Option<String> {
+        key.map(|bytes| decode_or_report(&*self.key, bytes))
+    }
+
+    pub fn decode_payload(&self, payload: Option<&[u8]>) -> Option<String> {
+        payload.map(|bytes| decode_or_report(&*self.payload, bytes))
+    }
+}
+
+struct DecoderRule {
+    cluster_id: Option<ClusterId>,
+    topic: Regex,
+    decoders: TopicDecoders,
+}
+
+/// Contains the decoders for all the rules in the configuration. The first rule matching the
+/// topic is used, and topics matching no rule are decoded as UTF-8.
+pub struct DecoderRegistry {
+    rules: Vec<DecoderRule>,
+    default: TopicDecoders,
+}
+
+impl DecoderRegistry {
+    pub fn new(config: &Config) -> Result<DecoderRegistry> {
+        let mut rules = Vec::new();
+        for rule in &config.decoders {
+            let topic = Regex::new(&rule.topic)
+                .chain_err(|| format!("Invalid decoder topic regex: {}", rule.topic))?;
+            let key = new_decoder(&rule.key)
+                .chain_err(|| format!("Failed to create key decoder for {}", rule.topic))?;
+            let payload = new_decoder(&rule.payload)
+                .chain_err(|| format!("Failed to create payload decoder for {}", rule.topic))?;
+            rules.push(DecoderRule {
+                cluster_id: rule.cluster.clone(),
+                topic,
+                decoders: TopicDecoders { key: Arc::from(key), payload: Arc::from(payload) },
+            });
+        }
+        let utf8 = Arc::new(text::Utf8Decoder) as Arc<Decoder>;
+        Ok(DecoderRegistry {
+            rules,
+            default: TopicDecoders { key: utf8.clone(), payload: utf8 },
+        })
+    }
+
+    pub fn for_topic(&self, cluster_id: &ClusterId, topic: &str) -> TopicDecoders {
+        self.rules.iter()
+            .find(|rule| {
+                rule.cluster_id.as_ref().map(|c| c == cluster_id).unwrap_or(true) && rule.topic.is_match(topic)
+            })
+            .map(|rule| rule.decoders.clone())
+            .unwrap_or_else(|| self.default.clone())
+    }
+}
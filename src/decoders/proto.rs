@@ -0,0 +1,420 @@
+use byteorder::{ByteOrder, LittleEndian};
+use protobuf;
+use protobuf::descriptor::{DescriptorProto, EnumDescriptorProto, FieldDescriptorProto,
+                           FieldDescriptorProto_Label, FieldDescriptorProto_Type, FileDescriptorSet};
+use serde_json::{self, Map, Number, Value};
+
+use decoders::Decoder;
+use error::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+
+/// Message and enum types defined in a descriptor set, indexed by their fully qualified name.
+struct TypeIndex {
+    messages: HashMap<String, DescriptorProto>,
+    enums: HashMap<String, HashMap<i32, String>>,
+}
+
+impl TypeIndex {
+    fn new(descriptor_set: &FileDescriptorSet) -> TypeIndex {
+        let mut index = TypeIndex { messages: HashMap::new(), enums: HashMap::new() };
+        for file in descriptor_set.get_file() {
+            let prefix = if file.get_package().is_empty() {
+                String::new()
+            } else {
+                format!(".{}", file.get_package())
+            };
+            for message in file.get_message_type() {
+                index.add_message(&prefix, message);
+            }
+            for enum_type in file.get_enum_type() {
+                index.add_enum(&prefix, enum_type);
+            }
+        }
+        index
+    }
+
+    fn add_message(&mut self, prefix: &str, message: &DescriptorProto) {
+        let name = format!("{}.{}", prefix, message.get_name());
+        for nested in message.get_nested_type() {
+            self.add_message(&name, nested);
+        }
+        for enum_type in message.get_enum_type() {
+            self.add_enum(&name, enum_type);
+        }
+        self.messages.insert(name, message.clone());
+    }
+
+    fn add_enum(&mut self, prefix: &str, enum_type: &EnumDescriptorProto) {
+        let values = enum_type.get_value().iter()
+            .map(|value| (value.get_number(), value.get_name().to_owned()))
+            .collect::<HashMap<_, _>>();
+        self.enums.insert(format!("{}.{}", prefix, enum_type.get_name()), values);
+    }
+}
+
+/// A single field value read from the wire, before being interpreted using the descriptor.
+enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    LengthDelimited(&'a [u8]),
+}
+
+struct WireReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> WireReader<'a> {
+    fn new(bytes: &'a [u8]) -> WireReader<'a> {
+        WireReader { bytes, position: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = match self.position.checked_add(length) {
+            Some(end) if end <= self.bytes.len() => end,
+            _ => bail!("Unexpected end of message at byte {}", self.position),
+        };
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            if shift >= 64 {
+                bail!("Invalid varint at byte {}", self.position);
+            }
+            let byte = self.read_bytes(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn read_field(&mut self) -> Result<(u32, WireValue<'a>)> {
+        let tag = self.read_varint()?;
+        let value = match tag & 0x7 {
+            0 => WireValue::Varint(self.read_varint()?),
+            1 => WireValue::Fixed64(LittleEndian::read_u64(self.read_bytes(8)?)),
+            2 => {
+                let length = self.read_varint()? as usize;
+                WireValue::LengthDelimited(self.read_bytes(length)?)
+            },
+            5 => WireValue::Fixed32(LittleEndian::read_u32(self.read_bytes(4)?)),
+            wire_type => bail!("Unsupported wire type {} at byte {}", wire_type, self.position),
+        };
+        Ok(((tag >> 3) as u32, value))
+    }
+}
+
+fn float_value(value: f64) -> Value {
+    Number::from_f64(value).map(Value::Number).unwrap_or(Value::Null)
+}
+
+fn is_packable(field_type: FieldDescriptorProto_Type) -> bool {
+    match field_type {
+        FieldDescriptorProto_Type::TYPE_STRING | FieldDescriptorProto_Type::TYPE_BYTES |
+        FieldDescriptorProto_Type::TYPE_MESSAGE | FieldDescriptorProto_Type::TYPE_GROUP => false,
+        _ => true,
+    }
+}
+
+/// Maximum nesting of the decoded messages, as in the recursion limit of the Protobuf library.
+const MAX_DEPTH: usize = 100;
+
+/// Decodes Protobuf messages to pretty printed JSON, using the message type found in a
+/// descriptor set. Unknown fields are shown using their field number.
+pub struct ProtobufDecoder {
+    types: TypeIndex,
+    message_type: String,
+}
+
+impl ProtobufDecoder {
+    pub fn new(descriptor_set_path: &str, message_type: &str) -> Result<ProtobufDecoder> {
+        let mut bytes = Vec::new();
+        File::open(descriptor_set_path)
+            .and_then(|mut f| f.read_to_end(&mut bytes))
+            .chain_err(|| format!("Unable to read descriptor set {}", descriptor_set_path))?;
+        let descriptor_set = protobuf::parse_from_bytes::<FileDescriptorSet>(&bytes)
+            .chain_err(|| format!("Invalid descriptor set {}", descriptor_set_path))?;
+        ProtobufDecoder::from_descriptor_set(&descriptor_set, message_type)
+            .chain_err(|| format!("Invalid message type for {}", descriptor_set_path))
+    }
+
+    fn from_descriptor_set(descriptor_set: &FileDescriptorSet, message_type: &str) -> Result<ProtobufDecoder> {
+        let types = TypeIndex::new(descriptor_set);
+        let message_type = if message_type.starts_with('.') {
+            message_type.to_owned()
+        } else {
+            format!(".{}", message_type)
+        };
+        if !types.messages.contains_key(&message_type) {
+            bail!("Message type {} not found", message_type);
+        }
+
+        Ok(ProtobufDecoder { types, message_type })
+    }
+
+    fn decode_message(&self, type_name: &str, bytes: &[u8], depth: usize) -> Result<Value> {
+        if depth > MAX_DEPTH {
+            bail!("Messages nested more than {} levels deep", MAX_DEPTH);
+        }
+        let descriptor = self.types.messages.get(type_name)
+            .ok_or_else(|| Error::from(format!("Unknown message type {}", type_name)))?;
+        let fields = descriptor.get_field().iter()
+            .map(|field| (field.get_number() as u32, field))
+            .collect::<HashMap<_, _>>();
+
+        let mut output = Map::new();
+        let mut reader = WireReader::new(bytes);
+        while !reader.is_empty() {
+            let (number, wire_value) = reader.read_field()?;
+            let field = match fields.get(&number) {
+                Some(field) => *field,
+                None => {
+                    let value = self.decode_unknown(wire_value);
+                    append_value(&mut output, number.to_string(), value, true);
+                    continue;
+                },
+            };
+            let repeated = field.get_label() == FieldDescriptorProto_Label::LABEL_REPEATED;
+            match wire_value {
+                WireValue::LengthDelimited(packed) if repeated && is_packable(field.get_field_type()) => {
+                    let mut packed_reader = WireReader::new(packed);
+                    while !packed_reader.is_empty() {
+                        let element = self.read_packed_element(field, &mut packed_reader)?;
+                        let value = self.decode_field(field, element, depth)?;
+                        append_value(&mut output, field.get_name().to_owned(), value, true);
+                    }
+                },
+                wire_value => {
+                    let value = self.decode_field(field, wire_value, depth)
+                        .chain_err(|| format!("Failed to decode field {}", field.get_name()))?;
+                    append_value(&mut output, field.get_name().to_owned(), value, repeated);
+                },
+            }
+        }
+        Ok(Value::Object(output))
+    }
+
+    fn read_packed_element<'a>(&self, field: &FieldDescriptorProto, reader: &mut WireReader<'a>) -> Result<WireValue<'a>> {
+        let value = match field.get_field_type() {
+            FieldDescriptorProto_Type::TYPE_DOUBLE | FieldDescriptorProto_Type::TYPE_FIXED64 |
+            FieldDescriptorProto_Type::TYPE_SFIXED64 => WireValue::Fixed64(LittleEndian::read_u64(reader.read_bytes(8)?)),
+            FieldDescriptorProto_Type::TYPE_FLOAT | FieldDescriptorProto_Type::TYPE_FIXED32 |
+            FieldDescriptorProto_Type::TYPE_SFIXED32 => WireValue::Fixed32(LittleEndian::read_u32(reader.read_bytes(4)?)),
+            _ => WireValue::Varint(reader.read_varint()?),
+        };
+        Ok(value)
+    }
+
+    fn decode_field(&self, field: &FieldDescriptorProto, wire_value: WireValue, depth: usize) -> Result<Value> {
+        let value = match (field.get_field_type(), wire_value) {
+            (FieldDescriptorProto_Type::TYPE_DOUBLE, WireValue::Fixed64(v)) => float_value(f64::from_bits(v)),
+            (FieldDescriptorProto_Type::TYPE_FLOAT, WireValue::Fixed32(v)) => float_value(f64::from(f32::from_bits(v))),
+            (FieldDescriptorProto_Type::TYPE_INT64, WireValue::Varint(v)) => json!(v as i64),
+            (FieldDescriptorProto_Type::TYPE_UINT64, WireValue::Varint(v)) => json!(v),
+            (FieldDescriptorProto_Type::TYPE_INT32, WireValue::Varint(v)) => json!(v as i32),
+            (FieldDescriptorProto_Type::TYPE_UINT32, WireValue::Varint(v)) => json!(v as u32),
+            (FieldDescriptorProto_Type::TYPE_SINT32, WireValue::Varint(v)) => json!(((v >> 1) as i64 ^ -((v & 1) as i64)) as i32),
+            (FieldDescriptorProto_Type::TYPE_SINT64, WireValue::Varint(v)) => json!((v >> 1) as i64 ^ -((v & 1) as i64)),
+            (FieldDescriptorProto_Type::TYPE_FIXED64, WireValue::Fixed64(v)) => json!(v),
+            (FieldDescriptorProto_Type::TYPE_SFIXED64, WireValue::Fixed64(v)) => json!(v as i64),
+            (FieldDescriptorProto_Type::TYPE_FIXED32, WireValue::Fixed32(v)) => json!(v),
+            (FieldDescriptorProto_Type::TYPE_SFIXED32, WireValue::Fixed32(v)) => json!(v as i32),
+            (FieldDescriptorProto_Type::TYPE_BOOL, WireValue::Varint(v)) => json!(v != 0),
+            (FieldDescriptorProto_Type::TYPE_ENUM, WireValue::Varint(v)) => {
+                match self.types.enums.get(field.get_type_name()).and_then(|values| values.get(&(v as i32))) {
+                    Some(name) => json!(name),
+                    None => json!(v as i32),
+                }
+            },
+            (FieldDescriptorProto_Type::TYPE_STRING, WireValue::LengthDelimited(bytes)) =>
+                json!(String::from_utf8_lossy(bytes)),
+            (FieldDescriptorProto_Type::TYPE_BYTES, WireValue::LengthDelimited(bytes)) =>
+                json!(bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()),
+            (FieldDescriptorProto_Type::TYPE_MESSAGE, WireValue::LengthDelimited(bytes)) =>
+                self.decode_message(field.get_type_name(), bytes, depth + 1)?,
+            (field_type, _) => bail!("Unexpected wire type for field of type {:?}", field_type),
+        };
+        Ok(value)
+    }
+
+    fn decode_unknown(&self, wire_value: WireValue) -> Value {
+        match wire_value {
+            WireValue::Varint(v) | WireValue::Fixed64(v) => json!(v),
+            WireValue::Fixed32(v) => json!(v),
+            WireValue::LengthDelimited(bytes) => json!(String::from_utf8_lossy(bytes)),
+        }
+    }
+}
+
+fn append_value(output: &mut Map<String, Value>, name: String, value: Value, repeated: bool) {
+    if !repeated {
+        output.insert(name, value);  // last value wins, as in the Protobuf specification
+        return;
+    }
+    if let Some(&mut Value::Array(ref mut values)) = output.get_mut(&name) {
+        values.push(value);
+        return;
+    }
+    output.insert(name, Value::Array(vec![value]));
+}
+
+impl Decoder for ProtobufDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        let value = self.decode_message(&self.message_type, bytes, 0)?;
+        serde_json::to_string_pretty(&value).chain_err(|| "Failed to format JSON")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::RepeatedField;
+    use protobuf::descriptor::{EnumValueDescriptorProto, FileDescriptorProto};
+
+    fn field(name: &str, number: i32, field_type: FieldDescriptorProto_Type, label: FieldDescriptorProto_Label,
+             type_name: &str) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::new();
+        field.set_name(name.to_owned());
+        field.set_number(number);
+        field.set_field_type(field_type);
+        field.set_label(label);
+        if !type_name.is_empty() {
+            field.set_type_name(type_name.to_owned());
+        }
+        field
+    }
+
+    fn message(name: &str, fields: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        let mut message = DescriptorProto::new();
+        message.set_name(name.to_owned());
+        message.set_field(RepeatedField::from_vec(fields));
+        message
+    }
+
+    /// package test;
+    /// enum Color { RED = 0; GREEN = 1; }
+    /// message Inner { int32 value = 1; }
+    /// message Outer { string name = 1; repeated int32 ids = 2; Inner inner = 3; Color color = 4; sint64 delta = 5; }
+    /// message Node { Node child = 1; }
+    fn decoder(message_type: &str) -> ProtobufDecoder {
+        use self::FieldDescriptorProto_Label::*;
+        use self::FieldDescriptorProto_Type::*;
+
+        let mut color = EnumDescriptorProto::new();
+        color.set_name("Color".to_owned());
+        let values = [("RED", 0), ("GREEN", 1)].iter()
+            .map(|&(name, number)| {
+                let mut value = EnumValueDescriptorProto::new();
+                value.set_name(name.to_owned());
+                value.set_number(number);
+                value
+            })
+            .collect();
+        color.set_value(RepeatedField::from_vec(values));
+
+        let messages = vec![
+            message("Inner", vec![field("value", 1, TYPE_INT32, LABEL_OPTIONAL, "")]),
+            message("Outer", vec![
+                field("name", 1, TYPE_STRING, LABEL_OPTIONAL, ""),
+                field("ids", 2, TYPE_INT32, LABEL_REPEATED, ""),
+                field("inner", 3, TYPE_MESSAGE, LABEL_OPTIONAL, ".test.Inner"),
+                field("color", 4, TYPE_ENUM, LABEL_OPTIONAL, ".test.Color"),
+                field("delta", 5, TYPE_SINT64, LABEL_OPTIONAL, ""),
+            ]),
+            message("Node", vec![field("child", 1, TYPE_MESSAGE, LABEL_OPTIONAL, ".test.Node")]),
+        ];
+        let mut file = FileDescriptorProto::new();
+        file.set_package("test".to_owned());
+        file.set_message_type(RepeatedField::from_vec(messages));
+        file.set_enum_type(RepeatedField::from_vec(vec![color]));
+        let mut descriptor_set = FileDescriptorSet::new();
+        descriptor_set.set_file(RepeatedField::from_vec(vec![file]));
+
+        ProtobufDecoder::from_descriptor_set(&descriptor_set, message_type).unwrap()
+    }
+
+    fn decode(decoder: &ProtobufDecoder, bytes: &[u8]) -> Result<Value> {
+        serde_json::from_str(&decoder.decode(bytes)?).chain_err(|| "Invalid JSON")
+    }
+
+    fn varint(mut value: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    #[test]
+    fn decodes_message() {
+        let bytes = [
+            0x0a, 0x02, b'a', b'b',  // name
+            0x12, 0x03, 0x01, 0xac, 0x02,  // packed ids
+            0x1a, 0x02, 0x08, 0x07,  // inner
+            0x20, 0x01,  // color
+            0x28, 0x03,  // delta
+            0x48, 0x05,  // unknown field 9
+        ];
+        let expected = json!({
+            "name": "ab", "ids": [1, 300], "inner": {"value": 7}, "color": "GREEN", "delta": -2, "9": [5]
+        });
+        assert_eq!(decode(&decoder("test.Outer"), &bytes).unwrap(), expected);
+    }
+
+    #[test]
+    fn decodes_unpacked_repeated_field() {
+        let bytes = [0x10, 0x01, 0x10, 0x02, 0x12, 0x01, 0x03];
+        assert_eq!(decode(&decoder("test.Outer"), &bytes).unwrap(), json!({"ids": [1, 2, 3]}));
+    }
+
+    #[test]
+    fn rejects_unknown_message_type() {
+        let descriptor_set = FileDescriptorSet::new();
+        assert!(ProtobufDecoder::from_descriptor_set(&descriptor_set, "test.Missing").is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        let decoder = decoder("test.Outer");
+        assert!(decode(&decoder, &[0x0a, 0x05, b'a', b'b']).is_err());
+        assert!(decode(&decoder, &[0x12, 0x02, 0xac]).is_err());
+        assert!(decode(&decoder, &[0x20]).is_err());
+    }
+
+    #[test]
+    fn rejects_overflowing_length() {
+        let bytes = [0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(decode(&decoder("test.Outer"), &bytes).is_err());
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth: usize| (0..depth).fold(Vec::new(), |child, _| {
+            let mut bytes = vec![0x0a];
+            bytes.extend(varint(child.len()));
+            bytes.extend(child);
+            bytes
+        });
+        let decoder = decoder("test.Node");
+        assert!(decode(&decoder, &nested(10)).is_ok());
+        assert!(decode(&decoder, &nested(MAX_DEPTH + 10)).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use base64;
+use serde_json::{self, Value};
+
+use decoders::Decoder;
+use error::*;
+
+use std::fmt::Write;
+
+
+pub struct Utf8Decoder;
+
+impl Decoder for Utf8Decoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+}
+
+pub struct JsonDecoder;
+
+impl Decoder for JsonDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        let value = serde_json::from_slice::<Value>(bytes)
+            .chain_err(|| "Invalid JSON")?;
+        serde_json::to_string_pretty(&value)
+            .chain_err(|| "Failed to format JSON")
+    }
+}
+
+pub struct HexDecoder;
+
+impl Decoder for HexDecoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        Ok(hex_dump(bytes))
+    }
+}
+
+pub struct Base64Decoder;
+
+impl Decoder for Base64Decoder {
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        Ok(base64::encode(bytes))
+    }
+}
+
+/// Formats the bytes as lines of offset, hex values and printable characters.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut output = String::with_capacity(bytes.len() * 4 + 16);
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let _ = write!(output, "{:08x} ", line * 16);
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(byte) => { let _ = write!(output, " {:02x}", byte); },
+                None => output.push_str("   "),
+            }
+        }
+        output.push_str("  |");
+        for &byte in chunk {
+            output.push(if byte >= 0x20 && byte < 0x7f { byte as char } else { '.' });
+        }
+        output.push_str("|\n");
+    }
+    output
+}
+
+/// Hex representation of the first `max_bytes` bytes, on a single line.
+pub fn hex_preview(bytes: &[u8], max_bytes: usize) -> String {
+    let mut output = bytes.iter()
+        .take(max_bytes)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if bytes.len() > max_bytes {
+        output.push_str(" ...");
+    }
+    output
+}
@@ -0,0 +1,274 @@
+use rdkafka::util::millis_to_epoch;
+
+use cache::{Cache, EventCache};
+use config::Config;
+use error::*;
+use metadata::{Broker, BrokerId, ClusterId, Group, Partition, TopicName};
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+
+/// A change in the metadata of a cluster, detected comparing two consecutive metadata fetches.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    TopicCreated { topic: TopicName, partitions: usize },
+    TopicDeleted { topic: TopicName },
+    PartitionsAdded { topic: TopicName, from: usize, to: usize },
+    LeaderChanged { topic: TopicName, partition: i32, from: BrokerId, to: BrokerId },
+    IsrShrank { topic: TopicName, partition: i32, removed: Vec<BrokerId> },
+    IsrExpanded { topic: TopicName, partition: i32, added: Vec<BrokerId> },
+    BrokerJoined { broker: BrokerId, hostname: String },
+    BrokerLeft { broker: BrokerId },
+    GroupStateChanged { group: String, from: String, to: String },
+}
+
+impl ChangeEvent {
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            ChangeEvent::TopicCreated { .. } => "topic_created",
+            ChangeEvent::TopicDeleted { .. } => "topic_deleted",
+            ChangeEvent::PartitionsAdded { .. } => "partitions_added",
+            ChangeEvent::LeaderChanged { .. } => "leader_changed",
+            ChangeEvent::IsrShrank { .. } => "isr_shrank",
+            ChangeEvent::IsrExpanded { .. } => "isr_expanded",
+            ChangeEvent::BrokerJoined { .. } => "broker_joined",
+            ChangeEvent::BrokerLeft { .. } => "broker_left",
+            ChangeEvent::GroupStateChanged { .. } => "group_state_changed",
+        }
+    }
+
+    /// The topic affected by the change, if any.
+    pub fn topic(&self) -> Option<&str> {
+        match *self {
+            ChangeEvent::TopicCreated { ref topic, .. } |
+            ChangeEvent::TopicDeleted { ref topic } |
+            ChangeEvent::PartitionsAdded { ref topic, .. } |
+            ChangeEvent::LeaderChanged { ref topic, .. } |
+            ChangeEvent::IsrShrank { ref topic, .. } |
+            ChangeEvent::IsrExpanded { ref topic, .. } => Some(topic),
+            _ => None,
+        }
+    }
+
+    /// Name of the topic, broker or group affected by the change.
+    pub fn target(&self) -> String {
+        match *self {
+            ChangeEvent::TopicCreated { ref topic, .. } |
+            ChangeEvent::TopicDeleted { ref topic } |
+            ChangeEvent::PartitionsAdded { ref topic, .. } => topic.clone(),
+            ChangeEvent::LeaderChanged { ref topic, partition, .. } |
+            ChangeEvent::IsrShrank { ref topic, partition, .. } |
+            ChangeEvent::IsrExpanded { ref topic, partition, .. } => format!("{}/{}", topic, partition),
+            ChangeEvent::BrokerJoined { broker, .. } |
+            ChangeEvent::BrokerLeft { broker } => format!("broker {}", broker),
+            ChangeEvent::GroupStateChanged { ref group, .. } => group.clone(),
+        }
+    }
+
+    /// Human readable description of the change.
+    pub fn description(&self) -> String {
+        match *self {
+            ChangeEvent::TopicCreated { partitions, .. } => format!("Topic created with {} partitions", partitions),
+            ChangeEvent::TopicDeleted { .. } => "Topic deleted".to_owned(),
+            ChangeEvent::PartitionsAdded { from, to, .. } => format!("Partitions increased from {} to {}", from, to),
+            ChangeEvent::LeaderChanged { from, to, .. } => format!("Leader changed from {} to {}", from, to),
+            ChangeEvent::IsrShrank { ref removed, .. } => format!("Replicas {:?} left the ISR", removed),
+            ChangeEvent::IsrExpanded { ref added, .. } => format!("Replicas {:?} joined the ISR", added),
+            ChangeEvent::BrokerJoined { ref hostname, .. } => format!("Broker joined ({})", hostname),
+            ChangeEvent::BrokerLeft { .. } => "Broker left".to_owned(),
+            ChangeEvent::GroupStateChanged { ref from, ref to, .. } => format!("State changed from {} to {}", from, to),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ClusterEvent {
+    pub timestamp: u64,  // millis since epoch
+    pub event: ChangeEvent,
+}
+
+//
+// ********** DIFF **********
+//
+
+fn missing_from(ids: &[BrokerId], other: &[BrokerId]) -> Vec<BrokerId> {
+    ids.iter().filter(|id| !other.contains(id)).cloned().collect()
+}
+
+pub fn diff_brokers(old: &[Broker], new: &[Broker]) -> Vec<ChangeEvent> {
+    let mut events = Vec::new();
+    for broker in new {
+        if !old.iter().any(|b| b.id == broker.id) {
+            events.push(ChangeEvent::BrokerJoined { broker: broker.id, hostname: broker.hostname.clone() });
+        }
+    }
+    for broker in old {
+        if !new.iter().any(|b| b.id == broker.id) {
+            events.push(ChangeEvent::BrokerLeft { broker: broker.id });
+        }
+    }
+    events
+}
+
+fn diff_partitions(topic: &str, old: &[Partition], new: &[Partition], events: &mut Vec<ChangeEvent>) {
+    if new.len() > old.len() {
+        events.push(ChangeEvent::PartitionsAdded { topic: topic.to_owned(), from: old.len(), to: new.len() });
+    }
+    for partition in new {
+        let previous = match old.iter().find(|p| p.id == partition.id) {
+            Some(previous) => previous,
+            None => continue,
+        };
+        if previous.leader != partition.leader {
+            events.push(ChangeEvent::LeaderChanged {
+                topic: topic.to_owned(),
+                partition: partition.id,
+                from: previous.leader,
+                to: partition.leader,
+            });
+        }
+        let removed = missing_from(&previous.isr, &partition.isr);
+        if !removed.is_empty() {
+            events.push(ChangeEvent::IsrShrank { topic: topic.to_owned(), partition: partition.id, removed });
+        }
+        let added = missing_from(&partition.isr, &previous.isr);
+        if !added.is_empty() {
+            events.push(ChangeEvent::IsrExpanded { topic: topic.to_owned(), partition: partition.id, added });
+        }
+    }
+}
+
+/// Compares the partitions of every topic in the cluster. Topics are considered deleted if
+/// they are missing from the new metadata.
+pub fn diff_topics(old: &HashMap<TopicName, Vec<Partition>>, new: &HashMap<TopicName, Vec<Partition>>)
+        -> Vec<ChangeEvent> {
+    let mut events = Vec::new();
+    let mut topics = new.keys().collect::<Vec<_>>();
+    topics.sort();
+    for topic in topics {
+        let partitions = &new[topic];
+        match old.get(topic) {
+            Some(old_partitions) => diff_partitions(topic, old_partitions, partitions, &mut events),
+            None => events.push(ChangeEvent::TopicCreated { topic: topic.clone(), partitions: partitions.len() }),
+        }
+    }
+    let mut deleted = old.keys().filter(|topic| !new.contains_key(*topic)).collect::<Vec<_>>();
+    deleted.sort();
+    for topic in deleted {
+        events.push(ChangeEvent::TopicDeleted { topic: topic.clone() });
+    }
+    events
+}
+
+/// Compares the state of the groups. The group list returned by the brokers might not be
+/// complete, so groups missing from the new list are not reported.
+pub fn diff_groups(old: &HashMap<String, Group>, new: &[Group]) -> Vec<ChangeEvent> {
+    let mut events = Vec::new();
+    for group in new {
+        match old.get(&group.name) {
+            Some(previous) if previous.state != group.state => {
+                events.push(ChangeEvent::GroupStateChanged {
+                    group: group.name.clone(),
+                    from: previous.state.clone(),
+                    to: group.state.clone(),
+                });
+            },
+            _ => {},
+        }
+    }
+    events
+}
+
+//
+// ********** EVENT LOG **********
+//
+
+/// Most recent change events of each cluster, recorded by the metadata fetch task of the
+/// cluster owner. Events are stored in the replicated cache, so that every instance shows
+/// the same events and they survive restarts.
+pub struct EventLog {
+    events: EventCache,
+    max_size: usize,
+}
+
+impl EventLog {
+    pub fn new(cache: &Cache, config: &Config) -> EventLog {
+        EventLog {
+            events: cache.events.alias(),
+            max_size: config.events_history_size,
+        }
+    }
+
+    pub fn alias(&self) -> EventLog {
+        EventLog {
+            events: self.events.alias(),
+            max_size: self.max_size,
+        }
+    }
+
+    pub fn record(&self, cluster_id: &ClusterId, events: Vec<ChangeEvent>) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let timestamp = millis_to_epoch(SystemTime::now()) as u64;
+        let mut cluster_events = self.events.get(cluster_id).unwrap_or_else(Vec::new);
+        for event in events {
+            info!("Cluster {}: {} {}", cluster_id, event.target(), event.description());
+            cluster_events.push(ClusterEvent { timestamp, event });
+        }
+        if cluster_events.len() > self.max_size {
+            let excess = cluster_events.len() - self.max_size;
+            cluster_events.drain(..excess);
+        }
+        self.events.insert(cluster_id.clone(), cluster_events)
+            .chain_err(|| format!("Failed to store the events of {}", cluster_id))
+    }
+
+    /// Returns the events of the cluster, most recent first.
+    pub fn events(&self, cluster_id: &ClusterId) -> Vec<ClusterEvent> {
+        let mut events = self.events.get(cluster_id).unwrap_or_else(Vec::new);
+        events.reverse();
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::{ReplicaReader, ReplicaWriter};
+    use config::CacheEncoding;
+    use storage::{CacheStorage, MemoryStorage};
+
+    use std::sync::Arc;
+
+    fn event_log(storage: &Arc<CacheStorage>, instance_id: &str) -> EventLog {
+        let cache = Cache::new(ReplicaWriter::new(storage.clone(), CacheEncoding::Json, instance_id));
+        ReplicaReader::new(storage.clone(), instance_id).load_state(cache.alias()).unwrap();
+        EventLog { events: cache.events.alias(), max_size: 3 }
+    }
+
+    fn broker_left(broker: BrokerId) -> ChangeEvent {
+        ChangeEvent::BrokerLeft { broker }
+    }
+
+    #[test]
+    fn keeps_the_latest_events_in_the_cache() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let cluster_id = ClusterId::from("local");
+        let log = event_log(&storage, "owner");
+        log.record(&cluster_id, Vec::new()).unwrap();
+        assert!(log.events(&cluster_id).is_empty());
+
+        log.record(&cluster_id, vec![broker_left(1), broker_left(2)]).unwrap();
+        log.record(&cluster_id, vec![broker_left(3), broker_left(4)]).unwrap();
+        let events = log.events(&cluster_id).into_iter().map(|e| e.event).collect::<Vec<_>>();
+        assert_eq!(events, vec![broker_left(4), broker_left(3), broker_left(2)]);
+        assert!(log.events(&ClusterId::from("other")).is_empty());
+
+        // Other instances, and this one after a restart, see the same events
+        let replica = event_log(&storage, "replica");
+        assert_eq!(replica.events(&cluster_id), log.events(&cluster_id));
+    }
+}
@@ -0,0 +1,189 @@
+use cache::Cache;
+use metadata::{BrokerId, ClusterId, Partition, TopicName};
+
+use std::collections::BTreeMap;
+
+
+#[derive(Serialize, PartialEq, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionIssue {
+    UnderReplicated,     // fewer in-sync replicas than replicas
+    Offline,             // no leader
+    NonPreferredLeader,  // the leader is not the first replica
+}
+
+impl PartitionIssue {
+    pub fn description(&self) -> &'static str {
+        match *self {
+            PartitionIssue::UnderReplicated => "Under-replicated",
+            PartitionIssue::Offline => "Offline",
+            PartitionIssue::NonPreferredLeader => "Non-preferred leader",
+        }
+    }
+}
+
+/// Returns the issues of the partition, if any.
+pub fn partition_issues(partition: &Partition) -> Vec<PartitionIssue> {
+    let mut issues = Vec::new();
+    if partition.isr.len() < partition.replicas.len() {
+        issues.push(PartitionIssue::UnderReplicated);
+    }
+    if partition.leader == -1 {
+        issues.push(PartitionIssue::Offline);
+    } else if partition.replicas.first().map(|&preferred| preferred != partition.leader).unwrap_or(false) {
+        issues.push(PartitionIssue::NonPreferredLeader);
+    }
+    issues
+}
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct HealthCounts {
+    pub under_replicated: usize,
+    pub offline: usize,
+    pub non_preferred_leader: usize,
+}
+
+impl HealthCounts {
+    fn add(&mut self, issue: PartitionIssue) {
+        match issue {
+            PartitionIssue::UnderReplicated => self.under_replicated += 1,
+            PartitionIssue::Offline => self.offline += 1,
+            PartitionIssue::NonPreferredLeader => self.non_preferred_leader += 1,
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.under_replicated == 0 && self.offline == 0 && self.non_preferred_leader == 0
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UnhealthyPartition {
+    pub topic: TopicName,
+    pub partition: Partition,
+    pub issues: Vec<PartitionIssue>,
+}
+
+/// Health of the partitions of a cluster. Issues are attributed to the brokers as follows:
+/// under-replicated to the replicas missing from the ISR, offline to all the replicas, and
+/// non-preferred leader to the preferred replica, which is not leading the partition.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ClusterHealth {
+    pub partition_count: usize,
+    pub totals: HealthCounts,
+    pub brokers: BTreeMap<BrokerId, HealthCounts>,
+    pub partitions: Vec<UnhealthyPartition>,
+}
+
+impl ClusterHealth {
+    fn add_partition(&mut self, topic: &str, partition: Partition) {
+        self.partition_count += 1;
+        let issues = partition_issues(&partition);
+        if issues.is_empty() {
+            return;
+        }
+        for &issue in &issues {
+            self.totals.add(issue);
+            let brokers = match issue {
+                PartitionIssue::UnderReplicated => partition.replicas.iter()
+                    .filter(|replica| !partition.isr.contains(replica))
+                    .cloned()
+                    .collect::<Vec<_>>(),
+                PartitionIssue::Offline => partition.replicas.clone(),
+                PartitionIssue::NonPreferredLeader => partition.replicas.iter().take(1).cloned().collect(),
+            };
+            for broker_id in brokers {
+                self.brokers.entry(broker_id).or_insert_with(HealthCounts::default).add(issue);
+            }
+        }
+        self.partitions.push(UnhealthyPartition { topic: topic.to_owned(), partition, issues });
+    }
+}
+
+/// Computes the health of all the partitions of the cluster in the topics matching the filter.
+pub fn cluster_health<F>(cache: &Cache, cluster_id: &ClusterId, topic_filter: F) -> ClusterHealth
+        where F: Fn(&str) -> bool {
+    let mut topics = cache.topics.filter_clone(|&(ref c, ref topic)| {
+        c == cluster_id && topic_filter(topic.as_str())
+    });
+    topics.sort_by(|a, b| (a.0).1.cmp(&(b.0).1));
+
+    let mut health = ClusterHealth::default();
+    for ((_, topic), partitions) in topics {
+        for partition in partitions {
+            health.add_partition(&topic, partition);
+        }
+    }
+    health
+}
+
+//
+// ********** LEADERSHIP **********
+//
+
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct BrokerLeadership {
+    pub leader: usize,            // partitions currently led by the broker
+    pub follower: usize,          // partitions replicated but not led by the broker
+    pub preferred_leader: usize,  // partitions having the broker as first replica
+}
+
+/// Distribution of the partition leaders among the brokers of a cluster.
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct LeadershipReport {
+    pub brokers: BTreeMap<BrokerId, BrokerLeadership>,
+}
+
+impl LeadershipReport {
+    pub fn get(&self, broker_id: BrokerId) -> BrokerLeadership {
+        self.brokers.get(&broker_id).cloned().unwrap_or_default()
+    }
+
+    /// Average number of partitions led by each broker.
+    pub fn average_leaders(&self) -> f64 {
+        if self.brokers.is_empty() {
+            return 0f64;
+        }
+        let total = self.brokers.values().map(|b| b.leader).sum::<usize>();
+        total as f64 / self.brokers.len() as f64
+    }
+
+    /// Difference between the partitions led by the broker and the average, as a percentage
+    /// of the average.
+    pub fn skew(&self, broker_id: BrokerId) -> f64 {
+        let average = self.average_leaders();
+        if average == 0f64 {
+            return 0f64;
+        }
+        (self.get(broker_id).leader as f64 - average) / average * 100f64
+    }
+}
+
+/// Computes the leadership of the brokers over the topics matching the filter. Every broker
+/// of the cluster is included, even if it doesn't host any partition.
+pub fn leadership_report<F>(cache: &Cache, cluster_id: &ClusterId, topic_filter: F) -> LeadershipReport
+        where F: Fn(&str) -> bool {
+    let mut report = LeadershipReport::default();
+    for broker in cache.brokers.get(cluster_id).unwrap_or_default() {
+        report.brokers.insert(broker.id, BrokerLeadership::default());
+    }
+    let topics = cache.topics.filter_clone(|&(ref c, ref topic)| {
+        c == cluster_id && topic_filter(topic.as_str())
+    });
+    for (_, partitions) in topics {
+        for partition in partitions {
+            for (index, &replica) in partition.replicas.iter().enumerate() {
+                let leadership = report.brokers.entry(replica).or_insert_with(BrokerLeadership::default);
+                if replica == partition.leader {
+                    leadership.leader += 1;
+                } else {
+                    leadership.follower += 1;
+                }
+                if index == 0 {
+                    leadership.preferred_leader += 1;
+                }
+            }
+        }
+    }
+    report
+}
@@ -0,0 +1,138 @@
+use rdkafka::util::millis_to_epoch;
+use scheduled_executor::TaskGroup;
+
+use cache::Cache;
+use config::SharedConfig;
+use error::*;
+use metadata::ClusterId;
+use offsets::{fetch_watermarks, OffsetStore};
+use ownership::Ownership;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+
+/// Lag of a consumer group on a topic at a specific point in time.
+#[derive(PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct LagSample {
+    pub timestamp: u64,  // millis since epoch
+    pub lag: Vec<i64>,   // lag for each partition, -1 if unknown
+}
+
+impl LagSample {
+    /// Sum of the lag of all the partitions with a known lag.
+    pub fn total_lag(&self) -> i64 {
+        self.lag.iter().filter(|&&lag| lag >= 0).sum()
+    }
+}
+
+/// Returns the lag of a partition given the committed offset and the high watermark, or -1
+/// if any of the two is unknown.
+pub fn partition_lag(offset: i64, high_watermark: i64) -> i64 {
+    if offset < 0 || high_watermark < 0 {
+        -1
+    } else {
+        cmp::max(high_watermark - offset, 0)
+    }
+}
+
+/// Periodically samples the lag of every group and topic in the offsets cache, and appends the
+/// sample to the lag history cache. Only the last `lag_history_size` samples are kept.
+///
+/// Each write to the storage contains the whole history of a group and topic, so the history
+/// is only written every `lag_history_persist_interval` seconds, and kept in memory in
+/// between. The other instances see the new samples after the next write.
+pub struct LagHistoryTaskGroup {
+    cache: Cache,
+    config: SharedConfig,
+    ownership: Ownership,
+    last_persisted: Arc<Mutex<HashMap<ClusterId, u64>>>,  // millis since epoch
+}
+
+impl LagHistoryTaskGroup {
+    pub fn new(cache: &Cache, config: &SharedConfig, ownership: &Ownership) -> LagHistoryTaskGroup {
+        LagHistoryTaskGroup {
+            cache: cache.alias(),
+            config: config.alias(),
+            ownership: ownership.alias(),
+            last_persisted: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns true if the history of the cluster should be written to the storage.
+    fn should_persist(&self, cluster_id: &ClusterId, timestamp: u64) -> bool {
+        let interval = self.config.get().lag_history_persist_interval * 1000;
+        let last_persisted = self.last_persisted.lock().expect("Poison error");
+        last_persisted.get(cluster_id)
+            .map(|&last| timestamp.saturating_sub(last) >= interval)
+            .unwrap_or(true)
+    }
+
+    /// Records a successful write of the history of the cluster. Failed writes are retried at
+    /// the next sample.
+    fn record_persisted(&self, cluster_id: &ClusterId, timestamp: u64) {
+        let mut last_persisted = self.last_persisted.lock().expect("Poison error");
+        last_persisted.insert(cluster_id.clone(), timestamp);
+    }
+
+    fn sample_cluster(&self, cluster_id: &ClusterId) -> Result<()> {
+        let offsets = self.cache.offsets_by_cluster(cluster_id);
+        if offsets.is_empty() {
+            return Ok(());
+        }
+
+        let wms = fetch_watermarks(cluster_id, &offsets)
+            .chain_err(|| format!("Failed to fetch watermarks for {}", cluster_id))?;
+
+        let timestamp = millis_to_epoch(SystemTime::now()) as u64;
+        let history_size = self.config.get().lag_history_size;
+        let persist = self.should_persist(cluster_id, timestamp);
+        for (key, partitions) in offsets {
+            let lag = partitions.iter().enumerate()
+                .map(|(partition_id, &offset)| {
+                    match wms.get(&(key.2.clone(), partition_id as i32)) {
+                        Some(&Ok((_, high))) => partition_lag(offset, high),
+                        _ => -1,
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut samples = self.cache.lag_history.get(&key).unwrap_or_else(Vec::new);
+            samples.push(LagSample { timestamp, lag });
+            if samples.len() > history_size {
+                let excess = samples.len() - history_size;
+                samples.drain(0..excess);
+            }
+            if persist {
+                self.cache.lag_history.insert(key, samples)
+                    .chain_err(|| "Failed to insert lag sample in cache")?;
+            } else {
+                self.cache.lag_history.insert_local(key, samples);
+            }
+        }
+        if persist {
+            self.record_persisted(cluster_id, timestamp);
+        }
+
+        Ok(())
+    }
+}
+
+impl TaskGroup for LagHistoryTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.get().clusters.keys().cloned().collect::<Vec<_>>()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        if !self.ownership.is_owner(&cluster_id) {
+            return;
+        }
+        debug!("Sampling consumer lag for {}", cluster_id);
+        if let Err(e) = self.sample_cluster(&cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+}
@@ -1,20 +1,28 @@
 use rdkafka::Message;
 use rdkafka::message::BorrowedMessage;
 use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
-use rdkafka::config::ClientConfig;
+use rand::random;
 use rocket::State;
-use rocket::http::RawStr;
+use rocket::http::{ContentType, RawStr};
+use rocket::response::{Content, Stream};
 use scheduled_executor::ThreadPoolExecutor;
+use serde_json::Value;
 
-use config::{ClusterConfig, Config};
+use config::{ClusterConfig, CurrentConfig};
+use decoders::{DecoderRegistry, TopicDecoders};
 use metadata::ClusterId;
 use error::*;
+use message_browser::truncate;
+use message_filter::{FilterParams, MessageFilter};
+use web_server::auth::Operator;
+use web_server::server::WORKERS;
 
+use std::cmp;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use std::borrow::Cow;
 
 
 pub struct LiveConsumer {
@@ -24,15 +32,17 @@ pub struct LiveConsumer {
     last_poll: RwLock<Instant>,
     consumer: BaseConsumer<EmptyConsumerContext>,
     active: AtomicBool,
+    streaming: bool,
+    filter: Mutex<FilterParams>,
+    scanned: AtomicUsize,
+    matched: AtomicUsize,
 }
 
 impl LiveConsumer {
-    fn new(id: u64, cluster_config: &ClusterConfig, topic: &str) -> Result<LiveConsumer> {
-        let consumer = ClientConfig::new()
-            .set("bootstrap.servers", &cluster_config.bootstrap_servers())
+    fn new(id: u64, cluster_config: &ClusterConfig, topic: &str, streaming: bool) -> Result<LiveConsumer> {
+        let consumer = cluster_config.client_config()?
             .set("group.id", &format!("kafka_view_live_consumer_{}", id))
             .set("enable.partition.eof", "false")
-            .set("api.version.request", "true")
             .set("enable.auto.commit", "false")
             .set("queued.max.messages.kbytes", "100") // Reduce memory usage
             .set("fetch.message.max.bytes", "102400")
@@ -47,8 +57,12 @@ impl LiveConsumer {
             cluster_id: cluster_config.cluster_id.clone().unwrap(),
             consumer,
             active: AtomicBool::new(false),
+            streaming,
             last_poll: RwLock::new(Instant::now()),
             topic: topic.to_owned(),
+            filter: Mutex::new(FilterParams::default()),
+            scanned: AtomicUsize::new(0),
+            matched: AtomicUsize::new(0),
         })
     }
 
@@ -67,6 +81,10 @@ impl LiveConsumer {
         self.active.load(Ordering::Relaxed)
     }
 
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
     pub fn last_poll(&self) -> Instant {
         *self.last_poll.read().unwrap()
     }
@@ -83,7 +101,26 @@ impl LiveConsumer {
         &self.topic
     }
 
-    fn poll(&self, max_msg: usize, timeout: Duration) -> Vec<BorrowedMessage> {
+    /// Number of messages received and number of messages that matched the filter, since
+    /// the last change of filter.
+    pub fn counters(&self) -> (usize, usize) {
+        (self.scanned.load(Ordering::Relaxed), self.matched.load(Ordering::Relaxed))
+    }
+
+    /// Sets the filter parameters, resetting the counters if they changed.
+    fn set_filter(&self, params: &FilterParams) {
+        let mut filter = self.filter.lock().expect("Poison error");
+        if *filter != *params {
+            *filter = params.clone();
+            self.scanned.store(0, Ordering::Relaxed);
+            self.matched.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Polls for messages until the timeout expires or `max_msg` messages have been accepted.
+    /// Each message is passed to `process`, and it's accepted if a value is returned.
+    fn poll<T, F>(&self, max_msg: usize, timeout: Duration, process: F) -> Vec<T>
+            where F: Fn(&BorrowedMessage) -> Option<T> {
         let start_time = Instant::now();
         let mut buffer = Vec::new();
         *self.last_poll.write().unwrap() = Instant::now();
@@ -91,7 +128,13 @@ impl LiveConsumer {
         while Instant::elapsed(&start_time) < timeout && buffer.len() < max_msg {
             match self.consumer.poll(100) {
                 None => {},
-                Some(Ok(m)) => buffer.push(m),
+                Some(Ok(m)) => {
+                    self.scanned.fetch_add(1, Ordering::Relaxed);
+                    if let Some(item) = process(&m) {
+                        self.matched.fetch_add(1, Ordering::Relaxed);
+                        buffer.push(item);
+                    }
+                },
                 Some(Err(e)) => {
                     error!("Error while receiving message {:?}", e);
                 },
@@ -111,8 +154,9 @@ impl Drop for LiveConsumer {
 
 type LiveConsumerMap = HashMap<u64, Arc<LiveConsumer>>;
 
+// Streaming consumers are removed when the connection is closed.
 fn remove_idle_consumers(consumers: &mut LiveConsumerMap) {
-    consumers.retain(|_, ref consumer| consumer.last_poll().elapsed() < Duration::from_secs(20));
+    consumers.retain(|_, ref consumer| consumer.is_streaming() || consumer.last_poll().elapsed() < Duration::from_secs(20));
 }
 
 pub struct LiveConsumerStore {
@@ -143,15 +187,34 @@ impl LiveConsumerStore {
         (*consumers).get(&id).cloned()
     }
 
-    fn add_consumer(&self, id: u64, cluster_config: &ClusterConfig, topic: &str) -> Result<Arc<LiveConsumer>> {
-        let live_consumer = LiveConsumer::new(id, cluster_config, topic)
+    fn add_consumer(&self, id: u64, cluster_config: &ClusterConfig, topic: &str, streaming: bool) -> Result<Arc<LiveConsumer>> {
+        self.add_consumer_within(id, cluster_config, topic, streaming, None)
+    }
+
+    /// Adds a streaming consumer, unless `MAX_STREAMING_CONSUMERS` are already open. Open
+    /// consumers are counted under the same lock used to add the new one, so that concurrent
+    /// requests can't exceed the limit.
+    fn try_add_streaming_consumer(&self, id: u64, cluster_config: &ClusterConfig, topic: &str) -> Result<Arc<LiveConsumer>> {
+        self.add_consumer_within(id, cluster_config, topic, true, Some(MAX_STREAMING_CONSUMERS))
+    }
+
+    fn add_consumer_within(&self, id: u64, cluster_config: &ClusterConfig, topic: &str, streaming: bool,
+                           max_streaming: Option<usize>) -> Result<Arc<LiveConsumer>> {
+        let live_consumer = LiveConsumer::new(id, cluster_config, topic, streaming)
             .chain_err(|| "Failed to create live consumer")?;
 
         let live_consumer_arc = Arc::new(live_consumer);
 
         // Add consumer immediately to the store, to prevent other threads from adding it again.
         match self.consumers.write() {
-            Ok(mut consumers) => (*consumers).insert(id, live_consumer_arc.clone()),
+            Ok(mut consumers) => {
+                if let Some(max) = max_streaming {
+                    if consumers.values().filter(|consumer| consumer.is_streaming()).count() >= max {
+                        bail!("Too many streaming tailers, at most {} can be open at the same time", max);
+                    }
+                }
+                (*consumers).insert(id, live_consumer_arc.clone())
+            },
             Err(_) => panic!("Poison error while writing consumer to cache")
         };
 
@@ -161,6 +224,10 @@ impl LiveConsumerStore {
         Ok(live_consumer_arc)
     }
 
+    fn remove_consumer(&self, id: u64) {
+        self.consumers.write().expect("Poison error").remove(&id);
+    }
+
     pub fn consumers(&self) -> Vec<Arc<LiveConsumer>> {
         self.consumers.read().unwrap().iter()
             .map(|(_, consumer)| consumer.clone())
@@ -176,39 +243,194 @@ pub fn test_live_consumer_api(
     cluster_id: ClusterId,
     topic: &RawStr,
     id: u64,
-    config: State<Config>,
+    operator: Operator,
+    config: CurrentConfig,
     live_consumers_store: State<LiveConsumerStore>,
+    decoders: State<DecoderRegistry>,
 ) -> Result<String> {
+    test_live_consumer_api_p(cluster_id, topic, id, FilterParams::default(), operator, config, live_consumers_store,
+                             decoders)
+}
+
+#[get("/api/tailer/<cluster_id>/<topic>/<id>?<filter_params>")]
+pub fn test_live_consumer_api_p(
+    cluster_id: ClusterId,
+    topic: &RawStr,
+    id: u64,
+    filter_params: FilterParams,
+    operator: Operator,
+    config: CurrentConfig,
+    live_consumers_store: State<LiveConsumerStore>,
+    decoders: State<DecoderRegistry>,
+) -> Result<String> {
+    let empty_response = json!({"messages": [], "scanned": 0, "matched": 0}).to_string();
     let cluster_config = config.clusters.get(&cluster_id);
 
-    if cluster_config.is_none() || cluster_config.unwrap().enable_tailing == false {
-        return Ok("[]".to_owned());
+    if cluster_config.is_none() || cluster_config.unwrap().enable_tailing == false
+        || !operator.0.can_access_topic(&cluster_id, topic) {
+        return Ok(empty_response);
     }
     let cluster_config = cluster_config.unwrap();
 
+    let filter = match MessageFilter::from_params(&filter_params) {
+        Ok(filter) => filter,
+        Err(e) => return Ok(json!({"error": e.to_string()}).to_string()),
+    };
+
     let consumer = match live_consumers_store.get_consumer(id) {
         Some(consumer) => consumer,
-        None => live_consumers_store.add_consumer(id, cluster_config, topic)
+        None => live_consumers_store.add_consumer(id, cluster_config, topic, false)
             .chain_err(|| format!("Error while creating live consumer for {} {}", cluster_id, topic))?,
     };
 
     if !consumer.is_active() {
         // Consumer is still being activated, no results for now.
-        return Ok("[]".to_owned());
+        return Ok(empty_response);
     }
 
-    let mut output = Vec::new();
-    for message in consumer.poll(100, Duration::from_secs(3)) {
-        let payload = message.payload()
-            .map(|bytes| String::from_utf8_lossy(bytes))
-            .unwrap_or(Cow::Borrowed(""));
-        if payload.len() > 1024 {
-            let truncated = format!("{}...", payload.chars().take(1024).collect::<String>());
-            output.push(json!{(message.partition(), message.offset(), truncated)});
+    consumer.set_filter(&filter_params);
+    let topic_decoders = decoders.for_topic(&cluster_id, topic);
+    let output = poll_messages(&consumer, &topic_decoders, &filter, Duration::from_secs(3));
+
+    let (scanned, matched) = consumer.counters();
+    Ok(json!({"messages": output, "scanned": scanned, "matched": matched}).to_string())
+}
+
+//
+// ********** STREAMING **********
+//
+
+// Each stream holds a web server worker for as long as the connection is open. Streams can use
+// at most half of the workers, so that the rest of the UI stays responsive.
+const MAX_STREAMING_CONSUMERS: usize = WORKERS as usize / 2;
+const KEEPALIVE_INTERVAL: u64 = 15;
+// Rocket only writes a chunk when the buffer is full, and hyper only flushes its own 8KB
+// buffer when a write is at least as large. Every event, keepalives included, is padded to a
+// multiple of the chunk size to make sure it's delivered immediately: without padding a
+// keepalive would never reach the socket, and a closed connection would go unnoticed.
+const STREAM_CHUNK_SIZE: usize = 8192;
+
+/// Decodes and filters the messages received within the timeout.
+fn poll_messages(consumer: &LiveConsumer, decoders: &TopicDecoders, filter: &Option<MessageFilter>,
+                 timeout: Duration) -> Vec<Value> {
+    consumer.poll(100, timeout, |message| {
+        let payload = decoders.decode_payload(message.payload());
+        let key = decoders.decode_key(message.key());
+        if let Some(ref filter) = *filter {
+            if !filter.matches(key.as_ref().map(|k| k.as_str()), payload.as_ref().map(|p| p.as_str())) {
+                return None;
+            }
+        }
+        let payload = payload.map(|payload| truncate(payload, 1024)).unwrap_or_default();
+        let key = key.map(|key| truncate(key, 1024));
+        Some(json!{(message.partition(), message.offset(), payload, key)})
+    })
+}
+
+/// Server-Sent Events stream of the messages of a topic. The consumer is owned by the
+/// stream, and it's removed from the store when the client disconnects. Since the response is
+/// written synchronously, messages are only polled when the client is ready to receive them.
+pub struct TailerEventStream {
+    consumer: Arc<LiveConsumer>,
+    store: Arc<RwLock<LiveConsumerMap>>,
+    decoders: TopicDecoders,
+    filter: Option<MessageFilter>,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl TailerEventStream {
+    fn fill_buffer(&mut self) {
+        let start_time = Instant::now();
+        let mut messages = Vec::new();
+        while messages.is_empty() && start_time.elapsed() < Duration::from_secs(KEEPALIVE_INTERVAL) {
+            messages = poll_messages(&self.consumer, &self.decoders, &self.filter, Duration::from_secs(1));
+        }
+
+        let mut event = if messages.is_empty() {
+            ": keepalive\n".to_owned()
         } else {
-            output.push(json!{(message.partition(), message.offset(), payload)});
+            let (scanned, matched) = self.consumer.counters();
+            let data = json!({"messages": messages, "scanned": scanned, "matched": matched});
+            format!("event: messages\ndata: {}\n\n", data)
         };
+        // Pad with a comment line, which is ignored by the client.
+        let padding = STREAM_CHUNK_SIZE - (event.len() + 2) % STREAM_CHUNK_SIZE;
+        event.push(':');
+        event.push_str(&" ".repeat(padding % STREAM_CHUNK_SIZE));
+        event.push('\n');
+
+        self.buffer = event.into_bytes();
+        self.position = 0;
+    }
+}
+
+impl Read for TailerEventStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.buffer.len() {
+            self.fill_buffer();
+        }
+        let len = cmp::min(buf.len(), self.buffer.len() - self.position);
+        buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Drop for TailerEventStream {
+    fn drop(&mut self) {
+        debug!("Tailer stream {} closed", self.consumer.id());
+        self.store.write().expect("Poison error").remove(&self.consumer.id());
     }
+}
+
+#[get("/api/tailer_stream/<cluster_id>/<topic>")]
+pub fn tailer_stream_api(
+    cluster_id: ClusterId,
+    topic: &RawStr,
+    operator: Operator,
+    config: CurrentConfig,
+    live_consumers_store: State<LiveConsumerStore>,
+    decoders: State<DecoderRegistry>,
+) -> Result<Content<Stream<TailerEventStream>>> {
+    tailer_stream_api_p(cluster_id, topic, FilterParams::default(), operator, config, live_consumers_store, decoders)
+}
+
+#[get("/api/tailer_stream/<cluster_id>/<topic>?<filter_params>")]
+pub fn tailer_stream_api_p(
+    cluster_id: ClusterId,
+    topic: &RawStr,
+    filter_params: FilterParams,
+    operator: Operator,
+    config: CurrentConfig,
+    live_consumers_store: State<LiveConsumerStore>,
+    decoders: State<DecoderRegistry>,
+) -> Result<Content<Stream<TailerEventStream>>> {
+    let cluster_config = match config.clusters.get(&cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        _ => bail!("Topic tailing is disabled for cluster {}", cluster_id),
+    };
+    if !operator.0.can_access_topic(&cluster_id, topic) {
+        bail!("User {} is not allowed to access topic {}", operator.0.name, topic);
+    }
+    let filter = MessageFilter::from_params(&filter_params)?;
 
-    Ok(json!(output).to_string())
+    let id = random::<u64>();
+    let consumer = live_consumers_store.try_add_streaming_consumer(id, cluster_config, topic)
+        .map_err(|e| {
+            live_consumers_store.remove_consumer(id);
+            e
+        })
+        .chain_err(|| format!("Error while creating streaming consumer for {} {}", cluster_id, topic))?;
+    consumer.set_filter(&filter_params);
+
+    let stream = TailerEventStream {
+        consumer,
+        store: live_consumers_store.consumers.clone(),
+        decoders: decoders.for_topic(&cluster_id, topic),
+        filter,
+        buffer: Vec::new(),
+        position: 0,
+    };
+    Ok(Content(ContentType::new("text", "event-stream"), Stream::chunked(stream, STREAM_CHUNK_SIZE as u64)))
 }
@@ -8,6 +8,8 @@ extern crate alloc_system;  // Rust issue: 27389
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
 #[macro_use] extern crate lazy_static;
+extern crate base64;
+extern crate bcrypt;
 extern crate brotli;
 extern crate byteorder;
 extern crate chrono;
@@ -17,34 +19,62 @@ extern crate env_logger;
 extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
+extern crate libc;
 extern crate maud;
+extern crate protobuf;
 extern crate rand;
 extern crate rdkafka;
 extern crate regex;
+extern crate rmp_serde;
 extern crate rocket;
 extern crate scheduled_executor;
 extern crate serde;
 extern crate serde_yaml;
 
 #[macro_use] mod utils;
+mod alerts;
+mod audit;
 mod cache;
 mod config;
+mod config_check;
+mod decoders;
 mod error;
+mod events;
+mod health;
+mod lag_history;
 mod live_consumer;
+mod message_browser;
+mod message_filter;
 mod metadata;
 mod metrics;
+mod offset_reset;
+mod ownership;
+mod reassignment;
+mod reload;
+mod snapshot;
+mod storage;
+mod topic_admin;
 mod web_server;
 mod offsets;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use scheduled_executor::{ThreadPoolExecutor, TaskGroupScheduler};
+use std::cmp;
+use std::sync::Arc;
 use std::time::Duration;
 
+use alerts::{AlertEvaluationTaskGroup, AlertStore};
 use cache::{Cache, ReplicaReader, ReplicaWriter};
+use config::SharedConfig;
 use error::*;
+use events::EventLog;
+use lag_history::LagHistoryTaskGroup;
 use metrics::MetricsFetchTaskGroup;
-use metadata::MetadataFetchTaskGroup;
+use metadata::{ClusterId, MetadataFetchTaskGroup};
 use offsets::run_offset_consumer;
+use ownership::{LeaseRenewalTaskGroup, Ownership};
+use reload::ConfigReloader;
+use storage::CacheStorage;
 
 // Use system allocator instead of jemalloc
 // Rust issue: 27389
@@ -54,93 +84,219 @@ use alloc_system::System;
 static A: System = System;
 
 
-fn run_kafka_web(config_path: &str) -> Result<()> {
-    let config = config::read_config(config_path)
-        .chain_err(|| format!("Unable to load configuration from '{}'", config_path))?;
+/// Seconds between two checks of the configuration file modification time.
+const CONFIG_CHECK_INTERVAL: u64 = 5;
 
-    let replicator_bootstrap_servers = match config.cluster(&config.caching.cluster) {
-        Some(cluster) => cluster.bootstrap_servers(),
-        None => bail!("Can't find cache cluster {}", config.caching.cluster),
-    };
-    let topic_name = &config.caching.topic;
-    let replica_writer = ReplicaWriter::new(&replicator_bootstrap_servers, topic_name)
-        .chain_err(|| format!("Replica writer creation failed (brokers: {}, topic: {})", replicator_bootstrap_servers, topic_name))?;
-    let mut replica_reader = ReplicaReader::new(&replicator_bootstrap_servers, topic_name)
-        .chain_err(|| format!("Replica reader creation failed (brokers: {}, topic: {})", replicator_bootstrap_servers, topic_name))?;
+/// Returns a filter matching the clusters whose cache entries can be expired by this instance:
+/// the ones it owns, and the ones no longer configured.
+fn expirable_clusters(ownership: &Ownership, config: &SharedConfig) -> impl Fn(&ClusterId) -> bool {
+    let owned = ownership.owned_clusters();
+    let config = config.get();
+    move |cluster_id| owned.contains(cluster_id) || config.cluster(cluster_id).is_none()
+}
+
+/// Creates the cache and restores its content from the storage.
+fn load_cache(config: &config::Config) -> Result<(Cache, Arc<CacheStorage>)> {
+    let storage = storage::create_storage(config)
+        .chain_err(|| "Cache storage creation failed")?;
+    let replica_writer = ReplicaWriter::new(storage.clone(), config.caching.encoding, &config.instance_id);
+    let mut replica_reader = ReplicaReader::new(storage.clone(), &config.instance_id);
 
     let cache = Cache::new(replica_writer);
 
-    // Load all the state from Kafka
+    // Load all the state from the storage
     let start_time = chrono::Utc::now();
     replica_reader.load_state(cache.alias())
-        .chain_err(|| format!("State load failed (brokers: {}, topic: {})", replicator_bootstrap_servers, topic_name))?;
+        .chain_err(|| format!("State load failed ({})", storage.description()))?;
     let elapsed_sec = chrono::Utc::now().signed_duration_since(start_time).num_milliseconds() as f32 / 1000f32;
     info!("Processed {} messages in {:.3} seconds ({:.0} msg/s).",
         replica_reader.processed_messages(), elapsed_sec, replica_reader.processed_messages() as f32 / elapsed_sec);
 
+    Ok((cache, storage))
+}
+
+fn run_kafka_web(config_path: &str, overrides: &[String]) -> Result<()> {
+    let (config, config_files) = config::read_config_files(config_path, overrides)
+        .chain_err(|| format!("Unable to load configuration from '{}'", config_path))?;
+
+    let (cache, storage) = load_cache(&config)?;
+
+    // Apply the updates written by the other instances sharing the storage
+    ReplicaReader::new(storage.clone(), &config.instance_id).follow(cache.alias())
+        .chain_err(|| format!("Failed to follow {}", storage.description()))?;
+
     let executor = ThreadPoolExecutor::new(4)
         .chain_err(|| "Failed to start thread pool executor")?;
 
+    // The clusters are read from the shared configuration, which is replaced on reload
+    let shared_config = SharedConfig::new(config.clone());
+
+    // Cluster ownership. Leases are acquired before starting the fetch tasks, which only run
+    // for the clusters owned by this instance.
+    let ownership = Ownership::new(&cache.leases, &config);
+    for cluster_id in config.clusters.keys() {
+        if let Err(e) = ownership.renew(cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+    let lease_renewal_interval = cmp::max(config.lease_duration / 3, 1);
+    executor.schedule(
+        LeaseRenewalTaskGroup::new(&ownership, &shared_config),
+        Duration::from_secs(lease_renewal_interval),
+        Duration::from_secs(lease_renewal_interval)
+    );
+
     // Metadata fetch
+    let event_log = EventLog::new(&cache, &config);
     executor.schedule(
-        MetadataFetchTaskGroup::new(&cache, &config),
+        MetadataFetchTaskGroup::new(&cache, &shared_config, &event_log, &ownership),
         Duration::from_secs(0),
         Duration::from_secs(config.metadata_refresh)
     );
 
     // Metrics fetch
     executor.schedule(
-        MetricsFetchTaskGroup::new(&cache, &config),
+        MetricsFetchTaskGroup::new(&cache, &shared_config, &ownership),
         Duration::from_secs(0),
         Duration::from_secs(config.metrics_refresh)
     );
 
+    // Consumer lag history
+    executor.schedule(
+        LagHistoryTaskGroup::new(&cache, &shared_config, &ownership),
+        Duration::from_secs(config.lag_history_interval),
+        Duration::from_secs(config.lag_history_interval)
+    );
+
+    // Alerts
+    let alert_store = AlertStore::new(&cache);
+    executor.schedule(
+        AlertEvaluationTaskGroup::new(&cache, &shared_config, &ownership, &alert_store),
+        Duration::from_secs(config.alerts.evaluation_interval),
+        Duration::from_secs(config.alerts.evaluation_interval)
+    );
+
     // Consumer offsets
-    for (cluster_id, cluster_config) in &config.clusters {
-        if let Err(e) = run_offset_consumer(cluster_id, cluster_config, &config, &cache) {
+    for cluster_id in config.clusters.keys() {
+        if let Err(e) = run_offset_consumer(cluster_id, &shared_config, &cache, &ownership) {
             format_error_chain!(e);
         }
     }
 
+    // Configuration reload, when the file changes or on SIGHUP
+    let config_reloader = ConfigReloader::new(config_path, overrides, config_files, &shared_config, &cache,
+                                              &ownership);
+    reload::reload_on_sighup();
+    let config_reloader_clone = config_reloader.alias();
+    executor.schedule_fixed_rate(
+        Duration::from_secs(CONFIG_CHECK_INTERVAL),
+        Duration::from_secs(CONFIG_CHECK_INTERVAL),
+        move |_| config_reloader_clone.reload_if_needed()
+    );
+
     // CACHE EXPIRATION
+    // Only the owner of a cluster refreshes its entries, so it's the only one expiring them.
     let cache_clone = cache.alias();
+    let ownership_clone = ownership.alias();
+    let config_clone = shared_config.alias();
     let metadata_expiration = config.metadata_refresh * 3;
     executor.schedule_fixed_rate(
         Duration::from_secs(config.metadata_refresh * 2),
         Duration::from_secs(config.metadata_refresh),
         move |_| {
-            cache_clone.topics.remove_expired(Duration::from_secs(metadata_expiration));
-            cache_clone.brokers.remove_expired(Duration::from_secs(metadata_expiration));
-            cache_clone.groups.remove_expired(Duration::from_secs(metadata_expiration));
+            let expirable = expirable_clusters(&ownership_clone, &config_clone);
+            let expiration = Duration::from_secs(metadata_expiration);
+            cache_clone.topics.remove_expired(expiration, |&(ref c, _)| expirable(c));
+            cache_clone.brokers.remove_expired(expiration, |c| expirable(c));
+            cache_clone.groups.remove_expired(expiration, |&(ref c, _)| expirable(c));
         }
     );
 
     let cache_clone = cache.alias();
+    let ownership_clone = ownership.alias();
+    let config_clone = shared_config.alias();
     let metrics_expiration = config.metrics_refresh * 3;
     executor.schedule_fixed_rate(
         Duration::from_secs(config.metrics_refresh * 2),
         Duration::from_secs(config.metrics_refresh),
         move |_| {
-            cache_clone.metrics.remove_expired(Duration::from_secs(metrics_expiration));
+            let expirable = expirable_clusters(&ownership_clone, &config_clone);
+            cache_clone.metrics.remove_expired(Duration::from_secs(metrics_expiration),
+                                               |&(ref c, _)| expirable(c));
         }
     );
 
     let cache_clone = cache.alias();
+    let ownership_clone = ownership.alias();
+    let config_clone = shared_config.alias();
     let offsets_store_duration = config.offsets_store_duration;
     executor.schedule_fixed_rate(
         Duration::from_secs(10),
         Duration::from_secs(120),
         move |_| {
-            cache_clone.offsets.remove_expired(Duration::from_secs(offsets_store_duration));
+            let expirable = expirable_clusters(&ownership_clone, &config_clone);
+            let expiration = Duration::from_secs(offsets_store_duration);
+            cache_clone.offsets.remove_expired(expiration, |&(ref c, _, _)| expirable(c));
+            cache_clone.lag_history.remove_expired(expiration, |&(ref c, _, _)| expirable(c));
+        }
+    );
+
+    // Cache storage compaction
+    let storage_clone = storage.clone();
+    executor.schedule_fixed_rate(
+        Duration::from_secs(config.caching.compaction_interval),
+        Duration::from_secs(config.caching.compaction_interval),
+        move |_| {
+            if let Err(e) = storage_clone.compact() {
+                format_error_chain!(e);
+            }
         }
     );
 
-    web_server::server::run_server(&executor, cache.alias(), &config)
+    web_server::server::run_server(&executor, cache.alias(), alert_store, event_log, ownership,
+                                   shared_config, config_reloader, &config)
         .chain_err(|| "Server initialization failed")?;
 
     Ok(())
 }
 
+fn export_snapshot(config_path: &str, overrides: &[String], snapshot_path: &str) -> Result<()> {
+    let config = config::read_config(config_path, overrides)
+        .chain_err(|| format!("Unable to load configuration from '{}'", config_path))?;
+    let (cache, _) = load_cache(&config)?;
+    let snapshot = snapshot::export(&cache)?;
+    snapshot::write_snapshot(&snapshot, snapshot_path)?;
+    info!("Snapshot written to {}", snapshot_path);
+    Ok(())
+}
+
+fn import_snapshot(config_path: &str, overrides: &[String], snapshot_path: &str) -> Result<()> {
+    let config = config::read_config(config_path, overrides)
+        .chain_err(|| format!("Unable to load configuration from '{}'", config_path))?;
+    let snapshot = snapshot::read_snapshot(snapshot_path)?;
+    let (cache, storage) = load_cache(&config)?;
+    let imported = snapshot::import(&cache, &snapshot)?;
+    storage.flush()
+        .chain_err(|| "Failed to persist the imported entries")?;
+    for (name, count) in imported {
+        info!("Imported {} entries in {}", count, name);
+    }
+    Ok(())
+}
+
+/// Validates the configuration, printing all the problems found.
+fn check_config(config_path: &str, overrides: &[String]) -> Result<()> {
+    let problems = config_check::check_config(config_path, overrides);
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        bail!("Found {} problems in '{}'", problems.len(), config_path);
+    }
+    println!("Configuration file '{}' is valid", config_path);
+    Ok(())
+}
+
 fn setup_args<'a>() -> ArgMatches<'a> {
     App::new("kafka web interface")
         .version(option_env!("CARGO_PKG_VERSION").unwrap_or(""))
@@ -148,13 +304,34 @@ fn setup_args<'a>() -> ArgMatches<'a> {
         .arg(Arg::with_name("conf")
             .short("c")
             .long("conf")
-            .help("Configuration file")
+            .help("Configuration file, or directory of configuration files merged in lexical order")
             .takes_value(true)
             .required(true))
+        .arg(Arg::with_name("set")
+            .long("set")
+            .help("Overrides a configuration value (example: 'clusters.local.zookeeper=zk:2181')")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1))
         .arg(Arg::with_name("log-conf")
             .long("log-conf")
             .help("Configure the logging format (example: 'rdkafka=trace')")
             .takes_value(true))
+        .arg(Arg::with_name("check-config")
+            .long("check-config")
+            .help("Validates the configuration file and exits"))
+        .subcommand(SubCommand::with_name("snapshot-export")
+            .about("Writes the content of the cache to a snapshot file")
+            .arg(Arg::with_name("file")
+                .help("Snapshot file")
+                .required(true)
+                .index(1)))
+        .subcommand(SubCommand::with_name("snapshot-import")
+            .about("Loads a snapshot file in the cache")
+            .arg(Arg::with_name("file")
+                .help("Snapshot file")
+                .required(true)
+                .index(1)))
         .get_matches()
 }
 
@@ -164,9 +341,20 @@ fn main() {
     utils::setup_logger(true, matches.value_of("log-conf"), "%F %T%z");
 
     let config_path = matches.value_of("conf").unwrap();
+    let overrides = matches.values_of("set")
+        .map(|values| values.map(|value| value.to_owned()).collect::<Vec<_>>())
+        .unwrap_or_else(Vec::new);
 
-    info!("Kafka-view is starting up!");
-    if let Err(e) = run_kafka_web(config_path) {
+    let result = match matches.subcommand() {
+        _ if matches.is_present("check-config") => check_config(config_path, &overrides),
+        ("snapshot-export", Some(args)) => export_snapshot(config_path, &overrides, args.value_of("file").unwrap()),
+        ("snapshot-import", Some(args)) => import_snapshot(config_path, &overrides, args.value_of("file").unwrap()),
+        _ => {
+            info!("Kafka-view is starting up!");
+            run_kafka_web(config_path, &overrides)
+        },
+    };
+    if let Err(e) = result {
         format_error_chain!(e);
         std::process::exit(1);
     }
@@ -0,0 +1,201 @@
+use rdkafka::{Message, Offset, TopicPartitionList};
+use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
+use rocket::State;
+use rocket::http::RawStr;
+use rand::random;
+
+use config::{ClusterConfig, CurrentConfig};
+use decoders::DecoderRegistry;
+use error::*;
+use metadata::ClusterId;
+use web_server::auth::Operator;
+
+use std::cmp;
+use std::time::{Duration, Instant};
+
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 500;
+const MAX_PAYLOAD_LENGTH: usize = 10240;
+
+/// A message read from a partition, detached from the consumer.
+#[derive(Debug, Clone)]
+pub struct BrowsedMessage {
+    pub offset: i64,
+    pub timestamp: Option<i64>,
+    pub key: Option<Vec<u8>>,
+    pub payload: Option<Vec<u8>>,
+}
+
+/// Reads messages from a specific position of a topic partition. Unlike the tailer, the
+/// consumer is assigned to the partition explicitly and never joins a consumer group.
+pub struct PartitionBrowser {
+    consumer: BaseConsumer<EmptyConsumerContext>,
+    topic: String,
+    partition: i32,
+}
+
+impl PartitionBrowser {
+    pub fn new(cluster_config: &ClusterConfig, topic: &str, partition: i32) -> Result<PartitionBrowser> {
+        let consumer = cluster_config.client_config()?
+            .set("group.id", &format!("kafka_view_browser_{}", random::<u64>()))
+            .set("enable.partition.eof", "false")
+            .set("enable.auto.commit", "false")
+            .set("queued.max.messages.kbytes", "1000") // Reduce memory usage
+            .create::<BaseConsumer<_>>()
+            .chain_err(|| "Failed to create rdkafka consumer")?;
+
+        Ok(PartitionBrowser {
+            consumer,
+            topic: topic.to_owned(),
+            partition,
+        })
+    }
+
+    pub fn watermarks(&self) -> Result<(i64, i64)> {
+        self.consumer.fetch_watermarks(&self.topic, self.partition, 10000)
+            .chain_err(|| format!("Failed to fetch watermarks for {}/{}", self.topic, self.partition))
+    }
+
+    /// Reads up to `max_messages` messages starting from `start`, stopping before `end`.
+    pub fn read(&self, start: i64, end: i64, max_messages: usize, timeout: Duration) -> Result<Vec<BrowsedMessage>> {
+        let mut messages = Vec::new();
+        if start >= end || max_messages == 0 {
+            return Ok(messages);
+        }
+
+        let mut tp_list = TopicPartitionList::new();
+        tp_list.add_partition_offset(&self.topic, self.partition, Offset::Offset(start));
+        self.consumer.assign(&tp_list)
+            .chain_err(|| format!("Failed to assign {}/{}", self.topic, self.partition))?;
+
+        let start_time = Instant::now();
+        while start_time.elapsed() < timeout && messages.len() < max_messages {
+            match self.consumer.poll(100) {
+                None => {},
+                Some(Ok(m)) => {
+                    if m.offset() >= end {
+                        break;
+                    }
+                    messages.push(BrowsedMessage {
+                        offset: m.offset(),
+                        timestamp: m.timestamp().to_millis(),
+                        key: m.key().map(|k| k.to_vec()),
+                        payload: m.payload().map(|p| p.to_vec()),
+                    });
+                    if m.offset() + 1 >= end {
+                        break;
+                    }
+                },
+                Some(Err(e)) => bail!("Error while reading {}/{}: {}", self.topic, self.partition, e),
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Finds the offset of the first message with a timestamp greater or equal than the one
+    /// provided, using the time index of the broker. Returns `high` if there is no such
+    /// message, and fails if the broker doesn't answer in time.
+    pub fn offset_for_timestamp(&self, timestamp: i64, low: i64, high: i64) -> Result<i64> {
+        let mut tp_list = TopicPartitionList::new();
+        tp_list.add_partition_offset(&self.topic, self.partition, Offset::End);
+        self.consumer.assign(&tp_list)
+            .chain_err(|| format!("Failed to assign {}/{}", self.topic, self.partition))?;
+        let offsets = self.consumer.offsets_for_timestamp(timestamp, 10000)
+            .chain_err(|| format!("Failed to look up the offset for time {} in {}/{}", timestamp, self.topic,
+                                  self.partition))?;
+        let elem = match offsets.find_partition(&self.topic, self.partition) {
+            Some(elem) => elem,
+            None => bail!("No offset returned for {}/{}", self.topic, self.partition),
+        };
+        elem.error()
+            .chain_err(|| format!("Failed to look up the offset for time {} in {}/{}", timestamp, self.topic,
+                                  self.partition))?;
+        match elem.offset() {
+            Offset::Offset(offset) => Ok(cmp::min(cmp::max(offset, low), high)),
+            Offset::End => Ok(high),  // all the messages are older
+            offset => bail!("Unexpected offset {:?} for time {} in {}/{}", offset, timestamp, self.topic,
+                            self.partition),
+        }
+    }
+}
+
+pub fn truncate(string: String, max_length: usize) -> String {
+    if string.len() > max_length {
+        format!("{}...", string.chars().take(max_length).collect::<String>())
+    } else {
+        string
+    }
+}
+
+//
+// ********** API **********
+//
+
+#[derive(FromForm, Debug)]
+pub struct BrowseParams {
+    offset: Option<i64>,    // read starting from this offset
+    from_end: Option<i64>,  // read the last N messages
+    time: Option<i64>,      // read starting from this timestamp in millis
+    before: Option<i64>,    // read the messages preceding this offset
+    count: Option<i64>,
+}
+
+#[get("/api/browser/<cluster_id>/<topic>/<partition>?<params>")]
+pub fn browse_partition_api(
+    cluster_id: ClusterId,
+    topic: &RawStr,
+    partition: i32,
+    params: BrowseParams,
+    operator: Operator,
+    config: CurrentConfig,
+    decoders: State<DecoderRegistry>,
+) -> Result<String> {
+    let cluster_config = match config.clusters.get(&cluster_id) {
+        Some(cluster_config) if cluster_config.enable_tailing => cluster_config,
+        _ => bail!("Message browsing is disabled for cluster {}", cluster_id),
+    };
+    if !operator.0.can_access_topic(&cluster_id, topic) {
+        bail!("User {} is not allowed to access topic {}", operator.0.name, topic);
+    }
+
+    let browser = PartitionBrowser::new(cluster_config, topic, partition)
+        .chain_err(|| format!("Error while creating message browser for {} {}", cluster_id, topic))?;
+    let (low, high) = browser.watermarks()?;
+    let count = cmp::min(cmp::max(params.count.unwrap_or(DEFAULT_PAGE_SIZE), 1), MAX_PAGE_SIZE);
+
+    let (start, end) = if let Some(before) = params.before {
+        (cmp::max(low, before - count), cmp::min(before, high))
+    } else if let Some(offset) = params.offset {
+        (cmp::max(low, offset), high)
+    } else if let Some(time) = params.time {
+        (browser.offset_for_timestamp(time, low, high)?, high)
+    } else {
+        (cmp::max(low, high - params.from_end.unwrap_or(count)), high)
+    };
+
+    let messages = browser.read(start, end, count as usize, Duration::from_secs(5))?;
+
+    let next_offset = messages.last().map(|m| m.offset + 1).unwrap_or(start);
+    let previous_offset = messages.first().map(|m| m.offset).unwrap_or(start);
+    let topic_decoders = decoders.for_topic(&cluster_id, topic);
+    let output = messages.iter()
+        .map(|m| json!({
+            "offset": m.offset,
+            "timestamp": m.timestamp,
+            "key": topic_decoders.decode_key(m.key.as_ref().map(|k| k.as_slice()))
+                .map(|key| truncate(key, MAX_PAYLOAD_LENGTH)),
+            "payload": topic_decoders.decode_payload(m.payload.as_ref().map(|p| p.as_slice()))
+                .map(|payload| truncate(payload, MAX_PAYLOAD_LENGTH)),
+        }))
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "partition": partition,
+        "low": low,
+        "high": high,
+        "previous_offset": previous_offset,
+        "next_offset": next_offset,
+        "messages": output,
+    }).to_string())
+}
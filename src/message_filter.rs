@@ -0,0 +1,258 @@
+use regex::Regex;
+use rocket::http::uri::URI;
+use rocket::request::{FromForm, FormItems};
+use serde_json::{self, Value};
+
+use error::*;
+
+
+/// Tailer filter parameters, as received from the query string.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FilterParams {
+    pub filter_type: String,  // "substring", "regex" or "json"
+    pub filter: String,       // the text to search, the regex or the JSON path
+    pub value: String,        // expected value for the JSON path
+    pub key_only: bool,
+}
+
+impl<'f> FromForm<'f> for FilterParams {
+    type Error = ();
+
+    fn from_form(form_items: &mut FormItems<'f>, _strict: bool) -> ::std::result::Result<Self, Self::Error> {
+        let mut params = FilterParams::default();
+        for (key, value) in form_items {
+            let value = value.as_str().replace('+', " ");
+            let decoded = URI::percent_decode_lossy(value.as_bytes()).into_owned();
+            match key.as_str() {
+                "filter_type" => params.filter_type = decoded,
+                "filter" => params.filter = decoded,
+                "value" => params.value = decoded,
+                "key_only" => params.key_only = value == "on" || value == "true",
+                _ => {},
+            }
+        }
+        Ok(params)
+    }
+}
+
+/// A segment of a JSON path: either an object field or an array index.
+#[derive(Debug, PartialEq)]
+enum PathSegment {
+    Field(String),
+    Index(usize),
+}
+
+/// Parses paths such as `$.order.items[0].id` or `order.id`.
+fn parse_json_path(path: &str) -> Result<Vec<PathSegment>> {
+    let path = path.trim();
+    let path = if path.starts_with('$') { &path[1..] } else { path };
+    let path = if path.starts_with('.') { &path[1..] } else { path };
+    if path.is_empty() {
+        bail!("Empty JSON path");
+    }
+    let mut segments = Vec::new();
+    for part in path.split('.') {
+        if part.is_empty() {
+            bail!("Empty segment in JSON path: {}", path);
+        }
+        let (field, mut indexes) = match part.find('[') {
+            Some(position) => (&part[..position], &part[position..]),
+            None => (part, ""),
+        };
+        if !field.is_empty() {
+            segments.push(PathSegment::Field(field.to_owned()));
+        }
+        while !indexes.is_empty() {
+            let end = match indexes.find(']') {
+                Some(end) if indexes.starts_with('[') => end,
+                _ => bail!("Invalid JSON path: {}", path),
+            };
+            let index = indexes[1..end].parse::<usize>()
+                .chain_err(|| format!("Invalid index in JSON path: {}", path))?;
+            segments.push(PathSegment::Index(index));
+            indexes = &indexes[end + 1..];
+        }
+    }
+    Ok(segments)
+}
+
+fn json_lookup<'a>(value: &'a Value, path: &[PathSegment]) -> Option<&'a Value> {
+    path.iter().fold(Some(value), |current, segment| {
+        match (current, segment) {
+            (Some(&Value::Object(ref map)), &PathSegment::Field(ref field)) => map.get(field),
+            (Some(&Value::Array(ref array)), &PathSegment::Index(index)) => array.get(index),
+            _ => None,
+        }
+    })
+}
+
+enum FilterKind {
+    Substring(String),
+    Regex(Regex),
+    JsonPath(Vec<PathSegment>, Value),
+}
+
+/// Filter applied by the tailer to the decoded key and payload of each message.
+pub struct MessageFilter {
+    kind: FilterKind,
+    key_only: bool,
+}
+
+impl MessageFilter {
+    /// Returns the filter described by the parameters, or `None` if no filter is specified.
+    pub fn from_params(params: &FilterParams) -> Result<Option<MessageFilter>> {
+        if params.filter.is_empty() {
+            return Ok(None);
+        }
+        let kind = match params.filter_type.as_str() {
+            "" | "substring" => FilterKind::Substring(params.filter.clone()),
+            "regex" => FilterKind::Regex(Regex::new(&params.filter)
+                .chain_err(|| format!("Invalid filter regex: {}", params.filter))?),
+            "json" => {
+                // The expected value can be any JSON value, or a plain string.
+                let value = serde_json::from_str::<Value>(&params.value)
+                    .unwrap_or_else(|_| Value::String(params.value.clone()));
+                FilterKind::JsonPath(parse_json_path(&params.filter)?, value)
+            },
+            other => bail!("Unknown filter type: {}", other),
+        };
+        Ok(Some(MessageFilter { kind, key_only: params.key_only }))
+    }
+
+    fn matches_text(&self, text: &str) -> bool {
+        match self.kind {
+            FilterKind::Substring(ref substring) => text.contains(substring.as_str()),
+            FilterKind::Regex(ref regex) => regex.is_match(text),
+            FilterKind::JsonPath(ref path, ref expected) => {
+                serde_json::from_str::<Value>(text).ok()
+                    .map(|value| json_lookup(&value, path) == Some(expected))
+                    .unwrap_or(false)
+            },
+        }
+    }
+
+    pub fn matches(&self, key: Option<&str>, payload: Option<&str>) -> bool {
+        let key_match = key.map(|key| self.matches_text(key)).unwrap_or(false);
+        if self.key_only {
+            key_match
+        } else {
+            key_match || payload.map(|payload| self.matches_text(payload)).unwrap_or(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(name: &str) -> PathSegment {
+        PathSegment::Field(name.to_owned())
+    }
+
+    fn filter(filter_type: &str, filter: &str, value: &str, key_only: bool) -> MessageFilter {
+        let params = FilterParams {
+            filter_type: filter_type.to_owned(),
+            filter: filter.to_owned(),
+            value: value.to_owned(),
+            key_only,
+        };
+        MessageFilter::from_params(&params).unwrap().unwrap()
+    }
+
+    #[test]
+    fn parses_json_paths() {
+        let expected = vec![field("order"), field("items"), PathSegment::Index(0), field("id")];
+        assert_eq!(parse_json_path("$.order.items[0].id").unwrap(), expected);
+        assert_eq!(parse_json_path(" order.items[0].id ").unwrap(), expected);
+        assert_eq!(parse_json_path("$[1][2]").unwrap(), vec![PathSegment::Index(1), PathSegment::Index(2)]);
+        assert_eq!(parse_json_path("a").unwrap(), vec![field("a")]);
+    }
+
+    #[test]
+    fn rejects_invalid_json_paths() {
+        for path in &["", "$", "$.", "a..b", "a.", "..a", "a[x]", "a[-1]", "a[0", "a[0]b", "a[]"] {
+            assert!(parse_json_path(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn looks_up_json_values() {
+        let value = json!({"order": {"items": [{"id": 7}], "total": 2.5}});
+        let lookup = |path| json_lookup(&value, &parse_json_path(path).unwrap()).cloned();
+        assert_eq!(lookup("order.items[0].id"), Some(json!(7)));
+        assert_eq!(lookup("order.total"), Some(json!(2.5)));
+        assert_eq!(lookup("order.items[1].id"), None);
+        assert_eq!(lookup("order[0]"), None);
+        assert_eq!(lookup("order.items.id"), None);
+    }
+
+    #[test]
+    fn matches_substrings_and_regexes() {
+        let substring = filter("", "error", "", false);
+        assert!(substring.matches(None, Some("an error occurred")));
+        assert!(substring.matches(Some("error-key"), None));
+        assert!(!substring.matches(Some("key"), Some("all good")));
+        assert!(!substring.matches(None, None));
+
+        let regex = filter("regex", "^user-[0-9]+$", "", false);
+        assert!(regex.matches(Some("user-42"), None));
+        assert!(!regex.matches(Some("user-x"), Some("user-1 logged in")));
+        assert!(MessageFilter::from_params(&FilterParams {
+            filter_type: "regex".to_owned(),
+            filter: "(".to_owned(),
+            ..FilterParams::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn matches_json_paths() {
+        let json = filter("json", "$.status", "\"failed\"", false);
+        assert!(json.matches(None, Some(r#"{"status": "failed"}"#)));
+        assert!(!json.matches(None, Some(r#"{"status": "ok"}"#)));
+        assert!(!json.matches(Some("status failed"), Some("not json at all")));
+        assert!(!json.matches(None, Some("")));
+
+        // Values that aren't valid JSON are compared as strings
+        let plain = filter("json", "status", "failed", false);
+        assert!(plain.matches(None, Some(r#"{"status": "failed"}"#)));
+        let number = filter("json", "retries", "3", false);
+        assert!(number.matches(None, Some(r#"{"retries": 3}"#)));
+        assert!(!number.matches(None, Some(r#"{"retries": "3"}"#)));
+    }
+
+    #[test]
+    fn matches_keys_only() {
+        let key_only = filter("substring", "user", "", true);
+        assert!(key_only.matches(Some("user-1"), Some("payload")));
+        assert!(!key_only.matches(Some("order-1"), Some("user-1 payload")));
+        assert!(!key_only.matches(None, Some("user")));
+
+        let json = filter("json", "id", "1", true);
+        assert!(json.matches(Some(r#"{"id": 1}"#), Some("not json")));
+        assert!(!json.matches(Some("1"), Some(r#"{"id": 1}"#)));
+    }
+
+    #[test]
+    fn ignores_missing_filters_and_rejects_unknown_types() {
+        assert!(MessageFilter::from_params(&FilterParams::default()).unwrap().is_none());
+        let unknown = FilterParams { filter_type: "xpath".to_owned(), filter: "/a".to_owned(), ..FilterParams::default() };
+        assert!(MessageFilter::from_params(&unknown).is_err());
+    }
+
+    #[test]
+    fn decodes_form_parameters() {
+        let form = "filter_type=json&filter=%24.order%5B0%5D&value=%22a+b%22&key_only=on&other=1";
+        let params = FilterParams::from_form(&mut FormItems::from(form), true).unwrap();
+        assert_eq!(params, FilterParams {
+            filter_type: "json".to_owned(),
+            filter: "$.order[0]".to_owned(),
+            value: "\"a b\"".to_owned(),
+            key_only: true,
+        });
+
+        let params = FilterParams::from_form(&mut FormItems::from("filter=a%2Bb&key_only=off"), true).unwrap();
+        assert_eq!(params.filter, "a+b");
+        assert!(!params.key_only);
+        assert_eq!(FilterParams::from_form(&mut FormItems::from(""), true).unwrap(), FilterParams::default());
+    }
+}
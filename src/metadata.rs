@@ -1,12 +1,13 @@
 use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
-use rdkafka::config::ClientConfig;
 use rdkafka::error as rderror;
 use scheduled_executor::TaskGroup;
 use byteorder::{BigEndian, ReadBytesExt};
 
 use cache::Cache;
-use config::{ClusterConfig, Config};
+use config::{ClusterConfig, SharedConfig};
 use error::*;
+use events::{diff_brokers, diff_groups, diff_topics, EventLog};
+use ownership::Ownership;
 use utils::read_str;
 
 use std::collections::HashMap;
@@ -44,15 +45,22 @@ impl MetadataConsumerCache {
         self.get(cluster_id).ok_or_else(|| ErrorKind::MissingConsumerError(cluster_id.clone()).into())
     }
 
+    /// Removes the consumer of the cluster, which will be created again with the current
+    /// configuration on the next `get_or_init`.
+    pub fn remove(&self, cluster_id: &ClusterId) {
+        match self.consumers.write() {
+            Ok(mut consumers) => (*consumers).remove(cluster_id),
+            Err(_) => panic!("Poison error while removing consumer from cache")
+        };
+    }
+
     pub fn get_or_init(&self, cluster_id: &ClusterId, config: &ClusterConfig) -> Result<Arc<MetadataConsumer>> {
         if let Some(consumer) = self.get(cluster_id) {
             return Ok(consumer);
         }
 
         debug!("Creating metadata consumer for {}", cluster_id);
-        let consumer = ClientConfig::new()
-            .set("bootstrap.servers", &config.bootstrap_servers())
-            .set("api.version.request", "true")
+        let consumer = config.client_config()?
             .create::<MetadataConsumer>()
             .chain_err(|| format!("Consumer creation failed for {}", cluster_id))?;
 
@@ -105,14 +113,13 @@ impl fmt::Display for ClusterId {
 pub struct Partition {
     pub id: i32,
     pub leader: BrokerId,
-    pub replicas: Vec<BrokerId>,
+    pub replicas: Vec<BrokerId>,  // assignment order, the first replica is the preferred leader
     pub isr: Vec<BrokerId>,
     pub error: Option<String>
 }
 
 impl Partition {
-    fn new(id: i32, leader: BrokerId, mut replicas: Vec<BrokerId>, mut isr: Vec<BrokerId>, error: Option<String>) -> Partition {
-        replicas.sort();
+    fn new(id: i32, leader: BrokerId, replicas: Vec<BrokerId>, mut isr: Vec<BrokerId>, error: Option<String>) -> Partition {
         isr.sort();
         Partition { id, leader, replicas, isr, error }
     }
@@ -207,16 +214,23 @@ fn fetch_groups(consumer: &MetadataConsumer, timeout_ms: i32) -> Result<Vec<Grou
     Ok(groups)
 }
 
+/// Periodically fetches the metadata of every cluster, and records the differences with the
+/// previous fetch in the event log.
 pub struct MetadataFetchTaskGroup {
     cache: Cache,
-    config: Config,
+    config: SharedConfig,
+    event_log: EventLog,
+    ownership: Ownership,
 }
 
 impl MetadataFetchTaskGroup {
-    pub fn new(cache: &Cache, config: &Config) -> MetadataFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &SharedConfig, event_log: &EventLog, ownership: &Ownership)
+            -> MetadataFetchTaskGroup {
         MetadataFetchTaskGroup {
             cache: cache.alias(),
-            config: config.clone(),
+            config: config.alias(),
+            event_log: event_log.alias(),
+            ownership: ownership.alias(),
         }
     }
 
@@ -224,15 +238,28 @@ impl MetadataFetchTaskGroup {
         let metadata = consumer.fetch_metadata(None, 120000)
             .chain_err(|| format!("Failed to fetch metadata from {}", cluster_id))?;
 
+        // If the cluster is not in the cache yet there is nothing to compare with, and every
+        // topic would be reported as created.
+        let previous_brokers = self.cache.brokers.get(cluster_id);
+        let mut events = Vec::new();
+
         // Brokers
         let mut brokers = Vec::new();
         for broker in metadata.brokers() {
             brokers.push(Broker::new(broker.id(), broker.host().to_owned(), broker.port()));
         }
+        if let Some(ref previous_brokers) = previous_brokers {
+            events.extend(diff_brokers(previous_brokers, &brokers));
+        }
         self.cache.brokers.insert(cluster_id.to_owned(), brokers)
             .chain_err(|| "Failed to insert broker information in cache")?;
 
         // Topics
+        let previous_topics = self.cache.topics.filter_clone(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|((_, topic_name), partitions)| (topic_name, partitions))
+            .collect::<HashMap<_, _>>();
+        let mut topics = HashMap::new();
         for topic in metadata.topics() {
             let mut partitions = Vec::with_capacity(topic.partitions().len());
             for p in topic.partitions() {
@@ -242,17 +269,33 @@ impl MetadataFetchTaskGroup {
                 partitions.push(partition);
             }
             partitions.sort_by(|a, b| a.id.cmp(&b.id));
-            // TODO: do not update if it's already there?
-            self.cache.topics.insert((cluster_id.clone(), topic.name().to_owned()), partitions)
-                .chain_err(|| "Failed to insert broker information in cache")?;
+            topics.insert(topic.name().to_owned(), partitions);
+        }
+        if previous_brokers.is_some() {
+            events.extend(diff_topics(&previous_topics, &topics));
+        }
+        for topic_name in previous_topics.keys().filter(|topic_name| !topics.contains_key(*topic_name)) {
+            self.cache.topics.remove(&(cluster_id.clone(), topic_name.clone()))
+                .chain_err(|| "Failed to remove deleted topic from cache")?;
+        }
+        for (topic_name, partitions) in topics {
+            // Unchanged topics are not written to the storage, only their update time is refreshed
+            self.cache.topics.insert((cluster_id.clone(), topic_name), partitions)
+                .chain_err(|| "Failed to insert topic information in cache")?;
         }
 
         // Groups
-        for group in fetch_groups(consumer.as_ref(), 30000)? {
+        let previous_groups = self.cache.groups.filter_clone(|&(ref c, _)| c == cluster_id)
+            .into_iter()
+            .map(|((_, group_name), group)| (group_name, group))
+            .collect::<HashMap<_, _>>();
+        let groups = fetch_groups(consumer.as_ref(), 30000)?;
+        events.extend(diff_groups(&previous_groups, &groups));
+        for group in groups {
             self.cache.groups.insert((cluster_id.clone(), group.name.to_owned()), group)?;
         }
 
-        Ok(())
+        self.event_log.record(cluster_id, events)
     }
 }
 
@@ -260,11 +303,20 @@ impl TaskGroup for MetadataFetchTaskGroup {
     type TaskId = ClusterId;
 
     fn get_tasks(&self) -> Vec<ClusterId> {
-        self.config.clusters.keys().cloned().collect::<Vec<_>>()
+        self.config.get().clusters.keys().cloned().collect::<Vec<_>>()
     }
 
     fn execute(&self, cluster_id: ClusterId) {
-        match CONSUMERS.get_or_init(&cluster_id, self.config.cluster(&cluster_id).unwrap()) {
+        if !self.ownership.is_owner(&cluster_id) {
+            debug!("Skipping metadata fetch for {}, owned by another instance", cluster_id);
+            return;
+        }
+        let config = self.config.get();
+        let cluster_config = match config.cluster(&cluster_id) {
+            Some(cluster_config) => cluster_config,
+            None => return,  // removed from the configuration
+        };
+        match CONSUMERS.get_or_init(&cluster_id, cluster_config) {
             Ok(consumer) => {
                 if let Err(e) = self.fetch_data(consumer, &cluster_id) {
                     format_error_chain!(e);
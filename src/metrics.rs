@@ -7,11 +7,14 @@ use scheduled_executor::TaskGroup;
 
 use std::collections::HashMap;
 use std::f64;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 use cache::Cache;
-use config::Config;
+use config::{MetricsSourceConfig, SharedConfig};
 use error::*;
-use metadata::{ClusterId, Broker, TopicName};
+use metadata::{ClusterId, Broker, BrokerId, TopicName};
+use ownership::Ownership;
 use utils::insert_at;
 
 
@@ -72,15 +75,44 @@ impl Default for TopicMetrics {
     }
 }
 
-fn format_jolokia_path(hostname: &str, port: i32, filter: &str) -> String {
-    format!("http://{}:{}/jolokia/read/{}?ignoreErrors=true&includeStackTrace=false&maxCollectionSize=0",
-            hostname, port, filter)
+/// Total size of the partitions stored by the broker, both as leader and as follower.
+pub fn broker_log_size(cache: &Cache, cluster_id: &ClusterId, broker_id: BrokerId) -> f64 {
+    cache.metrics.lock_iter(|iter| {
+        iter.filter(|&(&(ref c, ref topic), _)| c == cluster_id && topic != "__TOTAL__")
+            .filter_map(|(_, topic_metrics)| topic_metrics.brokers.get(&broker_id))
+            .map(|broker_metrics| broker_metrics.partitions.iter().map(|p| p.size_bytes).sum::<f64>())
+            .sum::<f64>()
+    })
 }
 
-fn fetch_metrics_json(hostname: &str, port: i32, filter: &str) -> Result<Value> {
+//
+// ********** METRICS SOURCES **********
+//
+
+/// Metrics of a single broker, as read from a metrics source. Broker-wide values are stored
+/// under the `__TOTAL__` topic name.
+#[derive(Debug, Default)]
+pub struct BrokerMetrics {
+    pub byte_rate: HashMap<TopicName, f64>,
+    pub msg_rate: HashMap<TopicName, f64>,
+    pub partition_sizes: HashMap<TopicName, Vec<PartitionMetrics>>,
+}
+
+/// A source of traffic and partition size metrics for the brokers of a cluster.
+pub trait MetricsSource: Send + Sync {
+    fn fetch_broker_metrics(&self, broker: &Broker) -> Result<BrokerMetrics>;
+}
+
+pub fn new_metrics_source(config: &MetricsSourceConfig) -> Box<MetricsSource> {
+    match *config {
+        MetricsSourceConfig::Jolokia { port } => Box::new(JolokiaSource::new(port)),
+        MetricsSourceConfig::Prometheus { port, ref path } => Box::new(PrometheusSource::new(port, path)),
+    }
+}
+
+pub fn http_get(url: &str) -> Result<String> {
     let mut req = Easy::new();
-    let url = format_jolokia_path(hostname, port, filter);
-    req.url(&url).chain_err(|| format!("Unable to parse url: '{}'", url))?;
+    req.url(url).chain_err(|| format!("Unable to parse url: '{}'", url))?;
 
     let mut buf = Vec::new();
     {
@@ -91,12 +123,22 @@ fn fetch_metrics_json(hostname: &str, port: i32, filter: &str) -> Result<Value>
         }).chain_err(|| "Data transfer failure")?;
         transfer.perform().chain_err(|| "Connection failure")?;
     }
-    let string = String::from_utf8(buf)
-        .chain_err(|| "Failed to parse buffer as UTF-8")?;
+    String::from_utf8(buf)
+        .chain_err(|| "Failed to parse buffer as UTF-8")
+}
+
+//
+// ********** JOLOKIA **********
+//
+
+fn format_jolokia_path(hostname: &str, port: i32, filter: &str) -> String {
+    format!("http://{}:{}/jolokia/read/{}?ignoreErrors=true&includeStackTrace=false&maxCollectionSize=0",
+            hostname, port, filter)
+}
+
+fn fetch_metrics_json(hostname: &str, port: i32, filter: &str) -> Result<Value> {
+    let string = http_get(&format_jolokia_path(hostname, port, filter))?;
     let value = serde_json::from_str(&string).chain_err(|| "Failed to parse JSON")?;
-    // let value = String::from_utf8(buf)
-    //     .and_then(|string| serde_json::from_str(&string))
-    //     .chain_err(|| "Failed to parse JSON")?;
     Ok(value)
 }
 
@@ -172,6 +214,273 @@ fn parse_partition_size_metrics(jolokia_json_response: &Value) -> Result<HashMap
     Ok(metrics)
 }
 
+pub struct JolokiaSource {
+    port: i32,
+}
+
+impl JolokiaSource {
+    pub fn new(port: i32) -> JolokiaSource {
+        JolokiaSource { port }
+    }
+}
+
+impl MetricsSource for JolokiaSource {
+    fn fetch_broker_metrics(&self, broker: &Broker) -> Result<BrokerMetrics> {
+        let byte_rate_json = fetch_metrics_json(&broker.hostname, self.port, "kafka.server:name=BytesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate")
+            .chain_err(|| format!("Failed to fetch byte rate metrics from {}", broker.hostname))?;
+        let byte_rate = parse_broker_rate_metrics(&byte_rate_json)
+            .chain_err(|| "Failed to parse byte rate broker metrics")?;
+        let msg_rate_json = fetch_metrics_json(&broker.hostname, self.port, "kafka.server:name=MessagesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate")
+            .chain_err(|| format!("Failed to fetch message rate metrics from {}", broker.hostname))?;
+        let msg_rate = parse_broker_rate_metrics(&msg_rate_json)
+            .chain_err(|| "Failed to parse message rate broker metrics")?;
+        let partition_metrics_json = fetch_metrics_json(&broker.hostname, self.port, "kafka.log:name=Size,*,type=Log/Value")
+            .chain_err(|| format!("Failed to fetch partition size metrics from {}", broker.hostname))?;
+        let partition_sizes = parse_partition_size_metrics(&partition_metrics_json)
+            .chain_err(|| "Failed to parse partition size broker metrics")?;
+        Ok(BrokerMetrics { byte_rate, msg_rate, partition_sizes })
+    }
+}
+
+//
+// ********** PROMETHEUS **********
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrometheusSample {
+    pub name: String,
+    pub labels: HashMap<String, String>,
+    pub value: f64,
+}
+
+fn parse_prometheus_value(value: &str) -> Result<f64> {
+    match value {
+        "NaN" => Ok(f64::NAN),
+        "+Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        _ => value.parse::<f64>().chain_err(|| format!("Invalid sample value: {}", value)),
+    }
+}
+
+fn parse_prometheus_labels(labels: &str) -> Result<HashMap<String, String>> {
+    let chars = labels.chars().collect::<Vec<_>>();
+    let mut result = HashMap::new();
+    let mut pos = 0;
+    loop {
+        while pos < chars.len() && (chars[pos] == ',' || chars[pos].is_whitespace()) {
+            pos += 1;
+        }
+        if pos == chars.len() {
+            break;
+        }
+        let mut key = String::new();
+        while pos < chars.len() && chars[pos] != '=' {
+            key.push(chars[pos]);
+            pos += 1;
+        }
+        pos += 1;  // skip '='
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        if pos >= chars.len() || chars[pos] != '"' {
+            bail!("Expected quoted value for label '{}'", key.trim());
+        }
+        pos += 1;
+        let mut value = String::new();
+        let mut closed = false;
+        while pos < chars.len() {
+            match chars[pos] {
+                '\\' if pos + 1 < chars.len() => {
+                    pos += 1;
+                    value.push(if chars[pos] == 'n' { '\n' } else { chars[pos] });
+                },
+                '"' => {
+                    closed = true;
+                    pos += 1;
+                    break;
+                },
+                c => value.push(c),
+            }
+            pos += 1;
+        }
+        if !closed {
+            bail!("Unterminated value for label '{}'", key.trim());
+        }
+        result.insert(key.trim().to_owned(), value);
+    }
+    Ok(result)
+}
+
+fn parse_prometheus_line(line: &str) -> Result<PrometheusSample> {
+    let (name, labels, rest) = match line.find('{') {
+        Some(open) => {
+            let close = match line.rfind('}') {
+                Some(close) if close > open => close,
+                _ => bail!("Unterminated label set"),
+            };
+            (&line[..open], parse_prometheus_labels(&line[open + 1..close])?, &line[close + 1..])
+        },
+        None => match line.find(char::is_whitespace) {
+            Some(pos) => (&line[..pos], HashMap::new(), &line[pos..]),
+            None => bail!("Missing sample value"),
+        },
+    };
+    let value = match rest.split_whitespace().next() {
+        Some(value) => parse_prometheus_value(value)?,
+        None => bail!("Missing sample value"),
+    };
+    Ok(PrometheusSample { name: name.trim().to_owned(), labels, value })
+}
+
+/// Parses a response in the Prometheus text exposition format. Comments and type
+/// information are ignored.
+pub fn parse_prometheus_text(text: &str) -> Result<Vec<PrometheusSample>> {
+    let mut samples = Vec::new();
+    for (line_number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let sample = parse_prometheus_line(line)
+            .chain_err(|| format!("Failed to parse line {}: {}", line_number + 1, line))?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RateMetric {
+    Bytes,
+    Messages,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RateKind {
+    FifteenMinuteRate,
+    Counter,
+}
+
+/// Recognizes the broker topic metrics, both as exported by the JMX exporter without any rule
+/// (`kafka_server_BrokerTopicMetrics_FifteenMinuteRate{name="BytesInPerSec",...}`) and as
+/// exported with the rules of the JMX exporter example configuration
+/// (`kafka_server_brokertopicmetrics_bytesin_total{...}`).
+fn classify_rate_sample(sample: &PrometheusSample) -> Option<(RateMetric, RateKind)> {
+    let name = sample.name.to_lowercase();
+    let bean_name = sample.labels.get("name").map(|name| name.to_lowercase());
+    let metric = |bean_name: &Option<String>| match bean_name.as_ref().map(|name| name.as_str()) {
+        Some("bytesinpersec") => Some(RateMetric::Bytes),
+        Some("messagesinpersec") => Some(RateMetric::Messages),
+        _ => None,
+    };
+    match name.as_str() {
+        "kafka_server_brokertopicmetrics_fifteenminuterate" =>
+            metric(&bean_name).map(|metric| (metric, RateKind::FifteenMinuteRate)),
+        "kafka_server_brokertopicmetrics_count" =>
+            metric(&bean_name).map(|metric| (metric, RateKind::Counter)),
+        "kafka_server_brokertopicmetrics_bytesin_total" => Some((RateMetric::Bytes, RateKind::Counter)),
+        "kafka_server_brokertopicmetrics_messagesin_total" => Some((RateMetric::Messages, RateKind::Counter)),
+        _ => None,
+    }
+}
+
+fn is_partition_size_sample(sample: &PrometheusSample) -> bool {
+    match sample.name.to_lowercase().as_str() {
+        "kafka_log_log_value" => sample.labels.get("name").map(|name| name.to_lowercase() == "size").unwrap_or(false),
+        "kafka_log_log_size" => true,
+        _ => false,
+    }
+}
+
+/// Reads the metrics from a Prometheus endpoint, usually exposed by the Prometheus JMX exporter.
+/// If the fifteen minutes rates are not exported, the rates are computed from the counters
+/// between two consecutive fetches.
+pub struct PrometheusSource {
+    port: i32,
+    path: String,
+    counters: Mutex<HashMap<(BrokerId, RateMetric, TopicName), (f64, Instant)>>,
+}
+
+impl PrometheusSource {
+    pub fn new(port: i32, path: &str) -> PrometheusSource {
+        PrometheusSource {
+            port,
+            path: path.to_owned(),
+            counters: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Stores the new value of the counter and returns the rate since the previous value.
+    fn counter_rate(&self, broker_id: BrokerId, metric: RateMetric, topic: &str, value: f64, now: Instant) -> Option<f64> {
+        let mut counters = self.counters.lock().expect("Poison error");
+        let previous = counters.insert((broker_id, metric, topic.to_owned()), (value, now));
+        match previous {
+            Some((previous_value, previous_time)) if value >= previous_value => {
+                let elapsed = now.duration_since(previous_time);
+                let elapsed_secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+                if elapsed_secs > 0f64 {
+                    Some((value - previous_value) / elapsed_secs)
+                } else {
+                    None
+                }
+            },
+            _ => None,  // first fetch or counter reset
+        }
+    }
+
+    pub fn broker_metrics(&self, broker_id: BrokerId, samples: &[PrometheusSample]) -> BrokerMetrics {
+        let now = Instant::now();
+        let mut metrics = BrokerMetrics::default();
+        let mut counter_rates = BrokerMetrics::default();
+        for sample in samples {
+            let topic = sample.labels.get("topic").map(|topic| topic.as_str()).unwrap_or("__TOTAL__");
+            if let Some((metric, kind)) = classify_rate_sample(sample) {
+                let rate = match kind {
+                    RateKind::FifteenMinuteRate => Some(sample.value),
+                    RateKind::Counter => self.counter_rate(broker_id, metric, topic, sample.value, now),
+                };
+                let target = match kind {
+                    RateKind::FifteenMinuteRate => &mut metrics,
+                    RateKind::Counter => &mut counter_rates,
+                };
+                if let Some(rate) = rate {
+                    match metric {
+                        RateMetric::Bytes => target.byte_rate.insert(topic.to_owned(), rate),
+                        RateMetric::Messages => target.msg_rate.insert(topic.to_owned(), rate),
+                    };
+                }
+            } else if is_partition_size_sample(sample) {
+                let partition = sample.labels.get("partition").and_then(|p| p.parse::<u32>().ok());
+                if let Some(partition) = partition {
+                    insert_at(
+                        metrics.partition_sizes.entry(topic.to_owned()).or_insert_with(Vec::new),
+                        partition as usize,
+                        PartitionMetrics { size_bytes: sample.value },
+                        PartitionMetrics::default());
+                }
+            }
+        }
+        // Fifteen minutes rates take precedence over the rates computed from counters
+        for (topic, rate) in counter_rates.byte_rate {
+            metrics.byte_rate.entry(topic).or_insert(rate);
+        }
+        for (topic, rate) in counter_rates.msg_rate {
+            metrics.msg_rate.entry(topic).or_insert(rate);
+        }
+        metrics
+    }
+}
+
+impl MetricsSource for PrometheusSource {
+    fn fetch_broker_metrics(&self, broker: &Broker) -> Result<BrokerMetrics> {
+        let url = format!("http://{}:{}{}", broker.hostname, self.port, self.path);
+        let text = http_get(&url)
+            .chain_err(|| format!("Failed to fetch Prometheus metrics from {}", url))?;
+        let samples = parse_prometheus_text(&text)
+            .chain_err(|| format!("Failed to parse Prometheus metrics from {}", url))?;
+        Ok(self.broker_metrics(broker.id, &samples))
+    }
+}
+
 fn log_elapsed_time(task_name: &str, start: DateTime<Utc>) {
     debug!("{} completed in: {:.3}ms", task_name, Utc::now().signed_duration_since(start).num_microseconds().unwrap() as f64 / 1000f64);
 }
@@ -179,36 +488,59 @@ fn log_elapsed_time(task_name: &str, start: DateTime<Utc>) {
 
 pub struct MetricsFetchTaskGroup {
     cache: Cache,
-    config: Config,
+    config: SharedConfig,
+    sources: RwLock<HashMap<ClusterId, (MetricsSourceConfig, Arc<MetricsSource>)>>,
+    ownership: Ownership,
 }
 
 impl MetricsFetchTaskGroup {
-    pub fn new(cache: &Cache, config: &Config) -> MetricsFetchTaskGroup {
-        MetricsFetchTaskGroup {
+    pub fn new(cache: &Cache, config: &SharedConfig, ownership: &Ownership) -> MetricsFetchTaskGroup {
+        let task_group = MetricsFetchTaskGroup {
             cache: cache.alias(),
-            config: config.clone(),
+            config: config.alias(),
+            sources: RwLock::new(HashMap::new()),
+            ownership: ownership.alias(),
+        };
+        task_group.update_sources();
+        task_group
+    }
+
+    /// Creates the metrics sources of the clusters added to the configuration, or whose source
+    /// configuration changed, and drops the ones of the removed clusters.
+    fn update_sources(&self) {
+        let config = self.config.get();
+        let mut sources = self.sources.write().expect("Poison error");
+        sources.retain(|cluster_id, &mut (ref source_config, _)| {
+            config.cluster(cluster_id)
+                .and_then(|cluster_config| cluster_config.metrics_source())
+                .map(|current| &current == source_config)
+                .unwrap_or(false)
+        });
+        for (cluster_id, cluster_config) in &config.clusters {
+            if sources.contains_key(cluster_id) {
+                continue;
+            }
+            if let Some(source_config) = cluster_config.metrics_source() {
+                let source = Arc::from(new_metrics_source(&source_config));
+                sources.insert(cluster_id.clone(), (source_config, source));
+            }
         }
     }
 
-    fn fetch_metrics(&self, cluster_id: &ClusterId, broker: &Broker, port: i32) -> Result<()> {
+    fn source(&self, cluster_id: &ClusterId) -> Option<Arc<MetricsSource>> {
+        self.sources.read().expect("Poison error").get(cluster_id)
+            .map(|&(_, ref source)| source.clone())
+    }
+
+    fn fetch_metrics(&self, cluster_id: &ClusterId, broker: &Broker, source: &MetricsSource) -> Result<()> {
         let start = Utc::now();
-        let byte_rate_json = fetch_metrics_json(&broker.hostname, port, "kafka.server:name=BytesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate")
-            .chain_err(|| format!("Failed to fetch byte rate metrics from {}", broker.hostname))?;
-        let byte_rate_metrics = parse_broker_rate_metrics(&byte_rate_json)
-            .chain_err(|| "Failed to parse byte rate broker metrics")?;
-        let msg_rate_json = fetch_metrics_json(&broker.hostname, port, "kafka.server:name=MessagesInPerSec,*,type=BrokerTopicMetrics/FifteenMinuteRate")
-            .chain_err(|| format!("Failed to fetch message rate metrics from {}", broker.hostname))?;
-        let msg_rate_metrics = parse_broker_rate_metrics(&msg_rate_json)
-            .chain_err(|| "Failed to parse message rate broker metrics")?;
-        let partition_metrics_json = fetch_metrics_json(&broker.hostname, port, "kafka.log:name=Size,*,type=Log/Value")
-            .chain_err(|| format!("Failed to fetch partition size metrics from {}", broker.hostname))?;
-        let pt_size_metrics = parse_partition_size_metrics(&partition_metrics_json)
-            .chain_err(|| "Failed to parse partition size broker metrics")?;
-        for (topic, b_rate_15) in byte_rate_metrics {
+        let broker_metrics = source.fetch_broker_metrics(broker)
+            .chain_err(|| format!("Failed to fetch metrics from broker {}", broker.id))?;
+        for (topic, b_rate_15) in broker_metrics.byte_rate {
             let mut topic_metrics = self.cache.metrics.get(&(cluster_id.clone(), topic.clone()))
                 .unwrap_or_default();
-            let m_rate_15 = *msg_rate_metrics.get(&topic).unwrap_or(&-1f64);
-            let partitions = pt_size_metrics.get(&topic).cloned()
+            let m_rate_15 = *broker_metrics.msg_rate.get(&topic).unwrap_or(&-1f64);
+            let partitions = broker_metrics.partition_sizes.get(&topic).cloned()
                 .unwrap_or_else(Vec::new);
             topic_metrics.brokers.insert(broker.id, TopicBrokerMetrics { m_rate_15, b_rate_15, partitions});
             self.cache.metrics.insert((cluster_id.clone(), topic.clone()), topic_metrics)
@@ -220,17 +552,18 @@ impl MetricsFetchTaskGroup {
 }
 
 impl TaskGroup for MetricsFetchTaskGroup {
-    type TaskId = (ClusterId, Broker, i32);
+    type TaskId = (ClusterId, Broker);
 
     fn get_tasks(&self) -> Vec<Self::TaskId> {
+        self.update_sources();
+        let owned_clusters = self.ownership.owned_clusters();
+        let sources = self.sources.read().expect("Poison error");
         self.cache.brokers.lock_iter(|iter| {
             let mut tasks = Vec::new();
             for (cluster_id, brokers) in iter {
-                let port = self.config.cluster(cluster_id)
-                    .and_then(|cluster_config| cluster_config.jolokia_port);
-                if port.is_some() {
+                if sources.contains_key(cluster_id) && owned_clusters.contains(cluster_id) {
                     for broker in brokers {
-                        tasks.push((cluster_id.clone(), broker.clone(), port.unwrap()));
+                        tasks.push((cluster_id.clone(), broker.clone()));
                     }
                 }
             }
@@ -239,11 +572,140 @@ impl TaskGroup for MetricsFetchTaskGroup {
         })
     }
 
-    fn execute(&self, task_id: (ClusterId, Broker, i32)) {
+    fn execute(&self, task_id: (ClusterId, Broker)) {
         debug!("Starting fetch for {}: {}", task_id.0, task_id.1.id);
-        if let Err(e) = self.fetch_metrics(&task_id.0, &task_id.1, task_id.2) {
-            format_error_chain!(e);
+        if let Some(source) = self.source(&task_id.0) {
+            if let Err(e) = self.fetch_metrics(&task_id.0, &task_id.1, source.as_ref()) {
+                format_error_chain!(e);
+            }
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Output of the JMX exporter without rules, for a broker with a single topic
+    const BROKER_FIXTURE: &str = r#"# HELP kafka_server_BrokerTopicMetrics_Count Attribute exposed for management (kafka.server<type=BrokerTopicMetrics, name=BytesInPerSec, topic=orders><>Count)
+# TYPE kafka_server_BrokerTopicMetrics_Count untyped
+kafka_server_BrokerTopicMetrics_Count{name="BytesInPerSec",topic="orders",} 1.2345678E7
+kafka_server_BrokerTopicMetrics_Count{name="MessagesInPerSec",topic="orders",} 45678.0
+kafka_server_BrokerTopicMetrics_Count{name="BytesInPerSec",} 2.0E7
+# HELP kafka_server_BrokerTopicMetrics_FifteenMinuteRate Attribute exposed for management (kafka.server<type=BrokerTopicMetrics, name=BytesInPerSec, topic=orders><>FifteenMinuteRate)
+# TYPE kafka_server_BrokerTopicMetrics_FifteenMinuteRate untyped
+kafka_server_BrokerTopicMetrics_FifteenMinuteRate{name="BytesInPerSec",topic="orders",} 1024.5
+kafka_server_BrokerTopicMetrics_FifteenMinuteRate{name="MessagesInPerSec",topic="orders",} 10.25
+kafka_server_BrokerTopicMetrics_FifteenMinuteRate{name="BytesOutPerSec",topic="orders",} 2048.0
+# TYPE kafka_server_BrokerTopicMetrics_MeanRate untyped
+kafka_server_BrokerTopicMetrics_MeanRate{name="BytesInPerSec",topic="orders",} NaN
+# HELP kafka_log_Log_Value Attribute exposed for management (kafka.log<type=Log, name=Size, topic=orders, partition=0><>Value)
+# TYPE kafka_log_Log_Value untyped
+kafka_log_Log_Value{name="Size",topic="orders",partition="0",} 4096.0
+kafka_log_Log_Value{name="Size",topic="orders",partition="2",} 8192.0
+kafka_log_Log_Value{name="LogEndOffset",topic="orders",partition="0",} 100.0
+# TYPE jvm_memory_bytes_max gauge
+jvm_memory_bytes_max{area="nonheap",} -1.0
+jvm_memory_bytes_max{area="direct",} +Inf
+
+jmx_scrape_duration_seconds 0.123 1500000000000
+"#;
+
+    fn sample(text: &str) -> PrometheusSample {
+        let mut samples = parse_prometheus_text(text).unwrap();
+        assert_eq!(samples.len(), 1);
+        samples.remove(0)
+    }
+
+    #[test]
+    fn parses_jmx_exporter_output() {
+        let samples = parse_prometheus_text(BROKER_FIXTURE).unwrap();
+        assert_eq!(samples.len(), 13);
+
+        assert_eq!(samples[0].name, "kafka_server_BrokerTopicMetrics_Count");
+        assert_eq!(samples[0].labels.len(), 2);
+        assert_eq!(samples[0].labels["name"], "BytesInPerSec");
+        assert_eq!(samples[0].labels["topic"], "orders");
+        assert_eq!(samples[0].value, 12_345_678f64);
+        assert!(samples[6].value.is_nan());
+        assert_eq!(samples[11].value, f64::INFINITY);
+        assert_eq!(samples[12].name, "jmx_scrape_duration_seconds");
+        assert!(samples[12].labels.is_empty());
+        assert_eq!(samples[12].value, 0.123);  // the timestamp is ignored
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(sample("metric -Inf").value, f64::NEG_INFINITY);
+        assert_eq!(sample("metric{} 1e3").value, 1000f64);
+        assert_eq!(sample("metric{a=\"b\"} -2.5 1500000000000").value, -2.5);
+    }
+
+    #[test]
+    fn parses_escaped_label_values() {
+        let sample = sample(r#"metric{path="C:\\kafka\\logs",quote="say \"hi\"",lines="a\nb",braces="}{", spaced = "x"} 1"#);
+        assert_eq!(sample.labels["path"], r"C:\kafka\logs");
+        assert_eq!(sample.labels["quote"], "say \"hi\"");
+        assert_eq!(sample.labels["lines"], "a\nb");
+        assert_eq!(sample.labels["braces"], "}{");
+        assert_eq!(sample.labels["spaced"], "x");
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse_prometheus_text("metric").is_err());
+        assert!(parse_prometheus_text("metric abc").is_err());
+        assert!(parse_prometheus_text("metric{name=\"x} 1").is_err());
+        assert!(parse_prometheus_text("metric{name=x} 1").is_err());
+        assert!(parse_prometheus_text("metric{name=\"x\" 1").is_err());
+        assert!(parse_prometheus_text("# only a comment\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn classifies_rate_samples() {
+        let samples = parse_prometheus_text(BROKER_FIXTURE).unwrap();
+        let classes = samples.iter().map(classify_rate_sample).collect::<Vec<_>>();
+        assert_eq!(classes[0], Some((RateMetric::Bytes, RateKind::Counter)));
+        assert_eq!(classes[1], Some((RateMetric::Messages, RateKind::Counter)));
+        assert_eq!(classes[3], Some((RateMetric::Bytes, RateKind::FifteenMinuteRate)));
+        assert_eq!(classes[4], Some((RateMetric::Messages, RateKind::FifteenMinuteRate)));
+        assert_eq!(classes[5], None);  // bytes out
+        assert_eq!(classes[6], None);  // mean rate
+        assert_eq!(classes[7], None);  // partition size
+
+        // Names produced by the rules of the JMX exporter example configuration
+        let bytes = sample(r#"kafka_server_brokertopicmetrics_bytesin_total{topic="orders",} 10.0"#);
+        assert_eq!(classify_rate_sample(&bytes), Some((RateMetric::Bytes, RateKind::Counter)));
+        let messages = sample(r#"kafka_server_brokertopicmetrics_messagesin_total{topic="orders",} 1.0"#);
+        assert_eq!(classify_rate_sample(&messages), Some((RateMetric::Messages, RateKind::Counter)));
+    }
+
+    #[test]
+    fn computes_counter_rates() {
+        let source = PrometheusSource::new(7071, "/metrics");
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(source.counter_rate(1, RateMetric::Bytes, "orders", 100f64, at(0)), None);
+        assert_eq!(source.counter_rate(1, RateMetric::Bytes, "orders", 200f64, at(10)), Some(10f64));
+        assert_eq!(source.counter_rate(1, RateMetric::Bytes, "orders", 200f64, at(10)), None);
+        // The counter is reset when the broker restarts
+        assert_eq!(source.counter_rate(1, RateMetric::Bytes, "orders", 50f64, at(20)), None);
+        assert_eq!(source.counter_rate(1, RateMetric::Bytes, "orders", 150f64, at(30)), Some(10f64));
+        // Counters are tracked by broker, metric and topic
+        assert_eq!(source.counter_rate(2, RateMetric::Bytes, "orders", 1000f64, at(30)), None);
+        assert_eq!(source.counter_rate(1, RateMetric::Messages, "orders", 1000f64, at(30)), None);
+    }
+
+    #[test]
+    fn builds_broker_metrics() {
+        let source = PrometheusSource::new(7071, "/metrics");
+        let samples = parse_prometheus_text(BROKER_FIXTURE).unwrap();
+        let metrics = source.broker_metrics(1, &samples);
+        assert_eq!(metrics.byte_rate.get("orders"), Some(&1024.5));
+        assert_eq!(metrics.msg_rate.get("orders"), Some(&10.25));
+        assert_eq!(metrics.byte_rate.get("__TOTAL__"), None);  // first fetch of the counter
+        let sizes = metrics.partition_sizes["orders"].iter().map(|p| p.size_bytes).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![4096f64, 0f64, 8192f64]);
+    }
+}
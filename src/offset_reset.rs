@@ -0,0 +1,189 @@
+use rdkafka::{Offset, TopicPartitionList};
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer, EmptyConsumerContext};
+
+use cache::Cache;
+use config::ClusterConfig;
+use error::*;
+use message_browser::PartitionBrowser;
+use metadata::ClusterId;
+
+use std::cmp;
+
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResetStrategy {
+    Earliest,
+    Latest,
+    Offset { offset: i64 },
+    Timestamp { timestamp: i64 },  // millis since epoch
+    Shift { by: i64 },             // relative to the current offset
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResetRequest {
+    pub topic: String,
+    pub strategy: ResetStrategy,
+    #[serde(default)]
+    pub partitions: Option<Vec<i32>>,  // all partitions if missing
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PartitionReset {
+    pub partition: i32,
+    pub current: i64,  // -1 if the group has no offset for the partition
+    pub low: i64,
+    pub high: i64,
+    pub target: i64,
+}
+
+fn reset_consumer(cluster_config: &ClusterConfig, group_name: &str) -> Result<BaseConsumer<EmptyConsumerContext>> {
+    cluster_config.client_config()?
+        .set("group.id", group_name)
+        .set("enable.auto.commit", "false")
+        .create::<BaseConsumer<_>>()
+        .chain_err(|| "Failed to create rdkafka consumer")
+}
+
+/// Computes the new offset of a partition, within its watermarks. `offset_for_timestamp` looks
+/// up the offset of the first message at or after a time, for the timestamp strategy.
+fn target_offset<F>(strategy: &ResetStrategy, current: i64, low: i64, high: i64, offset_for_timestamp: F)
+        -> Result<i64>
+        where F: FnOnce(i64) -> Result<i64> {
+    let target = match *strategy {
+        ResetStrategy::Earliest => low,
+        ResetStrategy::Latest => high,
+        ResetStrategy::Offset { offset } => offset,
+        ResetStrategy::Timestamp { timestamp } => offset_for_timestamp(timestamp)?,
+        ResetStrategy::Shift { by } => {
+            if current < 0 {
+                bail!("Can't shift the offset: the group has no offset for the partition");
+            }
+            current.saturating_add(by)
+        },
+    };
+    Ok(cmp::min(cmp::max(target, low), high))
+}
+
+/// Returns the number of members of the group, as currently known by the broker. The cached
+/// group might be missing or outdated.
+fn active_members(consumer: &BaseConsumer<EmptyConsumerContext>, group_name: &str) -> Result<usize> {
+    let group_list = consumer.fetch_group_list(Some(group_name), 10000)
+        .chain_err(|| format!("Failed to fetch group {}", group_name))?;
+    Ok(group_list.groups().iter()
+        .filter(|group| group.name() == group_name)
+        .map(|group| group.members().len())
+        .sum())
+}
+
+/// Computes the new offsets of the group for the requested partitions and, unless it's a dry
+/// run, commits them. The group must have no active members, otherwise the new offsets would
+/// be overwritten by the next commit of the consumers.
+pub fn reset_offsets(cluster_config: &ClusterConfig, cluster_id: &ClusterId, group_name: &str,
+                     request: &ResetRequest, cache: &Cache) -> Result<Vec<PartitionReset>> {
+    if !cluster_config.enable_offset_reset {
+        bail!("Offset reset is disabled for cluster {}", cluster_id);
+    }
+    if let Some(group) = cache.groups.get(&(cluster_id.clone(), group_name.to_owned())) {
+        if !group.members.is_empty() {
+            bail!("Group {} has {} active members, stop the consumers before resetting the offsets",
+                  group_name, group.members.len());
+        }
+    }
+
+    let topic_partitions = match cache.topics.get(&(cluster_id.clone(), request.topic.clone())) {
+        Some(partitions) => partitions.iter().map(|p| p.id).collect::<Vec<_>>(),
+        None => bail!("Topic {} not found in cluster {}", request.topic, cluster_id),
+    };
+    let partitions = match request.partitions {
+        Some(ref requested) => {
+            if let Some(missing) = requested.iter().find(|p| !topic_partitions.contains(*p)) {
+                bail!("Partition {} not found in topic {}", missing, request.topic);
+            }
+            requested.clone()
+        },
+        None => topic_partitions,
+    };
+
+    let current_offsets = cache.offsets.get(&(cluster_id.clone(), group_name.to_owned(), request.topic.clone()))
+        .unwrap_or_else(Vec::new);
+    let consumer = reset_consumer(cluster_config, group_name)?;
+
+    let mut resets = Vec::with_capacity(partitions.len());
+    for partition in partitions {
+        let (low, high) = consumer.fetch_watermarks(&request.topic, partition, 10000)
+            .chain_err(|| format!("Failed to fetch watermarks for {}/{}", request.topic, partition))?;
+        let current = current_offsets.get(partition as usize).cloned().unwrap_or(-1);
+        let target = target_offset(&request.strategy, current, low, high, |timestamp| {
+                PartitionBrowser::new(cluster_config, &request.topic, partition)?
+                    .offset_for_timestamp(timestamp, low, high)
+            })
+            .chain_err(|| format!("Failed to compute target offset for {}/{}", request.topic, partition))?;
+        resets.push(PartitionReset { partition, current, low, high, target });
+    }
+
+    if !request.dry_run {
+        let members = active_members(&consumer, group_name)?;
+        if members > 0 {
+            bail!("Group {} has {} active members, stop the consumers before resetting the offsets",
+                  group_name, members);
+        }
+        let mut tp_list = TopicPartitionList::new();
+        for reset in &resets {
+            tp_list.add_partition_offset(&request.topic, reset.partition, Offset::Offset(reset.target));
+        }
+        consumer.commit(&tp_list, CommitMode::Sync)
+            .chain_err(|| format!("Failed to commit offsets for group {}", group_name))?;
+        info!("Offsets of group {} on {} reset: {:?}", group_name, request.topic, resets);
+    }
+
+    Ok(resets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(strategy: ResetStrategy, current: i64) -> Result<i64> {
+        target_offset(&strategy, current, 100, 200, |_| panic!("Unexpected timestamp lookup"))
+    }
+
+    #[test]
+    fn clamps_to_watermarks() {
+        assert_eq!(target(ResetStrategy::Earliest, 150).unwrap(), 100);
+        assert_eq!(target(ResetStrategy::Latest, 150).unwrap(), 200);
+        assert_eq!(target(ResetStrategy::Offset { offset: 120 }, 150).unwrap(), 120);
+        assert_eq!(target(ResetStrategy::Offset { offset: 10 }, 150).unwrap(), 100);
+        assert_eq!(target(ResetStrategy::Offset { offset: 1000 }, 150).unwrap(), 200);
+        assert_eq!(target(ResetStrategy::Offset { offset: -1 }, -1).unwrap(), 100);
+    }
+
+    #[test]
+    fn shifts_current_offset() {
+        assert_eq!(target(ResetStrategy::Shift { by: 20 }, 150).unwrap(), 170);
+        assert_eq!(target(ResetStrategy::Shift { by: -20 }, 150).unwrap(), 130);
+        assert_eq!(target(ResetStrategy::Shift { by: -100 }, 150).unwrap(), 100);
+        assert_eq!(target(ResetStrategy::Shift { by: 100 }, 150).unwrap(), 200);
+        assert_eq!(target(ResetStrategy::Shift { by: i64::max_value() }, 150).unwrap(), 200);
+        assert_eq!(target(ResetStrategy::Shift { by: i64::min_value() }, 150).unwrap(), 100);
+        // The group has no offset for the partition
+        assert!(target(ResetStrategy::Shift { by: 10 }, -1).is_err());
+    }
+
+    #[test]
+    fn looks_up_timestamps() {
+        let strategy = ResetStrategy::Timestamp { timestamp: 1_500_000_000_000 };
+        let lookup = |offset: i64| move |timestamp| {
+            assert_eq!(timestamp, 1_500_000_000_000);
+            Ok(offset)
+        };
+        assert_eq!(target_offset(&strategy, 150, 100, 200, lookup(180)).unwrap(), 180);
+        assert_eq!(target_offset(&strategy, 150, 100, 200, lookup(50)).unwrap(), 100);
+        // All the messages are older than the timestamp
+        assert_eq!(target_offset(&strategy, 150, 100, 200, lookup(200)).unwrap(), 200);
+        // The broker returned no offset for the partition
+        assert!(target_offset(&strategy, 150, 100, 200, |_| bail!("No offset returned")).is_err());
+    }
+}
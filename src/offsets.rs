@@ -1,25 +1,33 @@
 use byteorder::{BigEndian, ReadBytesExt};
-use futures::Stream;
+use futures::{future, Future, Stream};
+use futures_cpupool::Builder;
 use rdkafka::{Message, TopicPartitionList, Offset};
-use rdkafka::config::{ClientConfig, TopicConfig};
+use rdkafka::config::TopicConfig;
 use rdkafka::consumer::stream_consumer::StreamConsumer;
 use rdkafka::consumer::{Consumer, EmptyConsumerContext};
-use rdkafka::error::KafkaError;
+use rdkafka::error::{KafkaError, KafkaResult};
 
 use cache::{Cache, OffsetsCache};
-use config::{Config, ClusterConfig};
+use config::{ClusterConfig, SharedConfig};
 use error::*;
-use metadata::{ClusterId, TopicName};
+use metadata::{CONSUMERS, ClusterId, TopicName};
+use ownership::Ownership;
 use utils::{insert_at, read_string};
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Cursor;
 use std::str;
+use std::sync::Mutex;
 use std::thread;
 use std::time::{Instant, Duration};
 
 
+lazy_static! {
+    // Clusters with a running offset consumer thread
+    static ref RUNNING_CONSUMERS: Mutex<HashSet<ClusterId>> = Mutex::new(HashSet::new());
+}
+
 #[derive(Debug)]
 enum ConsumerUpdate {
     Metadata,
@@ -51,14 +59,13 @@ fn parse_message(key: &[u8], payload: &[u8]) -> Result<ConsumerUpdate> {
     }
 }
 
-fn create_consumer(brokers: &str, group_id: &str, start_offsets: Option<Vec<i64>>) -> Result<StreamConsumer<EmptyConsumerContext>> {
-    let consumer = ClientConfig::new()
+fn create_consumer(cluster_config: &ClusterConfig, group_id: &str, start_offsets: Option<Vec<i64>>)
+        -> Result<StreamConsumer<EmptyConsumerContext>> {
+    let consumer = cluster_config.client_config()?
         .set("group.id", group_id)
-        .set("bootstrap.servers", brokers)
         .set("enable.partition.eof", "false")
         .set("enable.auto.commit", "false")
         .set("session.timeout.ms", "30000")
-        .set("api.version.request", "true")
         //.set("fetch.message.max.bytes", "1024000") // Reduce memory usage
         .set("queued.min.messages", "10000") // Reduce memory usage
         .set("message.max.bytes", "10485760")
@@ -66,7 +73,7 @@ fn create_consumer(brokers: &str, group_id: &str, start_offsets: Option<Vec<i64>
             .set("auto.offset.reset", "smallest")
             .finalize())
         .create::<StreamConsumer<_>>()
-        .chain_err(|| format!("Consumer creation failed: {}", brokers))?;
+        .chain_err(|| format!("Consumer creation failed: {}", cluster_config.bootstrap_servers()))?;
 
     match start_offsets {
         Some(pos) => {
@@ -81,7 +88,8 @@ fn create_consumer(brokers: &str, group_id: &str, start_offsets: Option<Vec<i64>
         None => {
             debug!("No previous offsets found, subscribing to topic");
             consumer.subscribe(&["__consumer_offsets"])
-                .chain_err(|| format!("Can't subscribe to offset __consumer_offsets ({})", brokers))?;
+                .chain_err(|| format!("Can't subscribe to offset __consumer_offsets ({})",
+                                      cluster_config.bootstrap_servers()))?;
         }
     }
 
@@ -120,10 +128,27 @@ fn commit_offset_position_to_array(tp_list: TopicPartitionList) -> Vec<i64> {
     offsets
 }
 
+/// Returns the reason why the consumer should stop, if the cluster is no longer owned by this
+/// instance or its configuration changed.
+fn stop_reason(cluster_id: &ClusterId, cluster_config: &ClusterConfig, config: &SharedConfig,
+               ownership: &Ownership) -> Option<&'static str> {
+    if !ownership.is_owner(cluster_id) {
+        Some("owned by another instance")
+    } else if config.get().cluster(cluster_id) != Some(cluster_config) {
+        Some("configuration changed")
+    } else {
+        None
+    }
+}
+
+/// Consumes the topic until an error occurs or `stop_reason` returns a reason to stop.
 fn consume_offset_topic(
     cluster_id: ClusterId,
+    cluster_config: &ClusterConfig,
     consumer: StreamConsumer<EmptyConsumerContext>,
-    cache: &Cache
+    cache: &Cache,
+    config: &SharedConfig,
+    ownership: &Ownership,
 ) -> Result<()> {
     let mut local_cache = HashMap::new();
     let mut last_dump = Instant::now();
@@ -150,6 +175,12 @@ fn consume_offset_topic(
         };
         // Update the cache if needed
         if (Instant::now() - last_dump) > Duration::from_secs(10) {
+            if let Some(reason) = stop_reason(&cluster_id, cluster_config, config, ownership) {
+                // The next consumer will restart from the last stored position
+                info!("Stopping offset consumer for {}, {}", cluster_id, reason);
+                consumer.stop();
+                return Ok(());
+            }
             trace!("Dumping local offset cache ({}: {} updates)", cluster_id, local_cache.len());
             update_global_cache(&cluster_id, &local_cache, &cache.offsets);
             // Consumer position is not up to date after start, so we have to merge with the
@@ -195,27 +226,100 @@ pub fn vec_merge_in_place<F, T: Copy>(vec1: &mut Vec<T>, vec2: &Vec<T>, default:
 //        .collect::<Vec<T>>()
 //}
 
-pub fn run_offset_consumer(cluster_id: &ClusterId, cluster_config: &ClusterConfig,
-                           config: &Config, cache: &Cache) -> Result<()> {
-    let start_position = cache.internal_offsets.get(cluster_id);
-    let consumer = create_consumer(&cluster_config.bootstrap_servers(), &config.consumer_offsets_group_id,
-                                   start_position)
-        .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id))?;
+/// Returns the configuration of the cluster, or None if it has been removed from the
+/// configuration, in which case the cluster is also marked as not running.
+fn running_cluster_config(cluster_id: &ClusterId, config: &SharedConfig) -> Option<ClusterConfig> {
+    let mut running = RUNNING_CONSUMERS.lock().expect("Poison error");
+    let cluster_config = config.get().cluster(cluster_id).cloned();
+    if cluster_config.is_none() {
+        running.remove(cluster_id);
+    }
+    cluster_config
+}
 
+/// Starts the consumer of the `__consumer_offsets` topic of the cluster, unless already
+/// running. The topic is only consumed while the cluster is owned by this instance, starting
+/// from the position stored by the previous consumer. The consumer is restarted when the
+/// configuration of the cluster changes, and stopped when the cluster is removed.
+pub fn run_offset_consumer(cluster_id: &ClusterId, config: &SharedConfig, cache: &Cache,
+                           ownership: &Ownership) -> Result<()> {
+    if !RUNNING_CONSUMERS.lock().expect("Poison error").insert(cluster_id.clone()) {
+        return Ok(());
+    }
     let cluster_id_clone = cluster_id.clone();
+    let config_alias = config.alias();
     let cache_alias = cache.alias();
-    let _ = thread::Builder::new()
+    let ownership_alias = ownership.alias();
+    let result = thread::Builder::new()
         .name("offset-consumer".to_owned())
         .spawn(move || {
-            if let Err(e) = consume_offset_topic(cluster_id_clone, consumer, &cache_alias) {
-                format_error_chain!(e);
+            loop {
+                let cluster_config = match running_cluster_config(&cluster_id_clone, &config_alias) {
+                    Some(cluster_config) => cluster_config,
+                    None => {
+                        info!("Offset consumer for {} terminated, cluster removed", cluster_id_clone);
+                        return;
+                    },
+                };
+                if !ownership_alias.is_owner(&cluster_id_clone) {
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+                info!("Starting offset consumer for {}", cluster_id_clone);
+                let group_id = config_alias.get().consumer_offsets_group_id.clone();
+                let start_position = cache_alias.internal_offsets.get(&cluster_id_clone);
+                let result = create_consumer(&cluster_config, &group_id, start_position)
+                    .chain_err(|| format!("Failed to create offset consumer for {}", cluster_id_clone))
+                    .and_then(|consumer| {
+                        consume_offset_topic(cluster_id_clone.clone(), &cluster_config, consumer,
+                                             &cache_alias, &config_alias, &ownership_alias)
+                    });
+                if let Err(e) = result {
+                    format_error_chain!(e);
+                    thread::sleep(Duration::from_secs(10));
+                }
             }
-        })
-        .chain_err(|| "Failed to start offset consumer thread")?;
+        });
+    if let Err(e) = result {
+        RUNNING_CONSUMERS.lock().expect("Poison error").remove(cluster_id);
+        return Err(e).chain_err(|| "Failed to start offset consumer thread");
+    }
 
     Ok(())
 }
 
+/// Fetches the low and high watermarks of every partition that has a stored offset in `offsets`.
+/// Each partition is only fetched once, even if multiple groups are consuming from it.
+pub fn fetch_watermarks(cluster_id: &ClusterId, offsets: &[((ClusterId, String, TopicName), Vec<i64>)])
+        -> Result<HashMap<(TopicName, i32), KafkaResult<(i64, i64)>>> {
+    let consumer = CONSUMERS.get_err(cluster_id)?;
+
+    let cpu_pool = Builder::new().pool_size(32).create();
+
+    let mut futures = Vec::new();
+    let mut requested = HashSet::new();
+
+    for &((_, _, ref topic), ref partitions) in offsets {
+        for partition_id in 0..partitions.len() {
+            if !requested.insert((topic.clone(), partition_id)) {
+                continue;
+            }
+            let consumer_clone = consumer.clone();
+            let topic_clone = topic.clone();
+            let wm_future = cpu_pool.spawn_fn(move || {
+                let wms = consumer_clone.fetch_watermarks(&topic_clone, partition_id as i32, 10000);
+                Ok::<_, ()>(((topic_clone, partition_id as i32), wms))  // never fail
+            });
+            futures.push(wm_future);
+        }
+    }
+
+    let watermarks = future::join_all(futures).wait().unwrap()
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    Ok(watermarks)
+}
 
 pub trait OffsetStore {
     fn offsets_by_cluster(&self, cluster_id: &ClusterId)
@@ -0,0 +1,240 @@
+use rdkafka::util::millis_to_epoch;
+use scheduled_executor::TaskGroup;
+
+use cache::LeaseCache;
+use config::{Config, SharedConfig};
+use error::*;
+use metadata::ClusterId;
+
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+
+/// A time limited claim of an instance on the fetch work of a cluster.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Lease {
+    pub owner: String,  // instance id
+    pub acquired: u64,  // millis since epoch
+    pub expires: u64,   // millis since epoch
+}
+
+impl Lease {
+    pub fn is_valid(&self, now: u64) -> bool {
+        self.expires > now
+    }
+}
+
+fn now_millis() -> u64 {
+    millis_to_epoch(SystemTime::now()) as u64
+}
+
+/// Decides which instance fetches the data of each cluster, among the instances sharing the
+/// cache storage. Each cluster is owned by the instance holding a valid lease, stored in the
+/// replicated cache. The owner renews the lease periodically; when it stops doing so, the
+/// lease expires and is acquired by another instance.
+///
+/// If two instances acquire the same lease at the same time, the most recent write wins on
+/// both of them once it has been replicated, and the other instance steps back.
+pub struct Ownership {
+    leases: LeaseCache,
+    instance_id: String,
+    lease_duration: u64,  // millis
+}
+
+impl Ownership {
+    pub fn new(leases: &LeaseCache, config: &Config) -> Ownership {
+        Ownership {
+            leases: leases.alias(),
+            instance_id: config.instance_id.clone(),
+            lease_duration: config.lease_duration * 1000,
+        }
+    }
+
+    pub fn alias(&self) -> Ownership {
+        Ownership {
+            leases: self.leases.alias(),
+            instance_id: self.instance_id.clone(),
+            lease_duration: self.lease_duration,
+        }
+    }
+
+    pub fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    /// Returns true if this instance holds a valid lease on the cluster.
+    pub fn is_owner(&self, cluster_id: &ClusterId) -> bool {
+        match self.leases.get(cluster_id) {
+            Some(lease) => lease.owner == self.instance_id && lease.is_valid(now_millis()),
+            None => false,
+        }
+    }
+
+    /// Returns the clusters currently owned by this instance.
+    pub fn owned_clusters(&self) -> HashSet<ClusterId> {
+        let now = now_millis();
+        self.leases.filter_clone(|_| true).into_iter()
+            .filter(|&(_, ref lease)| lease.owner == self.instance_id && lease.is_valid(now))
+            .map(|(cluster_id, _)| cluster_id)
+            .collect()
+    }
+
+    /// Returns the lease of every cluster having one, valid or not.
+    pub fn leases(&self) -> Vec<(ClusterId, Lease)> {
+        let mut leases = self.leases.filter_clone(|_| true);
+        leases.sort_by(|a, b| a.0.cmp(&b.0));
+        leases
+    }
+
+    /// Renews the lease of the cluster if owned by this instance, or acquires it if no other
+    /// instance holds a valid one.
+    pub fn renew(&self, cluster_id: &ClusterId) -> Result<()> {
+        let now = now_millis();
+        let acquired = match self.leases.get(cluster_id) {
+            Some(ref lease) if lease.is_valid(now) && lease.owner != self.instance_id => return Ok(()),
+            Some(ref lease) if lease.is_valid(now) => lease.acquired,
+            Some(ref lease) => {
+                info!("Acquiring expired lease on {} (previous owner: {})", cluster_id, lease.owner);
+                now
+            },
+            None => {
+                info!("Acquiring lease on {}", cluster_id);
+                now
+            },
+        };
+        let lease = Lease {
+            owner: self.instance_id.clone(),
+            acquired,
+            expires: now + self.lease_duration,
+        };
+        self.leases.insert(cluster_id.clone(), lease)
+            .chain_err(|| format!("Failed to renew lease on {}", cluster_id))
+    }
+}
+
+/// Periodically renews or acquires the lease of every configured cluster.
+pub struct LeaseRenewalTaskGroup {
+    ownership: Ownership,
+    config: SharedConfig,
+}
+
+impl LeaseRenewalTaskGroup {
+    pub fn new(ownership: &Ownership, config: &SharedConfig) -> LeaseRenewalTaskGroup {
+        LeaseRenewalTaskGroup {
+            ownership: ownership.alias(),
+            config: config.alias(),
+        }
+    }
+}
+
+impl TaskGroup for LeaseRenewalTaskGroup {
+    type TaskId = ClusterId;
+
+    fn get_tasks(&self) -> Vec<ClusterId> {
+        self.config.get().clusters.keys().cloned().collect::<Vec<_>>()
+    }
+
+    fn execute(&self, cluster_id: ClusterId) {
+        if let Err(e) = self.ownership.renew(&cluster_id) {
+            format_error_chain!(e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cache::{Cache, ReplicaReader, ReplicaWriter};
+    use config::CacheEncoding;
+    use storage::{CacheStorage, MemoryStorage};
+
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// An instance sharing the storage with the others, loaded from its current content.
+    fn instance(storage: &Arc<CacheStorage>, instance_id: &str, lease_duration: u64) -> (Cache, Ownership) {
+        let cache = Cache::new(ReplicaWriter::new(storage.clone(), CacheEncoding::Json, instance_id));
+        let ownership = Ownership {
+            leases: cache.leases.alias(),
+            instance_id: instance_id.to_owned(),
+            lease_duration,
+        };
+        replicate(storage, &cache);
+        (cache, ownership)
+    }
+
+    /// Applies the updates written by the other instances.
+    fn replicate(storage: &Arc<CacheStorage>, cache: &Cache) {
+        ReplicaReader::new(storage.clone(), "reader").load_state(cache.alias()).unwrap();
+    }
+
+    fn cluster() -> ClusterId {
+        ClusterId::from("local")
+    }
+
+    #[test]
+    fn acquires_and_renews_leases() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let (_cache, ownership) = instance(&storage, "a", 60_000);
+        assert!(!ownership.is_owner(&cluster()));
+
+        ownership.renew(&cluster()).unwrap();
+        let lease = ownership.leases.get(&cluster()).unwrap();
+        assert!(ownership.is_owner(&cluster()));
+        assert_eq!(ownership.owned_clusters().into_iter().collect::<Vec<_>>(), vec![cluster()]);
+        assert_eq!(lease.expires, lease.acquired + 60_000);
+
+        thread::sleep(Duration::from_millis(5));
+        ownership.renew(&cluster()).unwrap();
+        let renewed = ownership.leases.get(&cluster()).unwrap();
+        assert_eq!(renewed.acquired, lease.acquired);
+        assert!(renewed.expires > lease.expires);
+        assert_eq!(storage.load().unwrap().entries.len(), 1);
+    }
+
+    #[test]
+    fn takes_over_expired_leases_only() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let (_cache_a, owner) = instance(&storage, "a", 60_000);
+        owner.renew(&cluster()).unwrap();
+
+        let (_cache_b, other) = instance(&storage, "b", 60_000);
+        other.renew(&cluster()).unwrap();
+        assert!(!other.is_owner(&cluster()));
+        assert_eq!(other.leases.get(&cluster()).unwrap().owner, "a");
+
+        // An instance with no lease duration lets its lease expire immediately
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let (_cache_a, owner) = instance(&storage, "a", 0);
+        owner.renew(&cluster()).unwrap();
+        assert!(!owner.is_owner(&cluster()));
+
+        let (_cache_b, other) = instance(&storage, "b", 60_000);
+        other.renew(&cluster()).unwrap();
+        assert!(other.is_owner(&cluster()));
+        assert_eq!(other.leases().len(), 1);
+    }
+
+    #[test]
+    fn steps_back_when_another_instance_wins() {
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let (cache_a, a) = instance(&storage, "a", 60_000);
+        let (cache_b, b) = instance(&storage, "b", 60_000);
+
+        // Both acquire the lease before seeing the other's write: the most recent one wins
+        a.renew(&cluster()).unwrap();
+        thread::sleep(Duration::from_millis(5));
+        b.renew(&cluster()).unwrap();
+        assert!(a.is_owner(&cluster()) && b.is_owner(&cluster()));
+
+        replicate(&storage, &cache_a);
+        replicate(&storage, &cache_b);
+        assert!(!a.is_owner(&cluster()));
+        assert!(b.is_owner(&cluster()));
+
+        a.renew(&cluster()).unwrap();
+        assert_eq!(a.leases.get(&cluster()).unwrap().owner, "b");
+        assert!(a.owned_clusters().is_empty());
+    }
+}
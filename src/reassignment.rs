@@ -0,0 +1,375 @@
+use serde_json::Value;
+
+use error::*;
+use metadata::{BrokerId, Partition, TopicName};
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+
+/// Replicas and bytes stored by a broker.
+#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+pub struct BrokerLoad {
+    pub replicas: usize,
+    pub bytes: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PartitionAssignment {
+    pub topic: TopicName,
+    pub partition: i32,
+    pub replicas: Vec<BrokerId>,
+}
+
+/// A new assignment of the replicas, with the load of each broker before and after applying it.
+#[derive(Serialize, Debug, Clone)]
+pub struct ReassignmentPlan {
+    pub assignments: Vec<PartitionAssignment>,  // only the partitions whose replicas change
+    pub before: BTreeMap<BrokerId, BrokerLoad>,
+    pub after: BTreeMap<BrokerId, BrokerLoad>,
+    pub moved_replicas: usize,
+    pub moved_bytes: f64,
+}
+
+impl ReassignmentPlan {
+    /// Returns the plan in the format accepted by `kafka-reassign-partitions.sh --reassignment-json-file`.
+    pub fn to_reassignment_json(&self) -> Value {
+        let partitions = self.assignments.iter()
+            .map(|a| json!({"topic": a.topic, "partition": a.partition, "replicas": a.replicas}))
+            .collect::<Vec<_>>();
+        json!({"version": 1, "partitions": partitions})
+    }
+}
+
+struct PlannedPartition {
+    topic: TopicName,
+    id: i32,
+    size: f64,
+    original: Vec<BrokerId>,
+    replicas: Vec<BrokerId>,
+}
+
+fn compare_f64(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+fn compute_loads(partitions: &[PlannedPartition], brokers: &BTreeSet<BrokerId>, original: bool)
+        -> BTreeMap<BrokerId, BrokerLoad> {
+    let mut loads = brokers.iter()
+        .map(|&broker_id| (broker_id, BrokerLoad::default()))
+        .collect::<BTreeMap<_, _>>();
+    for partition in partitions {
+        let replicas = if original { &partition.original } else { &partition.replicas };
+        for &broker_id in replicas {
+            let load = loads.entry(broker_id).or_insert_with(BrokerLoad::default);
+            load.replicas += 1;
+            load.bytes += partition.size;
+        }
+    }
+    loads
+}
+
+fn by_replicas(a: &BrokerLoad, b: &BrokerLoad) -> Ordering {
+    a.replicas.cmp(&b.replicas).then(compare_f64(a.bytes, b.bytes))
+}
+
+fn by_bytes(a: &BrokerLoad, b: &BrokerLoad) -> Ordering {
+    compare_f64(a.bytes, b.bytes)
+}
+
+/// Returns the most and the least loaded brokers according to the ordering.
+fn extremes<F>(loads: &BTreeMap<BrokerId, BrokerLoad>, compare: F)
+        -> Option<((BrokerId, BrokerLoad), (BrokerId, BrokerLoad))>
+        where F: Fn(&BrokerLoad, &BrokerLoad) -> Ordering {
+    let most = loads.iter()
+        .max_by(|&(_, a), &(_, b)| compare(a, b))
+        .map(|(&broker_id, load)| (broker_id, load.clone()));
+    let least = loads.iter()
+        .min_by(|&(_, a), &(_, b)| compare(a, b))
+        .map(|(&broker_id, load)| (broker_id, load.clone()));
+    match (most, least) {
+        (Some(most), Some(least)) => Some((most, least)),
+        _ => None,
+    }
+}
+
+/// Returns the target broker with the fewest replicas (and then bytes) not in `exclude`.
+fn least_loaded(loads: &BTreeMap<BrokerId, BrokerLoad>, exclude: &[BrokerId]) -> Option<BrokerId> {
+    loads.iter()
+        .filter(|&(broker_id, _)| !exclude.contains(broker_id))
+        .min_by(|&(_, a), &(_, b)| by_replicas(a, b))
+        .map(|(&broker_id, _)| broker_id)
+}
+
+fn move_replica(partition: &mut PlannedPartition, loads: &mut BTreeMap<BrokerId, BrokerLoad>,
+                from: BrokerId, to: BrokerId) {
+    if let Some(position) = partition.replicas.iter().position(|&b| b == from) {
+        partition.replicas[position] = to;  // the position, and so the preferred leader, is kept
+    }
+    if let Some(load) = loads.get_mut(&from) {
+        load.replicas -= 1;
+        load.bytes -= partition.size;
+    }
+    let load = loads.entry(to).or_insert_with(BrokerLoad::default);
+    load.replicas += 1;
+    load.bytes += partition.size;
+}
+
+/// Moves the replicas hosted outside of the target brokers, largest partitions first.
+fn evacuate(partitions: &mut [PlannedPartition], loads: &mut BTreeMap<BrokerId, BrokerLoad>) {
+    let mut order = (0..partitions.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| compare_f64(partitions[b].size, partitions[a].size));
+    for index in order {
+        let partition = &mut partitions[index];
+        for position in 0..partition.replicas.len() {
+            let broker_id = partition.replicas[position];
+            if loads.contains_key(&broker_id) {
+                continue;
+            }
+            let target = least_loaded(loads, &partition.replicas);
+            if let Some(target) = target {
+                partition.replicas[position] = target;
+                let load = loads.get_mut(&target).unwrap();
+                load.replicas += 1;
+                load.bytes += partition.size;
+            }
+        }
+    }
+}
+
+/// Moves replicas from the brokers with most replicas to the ones with fewest, until the
+/// counts differ by at most one. Among the candidates, the replica that best evens out the
+/// bytes is moved.
+fn balance_replicas(partitions: &mut [PlannedPartition], loads: &mut BTreeMap<BrokerId, BrokerLoad>) {
+    loop {
+        let ((source, source_load), (target, target_load)) = match extremes(loads, by_replicas) {
+            Some(extremes) => extremes,
+            None => return,
+        };
+        if source_load.replicas <= target_load.replicas + 1 {
+            return;
+        }
+        let candidate = partitions.iter()
+            .enumerate()
+            .filter(|&(_, p)| p.replicas.contains(&source) && !p.replicas.contains(&target))
+            .min_by(|&(_, a), &(_, b)| {
+                let imbalance = |size: f64| ((source_load.bytes - size) - (target_load.bytes + size)).abs();
+                compare_f64(imbalance(a.size), imbalance(b.size))
+            })
+            .map(|(index, _)| index);
+        match candidate {
+            Some(index) => move_replica(&mut partitions[index], loads, source, target),
+            None => return,  // every partition on the source is already on the target
+        }
+    }
+}
+
+/// Swaps replicas between the brokers with the most and the fewest bytes, as long as each
+/// swap significantly reduces the difference. Replica counts are not affected.
+fn balance_bytes(partitions: &mut [PlannedPartition], loads: &mut BTreeMap<BrokerId, BrokerLoad>) {
+    let total_bytes = loads.values().map(|load| load.bytes).sum::<f64>();
+    let tolerance = total_bytes / loads.len() as f64 * 0.1;
+    for _ in 0..partitions.len() {
+        let ((heavy, heavy_load), (light, light_load)) = match extremes(loads, by_bytes) {
+            Some(extremes) => extremes,
+            None => return,
+        };
+        let gap = heavy_load.bytes - light_load.bytes;
+        if gap <= tolerance {
+            return;
+        }
+        let heavy_candidates = partitions.iter().enumerate()
+            .filter(|&(_, p)| p.replicas.contains(&heavy) && !p.replicas.contains(&light))
+            .map(|(index, p)| (index, p.size))
+            .collect::<Vec<_>>();
+        let light_candidates = partitions.iter().enumerate()
+            .filter(|&(_, p)| p.replicas.contains(&light) && !p.replicas.contains(&heavy))
+            .map(|(index, p)| (index, p.size))
+            .collect::<Vec<_>>();
+        let mut best: Option<(usize, usize, f64)> = None;
+        for &(heavy_index, heavy_size) in &heavy_candidates {
+            for &(light_index, light_size) in &light_candidates {
+                let delta = heavy_size - light_size;
+                let new_gap = (gap - 2f64 * delta).abs();
+                if delta > 0f64 && new_gap < gap - tolerance
+                        && best.map(|(_, _, best_gap)| new_gap < best_gap).unwrap_or(true) {
+                    best = Some((heavy_index, light_index, new_gap));
+                }
+            }
+        }
+        match best {
+            Some((heavy_index, light_index, _)) => {
+                move_replica(&mut partitions[heavy_index], loads, heavy, light);
+                move_replica(&mut partitions[light_index], loads, light, heavy);
+            },
+            None => return,
+        }
+    }
+}
+
+/// Generates a plan that moves all the replicas to the target brokers, balancing the number
+/// of replicas and the bytes stored by each of them. To minimise data movement, replicas are
+/// only moved away from brokers that are not in the target set, or from the most loaded
+/// brokers. The size of each partition is looked up in `sizes`, and missing sizes count as
+/// zero.
+pub fn plan_reassignment(topics: &BTreeMap<TopicName, Vec<Partition>>, sizes: &HashMap<(TopicName, i32), f64>,
+                         target_brokers: &[BrokerId]) -> Result<ReassignmentPlan> {
+    let targets = target_brokers.iter().cloned().collect::<BTreeSet<_>>();
+    if targets.is_empty() {
+        bail!("No target broker specified");
+    }
+
+    let mut partitions = Vec::new();
+    for (topic, topic_partitions) in topics {
+        for partition in topic_partitions {
+            if partition.replicas.len() > targets.len() {
+                bail!("Partition {}/{} has {} replicas, but only {} target brokers were specified",
+                      topic, partition.id, partition.replicas.len(), targets.len());
+            }
+            partitions.push(PlannedPartition {
+                topic: topic.clone(),
+                id: partition.id,
+                size: sizes.get(&(topic.clone(), partition.id)).cloned().unwrap_or(0f64).max(0f64),
+                original: partition.replicas.clone(),
+                replicas: partition.replicas.clone(),
+            });
+        }
+    }
+
+    let mut all_brokers = targets.clone();
+    for partition in &partitions {
+        all_brokers.extend(partition.original.iter().cloned());
+    }
+    let before = compute_loads(&partitions, &all_brokers, true);
+
+    // Only the target brokers are considered during planning
+    let mut loads = compute_loads(&partitions, &targets, true).into_iter()
+        .filter(|&(broker_id, _)| targets.contains(&broker_id))
+        .collect::<BTreeMap<_, _>>();
+    evacuate(&mut partitions, &mut loads);
+    balance_replicas(&mut partitions, &mut loads);
+    balance_bytes(&mut partitions, &mut loads);
+
+    let after = compute_loads(&partitions, &all_brokers, false);
+    let mut assignments = Vec::new();
+    let mut moved_replicas = 0;
+    let mut moved_bytes = 0f64;
+    for partition in partitions {
+        if partition.replicas == partition.original {
+            continue;
+        }
+        let new_replicas = partition.replicas.iter().filter(|b| !partition.original.contains(b)).count();
+        moved_replicas += new_replicas;
+        moved_bytes += new_replicas as f64 * partition.size;
+        assignments.push(PartitionAssignment {
+            topic: partition.topic,
+            partition: partition.id,
+            replicas: partition.replicas,
+        });
+    }
+
+    Ok(ReassignmentPlan { assignments, before, after, moved_replicas, moved_bytes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(id: i32, replicas: Vec<BrokerId>) -> Partition {
+        Partition { id, leader: replicas[0], replicas: replicas.clone(), isr: replicas, error: None }
+    }
+
+    fn topology(replicas: Vec<Vec<BrokerId>>) -> BTreeMap<TopicName, Vec<Partition>> {
+        let partitions = replicas.into_iter().enumerate()
+            .map(|(id, replicas)| partition(id as i32, replicas))
+            .collect();
+        let mut topics = BTreeMap::new();
+        topics.insert("topic".to_owned(), partitions);
+        topics
+    }
+
+    fn sizes(sizes: &[f64]) -> HashMap<(TopicName, i32), f64> {
+        sizes.iter().enumerate()
+            .map(|(id, &size)| (("topic".to_owned(), id as i32), size))
+            .collect()
+    }
+
+    fn replicas_of(plan: &ReassignmentPlan, topics: &BTreeMap<TopicName, Vec<Partition>>) -> Vec<Vec<BrokerId>> {
+        topics["topic"].iter()
+            .map(|p| plan.assignments.iter()
+                .find(|a| a.partition == p.id)
+                .map(|a| a.replicas.clone())
+                .unwrap_or_else(|| p.replicas.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn evacuates_removed_broker() {
+        let topics = topology(vec![vec![1, 3], vec![2, 3], vec![3, 1]]);
+        let plan = plan_reassignment(&topics, &sizes(&[1f64, 1f64, 1f64]), &[1, 2]).unwrap();
+
+        for replicas in replicas_of(&plan, &topics) {
+            assert!(replicas.iter().all(|&b| b == 1 || b == 2), "{:?}", replicas);
+            assert_ne!(replicas[0], replicas[1]);
+        }
+        assert_eq!(plan.after[&3].replicas, 0);
+        assert_eq!(plan.moved_replicas, 3);
+    }
+
+    #[test]
+    fn balances_replica_counts_on_added_broker() {
+        let topics = topology(vec![vec![1], vec![2], vec![1], vec![2], vec![1], vec![2]]);
+        let plan = plan_reassignment(&topics, &sizes(&[1f64; 6]), &[1, 2, 3]).unwrap();
+
+        for broker_id in 1..4 {
+            assert_eq!(plan.after[&broker_id].replicas, 2);
+        }
+        assert_eq!(plan.moved_replicas, 2);
+    }
+
+    #[test]
+    fn balances_skewed_bytes() {
+        let topics = topology(vec![vec![1], vec![1], vec![2], vec![2]]);
+        let plan = plan_reassignment(&topics, &sizes(&[60f64, 50f64, 10f64, 0f64]), &[1, 2]).unwrap();
+
+        assert_eq!(plan.before[&1].bytes, 110f64);
+        assert_eq!(plan.after[&1].replicas, 2);
+        assert_eq!(plan.after[&2].replicas, 2);
+        assert_eq!(plan.after[&1].bytes, 60f64);
+        assert_eq!(plan.after[&2].bytes, 60f64);
+    }
+
+    #[test]
+    fn move_replica_keeps_preferred_leader_position() {
+        let mut planned = PlannedPartition {
+            topic: "topic".to_owned(),
+            id: 0,
+            size: 5f64,
+            original: vec![1, 2, 3],
+            replicas: vec![1, 2, 3],
+        };
+        let mut loads = BTreeMap::new();
+        loads.insert(1, BrokerLoad { replicas: 1, bytes: 5f64 });
+        move_replica(&mut planned, &mut loads, 1, 4);
+
+        assert_eq!(planned.replicas, vec![4, 2, 3]);
+        assert_eq!(loads[&1], BrokerLoad { replicas: 0, bytes: 0f64 });
+        assert_eq!(loads[&4], BrokerLoad { replicas: 1, bytes: 5f64 });
+    }
+
+    #[test]
+    fn rejects_more_replicas_than_targets() {
+        let topics = topology(vec![vec![1, 2, 3]]);
+        assert!(plan_reassignment(&topics, &sizes(&[1f64]), &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn balanced_topology_is_noop() {
+        let topics = topology(vec![vec![1, 2], vec![2, 1], vec![1, 2], vec![2, 1]]);
+        let plan = plan_reassignment(&topics, &sizes(&[10f64; 4]), &[1, 2]).unwrap();
+
+        assert_eq!(plan.moved_replicas, 0);
+        assert!(plan.assignments.is_empty());
+        assert_eq!(plan.before, plan.after);
+    }
+}
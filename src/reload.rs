@@ -0,0 +1,255 @@
+use libc;
+
+use cache::Cache;
+use config::{read_config_files, CachingBackend, Config, SharedConfig};
+use config_check::validate;
+use error::*;
+use metadata::{ClusterId, CONSUMERS};
+use offsets::run_offset_consumer;
+use ownership::Ownership;
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+use std::time::SystemTime;
+
+
+static RELOAD_REQUESTED: AtomicBool = ATOMIC_BOOL_INIT;
+
+extern "C" fn handle_sighup(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Makes SIGHUP request a configuration reload, performed by the next `reload_if_needed`.
+pub fn reload_on_sighup() {
+    unsafe {
+        libc::signal(libc::SIGHUP, handle_sighup as libc::sighandler_t);
+    }
+}
+
+/// Clusters added, removed or modified by a configuration reload.
+#[derive(Serialize, Debug, Default)]
+pub struct ConfigChanges {
+    pub added: Vec<ClusterId>,
+    pub removed: Vec<ClusterId>,
+    pub modified: Vec<ClusterId>,
+    pub restart_required: Vec<&'static str>,  // changed settings only applied after a restart
+}
+
+pub fn diff_configs(old: &Config, new: &Config) -> ConfigChanges {
+    let mut changes = ConfigChanges::default();
+    for (cluster_id, cluster_config) in &new.clusters {
+        match old.cluster(cluster_id) {
+            Some(old_cluster_config) if old_cluster_config != cluster_config => {
+                changes.modified.push(cluster_id.clone());
+            },
+            Some(_) => {},
+            None => changes.added.push(cluster_id.clone()),
+        }
+    }
+    for cluster_id in old.clusters.keys() {
+        if new.cluster(cluster_id).is_none() {
+            changes.removed.push(cluster_id.clone());
+        }
+    }
+    changes.added.sort();
+    changes.removed.sort();
+    changes.modified.sort();
+
+    let settings: Vec<(&'static str, bool)> = vec![
+        ("instance_id", old.instance_id != new.instance_id),
+        ("lease_duration", old.lease_duration != new.lease_duration),
+        ("listen_host", old.listen_host != new.listen_host),
+        ("listen_port", old.listen_port != new.listen_port),
+        ("metadata_refresh", old.metadata_refresh != new.metadata_refresh),
+        ("metrics_refresh", old.metrics_refresh != new.metrics_refresh),
+        ("lag_history_interval", old.lag_history_interval != new.lag_history_interval),
+        ("events_history_size", old.events_history_size != new.events_history_size),
+        ("caching", old.caching != new.caching),
+        ("alerts.evaluation_interval", old.alerts.evaluation_interval != new.alerts.evaluation_interval),
+        ("decoders", old.decoders != new.decoders),
+        ("kafka_tools_path", old.kafka_tools_path != new.kafka_tools_path),
+        ("audit_log", old.audit_log != new.audit_log),
+        ("auth", old.auth != new.auth),
+    ];
+    changes.restart_required = settings.into_iter()
+        .filter(|&(_, changed)| changed)
+        .map(|(name, _)| name)
+        .collect();
+    changes
+}
+
+/// Checks that the new configuration can be used by the running instance.
+fn check_reload(config: &Config) -> Result<()> {
+    let problems = validate(config);
+    for problem in &problems {
+        warn!("Configuration problem: {}", problem);
+    }
+    if !problems.is_empty() {
+        bail!("Found {} problems in the configuration", problems.len());
+    }
+    for (cluster_id, cluster_config) in &config.clusters {
+        cluster_config.client_config()
+            .chain_err(|| format!("Invalid configuration for cluster {}", cluster_id))?;
+    }
+    if config.caching.backend == CachingBackend::Kafka {
+        if let Some(ref cluster_id) = config.caching.cluster {
+            if config.cluster(cluster_id).is_none() {
+                bail!("The cache cluster {} can't be removed", cluster_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the most recent modification time of the files.
+fn latest_modified(paths: &[PathBuf]) -> Option<SystemTime> {
+    paths.iter()
+        .filter_map(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .max()
+}
+
+/// The files and directories the configuration was read from, including the included ones.
+struct WatchedFiles {
+    paths: Vec<PathBuf>,
+    last_modified: Option<SystemTime>,
+}
+
+/// Reloads the configuration, applying the changes to the clusters without restarting.
+/// Metadata consumers of modified and removed clusters are dropped and created again on
+/// demand, while offset consumers are started for the new clusters and restart or stop by
+/// themselves when their cluster changes. An invalid configuration is rejected, leaving the
+/// current one in use.
+pub struct ConfigReloader {
+    path: String,
+    overrides: Vec<String>,  // applied again at every reload
+    config: SharedConfig,
+    cache: Cache,
+    ownership: Ownership,
+    watched: Arc<Mutex<WatchedFiles>>,  // also serializes the reloads
+}
+
+impl ConfigReloader {
+    pub fn new(path: &str, overrides: &[String], files: Vec<PathBuf>, config: &SharedConfig, cache: &Cache,
+               ownership: &Ownership) -> ConfigReloader {
+        let last_modified = latest_modified(&files);
+        ConfigReloader {
+            path: path.to_owned(),
+            overrides: overrides.to_vec(),
+            config: config.alias(),
+            cache: cache.alias(),
+            ownership: ownership.alias(),
+            watched: Arc::new(Mutex::new(WatchedFiles { paths: files, last_modified })),
+        }
+    }
+
+    pub fn alias(&self) -> ConfigReloader {
+        ConfigReloader {
+            path: self.path.clone(),
+            overrides: self.overrides.clone(),
+            config: self.config.alias(),
+            cache: self.cache.alias(),
+            ownership: self.ownership.alias(),
+            watched: self.watched.clone(),
+        }
+    }
+
+    pub fn reload(&self) -> Result<ConfigChanges> {
+        let mut watched = self.watched.lock().expect("Poison error");
+        let last_modified = latest_modified(&watched.paths);
+        watched.last_modified = last_modified;
+
+        let (new_config, files) = read_config_files(&self.path, &self.overrides)
+            .chain_err(|| format!("Unable to load configuration from '{}'", self.path))?;
+        check_reload(&new_config)
+            .chain_err(|| format!("Invalid configuration in '{}'", self.path))?;
+        // Files might have been included or removed
+        watched.last_modified = latest_modified(&files);
+        watched.paths = files;
+        let changes = diff_configs(&self.config.get(), &new_config);
+
+        for cluster_id in changes.removed.iter().chain(changes.modified.iter()) {
+            CONSUMERS.remove(cluster_id);
+        }
+        self.config.replace(new_config);
+        for cluster_id in &changes.added {
+            if let Err(e) = self.ownership.renew(cluster_id) {
+                format_error_chain!(e);
+            }
+            run_offset_consumer(cluster_id, &self.config, &self.cache, &self.ownership)
+                .chain_err(|| format!("Failed to start offset consumer for {}", cluster_id))?;
+        }
+
+        info!("Configuration reloaded (added: {:?}, removed: {:?}, modified: {:?})",
+              changes.added, changes.removed, changes.modified);
+        if !changes.restart_required.is_empty() {
+            warn!("Changes to {} will only be applied after a restart", changes.restart_required.join(", "));
+        }
+        Ok(changes)
+    }
+
+    /// Reloads the configuration if any of its files has been modified, or if SIGHUP was
+    /// received.
+    pub fn reload_if_needed(&self) {
+        let requested = RELOAD_REQUESTED.swap(false, Ordering::SeqCst);
+        let modified = {
+            let watched = self.watched.lock().expect("Poison error");
+            latest_modified(&watched.paths).map(|time| Some(time) != watched.last_modified).unwrap_or(false)
+        };
+        if requested || modified {
+            if let Err(e) = self.reload() {
+                format_error_chain!(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::parse_config;
+    use serde_yaml;
+
+    const CONFIG: &str = "\
+instance_id: test
+listen_port: 8080
+listen_host: localhost
+metadata_refresh: 60
+metrics_refresh: 60
+offsets_store_duration: 3600
+consumer_offsets_group_id: kafka-view
+clusters:
+  local:
+    broker_list: ['localhost:9092']
+    zookeeper: localhost:2181
+    security:
+      protocol: sasl_plaintext
+      username: kafka-view
+      password: old-secret
+caching:
+  backend: memory
+auth:
+  users:
+    - name: admin
+      role: admin
+      password_hash: old-hash
+";
+
+    fn config(yaml: &str) -> Config {
+        parse_config(&serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn detects_secret_only_changes() {
+        let old = config(CONFIG);
+        let unchanged = diff_configs(&old, &config(CONFIG));
+        assert!(unchanged.modified.is_empty() && unchanged.restart_required.is_empty());
+
+        let new_password = config(&CONFIG.replace("old-secret", "new-secret"));
+        assert_eq!(diff_configs(&old, &new_password).modified, vec![ClusterId::from("local")]);
+
+        let new_hash = config(&CONFIG.replace("old-hash", "new-hash"));
+        assert_eq!(diff_configs(&old, &new_hash).restart_required, vec!["auth"]);
+    }
+}
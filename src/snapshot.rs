@@ -0,0 +1,251 @@
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+use serde_json::{self, Value};
+
+use cache::{Cache, ReplicatedMap};
+use error::*;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, Write};
+
+
+/// Version of the snapshot format, increased on incompatible changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A copy of the content of all the replicated caches.
+///
+/// Snapshots are JSON documents containing the format version, the creation time and, for each
+/// cache, the list of entries. Keys and values are stored in the same JSON representation used
+/// by the replicated caches, together with the time of the last update in milliseconds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub version: u32,
+    pub created_at: String,
+    pub caches: BTreeMap<String, Vec<SnapshotEntry>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotEntry {
+    pub key: Value,
+    pub value: Value,
+    pub updated: u64,  // millis since epoch
+}
+
+fn export_map<K, V>(map: &ReplicatedMap<K, V>, caches: &mut BTreeMap<String, Vec<SnapshotEntry>>) -> Result<()>
+        where K: Eq + Hash + Clone + Serialize + DeserializeOwned,
+              V: Clone + PartialEq + Serialize + DeserializeOwned {
+    let mut entries = Vec::new();
+    for (key, value, updated) in map.dump() {
+        entries.push(SnapshotEntry {
+            key: serde_json::to_value(&key).chain_err(|| format!("Failed to serialize {} key", map.name()))?,
+            value: serde_json::to_value(&value).chain_err(|| format!("Failed to serialize {} value", map.name()))?,
+            updated,
+        });
+    }
+    caches.insert(map.name().to_owned(), entries);
+    Ok(())
+}
+
+/// Imports the entries in the replicated map, which will also write them to the storage.
+fn import_map<K, V>(map: &ReplicatedMap<K, V>, entries: &[SnapshotEntry]) -> Result<usize>
+        where K: Eq + Hash + Clone + Serialize + DeserializeOwned,
+              V: Clone + PartialEq + Serialize + DeserializeOwned {
+    for entry in entries {
+        let key = serde_json::from_value::<K>(entry.key.clone())
+            .chain_err(|| format!("Invalid key in {}: {}", map.name(), entry.key))?;
+        let value = serde_json::from_value::<V>(entry.value.clone())
+            .chain_err(|| format!("Invalid value in {} for key {}", map.name(), entry.key))?;
+        map.insert(key, value)?;
+    }
+    Ok(entries.len())
+}
+
+pub fn export(cache: &Cache) -> Result<Snapshot> {
+    let mut caches = BTreeMap::new();
+    export_map(&cache.metrics, &mut caches)?;
+    export_map(&cache.offsets, &mut caches)?;
+    export_map(&cache.brokers, &mut caches)?;
+    export_map(&cache.topics, &mut caches)?;
+    export_map(&cache.groups, &mut caches)?;
+    export_map(&cache.internal_offsets, &mut caches)?;
+    export_map(&cache.lag_history, &mut caches)?;
+    export_map(&cache.events, &mut caches)?;
+    export_map(&cache.alerts, &mut caches)?;
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        created_at: Utc::now().to_rfc3339(),
+        caches,
+    })
+}
+
+/// Loads the content of the snapshot in the cache, returning the number of entries imported
+/// for each cache. Imported entries are considered as updated now, and existing entries are
+/// overwritten.
+pub fn import(cache: &Cache, snapshot: &Snapshot) -> Result<BTreeMap<String, usize>> {
+    if snapshot.version > SNAPSHOT_VERSION {
+        bail!("Unsupported snapshot version {} (max supported: {})", snapshot.version, SNAPSHOT_VERSION);
+    }
+    let mut imported = BTreeMap::new();
+    for (name, entries) in &snapshot.caches {
+        let count = match name.as_str() {
+            "metrics" => import_map(&cache.metrics, entries)?,
+            "offsets" => import_map(&cache.offsets, entries)?,
+            "brokers" => import_map(&cache.brokers, entries)?,
+            "topics" => import_map(&cache.topics, entries)?,
+            "groups" => import_map(&cache.groups, entries)?,
+            "internal_offsets" => import_map(&cache.internal_offsets, entries)?,
+            "lag_history" => import_map(&cache.lag_history, entries)?,
+            "events" => import_map(&cache.events, entries)?,
+            "alerts" => import_map(&cache.alerts, entries)?,
+            _ => {
+                warn!("Unknown cache {} in snapshot, skipping", name);
+                continue;
+            },
+        };
+        imported.insert(name.to_owned(), count);
+    }
+    Ok(imported)
+}
+
+pub fn write_snapshot(snapshot: &Snapshot, path: &str) -> Result<()> {
+    let file = File::create(path)
+        .chain_err(|| format!("Unable to create snapshot file {}", path))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, snapshot)
+        .chain_err(|| format!("Unable to write snapshot file {}", path))?;
+    writer.flush()
+        .chain_err(|| format!("Unable to write snapshot file {}", path))
+}
+
+pub fn read_snapshot(path: &str) -> Result<Snapshot> {
+    let file = File::open(path)
+        .chain_err(|| format!("Unable to open snapshot file {}", path))?;
+    serde_json::from_reader(BufReader::new(file))
+        .chain_err(|| format!("Unable to parse snapshot file {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alerts::{AlertState, AlertStatus};
+    use cache::{ReplicaReader, ReplicaWriter};
+    use config::CacheEncoding;
+    use events::{ChangeEvent, ClusterEvent};
+    use lag_history::LagSample;
+    use metadata::{Broker, ClusterId, Group, Partition};
+    use metrics::TopicMetrics;
+    use ownership::Lease;
+    use storage::{CacheStorage, MemoryStorage};
+
+    use rand::random;
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    fn cache(storage: &Arc<CacheStorage>) -> Cache {
+        Cache::new(ReplicaWriter::new(storage.clone(), CacheEncoding::Json, "instance"))
+    }
+
+    fn populated_cache() -> Cache {
+        let cache = cache(&(Arc::new(MemoryStorage::new()) as Arc<CacheStorage>));
+        let cluster = ClusterId::from("local");
+        let key = |name: &str| (cluster.clone(), name.to_owned());
+        cache.metrics.insert(key("orders"), TopicMetrics::new()).unwrap();
+        cache.offsets.insert((cluster.clone(), "billing".to_owned(), "orders".to_owned()), vec![10, 20]).unwrap();
+        cache.brokers.insert(cluster.clone(), vec![Broker { id: 1, hostname: "kafka1".to_owned(), port: 9092 }]).unwrap();
+        cache.topics.insert(key("orders"), vec![
+            Partition { id: 0, leader: 1, replicas: vec![1], isr: vec![1], error: None },
+        ]).unwrap();
+        cache.groups.insert(key("billing"), Group {
+            name: "billing".to_owned(),
+            state: "Stable".to_owned(),
+            members: Vec::new(),
+        }).unwrap();
+        cache.internal_offsets.insert(cluster.clone(), vec![5, 6, 7]).unwrap();
+        cache.lag_history.insert((cluster.clone(), "billing".to_owned(), "orders".to_owned()),
+                                 vec![LagSample { timestamp: 1_500_000_000_000, lag: vec![3, 4] }]).unwrap();
+        cache.events.insert(cluster.clone(), vec![ClusterEvent {
+            timestamp: 1_500_000_000_000,
+            event: ChangeEvent::BrokerLeft { broker: 2 },
+        }]).unwrap();
+        cache.alerts.insert("orders_lag".to_owned(), AlertState {
+            rule: "orders_lag".to_owned(),
+            cluster_id: cluster.clone(),
+            status: AlertStatus::Firing,
+            since: 1_500_000_000_000,
+            last_evaluation: 1_500_000_060_000,
+            details: "Lag above threshold".to_owned(),
+        }).unwrap();
+        cache.leases.insert(cluster.clone(), Lease { owner: "instance".to_owned(), acquired: 1, expires: 2 }).unwrap();
+        cache
+    }
+
+    /// Keys and values of each cache in the snapshot, ignoring the update times.
+    fn contents(snapshot: &Snapshot) -> BTreeMap<String, Vec<(String, String)>> {
+        snapshot.caches.iter()
+            .map(|(name, entries)| {
+                let mut entries = entries.iter()
+                    .map(|entry| (entry.key.to_string(), entry.value.to_string()))
+                    .collect::<Vec<_>>();
+                entries.sort();
+                (name.clone(), entries)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_all_caches() {
+        let snapshot = export(&populated_cache()).unwrap();
+        let names = snapshot.caches.keys().map(|name| name.as_str()).collect::<Vec<_>>();
+        // Leases belong to the running instances and are not exported
+        assert_eq!(names, vec!["alerts", "brokers", "events", "groups", "internal_offsets", "lag_history",
+                               "metrics", "offsets", "topics"]);
+        assert!(snapshot.caches.values().all(|entries| entries.len() == 1));
+
+        let path = env::temp_dir().join(format!("kafka-view-snapshot-{}.json", random::<u64>()));
+        let path_str = path.to_string_lossy().into_owned();
+        write_snapshot(&snapshot, &path_str).unwrap();
+        let read = read_snapshot(&path_str);
+        fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+        assert_eq!(read.version, SNAPSHOT_VERSION);
+        assert_eq!(contents(&read), contents(&snapshot));
+
+        let storage: Arc<CacheStorage> = Arc::new(MemoryStorage::new());
+        let imported = import(&cache(&storage), &read).unwrap();
+        assert_eq!(imported.len(), 9);
+        assert!(imported.values().all(|&count| count == 1));
+
+        // The imported entries are written to the storage as well
+        let restored = cache(&storage);
+        ReplicaReader::new(storage.clone(), "restored").load_state(restored.alias()).unwrap();
+        assert_eq!(contents(&export(&restored).unwrap()), contents(&snapshot));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let mut snapshot = export(&populated_cache()).unwrap();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        let target = cache(&(Arc::new(MemoryStorage::new()) as Arc<CacheStorage>));
+        assert!(import(&target, &snapshot).is_err());
+        assert!(target.brokers.keys().is_empty());
+    }
+
+    #[test]
+    fn skips_unknown_caches() {
+        let mut snapshot = export(&populated_cache()).unwrap();
+        let entries = snapshot.caches.remove("brokers").unwrap();
+        snapshot.caches.insert("unknown".to_owned(), entries);
+        snapshot.caches.insert("leases".to_owned(), Vec::new());
+
+        let target = cache(&(Arc::new(MemoryStorage::new()) as Arc<CacheStorage>));
+        let imported = import(&target, &snapshot).unwrap();
+        assert!(!imported.contains_key("unknown") && !imported.contains_key("leases"));
+        assert_eq!(imported.len(), 8);
+        assert!(target.brokers.keys().is_empty());
+        assert_eq!(target.topics.keys().len(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use error::*;
+use storage::{CacheStorage, StoredEntry, StoredState};
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+
+/// Stores the cache updates in a local append-only file. Each record contains the key, the
+/// payload (or a tombstone) and the timestamp. Compaction rewrites the file keeping only the
+/// latest version of each key.
+///
+/// Record format (big endian): key length (u32), key, payload length (i32, -1 for
+/// tombstones), payload, timestamp (u64).
+pub struct FileStorage {
+    path: PathBuf,
+    writer: Mutex<BufWriter<File>>,
+}
+
+fn open_for_append(path: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)
+        .chain_err(|| format!("Unable to open cache file {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+fn write_record<W: Write>(writer: &mut W, key: &[u8], payload: Option<&[u8]>, timestamp: u64) -> io::Result<()> {
+    writer.write_u32::<BigEndian>(key.len() as u32)?;
+    writer.write_all(key)?;
+    match payload {
+        Some(payload) => {
+            writer.write_i32::<BigEndian>(payload.len() as i32)?;
+            writer.write_all(payload)?;
+        },
+        None => writer.write_i32::<BigEndian>(-1)?,
+    }
+    writer.write_u64::<BigEndian>(timestamp)
+}
+
+/// Reads `len` bytes of the current record. A length exceeding the rest of the file can only
+/// come from a partial or corrupted write, and is reported like a truncated record instead of
+/// being allocated.
+fn read_field<R: Read>(reader: &mut R, len: u64, remaining: &mut u64) -> io::Result<Vec<u8>> {
+    if len > *remaining {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "Record length exceeds the file size"));
+    }
+    *remaining -= len;
+    let mut buffer = vec![0; len as usize];
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Reads the next record, returning `None` at the end of the file. `remaining` is the number
+/// of bytes left in the file.
+fn read_record<R: Read>(reader: &mut R, mut remaining: u64) -> io::Result<Option<StoredEntry>> {
+    let key_len = match reader.read_u32::<BigEndian>() {
+        Ok(len) => u64::from(len),
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    remaining = remaining.saturating_sub(4 + 4 + 8);  // lengths and timestamp
+    let key = read_field(reader, key_len, &mut remaining)?;
+    let payload = match reader.read_i32::<BigEndian>()? {
+        -1 => None,
+        len if len < 0 => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Invalid payload length")),
+        len => Some(read_field(reader, len as u64, &mut remaining)?),
+    };
+    let timestamp = reader.read_u64::<BigEndian>()?;
+    Ok(Some(StoredEntry { key, payload, timestamp }))
+}
+
+fn record_size(entry: &StoredEntry) -> u64 {
+    (4 + entry.key.len() + 4 + entry.payload.as_ref().map(|p| p.len()).unwrap_or(0) + 8) as u64
+}
+
+/// Reads all the records in the file, returning them with the length of the valid part of
+/// the file.
+fn read_file(path: &Path) -> Result<(StoredState, u64)> {
+    let mut state = StoredState::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok((state, 0)),
+        Err(e) => return Err(e).chain_err(|| format!("Unable to open cache file {}", path.display())),
+    };
+    let file_len = file.metadata()
+        .chain_err(|| format!("Unable to read cache file {}", path.display()))?
+        .len();
+    let mut reader = BufReader::new(file);
+    let mut valid_len = 0;
+    loop {
+        match read_record(&mut reader, file_len.saturating_sub(valid_len)) {
+            Ok(Some(entry)) => {
+                valid_len += record_size(&entry);
+                state.add(entry);
+            },
+            Ok(None) => break,
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                warn!("Truncated record at the end of {}, ignoring it", path.display());
+                break;
+            },
+            Err(e) => return Err(e).chain_err(|| format!("Unable to read cache file {}", path.display())),
+        }
+    }
+    Ok((state, valid_len))
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<FileStorage> {
+        let path = path.as_ref().to_path_buf();
+        // If the last write was interrupted, the partial record is removed before appending.
+        let (_, valid_len) = read_file(&path)?;
+        let file_len = fs::metadata(&path).map(|metadata| metadata.len()).unwrap_or(0);
+        if valid_len < file_len {
+            OpenOptions::new().write(true).open(&path)
+                .and_then(|file| file.set_len(valid_len))
+                .chain_err(|| format!("Unable to truncate cache file {}", path.display()))?;
+        }
+        let writer = open_for_append(&path)?;
+        Ok(FileStorage { path, writer: Mutex::new(writer) })
+    }
+}
+
+impl CacheStorage for FileStorage {
+    fn write(&self, key: &[u8], payload: Option<&[u8]>, timestamp: u64) -> Result<()> {
+        let mut writer = self.writer.lock().expect("Poison error");
+        write_record(&mut *writer, key, payload, timestamp)
+            .and_then(|_| writer.flush())
+            .chain_err(|| format!("Unable to write to cache file {}", self.path.display()))
+    }
+
+    fn load(&self) -> Result<StoredState> {
+        read_file(&self.path).map(|(state, _)| state)
+    }
+
+    fn compact(&self) -> Result<()> {
+        // Writes are blocked until the compacted file replaces the current one.
+        let mut writer = self.writer.lock().expect("Poison error");
+        writer.flush().chain_err(|| "Unable to flush cache file")?;
+
+        let (state, _) = read_file(&self.path)?;
+        let tmp_path = self.path.with_extension("compacting");
+        {
+            let tmp_file = File::create(&tmp_path)
+                .chain_err(|| format!("Unable to create {}", tmp_path.display()))?;
+            let mut tmp_writer = BufWriter::new(tmp_file);
+            let mut live_entries = 0;
+            for entry in state.entries.values().filter(|entry| entry.payload.is_some()) {
+                let payload = entry.payload.as_ref().map(|p| p.as_slice());
+                write_record(&mut tmp_writer, &entry.key, payload, entry.timestamp)
+                    .chain_err(|| format!("Unable to write to {}", tmp_path.display()))?;
+                live_entries += 1;
+            }
+            tmp_writer.flush()
+                .and_then(|_| tmp_writer.get_ref().sync_all())
+                .chain_err(|| format!("Unable to sync {}", tmp_path.display()))?;
+            info!("Compacted {}: {} records, {} live entries", self.path.display(), state.processed_records,
+                  live_entries);
+        }
+        fs::rename(&tmp_path, &self.path)
+            .chain_err(|| format!("Unable to replace {}", self.path.display()))?;
+        *writer = open_for_append(&self.path)?;
+        Ok(())
+    }
+
+    fn description(&self) -> String {
+        format!("file {}", self.path.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::random;
+    use std::env;
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> TempFile {
+            TempFile(env::temp_dir().join(format!("kafka-view-storage-{}", random::<u64>())))
+        }
+
+        fn append(&self, bytes: &[u8]) {
+            OpenOptions::new().append(true).open(&self.0).and_then(|mut f| f.write_all(bytes)).unwrap();
+        }
+
+        fn len(&self) -> u64 {
+            fs::metadata(&self.0).unwrap().len()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn payload(state: &StoredState, key: &[u8]) -> Option<Vec<u8>> {
+        state.entries.get(key).and_then(|entry| entry.payload.clone())
+    }
+
+    #[test]
+    fn loads_the_latest_version_of_each_key() {
+        let file = TempFile::new();
+        let storage = FileStorage::new(&file.0).unwrap();
+        assert!(storage.load().unwrap().entries.is_empty());
+        storage.write(b"a", Some(b"1"), 10).unwrap();
+        storage.write(b"b", Some(b""), 11).unwrap();
+        storage.write(b"a", Some(b"2"), 12).unwrap();
+        storage.write(b"c", Some(b"3"), 13).unwrap();
+        storage.write(b"c", None, 14).unwrap();
+
+        let state = FileStorage::new(&file.0).unwrap().load().unwrap();
+        assert_eq!(state.processed_records, 5);
+        assert_eq!(payload(&state, b"a"), Some(b"2".to_vec()));
+        assert_eq!(payload(&state, b"b"), Some(Vec::new()));
+        assert_eq!(state.entries[&b"a".to_vec()].timestamp, 12);
+        assert_eq!(state.entries[&b"c".to_vec()].payload, None);
+    }
+
+    #[test]
+    fn compaction_drops_overwritten_and_deleted_entries() {
+        let file = TempFile::new();
+        let storage = FileStorage::new(&file.0).unwrap();
+        storage.write(b"a", Some(b"1"), 10).unwrap();
+        storage.write(b"a", Some(b"2"), 11).unwrap();
+        storage.write(b"b", Some(b"3"), 12).unwrap();
+        storage.write(b"b", None, 13).unwrap();
+        storage.compact().unwrap();
+        storage.write(b"c", Some(b"4"), 14).unwrap();
+
+        let state = storage.load().unwrap();
+        assert_eq!(state.processed_records, 2);
+        assert_eq!(payload(&state, b"a"), Some(b"2".to_vec()));
+        assert_eq!(payload(&state, b"c"), Some(b"4".to_vec()));
+        assert!(!state.entries.contains_key(&b"b".to_vec()));
+    }
+
+    #[test]
+    fn truncated_records_are_removed_on_open() {
+        let file = TempFile::new();
+        FileStorage::new(&file.0).unwrap().write(b"a", Some(b"1"), 10).unwrap();
+        let valid_len = file.len();
+        file.append(&[0, 0, 0, 1, b'b', 0, 0]);  // interrupted in the payload length
+
+        let storage = FileStorage::new(&file.0).unwrap();
+        assert_eq!(file.len(), valid_len);
+        storage.write(b"b", Some(b"2"), 11).unwrap();
+        let state = storage.load().unwrap();
+        assert_eq!(state.processed_records, 2);
+        assert_eq!(payload(&state, b"b"), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn oversize_records_are_handled_as_truncated() {
+        let file = TempFile::new();
+        FileStorage::new(&file.0).unwrap().write(b"a", Some(b"1"), 10).unwrap();
+        let valid_len = file.len();
+        file.append(&[0xff, 0xff, 0xff, 0xff, b'b', b'c']);  // huge key length
+
+        let state = FileStorage::new(&file.0).unwrap().load().unwrap();
+        assert_eq!(file.len(), valid_len);
+        assert_eq!(state.processed_records, 1);
+
+        file.append(&[0, 0, 0, 1, b'b', 0x7f, 0xff, 0xff, 0xff, 0]);  // huge payload length
+        FileStorage::new(&file.0).unwrap();
+        assert_eq!(file.len(), valid_len);
+        file.append(&[0, 0, 0, 1, b'b', 0xff, 0xff, 0xff, 0xfe, 0]);  // negative payload length
+        FileStorage::new(&file.0).unwrap();
+        assert_eq!(file.len(), valid_len);
+    }
+}
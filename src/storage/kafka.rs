@@ -0,0 +1,263 @@
+use futures::Future;
+use futures::stream::Stream;
+use rdkafka::{Offset, TopicPartitionList};
+use rdkafka::client::EmptyContext;
+use rdkafka::config::TopicConfig;
+use rdkafka::consumer::stream_consumer::StreamConsumer;
+use rdkafka::consumer::{BaseConsumer, Consumer, EmptyConsumerContext};
+use rdkafka::error::KafkaError;
+use rdkafka::message::{BorrowedMessage, Message};
+use rdkafka::producer::FutureProducer;
+use rdkafka::producer::future_producer::DeliveryFuture;
+use rdkafka::util::millis_to_epoch;
+use rand::random;
+
+use config::ClusterConfig;
+use error::*;
+use storage::{CacheStorage, StoredEntry, StoredState};
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+
+type ReplicaConsumer = StreamConsumer<EmptyConsumerContext>;
+
+/// Maximum number of messages waiting for delivery before `write` waits for the oldest ones.
+const MAX_PENDING_DELIVERIES: usize = 10000;
+/// Seconds between two lookups of the partitions to follow, while the cache topic has none.
+const FOLLOW_RETRY_INTERVAL: u64 = 10;
+
+/// Waits for the delivery of the messages, returning the number of failed deliveries.
+fn wait_deliveries(deliveries: Vec<DeliveryFuture>, topic_name: &str) -> usize {
+    let mut failures = 0;
+    for delivery in deliveries {
+        match delivery.wait() {
+            Ok(Ok(_)) => {},
+            Ok(Err((e, _))) => {
+                error!("Failed to write to cache topic {}: {}", topic_name, e);
+                failures += 1;
+            },
+            Err(_) => {
+                error!("Delivery to cache topic {} was canceled", topic_name);
+                failures += 1;
+            },
+        }
+    }
+    failures
+}
+
+fn message_to_entry(m: &BorrowedMessage) -> Option<StoredEntry> {
+    m.key().map(|key| StoredEntry {
+        key: key.to_vec(),
+        payload: m.payload().map(|p| p.to_vec()),
+        timestamp: m.timestamp().to_millis()
+            .unwrap_or_else(|| millis_to_epoch(SystemTime::now())) as u64,
+    })
+}
+
+/// Assigns all the partitions of the cache topic to the consumer, starting from the offsets
+/// reached by the load. Returns false if the topic has no partition.
+fn assign_partitions(consumer: &BaseConsumer<EmptyConsumerContext>, topic_name: &str,
+                     loaded_offsets: &HashMap<i32, i64>) -> Result<bool> {
+    let metadata = consumer.fetch_metadata(Some(topic_name), 30000)
+        .chain_err(|| "Failed to fetch metadata")?;
+    let mut tp_list = TopicPartitionList::new();
+    for topic_metadata in metadata.topics() {
+        for partition in topic_metadata.partitions() {
+            let offset = match loaded_offsets.get(&partition.id()) {
+                Some(&offset) => Offset::Offset(offset),
+                None => Offset::Beginning,
+            };
+            tp_list.add_partition_offset(topic_name, partition.id(), offset);
+        }
+    }
+    if tp_list.count() == 0 {
+        return Ok(false);
+    }
+    consumer.assign(&tp_list)
+        .chain_err(|| format!("Failed to assign cache topic {}", topic_name))?;
+    Ok(true)
+}
+
+/// Stores the cache updates in a compacted Kafka topic, using the entry key as message key.
+/// Deletions are written as tombstones.
+pub struct KafkaStorage {
+    cluster_config: ClusterConfig,
+    topic_name: String,
+    producer: FutureProducer<EmptyContext>,
+    loaded_offsets: Mutex<HashMap<i32, i64>>,  // next offset to read for each partition
+    pending_deliveries: Mutex<Vec<DeliveryFuture>>,
+}
+
+impl KafkaStorage {
+    pub fn new(cluster_config: &ClusterConfig, topic_name: &str) -> Result<KafkaStorage> {
+        let producer = cluster_config.client_config()?
+            .set("compression.codec", "gzip")
+            .set("message.max.bytes", "10000000")
+            .create::<FutureProducer<_>>()
+            .chain_err(|| "Producer creation failed")?;
+
+        Ok(KafkaStorage {
+            cluster_config: cluster_config.clone(),
+            topic_name: topic_name.to_owned(),
+            producer,
+            loaded_offsets: Mutex::new(HashMap::new()),
+            pending_deliveries: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn create_consumer(&self) -> Result<ReplicaConsumer> {
+        let consumer: ReplicaConsumer = self.cluster_config.client_config()?
+            .set("group.id", &format!("kafka_web_cache_reader_{}", random::<i64>()))
+            .set("session.timeout.ms", "6000")
+            .set("enable.auto.commit", "false")
+            .set("queued.min.messages", "10000") // Reduce memory usage
+            //.set("fetch.message.max.bytes", "102400")
+            .set_default_topic_config(
+                TopicConfig::new()
+                .set("auto.offset.reset", "smallest")
+                .finalize())
+            .create()
+            .chain_err(|| "Consumer creation failed")?;
+
+        consumer.subscribe(&[self.topic_name.as_str()])
+            .chain_err(|| "Can't subscribe to specified topics")?;
+
+        Ok(consumer)
+    }
+}
+
+impl CacheStorage for KafkaStorage {
+    /// Sends the update without waiting for its delivery. Failed deliveries are reported by
+    /// `flush`, or by a later `write` once too many deliveries are pending.
+    fn write(&self, key: &[u8], payload: Option<&[u8]>, timestamp: u64) -> Result<()> {
+        let delivery = self.producer.send_copy::<[u8], [u8]>(
+            self.topic_name.as_str(),
+            None,
+            payload,
+            Some(key),
+            Some(timestamp as i64),
+            1000,
+        );
+        let mut pending = self.pending_deliveries.lock().expect("Poison error");
+        pending.push(delivery);
+        if pending.len() > MAX_PENDING_DELIVERIES {
+            let oldest = pending.drain(..MAX_PENDING_DELIVERIES / 2).collect::<Vec<_>>();
+            let failures = wait_deliveries(oldest, &self.topic_name);
+            if failures > 0 {
+                bail!("Failed to write {} updates to cache topic {}", failures, self.topic_name);
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.producer.flush(30000);
+        let pending = mem::replace(&mut *self.pending_deliveries.lock().expect("Poison error"), Vec::new());
+        let failures = wait_deliveries(pending, &self.topic_name);
+        if failures > 0 {
+            bail!("Failed to write {} updates to cache topic {}", failures, self.topic_name);
+        }
+        Ok(())
+    }
+
+    /// Reads the topic from the beginning until the end of every partition.
+    fn load(&self) -> Result<StoredState> {
+        let mut state = StoredState::new();
+        let mut eof_set = HashSet::new();
+
+        let consumer = self.create_consumer()?;
+        let metadata = consumer.fetch_metadata(Some(&self.topic_name), 30000)
+            .chain_err(|| "Failed to fetch metadata")?;
+
+        if metadata.topics().is_empty() {
+            warn!("No replicator topic found ({} {})", self.cluster_config.bootstrap_servers(), self.topic_name);
+            return Ok(state);
+        }
+        let topic_metadata = &metadata.topics()[0];
+        if topic_metadata.partitions().is_empty() {
+            return Ok(state);  // Topic is empty and auto created
+        }
+
+        let message_stream = consumer.start();
+        let mut loaded_offsets = HashMap::new();
+
+        for message in message_stream.wait() {
+            match message {
+                Ok(Ok(m)) => {
+                    loaded_offsets.insert(m.partition(), m.offset() + 1);
+                    match message_to_entry(&m) {
+                        Some(entry) => state.add(entry),
+                        None => error!("Empty key found in cache topic {}", self.topic_name),
+                    }
+                },
+                Ok(Err(KafkaError::PartitionEOF(p))) => { eof_set.insert(p); () },
+                Ok(Err(e)) => error!("Error while reading from Kafka: {}", e),
+                Err(_) => error!("Stream receive error"),
+            };
+            if eof_set.len() == topic_metadata.partitions().len() {
+                break;
+            }
+        }
+        consumer.stop();
+        *self.loaded_offsets.lock().expect("Poison error") = loaded_offsets;
+        Ok(state)
+    }
+
+    /// Consumes the topic from where the last load stopped. Partitions that had no message
+    /// during the load are consumed from the beginning. If the topic has no partition yet, the
+    /// lookup is retried every `FOLLOW_RETRY_INTERVAL` seconds.
+    fn follow(&self, callback: Box<Fn(StoredEntry) + Send>) -> Result<bool> {
+        let consumer = self.cluster_config.client_config()?
+            .set("group.id", &format!("kafka_web_cache_follower_{}", random::<i64>()))
+            .set("enable.partition.eof", "false")
+            .set("enable.auto.commit", "false")
+            .set("queued.min.messages", "10000") // Reduce memory usage
+            .create::<BaseConsumer<EmptyConsumerContext>>()
+            .chain_err(|| "Consumer creation failed")?;
+
+        let loaded_offsets = self.loaded_offsets.lock().expect("Poison error").clone();
+        let mut assigned = assign_partitions(&consumer, &self.topic_name, &loaded_offsets)?;
+        if !assigned {
+            warn!("No partition to follow in cache topic {}, retrying every {}s", self.topic_name,
+                  FOLLOW_RETRY_INTERVAL);
+        }
+
+        let topic_name = self.topic_name.clone();
+        let _ = thread::Builder::new()
+            .name("cache-follower".to_owned())
+            .spawn(move || {
+                loop {
+                    if !assigned {
+                        thread::sleep(Duration::from_secs(FOLLOW_RETRY_INTERVAL));
+                        match assign_partitions(&consumer, &topic_name, &loaded_offsets) {
+                            Ok(true) => {
+                                info!("Following cache topic {}", topic_name);
+                                assigned = true;
+                            },
+                            Ok(false) => {},
+                            Err(e) => format_error_chain!(e),
+                        }
+                        continue;
+                    }
+                    match consumer.poll(1000) {
+                        None => {},
+                        Some(Ok(m)) => match message_to_entry(&m) {
+                            Some(entry) => callback(entry),
+                            None => error!("Empty key found in cache topic {}", topic_name),
+                        },
+                        Some(Err(e)) => error!("Error while following cache topic {}: {}", topic_name, e),
+                    }
+                }
+            })
+            .chain_err(|| "Failed to start cache follower thread")?;
+        Ok(true)
+    }
+
+    fn description(&self) -> String {
+        format!("kafka topic {} (brokers: {})", self.topic_name, self.cluster_config.bootstrap_servers())
+    }
+}
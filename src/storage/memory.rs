@@ -0,0 +1,73 @@
+use error::*;
+use storage::{CacheStorage, StoredEntry, StoredState};
+
+use std::sync::Mutex;
+
+
+/// Keeps the latest version of every key in memory. Nothing survives a restart, but the
+/// storage behaves like the persistent ones within the same process.
+pub struct MemoryStorage {
+    state: Mutex<StoredState>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage { state: Mutex::new(StoredState::new()) }
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn write(&self, key: &[u8], payload: Option<&[u8]>, timestamp: u64) -> Result<()> {
+        let entry = StoredEntry {
+            key: key.to_vec(),
+            payload: payload.map(|p| p.to_vec()),
+            timestamp,
+        };
+        self.state.lock().expect("Poison error").add(entry);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<StoredState> {
+        let state = self.state.lock().expect("Poison error");
+        let mut loaded = StoredState::new();
+        for entry in state.entries.values() {
+            loaded.add(entry.clone());
+        }
+        Ok(loaded)
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.state.lock().expect("Poison error").entries.retain(|_, entry| entry.payload.is_some());
+        Ok(())
+    }
+
+    fn description(&self) -> String {
+        "memory".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_and_compacts_entries() {
+        let storage = MemoryStorage::new();
+        storage.write(b"a", Some(b"1"), 10).unwrap();
+        storage.write(b"a", Some(b"2"), 11).unwrap();
+        storage.write(b"b", Some(b"3"), 12).unwrap();
+        storage.write(b"b", None, 13).unwrap();
+
+        let state = storage.load().unwrap();
+        assert_eq!(state.entries.len(), 2);
+        assert_eq!(state.entries[&b"a".to_vec()].payload, Some(b"2".to_vec()));
+        assert_eq!(state.entries[&b"a".to_vec()].timestamp, 11);
+        assert_eq!(state.entries[&b"b".to_vec()].payload, None);
+
+        storage.compact().unwrap();
+        let state = storage.load().unwrap();
+        assert_eq!(state.entries.len(), 1);
+        assert!(state.entries.contains_key(&b"a".to_vec()));
+        assert!(!storage.follow(Box::new(|_| {})).unwrap());  // nothing to follow
+    }
+}
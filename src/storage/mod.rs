@@ -0,0 +1,100 @@
+mod file;
+mod kafka;
+mod memory;
+
+use config::{CachingBackend, Config};
+use error::*;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub use self::file::FileStorage;
+pub use self::kafka::KafkaStorage;
+pub use self::memory::MemoryStorage;
+
+
+/// The latest version of a cache entry, as persisted by a storage backend. A missing payload
+/// means that the entry has been deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredEntry {
+    pub key: Vec<u8>,
+    pub payload: Option<Vec<u8>>,
+    pub timestamp: u64,  // millis since epoch
+}
+
+/// The entries loaded from a storage backend.
+pub struct StoredState {
+    pub entries: HashMap<Vec<u8>, StoredEntry>,
+    pub processed_records: i64,  // including the ones overwritten by later updates
+}
+
+impl StoredState {
+    pub fn new() -> StoredState {
+        StoredState {
+            entries: HashMap::new(),
+            processed_records: 0,
+        }
+    }
+
+    /// Adds a record, replacing any previous version of the same key.
+    pub fn add(&mut self, entry: StoredEntry) {
+        self.processed_records += 1;
+        self.entries.insert(entry.key.clone(), entry);
+    }
+}
+
+/// Persists the updates of the replicated caches, so that they can be restored at startup.
+/// Keys and payloads are opaque to the storage.
+pub trait CacheStorage: Send + Sync {
+    /// Persists a new version of the key. A missing payload deletes the key.
+    fn write(&self, key: &[u8], payload: Option<&[u8]>, timestamp: u64) -> Result<()>;
+
+    /// Returns the latest version of every key.
+    fn load(&self) -> Result<StoredState>;
+
+    /// Waits until all the writes have been persisted.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Discards the overwritten and deleted entries, if supported by the storage.
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Passes to the callback, in the background, every entry written to the storage after the
+    /// last `load`, including the ones written by other processes. Returns false if following
+    /// is not supported by the storage.
+    fn follow(&self, _callback: Box<Fn(StoredEntry) + Send>) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Human readable description, used in logs and errors.
+    fn description(&self) -> String;
+}
+
+pub fn create_storage(config: &Config) -> Result<Arc<CacheStorage>> {
+    let caching = &config.caching;
+    let storage: Arc<CacheStorage> = match caching.backend {
+        CachingBackend::Kafka => {
+            let (cluster_id, topic) = match (&caching.cluster, &caching.topic) {
+                (&Some(ref cluster_id), &Some(ref topic)) => (cluster_id, topic),
+                _ => bail!("The kafka caching backend requires cluster and topic"),
+            };
+            let cluster_config = match config.cluster(cluster_id) {
+                Some(cluster_config) => cluster_config,
+                None => bail!("Can't find cache cluster {}", cluster_id),
+            };
+            Arc::new(KafkaStorage::new(cluster_config, topic)?)
+        },
+        CachingBackend::File => {
+            let path = match caching.path {
+                Some(ref path) => path,
+                None => bail!("The file caching backend requires path"),
+            };
+            Arc::new(FileStorage::new(path)?)
+        },
+        CachingBackend::Memory => Arc::new(MemoryStorage::new()),
+    };
+    Ok(storage)
+}
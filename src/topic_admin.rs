@@ -0,0 +1,267 @@
+use rand::random;
+use regex::Regex;
+
+use config::{ClusterConfig, Config};
+use error::*;
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+
+lazy_static! {
+    static ref TOPIC_NAME_RE: Regex = Regex::new(r"^[a-zA-Z0-9._-]{1,249}$").unwrap();
+    static ref CONFIG_KEY_RE: Regex = Regex::new(r"^[a-z0-9.]+$").unwrap();
+}
+
+fn validate_topic_name(topic: &str) -> Result<()> {
+    if !TOPIC_NAME_RE.is_match(topic) {
+        bail!("Invalid topic name: {}", topic);
+    }
+    Ok(())
+}
+
+fn validate_config_key(key: &str) -> Result<()> {
+    if !CONFIG_KEY_RE.is_match(key) {
+        bail!("Invalid configuration key: {}", key);
+    }
+    Ok(())
+}
+
+fn validate_config_value(key: &str, value: &str) -> Result<()> {
+    // The command line tools use commas to separate configurations. Values are always passed
+    // after `key=`, so they can't be mistaken for options.
+    if value.is_empty() || value.contains(',') {
+        bail!("Invalid value for configuration {}: {}", key, value);
+    }
+    Ok(())
+}
+
+/// Seconds during which the configuration of a topic is served from memory, since each call
+/// to the command line tools starts a JVM.
+const DESCRIBE_CONFIGS_TTL_SECS: u64 = 300;
+
+type TopicConfigs = BTreeMap<String, String>;
+
+/// Topic management operations, implemented using the command line tools distributed
+/// with Kafka, since topic administration is not available in the client library.
+pub struct TopicAdmin {
+    tools_path: Option<String>,
+    described_configs: Mutex<HashMap<(String, String), (Instant, TopicConfigs)>>,  // (zookeeper, topic)
+}
+
+impl TopicAdmin {
+    pub fn new(config: &Config) -> TopicAdmin {
+        TopicAdmin {
+            tools_path: config.kafka_tools_path.clone(),
+            described_configs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn forget_configs(&self, cluster_config: &ClusterConfig, topic: &str) {
+        let key = (cluster_config.zookeeper.clone(), topic.to_owned());
+        self.described_configs.lock().expect("Poison error").remove(&key);
+    }
+
+    fn run(&self, script: &str, args: &[&str]) -> Result<String> {
+        let command = match self.tools_path {
+            Some(ref path) => Path::new(path).join(script).to_string_lossy().into_owned(),
+            None => script.to_owned(),
+        };
+        debug!("Running {} {}", command, args.join(" "));
+        let output = Command::new(&command)
+            .args(args)
+            .output()
+            .chain_err(|| format!("Failed to run {}", command))?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("{} failed ({}): {} {}", script, output.status, stdout.trim(), stderr.trim());
+        }
+        // Some versions of the tools report errors on stdout with a success exit code
+        if stdout.contains("Error while executing") {
+            bail!("{} failed: {}", script, stdout.trim());
+        }
+        Ok(stdout)
+    }
+
+    pub fn create_topic(&self, cluster_config: &ClusterConfig, topic: &str, partitions: i32,
+                        replication_factor: i32, configs: &HashMap<String, String>) -> Result<()> {
+        validate_topic_name(topic)?;
+        if partitions < 1 || replication_factor < 1 {
+            bail!("Partitions and replication factor should be positive");
+        }
+        let partitions = partitions.to_string();
+        let replication_factor = replication_factor.to_string();
+        let mut config_args = Vec::new();
+        for (key, value) in configs {
+            validate_config_key(key)?;
+            validate_config_value(key, value)?;
+            config_args.push(format!("{}={}", key, value));
+        }
+        let mut args = vec!["--zookeeper", cluster_config.zookeeper.as_str(), "--create", "--topic", topic,
+                            "--partitions", partitions.as_str(), "--replication-factor", replication_factor.as_str()];
+        for config_arg in &config_args {
+            args.push("--config");
+            args.push(config_arg);
+        }
+        let result = self.run("kafka-topics.sh", &args).map(|_| ());
+        self.forget_configs(cluster_config, topic);
+        result
+    }
+
+    pub fn delete_topic(&self, cluster_config: &ClusterConfig, topic: &str) -> Result<()> {
+        validate_topic_name(topic)?;
+        let result = self.run("kafka-topics.sh", &["--zookeeper", cluster_config.zookeeper.as_str(), "--delete",
+                                                   "--topic", topic])
+            .map(|_| ());
+        self.forget_configs(cluster_config, topic);
+        result
+    }
+
+    /// Increases the number of partitions of the topic to `partitions`.
+    pub fn add_partitions(&self, cluster_config: &ClusterConfig, topic: &str, partitions: i32) -> Result<()> {
+        validate_topic_name(topic)?;
+        let partitions = partitions.to_string();
+        self.run("kafka-topics.sh", &["--zookeeper", cluster_config.zookeeper.as_str(), "--alter", "--topic", topic,
+                                      "--partitions", partitions.as_str()])
+            .map(|_| ())
+    }
+
+    pub fn alter_configs(&self, cluster_config: &ClusterConfig, topic: &str, set: &HashMap<String, String>,
+                         delete: &[String]) -> Result<()> {
+        validate_topic_name(topic)?;
+        if set.is_empty() && delete.is_empty() {
+            bail!("No configuration to change");
+        }
+        let add_config = set.iter()
+            .map(|(key, value)| {
+                validate_config_key(key)?;
+                validate_config_value(key, value)?;
+                Ok(format!("{}={}", key, value))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .join(",");
+        for key in delete {
+            validate_config_key(key)?;
+        }
+        let delete_config = delete.join(",");
+
+        let mut args = vec!["--zookeeper", cluster_config.zookeeper.as_str(), "--alter", "--entity-type", "topics",
+                            "--entity-name", topic];
+        if !add_config.is_empty() {
+            args.push("--add-config");
+            args.push(&add_config);
+        }
+        if !delete_config.is_empty() {
+            args.push("--delete-config");
+            args.push(&delete_config);
+        }
+        let result = self.run("kafka-configs.sh", &args).map(|_| ());
+        self.forget_configs(cluster_config, topic);
+        result
+    }
+
+    /// Moves the leadership of the partitions back to their preferred replica.
+    pub fn elect_preferred_leaders(&self, cluster_config: &ClusterConfig, partitions: &[(String, i32)]) -> Result<()> {
+        if partitions.is_empty() {
+            bail!("No partition selected");
+        }
+        for &(ref topic, _) in partitions {
+            validate_topic_name(topic)?;
+        }
+        let election = json!({
+            "partitions": partitions.iter()
+                .map(|&(ref topic, partition)| json!({"topic": topic, "partition": partition}))
+                .collect::<Vec<_>>()
+        });
+        // The tool only reads the partition list from a file
+        let path = env::temp_dir().join(format!("kafka-view-election-{}.json", random::<u64>()));
+        File::create(&path)
+            .and_then(|mut file| file.write_all(election.to_string().as_bytes()))
+            .chain_err(|| format!("Unable to write {}", path.display()))?;
+        let path_str = path.to_string_lossy().into_owned();
+        let result = self.run("kafka-preferred-replica-election.sh", &["--zookeeper", cluster_config.zookeeper.as_str(),
+                                                                      "--path-to-json-file", path_str.as_str()]);
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Unable to remove {}: {}", path.display(), e);
+        }
+        result.map(|_| ())
+    }
+
+    /// Returns the configuration overrides of the topic. Values not listed are the broker defaults.
+    /// The result is kept for `DESCRIBE_CONFIGS_TTL_SECS`, or until the configuration is changed
+    /// by this instance.
+    pub fn describe_configs(&self, cluster_config: &ClusterConfig, topic: &str) -> Result<TopicConfigs> {
+        validate_topic_name(topic)?;
+        let key = (cluster_config.zookeeper.clone(), topic.to_owned());
+        let ttl = Duration::from_secs(DESCRIBE_CONFIGS_TTL_SECS);
+        if let Some(&(described, ref configs)) = self.described_configs.lock().expect("Poison error").get(&key) {
+            if described.elapsed() < ttl {
+                return Ok(configs.clone());
+            }
+        }
+        // The lock is not held while the tool runs; concurrent calls for the same topic are harmless.
+        let output = self.run("kafka-configs.sh", &["--zookeeper", cluster_config.zookeeper.as_str(), "--describe",
+                                                    "--entity-type", "topics", "--entity-name", topic])?;
+        let configs = parse_describe_configs(&output);
+        let mut described_configs = self.described_configs.lock().expect("Poison error");
+        described_configs.retain(|_, &mut (described, _)| described.elapsed() < ttl);
+        described_configs.insert(key, (Instant::now(), configs.clone()));
+        Ok(configs)
+    }
+}
+
+/// Parses the output of kafka-configs.sh, in the format:
+/// `Configs for topic 'name' are key1=value1,key2=value2`.
+fn parse_describe_configs(output: &str) -> TopicConfigs {
+    output.lines()
+        .filter_map(|line| line.find(" are ").map(|index| &line[index + 5..]))
+        .flat_map(|configs| configs.split(','))
+        .filter_map(|config| {
+            let mut parts = config.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if !key.trim().is_empty() => Some((key.trim().to_owned(), value.trim().to_owned())),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_config_values() {
+        assert!(validate_config_value("retention.ms", "-1").is_ok());
+        assert!(validate_config_value("retention.bytes", "1073741824").is_ok());
+        assert!(validate_config_value("cleanup.policy", "compact").is_ok());
+        assert!(validate_config_value("cleanup.policy", "compact,delete").is_err());
+        assert!(validate_config_value("retention.ms", "").is_err());
+    }
+
+    #[test]
+    fn validates_names() {
+        assert!(validate_topic_name("orders.v1_eu-west").is_ok());
+        assert!(validate_topic_name("orders/v1").is_err());
+        assert!(validate_topic_name("").is_err());
+        assert!(validate_config_key("retention.ms").is_ok());
+        assert!(validate_config_key("retention.ms=1").is_err());
+    }
+
+    #[test]
+    fn parses_describe_configs() {
+        let output = "Configs for topic 'orders' are retention.ms=-1,cleanup.policy=compact\n";
+        let configs = parse_describe_configs(output);
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs["retention.ms"], "-1");
+        assert_eq!(configs["cleanup.policy"], "compact");
+        assert!(parse_describe_configs("Configs for topic 'orders' are \n").is_empty());
+    }
+}
@@ -46,6 +46,11 @@ macro_rules! format_error_chain {
     }}
 }
 
+/// Returns the error and all its causes on a single line.
+pub fn error_chain_message(err: &Error) -> String {
+    err.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(": ")
+}
+
 macro_rules! time {
     ($title:expr, $msg:expr) => {{
         use chrono;
@@ -121,7 +126,11 @@ impl fairing::Fairing for GZip {
         use flate2::{Compression, FlateReadExt};
         use std::io::{Cursor, Read};
         let headers = request.headers();
-        if headers
+        // Event streams never end, and can't be compressed as a whole
+        let is_event_stream = response.content_type()
+            .map(|content_type| content_type.top() == "text" && content_type.sub() == "event-stream")
+            .unwrap_or(false);
+        if !is_event_stream && headers
             .get("Accept-Encoding")
             .any(|e| e.to_lowercase().contains("gzip"))
             {
@@ -153,7 +162,7 @@ impl fairing::Fairing for RequestLogger {
 
     fn on_request(&self, request: &mut Request, _: &Data) {
         let uri = request.uri().as_str();
-        if !uri.starts_with("/api") && !uri.starts_with("/public") {
+        if !uri.starts_with("/api") && !uri.starts_with("/public") && uri != "/metrics" {
             info!("User request: {}", uri);
         }
     }
@@ -1,19 +1,31 @@
-use futures::{future, Future};
-use futures_cpupool::Builder;
-use rdkafka::error::KafkaResult;
-use rdkafka::consumer::Consumer;
+use rdkafka::util::millis_to_epoch;
 use regex::Regex;
 use rocket::State;
 use rocket::http::RawStr;
+use serde_json::{self, Value};
 
+use alerts::AlertStore;
+use audit::AuditLog;
 use cache::Cache;
+use config::{ClusterConfig, Config, CurrentConfig};
 use error::*;
+use events::EventLog;
+use health::{cluster_health, leadership_report, partition_issues, PartitionIssue};
 use live_consumer::LiveConsumerStore;
-use metadata::{CONSUMERS, ClusterId, TopicName};
-use offsets::OffsetStore;
+use metadata::{BrokerId, ClusterId, Partition, TopicName};
+use offset_reset::{reset_offsets, ResetRequest};
+use offsets::{fetch_watermarks, OffsetStore};
+use ownership::Ownership;
+use reassignment::plan_reassignment;
+use reload::ConfigReloader;
+use snapshot::{self, Snapshot};
+use topic_admin::TopicAdmin;
+use utils::error_chain_message;
+use web_server::auth::{Admin, Operator, User};
 use web_server::pages::omnisearch::OmnisearchFormParams;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::SystemTime;
 
 //
 // ********** TOPICS LIST **********
@@ -29,15 +41,15 @@ struct TopicDetails {
 }
 
 #[get("/api/clusters/<cluster_id>/topics?<timestamp>")]
-pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>, timestamp: &str) -> String {
+pub fn cluster_topics(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
     let _ = timestamp;
     let brokers = cache.brokers.get(&cluster_id);
-    if brokers.is_none() {  // TODO: Improve here
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {  // TODO: Improve here
         return json!({"data": []}).to_string();
     }
 
     let result_data = cache.topics
-        .filter_clone(|&(ref c, _)| c == &cluster_id)
+        .filter_clone(|&(ref c, ref t)| c == &cluster_id && user.can_access_topic(c, t))
         .into_iter()
         .map(|((_, topic_name), partitions)| {
             let metrics = cache.metrics.get(&(cluster_id.clone(), topic_name.to_owned()))
@@ -62,10 +74,10 @@ pub fn cluster_topics(cluster_id: ClusterId, cache: State<Cache>, timestamp: &st
 //
 
 #[get("/api/clusters/<cluster_id>/brokers?<timestamp>")]
-pub fn brokers(cluster_id: ClusterId, cache: State<Cache>, timestamp: &str) -> String {
+pub fn brokers(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
     let _ = timestamp;
     let brokers = cache.brokers.get(&cluster_id);
-    if brokers.is_none() {  // TODO: Improve here
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {  // TODO: Improve here
         return json!({"data": []}).to_string();
     }
 
@@ -84,6 +96,151 @@ pub fn brokers(cluster_id: ClusterId, cache: State<Cache>, timestamp: &str) -> S
     json!({"data": result_data}).to_string()
 }
 
+#[get("/api/clusters/<cluster_id>/brokers/<broker_id>/partitions?<timestamp>")]
+pub fn broker_partitions(cluster_id: ClusterId, broker_id: BrokerId, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
+    let _ = timestamp;
+    if !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let topics = cache.topics
+        .filter_clone(|&(ref c, ref t)| c == &cluster_id && user.can_access_topic(c, t));
+    let mut result_data = Vec::new();
+    for ((_, topic_name), partitions) in topics {
+        let topic_metrics = cache.metrics.get(&(cluster_id.clone(), topic_name.clone()))
+            .unwrap_or_default();
+        for p in partitions.into_iter().filter(|p| p.replicas.contains(&broker_id)) {
+            let size_bytes = topic_metrics.brokers.get(&broker_id)
+                .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                .map(|partition_metrics| partition_metrics.size_bytes)
+                .unwrap_or(-1f64);
+            let role = if p.leader == broker_id { "Leader" } else { "Follower" };
+            let in_sync = if p.isr.contains(&broker_id) { "Yes" } else { "No" };
+            result_data.push(json!((&topic_name, p.id, role, in_sync, size_bytes)));
+        }
+    }
+
+    json!({"data": result_data}).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/leadership?<timestamp>")]
+pub fn cluster_leadership(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
+    let _ = timestamp;
+    let brokers = cache.brokers.get(&cluster_id);
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let report = leadership_report(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let result_data = brokers.unwrap().into_iter()
+        .map(|broker| {
+            let leadership = report.get(broker.id);
+            json!((broker.id, broker.hostname, leadership.leader, leadership.follower, leadership.preferred_leader,
+                   report.skew(broker.id).round()))
+        })
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
+/// Returns the partitions not led by their preferred replica, in the topics matching the filter.
+fn non_preferred_partitions<F>(cache: &Cache, cluster_id: &ClusterId, topic_filter: F) -> Vec<(TopicName, Partition)>
+        where F: Fn(&str) -> bool {
+    let mut topics = cache.topics.filter_clone(|&(ref c, ref t)| c == cluster_id && topic_filter(t.as_str()));
+    topics.sort_by(|a, b| (a.0).1.cmp(&(b.0).1));
+    let mut result = Vec::new();
+    for ((_, topic_name), partitions) in topics {
+        for p in partitions {
+            if partition_issues(&p).contains(&PartitionIssue::NonPreferredLeader) {
+                result.push((topic_name.clone(), p));
+            }
+        }
+    }
+    result
+}
+
+#[get("/api/clusters/<cluster_id>/leadership/partitions?<timestamp>")]
+pub fn cluster_leadership_partitions(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
+    let _ = timestamp;
+    if !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let result_data = non_preferred_partitions(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic))
+        .into_iter()
+        .map(|(topic_name, p)| json!((topic_name, p.id, p.leader, p.replicas[0], p.replicas, p.isr)))
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
+#[derive(Deserialize)]
+struct PartitionRef {
+    topic: String,
+    partition: i32,
+}
+
+#[derive(Deserialize)]
+struct LeaderElectionRequest {
+    #[serde(default)]
+    topics: Vec<String>,           // all the partitions with a non-preferred leader
+    #[serde(default)]
+    partitions: Vec<PartitionRef>,
+}
+
+/// Returns the partitions selected by the request. If nothing is selected, all the partitions
+/// with a non-preferred leader accessible by the user are returned.
+fn election_partitions(cache: &Cache, cluster_id: &ClusterId, user: &User, request: &LeaderElectionRequest)
+        -> Result<Vec<(String, i32)>> {
+    let select_all = request.topics.is_empty() && request.partitions.is_empty();
+    let mut selected = non_preferred_partitions(cache, cluster_id, |topic| {
+            user.can_access_topic(cluster_id, topic) && (select_all || request.topics.iter().any(|t| t == topic))
+        })
+        .into_iter()
+        .map(|(topic_name, p)| (topic_name, p.id))
+        .collect::<Vec<_>>();
+    for partition in &request.partitions {
+        check_topic_access(user, cluster_id, &partition.topic)?;
+        let exists = cache.topics.get(&(cluster_id.clone(), partition.topic.clone()))
+            .map(|partitions| partitions.iter().any(|p| p.id == partition.partition))
+            .unwrap_or(false);
+        if !exists {
+            bail!("Unknown partition {}/{}", partition.topic, partition.partition);
+        }
+        let key = (partition.topic.clone(), partition.partition);
+        if !selected.contains(&key) {
+            selected.push(key);
+        }
+    }
+    Ok(selected)
+}
+
+#[post("/api/clusters/<cluster_id>/leader_election", data = "<body>")]
+pub fn preferred_leader_election(cluster_id: ClusterId, body: String, admin: Admin, cache: State<Cache>,
+                                 config: CurrentConfig, topic_admin: State<TopicAdmin>, audit_log: State<AuditLog>)
+        -> String {
+    let Admin(user) = admin;
+    let request = match serde_json::from_str::<LeaderElectionRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid request: {}", e)}).to_string(),
+    };
+    let result = election_partitions(&cache, &cluster_id, &user, &request)
+        .and_then(|partitions| {
+            let cluster_config = cluster_config_for(&config, &cluster_id, "Preferred leader election",
+                                                    |c| c.enable_leader_election)?;
+            topic_admin.elect_preferred_leaders(cluster_config, &partitions)?;
+            Ok(partitions)
+        });
+    let mut targets = request.topics.clone();
+    targets.extend(request.partitions.iter().map(|p| format!("{}/{}", p.topic, p.partition)));
+    let target = if targets.is_empty() { "*".to_owned() } else { targets.join(",") };
+    audit_log.record(&user.name, &cluster_id, "preferred_leader_election", &target,
+                     json!({"partitions": result.as_ref().ok()}), &result);
+    api_result(result.map(|partitions| json!({"ok": true, "partitions": partitions.len()})))
+}
+
 //
 // ********** GROUP **********
 //
@@ -129,10 +286,10 @@ fn build_group_list<F>(cache: &Cache, filter: F) -> HashMap<(ClusterId, String),
 }
 
 #[get("/api/clusters/<cluster_id>/groups?<timestamp>")]
-pub fn cluster_groups(cluster_id: ClusterId, cache: State<Cache>, timestamp: &str) -> String {
+pub fn cluster_groups(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
     let _ = timestamp;
     let brokers = cache.brokers.get(&cluster_id);
-    if brokers.is_none() {  // TODO: Improve here
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {  // TODO: Improve here
         return json!({"data": []}).to_string();
     }
 
@@ -147,10 +304,11 @@ pub fn cluster_groups(cluster_id: ClusterId, cache: State<Cache>, timestamp: &st
 }
 
 #[get("/api/clusters/<cluster_id>/topics/<topic_name>/groups?<timestamp>")]
-pub fn topic_groups(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>, timestamp: &str) -> String {
+pub fn topic_groups(cluster_id: ClusterId, topic_name: &RawStr, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
     let _ = timestamp;
     let brokers = cache.brokers.get(&cluster_id);
-    if brokers.is_none() {  // TODO: Improve here
+    if brokers.is_none() || !user.can_access_topic(&cluster_id, topic_name) {  // TODO: Improve here
         return json!({"data": []}).to_string();
     }
 
@@ -168,10 +326,11 @@ pub fn topic_groups(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cac
 }
 
 #[get("/api/clusters/<cluster_id>/groups/<group_name>/members?<timestamp>")]
-pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>, timestamp: &str) -> String {
+pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
     let _ = timestamp;
     let group = cache.groups.get(&(cluster_id.clone(), group_name.to_string()));
-    if group.is_none() {  // TODO: Improve here
+    if group.is_none() || !user.can_access_cluster(&cluster_id) {  // TODO: Improve here
         return json!({"data": []}).to_string();
     }
 
@@ -189,9 +348,12 @@ pub fn group_members(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
 }
 
 #[get("/api/clusters/<cluster_id>/groups/<group_name>/offsets?<timestamp>")]
-pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>, timestamp: &str) -> String {
+pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
     let _ = timestamp;
-    let offsets = cache.offsets_by_cluster_group(&cluster_id, group_name.as_str());
+    let offsets = cache.offsets_by_cluster_group(&cluster_id, group_name.as_str()).into_iter()
+        .filter(|&((ref c, _, ref t), _)| user.can_access_topic(c, t))
+        .collect::<Vec<_>>();
 
     let wms = time!("fetching wms", fetch_watermarks(&cluster_id, &offsets));
     let wms = match wms {
@@ -221,31 +383,44 @@ pub fn group_offsets(cluster_id: ClusterId, group_name: &RawStr, cache: State<Ca
     json!({"data": result_data}).to_string()
 }
 
-fn fetch_watermarks(cluster_id: &ClusterId, offsets: &[((ClusterId, String, TopicName), Vec<i64>)])
-        -> Result<HashMap<(TopicName, i32), KafkaResult<(i64, i64)>>> {
-    let consumer = CONSUMERS.get_err(cluster_id)?;
+#[get("/api/clusters/<cluster_id>/groups/<group_name>/lag_history?<timestamp>")]
+pub fn group_lag_history(cluster_id: ClusterId, group_name: &RawStr, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
+    let _ = timestamp;
+    let history = cache.lag_history
+        .filter_clone(|&(ref c, ref g, ref t)| {
+            c == &cluster_id && g == group_name.as_str() && user.can_access_topic(c, t)
+        });
 
-    let cpu_pool = Builder::new().pool_size(32).create();
+    let mut result_data = Vec::with_capacity(history.len());
+    for ((_cluster_id, _group, topic), samples) in history {
+        let samples = samples.iter()
+            .map(|sample| json!((sample.timestamp, sample.total_lag(), &sample.lag)))
+            .collect::<Vec<_>>();
+        result_data.push(json!({"topic": topic, "samples": samples}));
+    }
 
-    let mut futures = Vec::new();
+    json!({"data": result_data}).to_string()
+}
 
-    for &((_, _, ref topic), ref partitions) in offsets {
-        for partition_id in 0..partitions.len() {
-            let consumer_clone = consumer.clone();
-            let topic_clone = topic.clone();
-            let wm_future = cpu_pool.spawn_fn(move || {
-                let wms = consumer_clone.fetch_watermarks(&topic_clone, partition_id as i32, 10000);
-                Ok::<_, ()>(((topic_clone, partition_id as i32), wms))  // never fail
-            });
-            futures.push(wm_future);
-        }
+#[post("/api/clusters/<cluster_id>/groups/<group_name>/reset_offsets", data = "<body>")]
+pub fn group_reset_offsets(cluster_id: ClusterId, group_name: &RawStr, body: String, operator: Operator,
+                           cache: State<Cache>, config: CurrentConfig, audit_log: State<AuditLog>) -> String {
+    let Operator(user) = operator;
+    let request = match serde_json::from_str::<ResetRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid reset request: {}", e)}).to_string(),
+    };
+    let result = check_topic_access(&user, &cluster_id, &request.topic)
+        .and_then(|_| cluster_config(&config, &cluster_id))
+        .and_then(|cluster_config| reset_offsets(cluster_config, &cluster_id, group_name, &request, &cache));
+    if !request.dry_run {
+        let details = json!({"topic": request.topic, "strategy": format!("{:?}", request.strategy),
+                             "partitions": result.as_ref().ok()});
+        audit_log.record(&user.name, &cluster_id, "reset_offsets", group_name, details, &result);
     }
 
-    let watermarks = future::join_all(futures).wait().unwrap()
-        .into_iter()
-        .collect::<HashMap<_, _>>();
-
-    Ok(watermarks)
+    api_result(result.map(|resets| json!({"dry_run": request.dry_run, "data": resets})))
 }
 
 //
@@ -253,10 +428,11 @@ fn fetch_watermarks(cluster_id: &ClusterId, offsets: &[((ClusterId, String, Topi
 //
 
 #[get("/api/clusters/<cluster_id>/topics/<topic_name>/topology?<timestamp>")]
-pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>, timestamp: &str) -> String {
+pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, user: User, cache: State<Cache>, timestamp: &str)
+        -> String {
     let _ = timestamp;
     let partitions = cache.topics.get(&(cluster_id.to_owned(), topic_name.to_string()));
-    if partitions.is_none() {
+    if partitions.is_none() || !user.can_access_topic(&cluster_id, topic_name) {
         return json!({"data": []}).to_string();
     }
 
@@ -276,18 +452,232 @@ pub fn topic_topology(cluster_id: ClusterId, topic_name: &RawStr, cache: State<C
     json!({"data": result_data}).to_string()
 }
 
+//
+// ********** REASSIGNMENT **********
+//
+
+#[derive(Deserialize)]
+struct ReassignmentRequest {
+    brokers: Vec<BrokerId>,
+    topics: Option<String>,  // regex, default: all topics
+}
+
+fn reassignment_plan(cache: &Cache, cluster_id: &ClusterId, request: &ReassignmentRequest) -> Result<Value> {
+    let filter = match request.topics {
+        Some(ref regex) if !regex.is_empty() => Some(Regex::new(regex)
+            .chain_err(|| format!("Invalid topic regex: {}", regex))?),
+        _ => None,
+    };
+    let known_brokers = cache.brokers.get(cluster_id).unwrap_or_default();
+    for broker_id in &request.brokers {
+        if !known_brokers.iter().any(|b| b.id == *broker_id) {
+            bail!("Unknown broker {} in cluster {}", broker_id, cluster_id);
+        }
+    }
+
+    let topics = cache.topics.filter_clone(|&(ref c, ref t)| {
+        c == cluster_id && filter.as_ref().map(|r| r.is_match(t)).unwrap_or(true)
+    });
+    let mut sizes = HashMap::new();
+    let mut topic_partitions = BTreeMap::new();
+    for ((_, topic_name), partitions) in topics {
+        let topic_metrics = cache.metrics.get(&(cluster_id.clone(), topic_name.clone())).unwrap_or_default();
+        for p in &partitions {
+            // The size reported by the leader, or by any replica if the leader is unknown
+            let size = topic_metrics.brokers.get(&p.leader)
+                .into_iter()
+                .chain(topic_metrics.brokers.values())
+                .filter_map(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                .map(|partition_metrics| partition_metrics.size_bytes)
+                .next();
+            if let Some(size) = size {
+                sizes.insert((topic_name.clone(), p.id), size);
+            }
+        }
+        topic_partitions.insert(topic_name, partitions);
+    }
+
+    let plan = plan_reassignment(&topic_partitions, &sizes, &request.brokers)?;
+    let brokers = plan.after.iter()
+        .map(|(broker_id, after)| {
+            let before = plan.before.get(broker_id).cloned().unwrap_or_default();
+            json!((broker_id, before.replicas, before.bytes.round(), after.replicas, after.bytes.round()))
+        })
+        .collect::<Vec<_>>();
+    Ok(json!({
+        "plan": plan.to_reassignment_json(),
+        "brokers": brokers,
+        "moved_replicas": plan.moved_replicas,
+        "moved_bytes": plan.moved_bytes.round(),
+    }))
+}
+
+#[post("/api/clusters/<cluster_id>/reassignment", data = "<body>")]
+pub fn cluster_reassignment_plan(cluster_id: ClusterId, body: String, admin: Admin, cache: State<Cache>) -> String {
+    let Admin(user) = admin;
+    let request = match serde_json::from_str::<ReassignmentRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid request: {}", e)}).to_string(),
+    };
+    if !user.is_unrestricted() {
+        return json!({"error": "Reassignment plans require access to all the topics"}).to_string();
+    }
+    api_result(reassignment_plan(&cache, &cluster_id, &request))
+}
+
+//
+// ********** TOPIC ADMINISTRATION **********
+//
+
+/// Formats the result of an action: the content of the value on success, or an object with
+/// the error message.
+fn api_result(result: Result<Value>) -> String {
+    match result {
+        Ok(value) => value.to_string(),
+        Err(e) => json!({"error": error_chain_message(&e)}).to_string(),
+    }
+}
+
+fn check_topic_access(user: &User, cluster_id: &ClusterId, topic_name: &str) -> Result<()> {
+    if !user.can_access_topic(cluster_id, topic_name) {
+        bail!("User {} is not allowed to access topic {} on cluster {}", user.name, topic_name, cluster_id);
+    }
+    Ok(())
+}
+
+fn cluster_config<'a>(config: &'a Config, cluster_id: &ClusterId) -> Result<&'a ClusterConfig> {
+    config.cluster(cluster_id)
+        .ok_or_else(|| Error::from(format!("Cluster {} not found", cluster_id)))
+}
+
+/// Returns the configuration of the cluster, if the action is enabled for it.
+fn cluster_config_for<'a, F>(config: &'a Config, cluster_id: &ClusterId, action: &str, enabled: F)
+        -> Result<&'a ClusterConfig>
+        where F: Fn(&ClusterConfig) -> bool {
+    let cluster_config = cluster_config(config, cluster_id)?;
+    if !enabled(cluster_config) {
+        bail!("{} is disabled for cluster {}", action, cluster_id);
+    }
+    Ok(cluster_config)
+}
+
+#[derive(Deserialize)]
+struct CreateTopicRequest {
+    topic: String,
+    partitions: i32,
+    replication_factor: i32,
+    #[serde(default)]
+    configs: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct AddPartitionsRequest {
+    partitions: i32,  // new total number of partitions
+}
+
+#[derive(Deserialize)]
+struct AlterConfigsRequest {
+    #[serde(default)]
+    set: HashMap<String, String>,
+    #[serde(default)]
+    delete: Vec<String>,
+}
+
+#[get("/api/clusters/<cluster_id>/topics/<topic_name>/config?<timestamp>")]
+pub fn topic_config(cluster_id: ClusterId, topic_name: &RawStr, user: User, config: CurrentConfig,
+                    topic_admin: State<TopicAdmin>, timestamp: &str) -> String {
+    let _ = timestamp;
+    let configs = check_topic_access(&user, &cluster_id, topic_name)
+        .and_then(|_| cluster_config(&config, &cluster_id))
+        .and_then(|cluster_config| topic_admin.describe_configs(cluster_config, topic_name));
+    match configs {
+        Ok(configs) => {
+            let result_data = configs.into_iter().map(|(key, value)| json!((key, value))).collect::<Vec<_>>();
+            json!({"data": result_data}).to_string()
+        },
+        Err(e) => {
+            format_error_chain!(e);
+            json!({"data": []}).to_string()
+        },
+    }
+}
+
+#[post("/api/clusters/<cluster_id>/topics", data = "<body>")]
+pub fn create_topic(cluster_id: ClusterId, body: String, admin: Admin, config: CurrentConfig,
+                    topic_admin: State<TopicAdmin>, audit_log: State<AuditLog>) -> String {
+    let Admin(user) = admin;
+    let request = match serde_json::from_str::<CreateTopicRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid request: {}", e)}).to_string(),
+    };
+    let result = check_topic_access(&user, &cluster_id, &request.topic)
+        .and_then(|_| cluster_config_for(&config, &cluster_id, "Topic creation", |c| c.enable_topic_create))
+        .and_then(|cluster_config| {
+            topic_admin.create_topic(cluster_config, &request.topic, request.partitions,
+                                     request.replication_factor, &request.configs)
+        });
+    let details = json!({"partitions": request.partitions, "replication_factor": request.replication_factor,
+                         "configs": request.configs});
+    audit_log.record(&user.name, &cluster_id, "create_topic", &request.topic, details, &result);
+    api_result(result.map(|_| json!({"ok": true})))
+}
+
+#[delete("/api/clusters/<cluster_id>/topics/<topic_name>")]
+pub fn delete_topic(cluster_id: ClusterId, topic_name: &RawStr, admin: Admin, config: CurrentConfig,
+                    topic_admin: State<TopicAdmin>, audit_log: State<AuditLog>) -> String {
+    let Admin(user) = admin;
+    let result = check_topic_access(&user, &cluster_id, topic_name)
+        .and_then(|_| cluster_config_for(&config, &cluster_id, "Topic deletion", |c| c.enable_topic_delete))
+        .and_then(|cluster_config| topic_admin.delete_topic(cluster_config, topic_name));
+    audit_log.record(&user.name, &cluster_id, "delete_topic", topic_name, json!({}), &result);
+    api_result(result.map(|_| json!({"ok": true})))
+}
+
+#[post("/api/clusters/<cluster_id>/topics/<topic_name>/partitions", data = "<body>")]
+pub fn add_partitions(cluster_id: ClusterId, topic_name: &RawStr, body: String, admin: Admin, config: CurrentConfig,
+                      topic_admin: State<TopicAdmin>, audit_log: State<AuditLog>) -> String {
+    let Admin(user) = admin;
+    let request = match serde_json::from_str::<AddPartitionsRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid request: {}", e)}).to_string(),
+    };
+    let result = check_topic_access(&user, &cluster_id, topic_name)
+        .and_then(|_| cluster_config_for(&config, &cluster_id, "Topic alteration", |c| c.enable_topic_alter))
+        .and_then(|cluster_config| topic_admin.add_partitions(cluster_config, topic_name, request.partitions));
+    audit_log.record(&user.name, &cluster_id, "add_partitions", topic_name, json!({"partitions": request.partitions}), &result);
+    api_result(result.map(|_| json!({"ok": true})))
+}
+
+#[post("/api/clusters/<cluster_id>/topics/<topic_name>/config", data = "<body>")]
+pub fn alter_topic_config(cluster_id: ClusterId, topic_name: &RawStr, body: String, admin: Admin,
+                          config: CurrentConfig, topic_admin: State<TopicAdmin>, audit_log: State<AuditLog>) -> String {
+    let Admin(user) = admin;
+    let request = match serde_json::from_str::<AlterConfigsRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return json!({"error": format!("Invalid request: {}", e)}).to_string(),
+    };
+    let result = check_topic_access(&user, &cluster_id, topic_name)
+        .and_then(|_| cluster_config_for(&config, &cluster_id, "Topic alteration", |c| c.enable_topic_alter))
+        .and_then(|cluster_config| {
+            topic_admin.alter_configs(cluster_config, topic_name, &request.set, &request.delete)
+        });
+    audit_log.record(&user.name, &cluster_id, "alter_topic_config", topic_name,
+                     json!({"set": request.set, "delete": request.delete}), &result);
+    api_result(result.map(|_| json!({"ok": true})))
+}
+
 //
 // ********** SEARCH **********
 //
 
 #[get("/api/search/consumer?<search>")]
-pub fn consumer_search(search: OmnisearchFormParams, cache: State<Cache>) -> String {
+pub fn consumer_search(search: OmnisearchFormParams, user: User, cache: State<Cache>) -> String {
     let groups = if search.regex {
         Regex::new(&search.string)
-            .map(|r| build_group_list(&cache, |_, g| r.is_match(g)))
+            .map(|r| build_group_list(&cache, |c, g| user.can_access_cluster(c) && r.is_match(g)))
             .unwrap_or_default()
     } else {
-        build_group_list(&cache, |_, g| g.contains(&search.string))
+        build_group_list(&cache, |c, g| user.can_access_cluster(c) && g.contains(&search.string))
     };
 
     let mut result_data = Vec::with_capacity(groups.len());
@@ -299,13 +689,13 @@ pub fn consumer_search(search: OmnisearchFormParams, cache: State<Cache>) -> Str
 }
 
 #[get("/api/search/topic?<search>")]
-pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String {
+pub fn topic_search(search: OmnisearchFormParams, user: User, cache: State<Cache>) -> String {
     let topics = if search.regex {
         Regex::new(&search.string)
-            .map(|r| cache.topics.filter_clone(|&(_, ref name)| r.is_match(name)))
+            .map(|r| cache.topics.filter_clone(|&(ref c, ref name)| user.can_access_topic(c, name) && r.is_match(name)))
             .unwrap_or_default()
     } else {
-        cache.topics.filter_clone(|&(_, ref name)| name.contains(&search.string))
+        cache.topics.filter_clone(|&(ref c, ref name)| user.can_access_topic(c, name) && name.contains(&search.string))
     };
 
     let mut result_data = Vec::new();
@@ -320,12 +710,87 @@ pub fn topic_search(search: OmnisearchFormParams, cache: State<Cache>) -> String
     json!({"data": result_data}).to_string()
 }
 
+//
+// ********** HEALTH **********
+//
+
+#[get("/api/clusters/<cluster_id>/health?<timestamp>")]
+pub fn cluster_health_partitions(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
+    let _ = timestamp;
+    if !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let health = cluster_health(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let result_data = health.partitions.into_iter()
+        .map(|p| {
+            let issues = p.issues.iter().map(|issue| issue.description()).collect::<Vec<_>>().join(", ");
+            json!((p.topic, p.partition.id, p.partition.leader, p.partition.replicas, p.partition.isr, issues))
+        })
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
+#[get("/api/clusters/<cluster_id>/health/brokers?<timestamp>")]
+pub fn cluster_health_brokers(cluster_id: ClusterId, user: User, cache: State<Cache>, timestamp: &str) -> String {
+    let _ = timestamp;
+    let brokers = cache.brokers.get(&cluster_id);
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let health = cluster_health(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let result_data = brokers.unwrap().into_iter()
+        .map(|broker| {
+            let counts = health.brokers.get(&broker.id).cloned().unwrap_or_default();
+            json!((broker.id, broker.hostname, counts.under_replicated, counts.offline, counts.non_preferred_leader))
+        })
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
+//
+// ********** EVENTS **********
+//
+
+#[get("/api/clusters/<cluster_id>/events?<timestamp>")]
+pub fn cluster_events(cluster_id: ClusterId, user: User, event_log: State<EventLog>, timestamp: &str) -> String {
+    let _ = timestamp;
+    if !user.can_access_cluster(&cluster_id) {
+        return json!({"data": []}).to_string();
+    }
+
+    let result_data = event_log.events(&cluster_id).into_iter()
+        .filter(|e| e.event.topic().map(|topic| user.can_access_topic(&cluster_id, topic)).unwrap_or(true))
+        .map(|e| json!((e.timestamp, e.event.kind(), e.event.target(), e.event.description())))
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
+//
+// ********** ALERTS **********
+//
+
+#[get("/api/alerts?<timestamp>")]
+pub fn alerts(user: User, alert_store: State<AlertStore>, config: CurrentConfig, timestamp: &str) -> String {
+    let _ = timestamp;
+    let result_data = alert_store.states(&config.alerts.rules).into_iter()
+        .filter(|state| user.can_access_cluster(&state.cluster_id))
+        .map(|state| json!((state.rule, state.cluster_id, state.status, state.since, state.last_evaluation, state.details)))
+        .collect::<Vec<_>>();
+
+    json!({"data": result_data}).to_string()
+}
+
 //
 // ********** INTERNALS **********
 //
 
 #[get("/api/internals/cache/brokers?<timestamp>")]
-pub fn cache_brokers(cache: State<Cache>, timestamp: &str) -> String {
+pub fn cache_brokers(cache: State<Cache>, _admin: Admin, timestamp: &str) -> String {
     let _ = timestamp;
     let result_data = cache.brokers.lock_iter(|brokers_cache_entry| {
         brokers_cache_entry.map(|(cluster_id, brokers)| {
@@ -338,7 +803,7 @@ pub fn cache_brokers(cache: State<Cache>, timestamp: &str) -> String {
 }
 
 #[get("/api/internals/cache/metrics?<timestamp>")]
-pub fn cache_metrics(cache: State<Cache>, timestamp: &str) -> String {
+pub fn cache_metrics(cache: State<Cache>, _admin: Admin, timestamp: &str) -> String {
     let _ = timestamp;
     let result_data = cache.metrics.lock_iter(|metrics_cache_entry| {
         metrics_cache_entry
@@ -351,7 +816,7 @@ pub fn cache_metrics(cache: State<Cache>, timestamp: &str) -> String {
 }
 
 #[get("/api/internals/cache/offsets?<timestamp>")]
-pub fn cache_offsets(cache: State<Cache>, timestamp: &str) -> String {
+pub fn cache_offsets(cache: State<Cache>, _admin: Admin, timestamp: &str) -> String {
     let _ = timestamp;
     let result_data = cache.offsets.lock_iter(|offsets_cache_entry| {
         offsets_cache_entry
@@ -363,8 +828,41 @@ pub fn cache_offsets(cache: State<Cache>, timestamp: &str) -> String {
     json!({"data": result_data}).to_string()
 }
 
+#[get("/api/internals/ownership?<timestamp>")]
+pub fn cluster_ownership(ownership: State<Ownership>, config: CurrentConfig, _admin: Admin, timestamp: &str) -> String {
+    let _ = timestamp;
+    let now = millis_to_epoch(SystemTime::now()) as u64;
+    let leases = ownership.leases().into_iter().collect::<HashMap<_, _>>();
+    let mut cluster_ids = config.clusters.keys().collect::<Vec<_>>();
+    cluster_ids.sort();
+    let result_data = cluster_ids.into_iter()
+        .map(|cluster_id| match leases.get(cluster_id) {
+            Some(lease) => {
+                let status = if !lease.is_valid(now) {
+                    "Expired"
+                } else if lease.owner == ownership.instance_id() {
+                    "Owned by this instance"
+                } else {
+                    "Owned by another instance"
+                };
+                json!((cluster_id, &lease.owner, lease.acquired, lease.expires, status))
+            },
+            None => json!((cluster_id, "", 0, 0, "No owner")),
+        })
+        .collect::<Vec<_>>();
+    json!({"data": result_data}).to_string()
+}
+
+#[post("/api/internals/config/reload")]
+pub fn reload_config(config_reloader: State<ConfigReloader>, admin: Admin) -> String {
+    info!("Configuration reload requested by {}", admin.0.name);
+    api_result(config_reloader.reload().and_then(|changes| {
+        serde_json::to_value(&changes).chain_err(|| "Failed to serialize configuration changes")
+    }))
+}
+
 #[get("/api/internals/live_consumers?<timestamp>")]
-pub fn live_consumers(live_consumers: State<LiveConsumerStore>, timestamp: &str) -> String {
+pub fn live_consumers(live_consumers: State<LiveConsumerStore>, _admin: Admin, timestamp: &str) -> String {
     let _ = timestamp;
     let result_data = live_consumers.consumers().iter()
         .map(|consumer| (consumer.id(), consumer.cluster_id().to_owned(), consumer.topic().to_owned(),
@@ -372,3 +870,25 @@ pub fn live_consumers(live_consumers: State<LiveConsumerStore>, timestamp: &str)
         .collect::<Vec<_>>();
     json!({"data": result_data}).to_string()
 }
+
+#[get("/api/internals/snapshot")]
+pub fn cache_snapshot(cache: State<Cache>, admin: Admin) -> String {
+    info!("Cache snapshot exported by {}", admin.0.name);
+    api_result(snapshot::export(&cache).and_then(|snapshot| {
+        serde_json::to_value(&snapshot).chain_err(|| "Failed to serialize snapshot")
+    }))
+}
+
+#[post("/api/internals/snapshot", data = "<body>")]
+pub fn import_cache_snapshot(body: String, cache: State<Cache>, admin: Admin) -> String {
+    let snapshot = match serde_json::from_str::<Snapshot>(&body) {
+        Ok(snapshot) => snapshot,
+        Err(e) => return json!({"error": format!("Invalid snapshot: {}", e)}).to_string(),
+    };
+    let result = snapshot::import(&cache, &snapshot);
+    match result {
+        Ok(ref imported) => info!("Cache snapshot imported by {}: {:?}", admin.0.name, imported),
+        Err(ref e) => error!("Cache snapshot import by {} failed: {}", admin.0.name, error_chain_message(e)),
+    }
+    api_result(result.map(|imported| json!({"imported": imported})))
+}
@@ -0,0 +1,372 @@
+use base64;
+use bcrypt;
+use maud::Markup;
+use regex::Regex;
+use rocket::Outcome;
+use rocket::http::{Method, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{self, Catcher, State};
+
+use config::{AuthConfig, Config, Role, UserConfig};
+use error::*;
+use metadata::ClusterId;
+use web_server::pages;
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::str;
+use std::sync::Mutex;
+
+
+/// An authenticated user, with the role and the restrictions specified in the configuration.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    clusters: Option<Vec<ClusterId>>,
+    topics: Option<Regex>,
+}
+
+impl User {
+    fn new(name: &str, role: Role) -> User {
+        User { name: name.to_owned(), role, clusters: None, topics: None }
+    }
+
+    fn from_config(user_config: &UserConfig) -> Result<User> {
+        let topics = match user_config.topics {
+            Some(ref topics) => Some(Regex::new(topics)
+                .chain_err(|| format!("Invalid topic regex for user {}", user_config.name))?),
+            None => None,
+        };
+        Ok(User {
+            name: user_config.name.to_owned(),
+            role: user_config.role,
+            clusters: user_config.clusters.clone(),
+            topics,
+        })
+    }
+
+    pub fn can_access_cluster(&self, cluster_id: &ClusterId) -> bool {
+        self.clusters.as_ref().map(|clusters| clusters.contains(cluster_id)).unwrap_or(true)
+    }
+
+    pub fn can_access_topic(&self, cluster_id: &ClusterId, topic: &str) -> bool {
+        self.can_access_cluster(cluster_id)
+            && self.topics.as_ref().map(|regex| regex.is_match(topic)).unwrap_or(true)
+    }
+
+    /// Returns true if the user can access all clusters and topics.
+    pub fn is_unrestricted(&self) -> bool {
+        self.clusters.is_none() && self.topics.is_none()
+    }
+}
+
+/// The identity of the author of a request, as established by an `AuthProvider`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Identity {
+    Password(String),  // a configured user, verified with its password
+    Proxy(String),     // a name asserted by the reverse proxy
+}
+
+/// Extracts the identity of the user from a request.
+pub trait AuthProvider: Send + Sync {
+    fn authenticate(&self, request: &Request) -> Option<Identity>;
+}
+
+/// HTTP basic authentication, checked against the bcrypt hashes in the configuration.
+pub struct BasicAuthProvider {
+    password_hashes: HashMap<String, String>,
+    // bcrypt is slow by design: the passwords that have already been verified are
+    // remembered as a (keyed) hash, to avoid paying the cost on every request.
+    verified: Mutex<HashMap<String, u64>>,
+    hash_state: RandomState,
+}
+
+impl BasicAuthProvider {
+    pub fn new(users: &[UserConfig]) -> BasicAuthProvider {
+        let password_hashes = users.iter()
+            .filter_map(|user| user.password_hash.as_ref().map(|hash| (user.name.to_owned(), hash.to_owned())))
+            .collect();
+        BasicAuthProvider {
+            password_hashes,
+            verified: Mutex::new(HashMap::new()),
+            hash_state: RandomState::new(),
+        }
+    }
+
+    fn password_digest(&self, password: &str) -> u64 {
+        let mut hasher = self.hash_state.build_hasher();
+        password.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn verify(&self, name: &str, password: &str) -> bool {
+        let password_hash = match self.password_hashes.get(name) {
+            Some(hash) => hash,
+            None => return false,
+        };
+        let digest = self.password_digest(password);
+        if self.verified.lock().expect("Poison error").get(name) == Some(&digest) {
+            return true;
+        }
+        match bcrypt::verify(password, password_hash) {
+            Ok(true) => {
+                self.verified.lock().expect("Poison error").insert(name.to_owned(), digest);
+                true
+            },
+            Ok(false) => false,
+            Err(e) => {
+                warn!("Invalid password hash for user {}: {:?}", name, e);
+                false
+            },
+        }
+    }
+}
+
+impl AuthProvider for BasicAuthProvider {
+    fn authenticate(&self, request: &Request) -> Option<Identity> {
+        let header = request.headers().get_one("Authorization")?;
+        if !header.starts_with("Basic ") {
+            return None;
+        }
+        let decoded = base64::decode(header[6..].trim()).ok()?;
+        let credentials = str::from_utf8(&decoded).ok()?;
+        let mut parts = credentials.splitn(2, ':');
+        let (name, password) = (parts.next()?, parts.next()?);
+        if self.verify(name, password) {
+            Some(Identity::Password(name.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Trusts the user name set in a header by a reverse proxy, which is expected to take care
+/// of the authentication.
+pub struct ProxyHeaderProvider {
+    header: String,
+}
+
+impl ProxyHeaderProvider {
+    pub fn new(header: &str) -> ProxyHeaderProvider {
+        ProxyHeaderProvider { header: header.to_owned() }
+    }
+}
+
+impl AuthProvider for ProxyHeaderProvider {
+    fn authenticate(&self, request: &Request) -> Option<Identity> {
+        match request.headers().get_one(&self.header).map(|name| name.trim()) {
+            Some(name) if !name.is_empty() => Some(Identity::Proxy(name.to_owned())),
+            _ => None,
+        }
+    }
+}
+
+/// Identifies the user of each request. If authentication is not configured, every request
+/// is considered to come from an anonymous administrator.
+///
+/// Users with a password can only authenticate with it. The names set by the proxy are
+/// mapped to the configured users without a password, and get `proxy_role` otherwise: a
+/// client able to reach kafka-view directly could set the header itself.
+pub struct Authenticator {
+    providers: Vec<Box<AuthProvider>>,
+    users: HashMap<String, User>,
+    password_users: HashSet<String>,
+    proxy_role: Option<Role>,
+    anonymous_role: Option<Role>,
+}
+
+impl Authenticator {
+    pub fn new(config: &Config) -> Result<Authenticator> {
+        let auth_config = match config.auth {
+            Some(ref auth_config) => auth_config,
+            None => return Ok(Authenticator {
+                providers: Vec::new(),
+                users: HashMap::new(),
+                password_users: HashSet::new(),
+                proxy_role: None,
+                anonymous_role: Some(Role::Admin),
+            }),
+        };
+
+        let mut users = HashMap::new();
+        for user_config in &auth_config.users {
+            users.insert(user_config.name.to_owned(), User::from_config(user_config)?);
+        }
+        let password_users = auth_config.users.iter()
+            .filter(|user| user.password_hash.is_some())
+            .map(|user| user.name.to_owned())
+            .collect();
+
+        Ok(Authenticator {
+            providers: Authenticator::providers(auth_config),
+            users,
+            password_users,
+            proxy_role: auth_config.proxy_role,
+            anonymous_role: auth_config.anonymous_role,
+        })
+    }
+
+    fn providers(auth_config: &AuthConfig) -> Vec<Box<AuthProvider>> {
+        let mut providers: Vec<Box<AuthProvider>> = Vec::new();
+        if auth_config.users.iter().any(|user| user.password_hash.is_some()) {
+            providers.push(Box::new(BasicAuthProvider::new(&auth_config.users)));
+        }
+        if let Some(ref header) = auth_config.proxy_header {
+            providers.push(Box::new(ProxyHeaderProvider::new(header)));
+        }
+        providers
+    }
+
+    fn user_for_identity(&self, identity: Identity) -> Option<User> {
+        match identity {
+            Identity::Password(name) => self.users.get(&name).cloned(),
+            Identity::Proxy(ref name) if self.password_users.contains(name) => {
+                warn!("Ignoring proxy header for {}, who must authenticate with a password", name);
+                None
+            },
+            Identity::Proxy(name) => self.users.get(&name).cloned()
+                .or_else(|| self.proxy_role.map(|role| User::new(&name, role))),
+        }
+    }
+
+    /// Returns the user associated with the request, if any.
+    pub fn user_for(&self, request: &Request) -> Option<User> {
+        let identity = self.providers.iter().filter_map(|provider| provider.authenticate(request)).next();
+        match identity {
+            Some(identity) => self.user_for_identity(identity),
+            None => self.anonymous_role.map(|role| User::new("anonymous", role)),
+        }
+    }
+}
+
+/// Header required on the requests changing the state, which can't be set by a cross-site
+/// form. Cross-origin scripts need a CORS preflight to set it, which is never allowed.
+/// jQuery adds it to every same-origin request.
+pub const CSRF_HEADER: &str = "X-Requested-With";
+
+fn is_csrf_safe(request: &Request) -> bool {
+    match request.method() {
+        Method::Get | Method::Head | Method::Options => true,
+        _ => request.headers().get_one(CSRF_HEADER).is_some(),
+    }
+}
+
+fn user_with_role(request: &Request, role: Role) -> request::Outcome<User, ()> {
+    if !is_csrf_safe(request) {
+        warn!("Rejecting {} {} without the {} header", request.method(), request.uri(), CSRF_HEADER);
+        return Outcome::Failure((Status::Forbidden, ()));
+    }
+    let authenticator = match request.guard::<State<Authenticator>>() {
+        Outcome::Success(authenticator) => authenticator,
+        _ => return Outcome::Failure((Status::InternalServerError, ())),
+    };
+    match authenticator.user_for(request) {
+        Some(ref user) if user.role < role => Outcome::Failure((Status::Forbidden, ())),
+        Some(user) => Outcome::Success(user),
+        None => Outcome::Failure((Status::Unauthorized, ())),
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for User {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<User, ()> {
+        user_with_role(request, Role::Viewer)
+    }
+}
+
+/// A user with at least the operator role.
+pub struct Operator(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Operator {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Operator, ()> {
+        user_with_role(request, Role::Operator).map(Operator)
+    }
+}
+
+/// A user with the admin role.
+pub struct Admin(pub User);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Admin {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Admin, ()> {
+        user_with_role(request, Role::Admin).map(Admin)
+    }
+}
+
+/// Asks the browser for credentials.
+pub struct AuthenticationRequired(Markup);
+
+impl<'r> Responder<'r> for AuthenticationRequired {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        Response::build_from(self.0.respond_to(request)?)
+            .status(Status::Unauthorized)
+            .raw_header("WWW-Authenticate", "Basic realm=\"kafka-view\"")
+            .ok()
+    }
+}
+
+// The catchers are registered without the `#[error]` attribute, which can't be used
+// together with the `error!` macro of the log crate.
+fn unauthorized<'r>(_: rocket::Error, request: &'r Request) -> response::Result<'r> {
+    AuthenticationRequired(pages::warning_page("Unauthorized", "Authentication is required to access this page."))
+        .respond_to(request)
+}
+
+fn forbidden<'r>(_: rocket::Error, request: &'r Request) -> response::Result<'r> {
+    let page = pages::warning_page("Forbidden", "You don't have the permissions to access this page.");
+    Response::build_from(page.respond_to(request)?)
+        .status(Status::Forbidden)
+        .ok()
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    vec![Catcher::new(401, unauthorized), Catcher::new(403, forbidden)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authenticator() -> Authenticator {
+        let mut users = HashMap::new();
+        users.insert("alice".to_owned(), User::new("alice", Role::Admin));
+        users.insert("bob".to_owned(), User::new("bob", Role::Operator));
+        let mut password_users = HashSet::new();
+        password_users.insert("alice".to_owned());
+        Authenticator {
+            providers: Vec::new(),
+            users,
+            password_users,
+            proxy_role: Some(Role::Viewer),
+            anonymous_role: None,
+        }
+    }
+
+    fn role_of(identity: Identity) -> Option<Role> {
+        authenticator().user_for_identity(identity).map(|user| user.role)
+    }
+
+    #[test]
+    fn password_identity_maps_to_configured_user() {
+        assert_eq!(role_of(Identity::Password("alice".to_owned())), Some(Role::Admin));
+        assert_eq!(role_of(Identity::Password("mallory".to_owned())), None);
+    }
+
+    #[test]
+    fn proxy_identity_never_maps_to_password_user() {
+        assert_eq!(role_of(Identity::Proxy("alice".to_owned())), None);
+    }
+
+    #[test]
+    fn proxy_identity_maps_to_passwordless_user_or_proxy_role() {
+        assert_eq!(role_of(Identity::Proxy("bob".to_owned())), Some(Role::Operator));
+        assert_eq!(role_of(Identity::Proxy("carol".to_owned())), Some(Role::Viewer));
+    }
+}
@@ -1,5 +1,7 @@
 mod api;
+pub mod auth;
 mod pages;
+mod prometheus;
 mod view;
 
 pub mod server;
@@ -0,0 +1,28 @@
+use maud::{Markup, PreEscaped, html};
+
+use config::CurrentConfig;
+use web_server::auth::User;
+use web_server::view::layout;
+
+
+fn alerts_table() -> PreEscaped<String> {
+    layout::datatable_ajax("alerts-ajax", "/api/alerts", "",
+        html! { tr { th "Rule" th "Cluster" th "Status" th "Since" th "Last evaluation" th "Details" } }
+    )
+}
+
+#[get("/alerts")]
+pub fn alerts_page(config: CurrentConfig, _user: User) -> Markup {
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        dl class="dl-horizontal" {
+            dt "Rules: " dd (config.alerts.rules.len())
+            dt "Evaluation interval: " dd (format!("{}s", config.alerts.evaluation_interval))
+            dt "Webhook: " dd (config.alerts.webhook_url.as_ref().map(|url| url.as_str()).unwrap_or("Not configured"))
+        }
+        h3 "Alerts"
+        p "Rules are evaluated, and their changes notified, by the instance owning the cluster."
+        div (alerts_table())
+    };
+    layout::page("Alerts", content)
+}
@@ -1,11 +1,14 @@
 use maud::{PreEscaped, Markup, html};
 
+use web_server::auth::{Admin, User};
 use web_server::pages;
 use web_server::view::layout;
 use metadata::{BrokerId, ClusterId};
 
 use cache::Cache;
-use config::Config;
+use config::{CurrentConfig, Role};
+use health::{cluster_health, leadership_report};
+use metrics::broker_log_size;
 
 use rocket::State;
 
@@ -41,9 +44,123 @@ fn groups_table(cluster_id: &ClusterId) -> PreEscaped<String> {
     )
 }
 
+fn health_brokers_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/health/brokers", cluster_id);
+    layout::datatable_ajax("health-brokers-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Broker id" th "Hostname"
+            th data-toggle="tooltip" data-container="body"
+                title="Replicas hosted by the broker that are not in sync" "Under-replicated"
+            th data-toggle="tooltip" data-container="body"
+                title="Partitions without leader with a replica on the broker" "Offline"
+            th data-toggle="tooltip" data-container="body"
+                title="Partitions where the broker is the preferred leader but not the leader" "Non-preferred leader"
+            }
+        },
+    )
+}
+
+fn health_partitions_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/health", cluster_id);
+    layout::datatable_ajax("health-partitions-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Topic" th "Partition" th "Leader" th "Replicas" th "ISR" th "Issues" } },
+    )
+}
+
+fn broker_partitions_table(cluster_id: &ClusterId, broker_id: BrokerId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/brokers/{}/partitions", cluster_id, broker_id);
+    layout::datatable_ajax("broker-partitions-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Topic" th "Partition" th "Role" th "In sync" th "Size" } },
+    )
+}
+
+fn leadership_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/leadership", cluster_id);
+    layout::datatable_ajax("leadership-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Broker id" th "Hostname" th "Leader" th "Follower"
+            th data-toggle="tooltip" data-container="body"
+                title="Partitions having the broker as first replica" "Preferred leader"
+            th data-toggle="tooltip" data-container="body"
+                title="Led partitions compared to the cluster average" "Skew"
+            }
+        },
+    )
+}
+
+fn reassignment_form(cluster_id: &ClusterId, brokers: &[BrokerId]) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/reassignment", cluster_id);
+    let broker_list = brokers.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(",");
+    html! {
+        form class="form-inline reassignment_form" data-url=(api_url) {
+            div class="form-group" {
+                label for="reassignment_brokers" "Target brokers "
+                input type="text" class="form-control" id="reassignment_brokers" name="brokers"
+                    value=(broker_list) {}
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control" id="reassignment_topics" name="topics"
+                    placeholder="Topic regex (default: all)" {}
+            }
+            " "
+            button type="submit" class="btn btn-default" "Generate plan"
+        }
+        div class="reassignment_status" style="margin-top: 10pt" {}
+        table id="reassignment_table" width="100%" class="table table-striped table-bordered table-hover" {
+            thead { tr { th "Broker id" th "Replicas before" th "Size before" th "Replicas after" th "Size after" } }
+            tbody {}
+        }
+        textarea class="form-control" id="reassignment_json" rows="10" readonly="readonly" {}
+    }
+}
+
+fn non_preferred_partitions_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/leadership/partitions", cluster_id);
+    layout::datatable_ajax("non-preferred-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Topic" th "Partition" th "Leader" th "Preferred leader" th "Replicas" th "ISR" } },
+    )
+}
+
+fn leader_election_form(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/leader_election", cluster_id);
+    html! {
+        form class="leader_election_form" data-url=(api_url) {
+            textarea class="form-control" name="partitions" rows="3"
+                placeholder="One topic or topic/partition per line (default: all non-preferred leaders)" {}
+            button type="submit" class="btn btn-default" style="margin-top: 5pt" "Elect preferred leaders"
+            span class="leader_election_status" style="margin-left: 10pt" {}
+        }
+    }
+}
+
+fn events_table(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/events", cluster_id);
+    layout::datatable_ajax("events-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Time" th "Type" th "Target" th "Description" } },
+    )
+}
+
+fn create_topic_form(cluster_id: &ClusterId) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics", cluster_id);
+    html! {
+        form class="admin_form" data-action="create_topic" data-url=(api_url) {
+            div class="form-inline" {
+                input type="text" class="form-control" name="topic" placeholder="Topic name" {}
+                " "
+                input type="number" class="form-control" name="partitions" placeholder="Partitions" min="1" {}
+                " "
+                input type="number" class="form-control" name="replication_factor" placeholder="Replication factor" min="1" {}
+            }
+            textarea class="form-control" name="configs" rows="3" style="margin-top: 5pt"
+                placeholder="Topic configuration, one key=value per line" {}
+            button type="submit" class="btn btn-default" style="margin-top: 5pt" "Create topic"
+            span class="admin_status" style="margin-left: 10pt" {}
+        }
+    }
+}
+
 #[get("/clusters/<cluster_id>")]
-pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Config>) -> Markup {
-    if cache.brokers.get(&cluster_id).is_none() {
+pub fn cluster_page(cluster_id: ClusterId, user: User, cache: State<Cache>, config: CurrentConfig) -> Markup {
+    if cache.brokers.get(&cluster_id).is_none() || !user.can_access_cluster(&cluster_id) {
         return pages::warning_page(
             &format!("Cluster: {}", cluster_id),
             "The specified cluster doesn't exist.")
@@ -68,19 +185,145 @@ pub fn cluster_page(cluster_id: ClusterId, cache: State<Cache>, config: State<Co
         (topic_table(&cluster_id))
         h3 "Consumer groups"
         (groups_table(&cluster_id))
+        p {
+            a href=(format!("/clusters/{}/health", cluster_id)) "Cluster health"
+            " | "
+            a href=(format!("/clusters/{}/leadership", cluster_id)) "Leadership"
+            " | "
+            a href=(format!("/clusters/{}/events", cluster_id)) "Cluster events"
+            @if user.role >= Role::Admin {
+                " | "
+                a href=(format!("/clusters/{}/reassignment", cluster_id)) "Partition reassignment"
+            }
+        }
+        @if cluster_config.map(|c| c.enable_topic_create).unwrap_or(false) && user.role >= Role::Admin {
+            h3 "Create topic"
+            (create_topic_form(&cluster_id))
+        }
     };
     layout::page(&format!("Cluster: {}", cluster_id), content)
 }
 
+#[get("/clusters/<cluster_id>/health")]
+pub fn cluster_health_page(cluster_id: ClusterId, user: User, cache: State<Cache>) -> Markup {
+    if cache.brokers.get(&cluster_id).is_none() || !user.can_access_cluster(&cluster_id) {
+        return pages::warning_page(
+            &format!("Cluster health: {}", cluster_id),
+            "The specified cluster doesn't exist.")
+    }
+
+    let health = cluster_health(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        dl class="dl-horizontal" {
+            dt "Cluster name: " dd a href=(format!("/clusters/{}", cluster_id)) (cluster_id.name())
+            dt "Partitions: " dd (health.partition_count)
+            dt "Under-replicated: " dd (health.totals.under_replicated)
+            dt "Offline: " dd (health.totals.offline)
+            dt "Non-preferred leader: " dd (health.totals.non_preferred_leader)
+        }
+        h3 "Brokers"
+        div (health_brokers_table(&cluster_id))
+        h3 "Unhealthy partitions"
+        div (health_partitions_table(&cluster_id))
+    };
+    layout::page(&format!("Cluster health: {}", cluster_id), content)
+}
+
+#[get("/clusters/<cluster_id>/leadership")]
+pub fn leadership_page(cluster_id: ClusterId, user: User, cache: State<Cache>, config: CurrentConfig) -> Markup {
+    if cache.brokers.get(&cluster_id).is_none() || !user.can_access_cluster(&cluster_id) {
+        return pages::warning_page(
+            &format!("Leadership: {}", cluster_id),
+            "The specified cluster doesn't exist.")
+    }
+
+    let report = leadership_report(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let max_skew = report.brokers.keys().map(|&broker_id| report.skew(broker_id).abs()).fold(0f64, f64::max);
+    let enable_election = config.cluster(&cluster_id).map(|c| c.enable_leader_election).unwrap_or(false)
+        && user.role >= Role::Admin;
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        dl class="dl-horizontal" {
+            dt "Cluster name: " dd a href=(format!("/clusters/{}", cluster_id)) (cluster_id.name())
+            dt "Average leaders: " dd (format!("{:.1} per broker", report.average_leaders()))
+            dt "Max skew: " dd (format!("{:.0}%", max_skew))
+        }
+        h3 "Brokers"
+        div (leadership_table(&cluster_id))
+        h3 "Partitions with a non-preferred leader"
+        div (non_preferred_partitions_table(&cluster_id))
+        @if enable_election {
+            h3 "Preferred leader election"
+            (leader_election_form(&cluster_id))
+        }
+    };
+    layout::page(&format!("Leadership: {}", cluster_id), content)
+}
+
+#[get("/clusters/<cluster_id>/reassignment")]
+pub fn reassignment_page(cluster_id: ClusterId, admin: Admin, cache: State<Cache>, config: CurrentConfig) -> Markup {
+    let Admin(user) = admin;
+    let brokers = cache.brokers.get(&cluster_id);
+    if brokers.is_none() || !user.can_access_cluster(&cluster_id) {
+        return pages::warning_page(
+            &format!("Partition reassignment: {}", cluster_id),
+            "The specified cluster doesn't exist.")
+    }
+
+    let broker_ids = brokers.unwrap().iter().map(|b| b.id).collect::<Vec<_>>();
+    let tools_path = config.kafka_tools_path.as_ref().map(|path| format!("{}/", path)).unwrap_or_default();
+    let zookeeper = config.cluster(&cluster_id).map(|c| c.zookeeper.as_str()).unwrap_or("<zookeeper>");
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        dl class="dl-horizontal" {
+            dt "Cluster name: " dd a href=(format!("/clusters/{}", cluster_id)) (cluster_id.name())
+            dt "Brokers: " dd (broker_ids.len())
+        }
+        p {
+            "The plan moves every replica to the target brokers, balancing the number of replicas and their size, "
+            "while moving as little data as possible. Nothing is changed in the cluster: to apply the plan, save it "
+            "to a file and run:"
+        }
+        pre (format!("{}kafka-reassign-partitions.sh --zookeeper {} --reassignment-json-file plan.json --execute",
+                     tools_path, zookeeper))
+        h3 "Plan"
+        (reassignment_form(&cluster_id, &broker_ids))
+    };
+    layout::page(&format!("Partition reassignment: {}", cluster_id), content)
+}
+
+#[get("/clusters/<cluster_id>/events")]
+pub fn cluster_events_page(cluster_id: ClusterId, user: User, cache: State<Cache>, config: CurrentConfig) -> Markup {
+    if cache.brokers.get(&cluster_id).is_none() || !user.can_access_cluster(&cluster_id) {
+        return pages::warning_page(
+            &format!("Cluster events: {}", cluster_id),
+            "The specified cluster doesn't exist.")
+    }
+
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        dl class="dl-horizontal" {
+            dt "Cluster name: " dd a href=(format!("/clusters/{}", cluster_id)) (cluster_id.name())
+            dt "Metadata refresh: " dd (format!("{}s", config.metadata_refresh))
+            dt "History size: " dd (config.events_history_size)
+        }
+        h3 "Events"
+        div (events_table(&cluster_id))
+    };
+    layout::page(&format!("Cluster events: {}", cluster_id), content)
+}
+
 #[get("/clusters/<cluster_id>/brokers/<broker_id>")]
-pub fn broker_page(cluster_id: ClusterId, broker_id: BrokerId, cache: State<Cache>, config: State<Config>) -> Markup {
+pub fn broker_page(cluster_id: ClusterId, broker_id: BrokerId, user: User, cache: State<Cache>, config: CurrentConfig)
+        -> Markup {
     let broker = cache.brokers.get(&cluster_id)
         .and_then(|brokers| brokers.iter()
             .find(|b| b.id == broker_id)
             .cloned());
     let cluster_config = config.clusters.get(&cluster_id);
 
-    if broker.is_none() || cluster_config.is_none() {
+    if broker.is_none() || cluster_config.is_none() || !user.can_access_cluster(&cluster_id) {
         return pages::warning_page(
             &format!("Broker: {}", broker_id),
             "The specified broker doesn't exist.")
@@ -88,17 +331,28 @@ pub fn broker_page(cluster_id: ClusterId, broker_id: BrokerId, cache: State<Cach
 
     let broker = broker.unwrap();
     let metrics = cache.metrics.get(&(cluster_id.to_owned(), "__TOTAL__".to_owned()))
-        .unwrap_or_default()
-        .aggregate_broker_metrics();
+        .and_then(|topic_metrics| topic_metrics.brokers.get(&broker_id).cloned())
+        .unwrap_or_default();
+    let log_size = broker_log_size(&cache, &cluster_id, broker_id);
+    let report = leadership_report(&cache, &cluster_id, |topic| user.can_access_topic(&cluster_id, topic));
+    let leadership = report.get(broker_id);
     let content = html! {
         h3 style="margin-top: 0px" "Information"
         dl class="dl-horizontal" {
-            dt "Cluster name: " dd (cluster_id.name())
+            dt "Cluster name: " dd a href=(format!("/clusters/{}", cluster_id)) (cluster_id.name())
             dt "Bootstrap list: " dd (cluster_config.unwrap().broker_list.join(", "))
             dt "Zookeeper: " dd (cluster_config.unwrap().zookeeper)
-            dt "Hostname" dd (broker.hostname)
+            dt "Hostname" dd (format!("{}:{}", broker.hostname, broker.port))
             dt "Traffic" dd (format!("{:.1} KB/s  {:.0} msg/s", metrics.b_rate_15 / 1000f64, metrics.m_rate_15))
+            dt "Log size" dd (format!("{:.1} GB", log_size / 1_000_000_000f64))
+            dt "Partitions" dd (format!("{} leader, {} follower", leadership.leader, leadership.follower))
+            dt "Leadership skew" dd (format!("{:+.0}% (cluster average: {:.1} leaders per broker)",
+                                             report.skew(broker_id), report.average_leaders()))
         }
+        h3 "Hosted partitions"
+        div (broker_partitions_table(&cluster_id, broker_id))
+        h3 "Leadership"
+        div (leadership_table(&cluster_id))
     };
     layout::page(&format!("Broker: {}", cluster_id), content)
 }
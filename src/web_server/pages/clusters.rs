@@ -1,12 +1,16 @@
 use maud::{Markup, PreEscaped, html};
 use rocket::State;
 
+use web_server::auth::User;
 use web_server::view::layout;
-use cache::{Cache, BrokerCache, TopicCache};
+use cache::Cache;
+use health::{cluster_health, HealthCounts};
 use metadata::ClusterId;
 
 
-fn cluster_pane_layout(cluster_id: &ClusterId, brokers: usize, topics: usize) -> PreEscaped<String> {
+fn cluster_pane_layout(cluster_id: &ClusterId, brokers: usize, topics: usize, health: &HealthCounts)
+        -> PreEscaped<String> {
+    let health_link = format!("/clusters/{}/health", cluster_id.name());
     let link = format!("/clusters/{}/", cluster_id.name());
     html! {
         div class="col-lg-4 col-md-6" {
@@ -21,6 +25,14 @@ fn cluster_pane_layout(cluster_id: &ClusterId, brokers: usize, topics: usize) ->
                             }
                             div { (brokers) " brokers" }
                             div { (topics) " topics" }
+                            a href=(health_link) style="color: inherit;" {
+                                @if health.is_healthy() {
+                                    div "All partitions healthy"
+                                } @else {
+                                    div { (health.under_replicated) " under-replicated, " (health.offline) " offline" }
+                                    div { (health.non_preferred_leader) " non-preferred leaders" }
+                                }
+                            }
                         }
                     }
                 }
@@ -36,20 +48,22 @@ fn cluster_pane_layout(cluster_id: &ClusterId, brokers: usize, topics: usize) ->
     }
 }
 
-fn cluster_pane(cluster_id: &ClusterId, broker_cache: &BrokerCache, topic_cache: &TopicCache) -> PreEscaped<String> {
-    let broker_count = broker_cache.get(cluster_id).unwrap_or_default().len();
-    let topics_count = topic_cache.count(|&(ref c, _)| c == cluster_id);
-    cluster_pane_layout(cluster_id, broker_count, topics_count)
+fn cluster_pane(cluster_id: &ClusterId, user: &User, cache: &Cache) -> PreEscaped<String> {
+    let broker_count = cache.brokers.get(cluster_id).unwrap_or_default().len();
+    let topics_count = cache.topics.count(|&(ref c, _)| c == cluster_id);
+    let health = cluster_health(cache, cluster_id, |topic| user.can_access_topic(cluster_id, topic));
+    cluster_pane_layout(cluster_id, broker_count, topics_count, &health.totals)
 }
 
 #[get("/clusters")]
-pub fn clusters_page(cache: State<Cache>) -> Markup {
+pub fn clusters_page(user: User, cache: State<Cache>) -> Markup {
     let mut cluster_ids = cache.brokers.keys();
+    cluster_ids.retain(|cluster_id| user.can_access_cluster(cluster_id));
     cluster_ids.sort();
 
     let content = html! {
         @for cluster_id in &cluster_ids {
-            (cluster_pane(cluster_id, &cache.brokers, &cache.topics))
+            (cluster_pane(cluster_id, &user, &cache))
         }
     };
 
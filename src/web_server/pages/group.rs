@@ -1,10 +1,12 @@
 use maud::{Markup, PreEscaped, html};
 use rocket::http::RawStr;
 
+use web_server::auth::User;
 use web_server::pages;
 use web_server::view::layout;
 use metadata::ClusterId;
 use cache::Cache;
+use config::{CurrentConfig, Role};
 
 use rocket::State;
 
@@ -23,9 +25,65 @@ fn group_offsets_table(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<S
     )
 }
 
+fn group_lag_history_chart(cluster_id: &ClusterId, group_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/groups/{}/lag_history", cluster_id, group_name);
+    html! {
+        div class="lag_history_chart" data-url=(api_url) {
+            canvas width="1000" height="300" {}
+        }
+    }
+}
+
+fn group_reset_offsets_form(cluster_id: &ClusterId, group_name: &str, topics: &[String]) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/groups/{}/reset_offsets", cluster_id, group_name);
+    html! {
+        form class="form-inline reset_offsets_form" data-url=(api_url) {
+            div class="form-group" {
+                label for="reset_topic" "Topic "
+                select class="form-control" id="reset_topic" name="topic" {
+                    @for topic in topics {
+                        option value=(topic) (topic)
+                    }
+                }
+            }
+            " "
+            div class="form-group" {
+                label for="reset_strategy" "Reset to "
+                select class="form-control" id="reset_strategy" name="strategy" {
+                    option value="earliest" "Earliest"
+                    option value="latest" "Latest"
+                    option value="offset" "Offset"
+                    option value="timestamp" "Timestamp"
+                    option value="shift" "Shift by"
+                }
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control" id="reset_value" name="value"
+                    placeholder="Offset, YYYY-MM-DD hh:mm:ss or N" {}
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control" id="reset_partitions" name="partitions"
+                    placeholder="Partitions (default: all)" {}
+            }
+            " "
+            button type="button" class="btn btn-default" id="reset_preview" "Preview"
+            " "
+            button type="button" class="btn btn-danger" id="reset_apply" "Reset offsets"
+        }
+        div class="reset_offsets_status" style="margin-top: 10pt" {}
+        table id="reset_offsets_table" width="100%" class="table table-striped table-bordered table-hover" {
+            thead { tr { th "Partition" th "Low mark" th "High mark" th "Current offset" th "New offset" } }
+            tbody {}
+        }
+    }
+}
+
 #[get("/clusters/<cluster_id>/groups/<group_name>")]
-pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache>) -> Markup {
-    if cache.brokers.get(&cluster_id).is_none() {
+pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, user: User, cache: State<Cache>, config: CurrentConfig)
+        -> Markup {
+    if cache.brokers.get(&cluster_id).is_none() || !user.can_access_cluster(&cluster_id) {
         return pages::warning_page(group_name, "The specified cluster doesn't exist.")
     }
 
@@ -34,6 +92,17 @@ pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache
         None => "Not registered".to_string(),
     };
 
+    let enable_offset_reset = config.cluster(&cluster_id).map(|c| c.enable_offset_reset).unwrap_or(false)
+        && user.role >= Role::Operator;
+    let mut topics = cache.offsets
+        .filter_clone_k(|&(ref c, ref g, ref t)| {
+            c == &cluster_id && g == group_name.as_str() && user.can_access_topic(c, t)
+        })
+        .into_iter()
+        .map(|(_, _, topic)| topic)
+        .collect::<Vec<_>>();
+    topics.sort();
+
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
     let content = html! {
         h3 style="margin-top: 0px" "Information"
@@ -46,6 +115,13 @@ pub fn group_page(cluster_id: ClusterId, group_name: &RawStr, cache: State<Cache
         div (group_members_table(&cluster_id, group_name))
         h3 "Offsets"
         div (group_offsets_table(&cluster_id, group_name))
+        h3 "Lag history"
+        div (group_lag_history_chart(&cluster_id, group_name))
+        @if enable_offset_reset {
+            h3 "Reset offsets"
+            p "Offsets can only be reset when the group has no active members."
+            (group_reset_offsets_form(&cluster_id, group_name, &topics))
+        }
     };
 
     layout::page(&format!("Group: {}", group_name), content)
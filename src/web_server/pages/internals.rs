@@ -2,6 +2,8 @@ use maud::{Markup, PreEscaped, html};
 use rocket::State;
 
 use cache::Cache;
+use ownership::Ownership;
+use web_server::auth::Admin;
 use web_server::view::layout;
 
 fn broker_table() -> PreEscaped<String> {
@@ -45,8 +47,23 @@ fn cache_description_table(name: &str, key: &str, value: &str, count: usize) ->
     }
 }
 
+fn snapshot_forms() -> PreEscaped<String> {
+    html! {
+        p {
+            a href="/api/internals/snapshot" download="kafka-view-snapshot.json" "Download a snapshot"
+            " of all the caches."
+        }
+        form class="form-inline snapshot_import_form" data-url="/api/internals/snapshot" {
+            input type="file" class="form-control" name="snapshot" accept=".json" {}
+            " "
+            button type="submit" class="btn btn-default" "Import snapshot"
+            span class="admin_status" style="margin-left: 10pt" {}
+        }
+    }
+}
+
 #[get("/internals/caches")]
-pub fn caches_page(cache: State<Cache>) -> Markup {
+pub fn caches_page(cache: State<Cache>, _admin: Admin) -> Markup {
     let content = html! {
         h3 style="margin-top: 0px" "Information"
         h3 "Brokers"
@@ -58,6 +75,10 @@ pub fn caches_page(cache: State<Cache>) -> Markup {
         h3 "Offsets"
         (cache_description_table("OffsetsCache", "(ClusterId, GroupName, TopicName)", "Vec<i64>", cache.offsets.keys().len()))
         div (offsets_table())
+        h3 "Lag history"
+        (cache_description_table("LagHistoryCache", "(ClusterId, GroupName, TopicName)", "Vec<LagSample>", cache.lag_history.keys().len()))
+        h3 "Snapshot"
+        (snapshot_forms())
     };
     layout::page("Caches", content)
 }
@@ -70,10 +91,32 @@ fn live_consumers_table() -> PreEscaped<String> {
 }
 
 #[get("/internals/live_consumers")]
-pub fn live_consumers_page() -> Markup {
+pub fn live_consumers_page(_admin: Admin) -> Markup {
     let content = html! {
         h3 style="margin-top: 0px" "Active instances"
         div (live_consumers_table())
     };
     layout::page("Live consumers", content)
 }
+
+
+fn ownership_table() -> PreEscaped<String> {
+    layout::datatable_ajax("internals-ownership-ajax", "/api/internals/ownership", "",
+       html! { tr { th "Cluster id" th "Owner" th "Acquired" th "Expires" th "Status" } }
+    )
+}
+
+#[get("/internals/ownership")]
+pub fn ownership_page(ownership: State<Ownership>, _admin: Admin) -> Markup {
+    let content = html! {
+        h3 style="margin-top: 0px" "Information"
+        p {
+            "Each cluster is fetched by the instance holding its lease. This instance is "
+            span style="font-family: monospace" (ownership.instance_id())
+            "."
+        }
+        h3 "Cluster ownership"
+        div (ownership_table())
+    };
+    layout::page("Cluster ownership", content)
+}
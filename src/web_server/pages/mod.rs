@@ -1,3 +1,4 @@
+pub mod alerts;
 pub mod cluster;
 pub mod clusters;
 pub mod error_defaults;
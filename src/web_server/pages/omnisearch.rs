@@ -2,6 +2,7 @@ use maud::{Markup, html};
 use rocket::request::{FromForm, FormItems};
 use rocket::http::uri::URI;
 
+use web_server::auth::User;
 use web_server::view::layout;
 
 #[derive(Debug)]
@@ -27,12 +28,12 @@ impl<'f> FromForm<'f> for OmnisearchFormParams {
 }
 
 #[get("/omnisearch")]
-pub fn omnisearch() -> Markup {
-    omnisearch_p(OmnisearchFormParams{string: "".to_owned(), regex: false})
+pub fn omnisearch(user: User) -> Markup {
+    omnisearch_p(OmnisearchFormParams{string: "".to_owned(), regex: false}, user)
 }
 
 #[get("/omnisearch?<search>")]
-pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
+pub fn omnisearch_p(search: OmnisearchFormParams, _user: User) -> Markup {
     let search_form = layout::search_form("/omnisearch", "Omnisearch", &search.string, search.regex);
     let api_url = format!("/api/search/topic?string={}&regex={}", &search.string, search.regex);
     let topics = layout::datatable_ajax("topic-search-ajax", &api_url, "",
@@ -60,12 +61,12 @@ pub fn omnisearch_p(search: OmnisearchFormParams) -> Markup {
 }
 
 #[get("/consumers")]
-pub fn consumer_search() -> Markup {
-    consumer_search_p(OmnisearchFormParams{string: "".to_owned(), regex: false})
+pub fn consumer_search(user: User) -> Markup {
+    consumer_search_p(OmnisearchFormParams{string: "".to_owned(), regex: false}, user)
 }
 
 #[get("/consumers?<search>")]
-pub fn consumer_search_p(search: OmnisearchFormParams) -> Markup {
+pub fn consumer_search_p(search: OmnisearchFormParams, _user: User) -> Markup {
     let search_form = layout::search_form("/consumers", "Consumer name", &search.string, search.regex);
     let api_url = format!("/api/search/consumer?string={}&regex={}", &search.string, search.regex);
     let results = layout::datatable_ajax("group-search-ajax", &api_url, "",
@@ -83,12 +84,12 @@ pub fn consumer_search_p(search: OmnisearchFormParams) -> Markup {
 
 
 #[get("/topics")]
-pub fn topic_search() -> Markup {
-    topic_search_p(OmnisearchFormParams{string: "".to_owned(), regex: false})
+pub fn topic_search(user: User) -> Markup {
+    topic_search_p(OmnisearchFormParams{string: "".to_owned(), regex: false}, user)
 }
 
 #[get("/topics?<search>")]
-pub fn topic_search_p(search: OmnisearchFormParams) -> Markup {
+pub fn topic_search_p(search: OmnisearchFormParams, _user: User) -> Markup {
     let search_form = layout::search_form("/topics", "Topic name", &search.string, search.regex);
     let api_url = format!("/api/search/topic?string={}&regex={}", &search.string, search.regex);
     let results = layout::datatable_ajax("topic-search-ajax", &api_url, "",
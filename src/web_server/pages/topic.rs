@@ -3,8 +3,9 @@ use rand::random;
 use rocket::http::RawStr;
 
 use cache::Cache;
-use config::Config;
+use config::{ClusterConfig, CurrentConfig, Role};
 use metadata::ClusterId;
+use web_server::auth::{Operator, User};
 use web_server::pages;
 use web_server::view::layout;
 
@@ -18,6 +19,47 @@ fn topic_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
     )
 }
 
+fn topic_config_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
+    let api_url = format!("/api/clusters/{}/topics/{}/config", cluster_id, topic_name);
+    layout::datatable_ajax("topic-config-ajax", &api_url, cluster_id.name(),
+        html! { tr { th "Configuration" th "Value" } }
+    )
+}
+
+fn topic_admin_forms(cluster_config: &ClusterConfig, topic_name: &str, partitions: usize) -> PreEscaped<String> {
+    let cluster_id = cluster_config.cluster_id.as_ref().unwrap();
+    let partitions_url = format!("/api/clusters/{}/topics/{}/partitions", cluster_id, topic_name);
+    let config_url = format!("/api/clusters/{}/topics/{}/config", cluster_id, topic_name);
+    let topic_url = format!("/api/clusters/{}/topics/{}", cluster_id, topic_name);
+    let min_partitions = partitions + 1;
+    html! {
+        @if cluster_config.enable_topic_alter {
+            form class="admin_form form-inline" data-action="add_partitions" data-url=(partitions_url) {
+                input type="number" class="form-control" name="partitions" min=(min_partitions)
+                    placeholder="New number of partitions" {}
+                " "
+                button type="submit" class="btn btn-default" "Add partitions"
+                span class="admin_status" style="margin-left: 10pt" {}
+            }
+            form class="admin_form" data-action="alter_topic_config" data-url=(config_url) style="margin-top: 10pt" {
+                textarea class="form-control" name="set" rows="3"
+                    placeholder="Configuration to set, one key=value per line" {}
+                input type="text" class="form-control" name="delete" style="margin-top: 5pt"
+                    placeholder="Configuration to remove, comma separated" {}
+                button type="submit" class="btn btn-default" style="margin-top: 5pt" "Change configuration"
+                span class="admin_status" style="margin-left: 10pt" {}
+            }
+        }
+        @if cluster_config.enable_topic_delete {
+            form class="admin_form form-inline" data-action="delete_topic" data-url=(topic_url)
+                data-topic=(topic_name) style="margin-top: 10pt" {
+                button type="submit" class="btn btn-danger" "Delete topic"
+                span class="admin_status" style="margin-left: 10pt" {}
+            }
+        }
+    }
+}
+
 fn consumer_groups_table(cluster_id: &ClusterId, topic_name: &str) -> PreEscaped<String> {
     let api_url = format!("/api/clusters/{}/topics/{}/groups", cluster_id, topic_name);
     layout::datatable_ajax("groups-ajax", &api_url, cluster_id.name(),
@@ -37,6 +79,30 @@ fn topic_tailer_panel(cluster_id: &ClusterId, topic: &str, tailer_id: u64) -> Pr
         i class="fa fa-align-left fa-fw" {} "Messages"
     };
     let panel_body = html! {
+        form class="form-inline tailer_filter_form" style="margin-bottom: 10pt" {
+            div class="form-group" {
+                select class="form-control input-sm" name="filter_type" {
+                    option value="substring" "Contains"
+                    option value="regex" "Regex"
+                    option value="json" "JSON path"
+                }
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control input-sm" name="filter" placeholder="Filter, or JSON path ($.order.id)" {}
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control input-sm" name="value" placeholder="JSON path value" {}
+            }
+            " "
+            label class="checkbox-inline" {
+                input type="checkbox" name="key_only" {} "Key only"
+            }
+            " "
+            button type="submit" class="btn btn-default btn-sm" "Apply"
+            span class="tailer_counters" style="margin-left: 10pt" {}
+        }
         div class="topic_tailer" data-cluster=(cluster_id) data-topic=(topic) data-tailer=(tailer_id) {
             "Tailing recent messages..."
         }
@@ -45,7 +111,13 @@ fn topic_tailer_panel(cluster_id: &ClusterId, topic: &str, tailer_id: u64) -> Pr
 }
 
 #[get("/clusters/<cluster_id>/topics/<topic_name>")]
-pub fn topic_page(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache>, config: State<Config>) -> Markup {
+pub fn topic_page(cluster_id: ClusterId, topic_name: &RawStr, user: User, cache: State<Cache>, config: CurrentConfig)
+        -> Markup {
+    if !user.can_access_topic(&cluster_id, topic_name) {
+        return pages::warning_page(
+            &format!("Topic: {}", topic_name),
+            "You don't have the permissions to access this topic.")
+    }
     let partitions = match cache.topics.get(&(cluster_id.clone(), topic_name.to_string())) {
         Some(partitions) => partitions,
         None => {
@@ -63,11 +135,18 @@ pub fn topic_page(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache
         .aggregate_broker_metrics();
 
     let cluster_link = format!("/clusters/{}/", cluster_id.name());
+    let browser_link = format!("/clusters/{}/topics/{}/browser", cluster_id.name(), topic_name);
+    let enable_tailing = cluster_config.enable_tailing && user.role >= Role::Operator;
+    let enable_admin = (cluster_config.enable_topic_alter || cluster_config.enable_topic_delete)
+        && user.role >= Role::Admin;
     let content = html! {
         h3 style="margin-top: 0px" "General information"
         dl class="dl-horizontal" {
             dt "Cluster name " dd { a href=(cluster_link) (cluster_id) }
             dt "Topic name " dd (topic_name)
+            @if enable_tailing {
+                dt "Messages " dd { a href=(browser_link) "Browse messages" }
+            }
             dt "Number of partitions " dd (partitions.len())
             dt "Number of replicas " dd (partitions[0].replicas.len())
             dt "Traffic last 15 minutes"
@@ -78,11 +157,19 @@ pub fn topic_page(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache
         }
         h3 "Topology"
         (topic_table(&cluster_id, topic_name))
+        h3 "Configuration"
+        (topic_config_table(&cluster_id, topic_name))
+        @if enable_admin {
+            h3 "Administration"
+            (topic_admin_forms(cluster_config, topic_name, partitions.len()))
+        }
         h3 "Consumer groups"
         (consumer_groups_table(&cluster_id, topic_name))
         h3 "Tailer"
-        @if cluster_config.enable_tailing {
+        @if enable_tailing {
             (topic_tailer_panel(&cluster_id, topic_name, random::<u64>()))
+        } @else if cluster_config.enable_tailing {
+            p "Topic tailing requires the operator role."
         } @else {
             p "Topic tailing is disabled in this cluster."
         }
@@ -91,3 +178,90 @@ pub fn topic_page(cluster_id: ClusterId, topic_name: &RawStr, cache: State<Cache
     layout::page(&format!("Topic: {}", topic_name), content)
 }
 
+
+fn message_browser_form(cluster_id: &ClusterId, topic: &str, partitions: usize) -> PreEscaped<String> {
+    let api_url = format!("/api/browser/{}/{}", cluster_id, topic);
+    html! {
+        form class="form-inline message_browser_form" data-url=(api_url) {
+            div class="form-group" {
+                label for="browser_partition" "Partition "
+                select class="form-control" id="browser_partition" name="partition" {
+                    @for partition_id in 0..partitions {
+                        option value=(partition_id) (partition_id)
+                    }
+                }
+            }
+            " "
+            div class="form-group" {
+                label for="browser_mode" "Start from "
+                select class="form-control" id="browser_mode" name="mode" {
+                    option value="from_end" "Last N messages"
+                    option value="offset" "Offset"
+                    option value="time" "Timestamp"
+                }
+            }
+            " "
+            div class="form-group" {
+                input type="text" class="form-control" id="browser_position" name="position"
+                    placeholder="N, offset or YYYY-MM-DD hh:mm:ss" {}
+            }
+            " "
+            div class="form-group" {
+                label for="browser_count" "Page size "
+                input type="number" class="form-control" id="browser_count" name="count" value="20" min="1" max="500" style="width: 6em" {}
+            }
+            " "
+            button type="submit" class="btn btn-default" "Load"
+            " "
+            div class="btn-group" {
+                button type="button" class="btn btn-default" id="browser_previous" disabled="" {
+                    i class="fa fa-chevron-left" {} " Previous"
+                }
+                button type="button" class="btn btn-default" id="browser_next" disabled="" {
+                    "Next " i class="fa fa-chevron-right" {}
+                }
+            }
+        }
+    }
+}
+
+#[get("/clusters/<cluster_id>/topics/<topic_name>/browser")]
+pub fn topic_browser_page(cluster_id: ClusterId, topic_name: &RawStr, operator: Operator, cache: State<Cache>,
+                          config: CurrentConfig) -> Markup {
+    if !operator.0.can_access_topic(&cluster_id, topic_name) {
+        return pages::warning_page(
+            &format!("Messages: {}", topic_name),
+            "You don't have the permissions to access this topic.")
+    }
+    let partitions = match cache.topics.get(&(cluster_id.clone(), topic_name.to_string())) {
+        Some(partitions) => partitions,
+        None => {
+            return pages::warning_page(
+                &format!("Topic: {}", cluster_id),
+                "The specified cluster doesn't exist.")
+        }
+    };
+
+    let enable_tailing = config.clusters.get(&cluster_id).map(|c| c.enable_tailing).unwrap_or(false);
+    let topic_link = format!("/clusters/{}/topics/{}", cluster_id.name(), topic_name);
+    let content = html! {
+        h3 style="margin-top: 0px" "General information"
+        dl class="dl-horizontal" {
+            dt "Cluster name " dd (cluster_id)
+            dt "Topic name " dd { a href=(topic_link) (topic_name) }
+        }
+        @if enable_tailing {
+            h3 "Messages"
+            (message_browser_form(&cluster_id, topic_name, partitions.len()))
+            div class="message_browser_status" style="margin-top: 10pt" {}
+            table id="message_browser_table" width="100%" class="table table-striped table-bordered table-hover" {
+                thead { tr { th "Offset" th "Timestamp" th "Key" th "Payload" } }
+                tbody {}
+            }
+        } @else {
+            p "Message browsing is disabled in this cluster."
+        }
+    };
+
+    layout::page(&format!("Messages: {}", topic_name), content)
+}
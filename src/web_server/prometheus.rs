@@ -0,0 +1,231 @@
+use rocket::State;
+use rocket::http::Status;
+use rocket::response::Failure;
+
+use cache::Cache;
+use live_consumer::LiveConsumerStore;
+use web_server::auth::User;
+
+use std::f64;
+use std::fmt::Write;
+
+
+/// Builds a response in the Prometheus text exposition format.
+struct PrometheusWriter {
+    output: String,
+}
+
+impl PrometheusWriter {
+    fn new() -> PrometheusWriter {
+        PrometheusWriter { output: String::with_capacity(64 * 1024) }
+    }
+
+    fn metric(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {} {}", name, help);
+        let _ = writeln!(self.output, "# TYPE {} {}", name, metric_type);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels = labels.iter()
+                .map(|&(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                .collect::<Vec<_>>()
+                .join(",");
+            let _ = write!(self.output, "{{{}}}", labels);
+        }
+        let _ = writeln!(self.output, " {}", format_value(value));
+    }
+
+    fn into_string(self) -> String {
+        self.output
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_cluster_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    writer.metric("kafka_view_cluster_brokers", "gauge", "Number of brokers in the cluster.");
+    let mut brokers = cache.brokers.filter_clone(|_| true);
+    brokers.sort_by(|a, b| a.0.cmp(&b.0));
+    for (cluster_id, brokers) in brokers {
+        writer.sample("kafka_view_cluster_brokers", &[("cluster", cluster_id.name())], brokers.len() as f64);
+    }
+
+    let totals = cache.metrics.filter_clone(|&(_, ref topic)| topic == "__TOTAL__");
+    writer.metric("kafka_view_broker_bytes_in_rate", "gauge", "Incoming byte rate of the broker, 15 minutes average.");
+    for &((ref cluster_id, _), ref metrics) in &totals {
+        for (broker_id, broker_metrics) in &metrics.brokers {
+            let broker_id = broker_id.to_string();
+            writer.sample("kafka_view_broker_bytes_in_rate", &[("cluster", cluster_id.name()), ("broker", broker_id.as_str())],
+                          broker_metrics.b_rate_15);
+        }
+    }
+    writer.metric("kafka_view_broker_messages_in_rate", "gauge", "Incoming message rate of the broker, 15 minutes average.");
+    for &((ref cluster_id, _), ref metrics) in &totals {
+        for (broker_id, broker_metrics) in &metrics.brokers {
+            let broker_id = broker_id.to_string();
+            writer.sample("kafka_view_broker_messages_in_rate", &[("cluster", cluster_id.name()), ("broker", broker_id.as_str())],
+                          broker_metrics.m_rate_15);
+        }
+    }
+}
+
+fn write_topic_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    let mut topics = cache.topics.filter_clone(|_| true);
+    topics.sort_by(|a, b| a.0.cmp(&b.0));
+
+    writer.metric("kafka_view_topic_partitions", "gauge", "Number of partitions of the topic.");
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        writer.sample("kafka_view_topic_partitions", &[("cluster", cluster_id.name()), ("topic", topic.as_str())],
+                      partitions.len() as f64);
+    }
+
+    writer.metric("kafka_view_topic_under_replicated_partitions", "gauge",
+                  "Number of partitions of the topic with fewer in-sync replicas than replicas.");
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let count = partitions.iter().filter(|p| p.isr.len() < p.replicas.len()).count();
+        writer.sample("kafka_view_topic_under_replicated_partitions",
+                      &[("cluster", cluster_id.name()), ("topic", topic.as_str())], count as f64);
+    }
+
+    writer.metric("kafka_view_topic_error_partitions", "gauge", "Number of partitions of the topic reporting an error.");
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let count = partitions.iter().filter(|p| p.error.is_some()).count();
+        writer.sample("kafka_view_topic_error_partitions",
+                      &[("cluster", cluster_id.name()), ("topic", topic.as_str())], count as f64);
+    }
+
+    let mut metrics = cache.metrics.filter_clone(|&(_, ref topic)| topic != "__TOTAL__");
+    metrics.sort_by(|a, b| a.0.cmp(&b.0));
+
+    writer.metric("kafka_view_topic_bytes_in_rate", "gauge", "Incoming byte rate of the topic, 15 minutes average.");
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        writer.sample("kafka_view_topic_bytes_in_rate", &[("cluster", cluster_id.name()), ("topic", topic.as_str())],
+                      topic_metrics.aggregate_broker_metrics().b_rate_15);
+    }
+
+    writer.metric("kafka_view_topic_messages_in_rate", "gauge", "Incoming message rate of the topic, 15 minutes average.");
+    for &((ref cluster_id, ref topic), ref topic_metrics) in &metrics {
+        writer.sample("kafka_view_topic_messages_in_rate", &[("cluster", cluster_id.name()), ("topic", topic.as_str())],
+                      topic_metrics.aggregate_broker_metrics().m_rate_15);
+    }
+
+    writer.metric("kafka_view_partition_size_bytes", "gauge", "Size of the partition on the leader broker.");
+    for &((ref cluster_id, ref topic), ref partitions) in &topics {
+        let topic_metrics = match metrics.binary_search_by(|probe| probe.0.cmp(&(cluster_id.clone(), topic.clone()))) {
+            Ok(index) => &metrics[index].1,
+            Err(_) => continue,
+        };
+        for p in partitions {
+            let size = topic_metrics.brokers.get(&p.leader)
+                .and_then(|broker_metrics| broker_metrics.partitions.get(p.id as usize))
+                .map(|partition_metrics| partition_metrics.size_bytes);
+            if let Some(size) = size {
+                let partition_id = p.id.to_string();
+                writer.sample("kafka_view_partition_size_bytes",
+                              &[("cluster", cluster_id.name()), ("topic", topic.as_str()),
+                                ("partition", partition_id.as_str())], size);
+            }
+        }
+    }
+}
+
+fn write_group_metrics(writer: &mut PrometheusWriter, cache: &Cache) {
+    writer.metric("kafka_view_group_members", "gauge", "Number of members of the consumer group.");
+    for ((cluster_id, group_name), group) in cache.groups.filter_clone(|_| true) {
+        writer.sample("kafka_view_group_members",
+                      &[("cluster", cluster_id.name()), ("group", group_name.as_str()), ("state", group.state.as_str())],
+                      group.members.len() as f64);
+    }
+
+    writer.metric("kafka_view_group_offset", "gauge", "Committed offset of the consumer group.");
+    for ((cluster_id, group_name, topic), offsets) in cache.offsets.filter_clone(|_| true) {
+        for (partition_id, offset) in offsets.into_iter().enumerate() {
+            if offset < 0 {
+                continue;
+            }
+            let partition_id = partition_id.to_string();
+            writer.sample("kafka_view_group_offset",
+                          &[("cluster", cluster_id.name()), ("group", group_name.as_str()), ("topic", topic.as_str()),
+                            ("partition", partition_id.as_str())],
+                          offset as f64);
+        }
+    }
+
+    // The lag is taken from the last sample of the lag history, to avoid fetching the
+    // watermarks of every partition at each scrape.
+    let last_samples = cache.lag_history.filter_clone(|_| true).into_iter()
+        .filter_map(|(key, mut samples)| samples.pop().map(|sample| (key, sample)))
+        .collect::<Vec<_>>();
+
+    writer.metric("kafka_view_group_lag", "gauge", "Lag of the consumer group, as of the last lag history sample.");
+    for &((ref cluster_id, ref group_name, ref topic), ref sample) in &last_samples {
+        for (partition_id, &lag) in sample.lag.iter().enumerate() {
+            if lag < 0 {
+                continue;
+            }
+            let partition_id = partition_id.to_string();
+            writer.sample("kafka_view_group_lag",
+                          &[("cluster", cluster_id.name()), ("group", group_name.as_str()), ("topic", topic.as_str()),
+                            ("partition", partition_id.as_str())],
+                          lag as f64);
+        }
+    }
+
+    writer.metric("kafka_view_group_lag_sample_timestamp_seconds", "gauge", "Time of the last lag history sample.");
+    for &((ref cluster_id, ref group_name, ref topic), ref sample) in &last_samples {
+        writer.sample("kafka_view_group_lag_sample_timestamp_seconds",
+                      &[("cluster", cluster_id.name()), ("group", group_name.as_str()), ("topic", topic.as_str())],
+                      sample.timestamp as f64 / 1000f64);
+    }
+}
+
+fn write_internal_metrics(writer: &mut PrometheusWriter, cache: &Cache, live_consumers: &LiveConsumerStore) {
+    writer.metric("kafka_view_cache_items", "gauge", "Number of items in each internal cache.");
+    let cache_sizes = vec![
+        ("alerts", cache.alerts.keys().len()),
+        ("brokers", cache.brokers.keys().len()),
+        ("events", cache.events.keys().len()),
+        ("groups", cache.groups.keys().len()),
+        ("internal_offsets", cache.internal_offsets.keys().len()),
+        ("lag_history", cache.lag_history.keys().len()),
+        ("metrics", cache.metrics.keys().len()),
+        ("offsets", cache.offsets.keys().len()),
+        ("topics", cache.topics.keys().len()),
+    ];
+    for (name, size) in cache_sizes {
+        writer.sample("kafka_view_cache_items", &[("cache", name)], size as f64);
+    }
+
+    writer.metric("kafka_view_live_consumers", "gauge", "Number of active topic tailers.");
+    writer.sample("kafka_view_live_consumers", &[], live_consumers.consumers().len() as f64);
+}
+
+#[get("/metrics")]
+pub fn metrics(user: User, cache: State<Cache>, live_consumers: State<LiveConsumerStore>) -> Result<String, Failure> {
+    // Metrics are exported for all clusters and topics.
+    if !user.is_unrestricted() {
+        return Err(Failure(Status::Forbidden));
+    }
+    let mut writer = PrometheusWriter::new();
+    write_cluster_metrics(&mut writer, &cache);
+    write_topic_metrics(&mut writer, &cache);
+    write_group_metrics(&mut writer, &cache);
+    write_internal_metrics(&mut writer, &cache, &live_consumers);
+    Ok(writer.into_string())
+}
@@ -1,16 +1,27 @@
-use rocket::request::{FromParam, Request};
+use rocket::request::{self, FromParam, FromRequest, Request};
 use rocket::response::{self, Redirect, Responder, NamedFile};
 use rocket;
+use rocket::State;
 use rocket::http::RawStr;
 use scheduled_executor::ThreadPoolExecutor;
 
+use alerts::AlertStore;
+use audit::AuditLog;
+use decoders::DecoderRegistry;
+use events::EventLog;
 use error::*;
 use web_server::pages;
 use web_server::api;
+use web_server::auth::{self, Authenticator};
+use web_server::prometheus;
 use cache::Cache;
-use config::Config;
+use config::{Config, CurrentConfig, SharedConfig};
 use metadata::ClusterId;
+use ownership::Ownership;
+use reload::ConfigReloader;
 use live_consumer::{self, LiveConsumerStore};
+use topic_admin::TopicAdmin;
+use message_browser;
 use utils::{GZip, RequestLogger};
 
 use std::path::{Path, PathBuf};
@@ -31,6 +42,15 @@ impl<'a> FromParam<'a> for ClusterId {
     }
 }
 
+// The configuration in use when the request is received
+impl<'a, 'r> FromRequest<'a, 'r> for CurrentConfig {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<CurrentConfig, ()> {
+        request.guard::<State<SharedConfig>>().map(|config| config.get())
+    }
+}
+
 #[get("/public/<file..>")]
 fn files(file: PathBuf) -> Option<CachedFile> {
     NamedFile::open(Path::new("resources/web_server/public/").join(file))
@@ -70,34 +90,61 @@ impl<'a> Responder<'a> for CachedFile {
     }
 }
 
-pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config) -> Result<()> {
+/// Number of threads serving the web requests. Streaming tailers hold one each, see
+/// `live_consumer::MAX_STREAMING_CONSUMERS`.
+pub const WORKERS: u16 = 16;
+
+pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, alert_store: AlertStore, event_log: EventLog,
+                  ownership: Ownership, shared_config: SharedConfig, config_reloader: ConfigReloader,
+                  config: &Config) -> Result<()> {
     let version = option_env!("CARGO_PKG_VERSION").unwrap_or("?");
     info!("Starting kafka-view v{}, listening on {}:{}.", version, config.listen_host, config.listen_port);
 
     let rocket_config = rocket::config::Config::build(rocket::config::Environment::Development)
         .address(config.listen_host.to_owned())
         .port(config.listen_port)
-        .workers(4)
+        .workers(WORKERS)
         .log_level(rocket::logger::LoggingLevel::Critical)
         .finalize()
         .chain_err(|| "Invalid rocket configuration")?;
 
+    let decoders = DecoderRegistry::new(config)
+        .chain_err(|| "Failed to load payload decoders")?;
+    let audit_log = AuditLog::new(&config.audit_log)
+        .chain_err(|| "Failed to create audit log")?;
+    let authenticator = Authenticator::new(config)
+        .chain_err(|| "Invalid authentication configuration")?;
+
     rocket::custom(rocket_config, false)
         .attach(GZip)
         .attach(RequestLogger)
         .manage(cache)
-        .manage(config.clone())
+        .manage(alert_store)
+        .manage(event_log)
+        .manage(ownership)
+        .manage(shared_config)
+        .manage(config_reloader)
+        .manage(decoders)
+        .manage(audit_log)
+        .manage(authenticator)
+        .manage(TopicAdmin::new(config))
         .manage(LiveConsumerStore::new(executor.clone()))
         .mount("/", routes![
             index,
             files,
             files_v,
+            pages::alerts::alerts_page,
             pages::cluster::cluster_page,
             pages::cluster::broker_page,
+            pages::cluster::cluster_events_page,
+            pages::cluster::cluster_health_page,
+            pages::cluster::leadership_page,
+            pages::cluster::reassignment_page,
             pages::clusters::clusters_page,
             pages::group::group_page,
             pages::internals::caches_page,
             pages::internals::live_consumers_page,
+            pages::internals::ownership_page,
             pages::omnisearch::consumer_search,
             pages::omnisearch::consumer_search_p,
             pages::omnisearch::omnisearch,
@@ -105,21 +152,48 @@ pub fn run_server(executor: &ThreadPoolExecutor, cache: Cache, config: &Config)
             pages::omnisearch::topic_search,
             pages::omnisearch::topic_search_p,
             pages::topic::topic_page,
+            pages::topic::topic_browser_page,
+            api::alerts,
             api::brokers,
+            api::broker_partitions,
             api::cache_brokers,
             api::cache_metrics,
+            api::cluster_ownership,
+            api::reload_config,
             api::cache_offsets,
+            api::cache_snapshot,
+            api::import_cache_snapshot,
+            api::add_partitions,
+            api::alter_topic_config,
+            api::create_topic,
+            api::delete_topic,
             api::live_consumers,
+            api::preferred_leader_election,
+            api::cluster_events,
             api::cluster_groups,
+            api::cluster_health_brokers,
+            api::cluster_health_partitions,
+            api::cluster_leadership,
+            api::cluster_leadership_partitions,
+            api::cluster_reassignment_plan,
             api::cluster_topics,
             api::consumer_search,
+            api::group_lag_history,
             api::group_members,
             api::group_offsets,
+            api::group_reset_offsets,
+            api::topic_config,
             api::topic_groups,
             api::topic_search,
             api::topic_topology,
             live_consumer::test_live_consumer_api,
+            live_consumer::test_live_consumer_api_p,
+            live_consumer::tailer_stream_api,
+            live_consumer::tailer_stream_api_p,
+            message_browser::browse_partition_api,
+            prometheus::metrics,
         ])
+        .catch(auth::catchers())
         .launch();
 
     Ok(())
@@ -173,6 +173,7 @@ fn navbar_side() -> PreEscaped<String> {
                     li a href="/clusters/" style="font-size: 12pt" { i class="fa fa-server fa-fw" {}  " Clusters" }
                     li a href="/topics/" style="font-size: 12pt" { i class="fa fa-cubes fa-fw" {}  " Topics" }
                     li a href="/consumers/" style="font-size: 12pt" { i class="fa fa-exchange fa-fw" {}  " Consumers" }
+                    li a href="/alerts" style="font-size: 12pt" { i class="fa fa-bell fa-fw" {}  " Alerts" }
                     li {
                         a href="#" style="font-size: 12pt" {
                             i class="fa fa-gear fa-fw" {} " Internals"
@@ -185,6 +186,9 @@ fn navbar_side() -> PreEscaped<String> {
                             li a href="/internals/live_consumers" {
                                 i class="fa fa-microchip fa-fw" {}  " Live consumers"
                             }
+                            li a href="/internals/ownership" {
+                                i class="fa fa-microchip fa-fw" {}  " Ownership"
+                            }
                             // li {
                             //     a href="#" { "Third Level" span class="fa arrow" {} }
                             //     ul class="nav nav-third-level" {